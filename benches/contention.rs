@@ -0,0 +1,80 @@
+extern crate fiesta_net;
+extern crate mio;
+
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use mio::Token;
+use mio::tcp::TcpStream;
+
+use fiesta_net::client::{frame_packet, Connection};
+
+const PACKETS_PER_THREAD: usize = 200_000;
+
+/* several worker threads send through the same client while the "event loop"
+ * (this thread) keeps draining its queue into a loopback socket */
+fn concurrent_senders(threads: usize) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	let reader = thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut buf = vec![0; 64 * 1024];
+		let mut total = 0;
+		loop {
+			match stream.read(&mut buf[..]) {
+				Ok(0) | Err(_) => break,
+				Ok(size) => total += size,
+			}
+		}
+		total
+	});
+
+	let stream = TcpStream::connect(&addr).unwrap();
+	let mut connection = Connection::new(stream, Token(1), None);
+	let frame = Arc::new(frame_packet(0x2012, &[0x11; 24][..]));
+	let done = Arc::new(AtomicBool::new(false));
+	let start = Instant::now();
+
+	let workers: Vec<_> = (0..threads).map(|_| {
+		let client = connection.client().clone();
+		let frame = frame.clone();
+		thread::spawn(move || {
+			for _ in 0..PACKETS_PER_THREAD {
+				client.append_send(&frame[..]);
+			}
+		})
+	}).collect();
+	let waiter = {
+		let done = done.clone();
+		thread::spawn(move || {
+			for worker in workers {
+				worker.join().unwrap();
+			}
+			done.store(true, Ordering::Release);
+		})
+	};
+
+	let mut disconnect = false;
+	while !disconnect && (!done.load(Ordering::Acquire) || connection.client().queued_bytes() > 0) {
+		connection.writeable(&mut disconnect);
+	}
+	waiter.join().unwrap();
+	let send_time = start.elapsed();
+	connection.shutdown();
+	let received = reader.join().unwrap();
+
+	let secs = send_time.as_secs() as f64 + send_time.subsec_nanos() as f64 / 1_000_000_000.0;
+	let packets = threads * PACKETS_PER_THREAD;
+	println!("{:>2} sender thread(s): {:>10.0} packets/s ({} bytes in {:.3} s)", threads, packets as f64 / secs, received, secs);
+	assert_eq!(received, packets * frame.len());
+}
+
+fn main() {
+	for threads in &[1, 2, 4, 8] {
+		concurrent_senders(*threads);
+	}
+}
@@ -0,0 +1,107 @@
+extern crate fiesta_net;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::LinkedList;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use fiesta_net::buffer::{BinaryReadable, Buffer};
+use fiesta_net::client::{frame_packet, read_packets, FiestaPacket};
+
+/* counts every allocation made by this process */
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ROUNDS: usize = 20_000;
+const PACKETS_PER_READ: usize = 100;
+
+/* a walk command: handle, from x/y, to x/y, speed */
+fn movement_frames() -> Vec<u8> {
+	let mut frames = Vec::new();
+	for i in 0..PACKETS_PER_READ {
+		let mut body = Vec::new();
+		body.extend_from_slice(&(i as u16).to_be_bytes());
+		for coordinate in &[1000u32, 2000, 1010, 2020] {
+			body.extend_from_slice(&coordinate.to_be_bytes());
+		}
+		body.extend_from_slice(&120u16.to_be_bytes());
+		frames.extend(frame_packet(0x2018, &body[..]));
+	}
+	frames
+}
+
+fn decode_movement<R: BinaryReadable>(reader: &mut R) -> u64 {
+	let handle = reader.read_u16().unwrap() as u64;
+	let mut sum = handle;
+	for _ in 0..4 {
+		sum += reader.read_u32().unwrap() as u64;
+	}
+	sum + reader.read_u16().unwrap() as u64
+}
+
+/* how bodies were decoded before: copied out of the ring buffer into a `Vec`,
+ * again into a fresh `Buffer`, and every primitive read allocates */
+fn copying(frames: &[u8]) -> u64 {
+	let mut read_buffer = Buffer::with_capacity(frames.len());
+	let mut checksum = 0;
+	for _ in 0..ROUNDS {
+		read_buffer.append(frames);
+		while read_buffer.bytes_remaining() > 0 {
+			let size = read_buffer.read_u8().unwrap() as usize;
+			let _header = read_buffer.read_u16().unwrap();
+			let body = read_buffer.read_bytes(size).unwrap();
+			let mut data = Buffer::with_capacity(size);
+			data.append(&body[..]);
+			checksum += decode_movement(&mut data);
+		}
+	}
+	checksum
+}
+
+fn shared(frames: &[u8]) -> u64 {
+	let mut read_buffer = Buffer::with_capacity(frames.len());
+	let mut queue: LinkedList<FiestaPacket> = LinkedList::new();
+	let mut checksum = 0;
+	for _ in 0..ROUNDS {
+		read_buffer.append(frames);
+		read_packets(&mut read_buffer, &mut queue, usize::MAX);
+		while let Some(packet) = queue.pop_front() {
+			checksum += decode_movement(&mut packet.cursor());
+		}
+	}
+	checksum
+}
+
+fn run(name: &str, frames: &[u8], f: fn(&[u8]) -> u64) -> u64 {
+	let before = ALLOCATIONS.load(Ordering::Relaxed);
+	let start = Instant::now();
+	let checksum = f(frames);
+	let elapsed = start.elapsed();
+	let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+	let packets = ROUNDS * PACKETS_PER_READ;
+	let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+	println!("{:<8} {:>6.2} allocations/packet, {:>10.0} packets/s", name, allocations as f64 / packets as f64, packets as f64 / secs);
+	checksum
+}
+
+fn main() {
+	let frames = movement_frames();
+	let a = run("copying", &frames[..], copying);
+	let b = run("shared", &frames[..], shared);
+	assert_eq!(a, b);
+}
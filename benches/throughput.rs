@@ -0,0 +1,82 @@
+extern crate fiesta_net;
+extern crate mio;
+
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Instant;
+
+use mio::Token;
+use mio::tcp::TcpStream;
+
+use fiesta_net::buffer::Buffer;
+use fiesta_net::client::{frame_packet, Connection};
+
+const TOTAL: usize = 256 * 1024 * 1024;
+
+fn report(name: &str, bytes: usize, start: Instant) {
+	let elapsed = start.elapsed();
+	let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+	println!("{:<24} {:>10.1} MB/s ({} bytes in {:.3} s)", name, bytes as f64 / secs / (1024.0 * 1024.0), bytes, secs);
+}
+
+/* wrapping appends and vectored drains, without a socket */
+fn buffer_to_sink() {
+	let chunk = vec![0x5a; 1500];
+	let mut buffer = Buffer::new();
+	let mut sink = io::sink();
+	let mut total = 0;
+	let start = Instant::now();
+
+	while total < TOTAL {
+		while buffer.free_space() >= chunk.len() {
+			buffer.append(&chunk[..]);
+		}
+		total += buffer.drain_into(&mut sink).unwrap();
+	}
+	report("buffer -> sink", total, start);
+}
+
+/* packets through `append_send`/`writeable` over loopback */
+fn client_to_socket(body_size: usize) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	let reader = thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut buf = vec![0; 64 * 1024];
+		let mut total = 0;
+		loop {
+			match stream.read(&mut buf[..]) {
+				Ok(0) | Err(_) => break,
+				Ok(size) => total += size,
+			}
+		}
+		total
+	});
+
+	let stream = TcpStream::connect(&addr).unwrap();
+	let mut connection = Connection::new(stream, Token(1), None);
+	let client = connection.client().clone();
+	let frame = frame_packet(0x0801, &vec![0x5a; body_size][..]);
+	let mut disconnect = false;
+	let mut sent = 0;
+	let start = Instant::now();
+
+	while sent < TOTAL / 4 && !disconnect {
+		client.append_send(&frame[..]);
+		sent += frame.len();
+		connection.writeable(&mut disconnect);
+		while connection.interest().is_writable() && !disconnect {
+			connection.writeable(&mut disconnect);
+		}
+	}
+	connection.shutdown();
+	let received = reader.join().unwrap();
+	report(&format!("client -> socket ({} B)", body_size), received, start);
+}
+
+fn main() {
+	buffer_to_sink();
+	client_to_socket(64);
+	client_to_socket(1400);
+}
@@ -0,0 +1,39 @@
+/* generates the packet structs of `src/packets.rs` from the `.fps` files in `schemas/` */
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+#[allow(dead_code, deprecated, clippy::redundant_field_names)]
+#[path = "src/schema.rs"]
+mod schema;
+
+use schema::Schema;
+
+fn main() {
+	let dir = Path::new("schemas");
+	println!("cargo:rerun-if-changed={}", dir.display());
+	let mut files: Vec<_> = fs::read_dir(dir)
+		.expect("could not read schemas/")
+		.map(|entry| entry.expect("could not read schemas/").path())
+		.filter(|path| path.extension().is_some_and(|extension| extension == "fps"))
+		.collect();
+	files.sort();
+
+	let mut schema = Schema::default();
+	for path in &files {
+		println!("cargo:rerun-if-changed={}", path.display());
+		let source = fs::read_to_string(path).unwrap_or_else(|e| fail(path, &e));
+		let file = Schema::parse(&source).unwrap_or_else(|e| fail(path, &e));
+		schema.merge(file).unwrap_or_else(|e| fail(path, &e));
+	}
+
+	let out = Path::new(&env::var_os("OUT_DIR").unwrap()).join("packets.rs");
+	let code = format!("/* generated from the schema files by build.rs, do not edit */\n\n{}", schema.generate(""));
+	fs::write(&out, code).unwrap_or_else(|e| fail(&out, &e));
+}
+
+fn fail<T>(path: &Path, error: &dyn std::fmt::Display) -> T {
+	eprintln!("error: {}: {}", path.display(), error);
+	process::exit(1);
+}
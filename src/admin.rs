@@ -0,0 +1,451 @@
+/* A line based admin console, served by the event loop of a `FiestaHandler`
+ * next to its clients. Bind it to localhost or a Unix socket:
+ *
+ *     $ nc -U /run/fiesta/zone.admin
+ *     auth s3cret
+ *     ok
+ *     list
+ *     Token(3) 127.0.0.1:50412 default up 12s queued 0 tracing off
+ *     ok
+ *
+ * Every command answers with its output followed by `ok` or `error: ...`.
+ * Without an `Authenticator` the server only starts the console on a Unix
+ * socket or a loopback address. A session is closed after
+ * `MAX_AUTH_FAILURES` failed `auth` attempts. */
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::sync::Arc;
+use mio::*;
+
+use client::{frame_packet, FiestaNetworkClient, MAX_BODY_SIZE};
+use dump::parse_hex;
+use metrics::Metrics;
+use opcodes::parse_opcode;
+use registry::ClientRegistry;
+use stream::{ListenAddr, Listener, Stream};
+
+/* longest line a session may send */
+const MAX_LINE: usize = 4096;
+/* sessions served at the same time, more are closed right away */
+const MAX_SESSIONS: usize = 8;
+pub const MAX_AUTH_FAILURES: u32 = 3;
+
+/* checks the credentials given with `auth <credentials>` */
+pub trait Authenticator: Send + Sync {
+	fn authenticate(&self, credentials: &str) -> bool;
+}
+
+/* the credentials have to be exactly this secret */
+pub struct SharedSecret(pub String);
+
+/* output of a command, or the reason it failed */
+pub type CommandResult = Result<String, String>;
+
+/* a command registered by the application; gets the arguments after its name */
+pub type AdminCommand = dyn Fn(&AdminContext, &[&str]) -> CommandResult + Send + Sync;
+
+#[derive(Clone)]
+struct CustomCommand {
+	help:			String,
+	run:			Arc<AdminCommand>,
+}
+
+#[derive(Clone)]
+pub struct AdminConfig {
+	pub addr:		ListenAddr,
+	/* `None` lets every session in without `auth`, see `validate` */
+	auth:			Option<Arc<dyn Authenticator>>,
+	commands:		BTreeMap<String, CustomCommand>,
+}
+
+/* what a command can look at and do */
+pub struct AdminContext<'a> {
+	pub registry:	&'a ClientRegistry,
+	pub metrics:	&'a Metrics,
+	/* whether this event loop accepts clients right now */
+	pub accepting:	bool,
+	accept_change:	Cell<Option<bool>>,
+}
+
+struct Session {
+	stream:			Stream,
+	input:			Vec<u8>,
+	output:			Vec<u8>,
+	authenticated:	bool,
+	failed_auths:	u32,
+	/* close once the output is written */
+	closing:		bool,
+}
+
+pub struct AdminConsole {
+	listener:		Listener,
+	token:			Token,
+	config:			AdminConfig,
+	sessions:		HashMap<Token, Session>,
+}
+
+const BUILTIN_HELP: &[(&str, &str)] = &[
+	("help", "lists the commands"),
+	("auth <credentials>", "authenticates the session"),
+	("status", "clients and whether accepts are paused"),
+	("list", "connected clients: token, address, listener, uptime, queued bytes, tracing"),
+	("kick <token>", "disconnects a client"),
+	("metrics", "dumps the metrics in the Prometheus format"),
+	("trace <token> on|off", "logs every packet of a client"),
+	("broadcast <opcode> [hex body]", "sends a packet to every client"),
+	("pause", "stops accepting clients"),
+	("resume", "accepts clients again"),
+	("quit", "closes the session"),
+];
+
+impl Authenticator for SharedSecret {
+	fn authenticate(&self, credentials: &str) -> bool {
+		/* same time for every wrong guess of the same length */
+		let (a, b) = (self.0.as_bytes(), credentials.as_bytes());
+		a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+	}
+}
+
+impl<F> Authenticator for F where F: Fn(&str) -> bool + Send + Sync {
+	fn authenticate(&self, credentials: &str) -> bool {
+		self(credentials)
+	}
+}
+
+impl AdminConfig {
+	pub fn new<A: Into<ListenAddr>>(addr: A) -> Self {
+		AdminConfig {
+			addr:		addr.into(),
+			auth:		None,
+			commands:	BTreeMap::new(),
+		}
+	}
+
+	pub fn auth<A: Authenticator + 'static>(mut self, auth: A) -> Self {
+		self.auth = Some(Arc::new(auth));
+		self
+	}
+
+	/* anyone who can connect may run every command without an authenticator,
+	 * so then only Unix sockets and loopback addresses are allowed */
+	pub fn validate(&self) -> io::Result<()> {
+		match self.addr {
+			ListenAddr::Tcp(ref addr) if self.auth.is_none() && !addr.ip().is_loopback() => Err(Error::new(ErrorKind::InvalidInput,
+				format!("the admin console on {} needs an authenticator, it is not a loopback address", addr))),
+			_ => Ok(()),
+		}
+	}
+
+	/* adds a command, or replaces a built-in one with the same name */
+	pub fn command<F>(mut self, name: &str, help: &str, run: F) -> Self
+			where F: Fn(&AdminContext, &[&str]) -> CommandResult + Send + Sync + 'static {
+		self.commands.insert(name.to_string(), CustomCommand {
+			help:		help.to_string(),
+			run:		Arc::new(run),
+		});
+		self
+	}
+}
+
+impl<'a> AdminContext<'a> {
+	pub fn new(registry: &'a ClientRegistry, metrics: &'a Metrics, accepting: bool) -> Self {
+		AdminContext {
+			registry,
+			metrics,
+			accepting,
+			accept_change:	Cell::new(None),
+		}
+	}
+
+	/* applied by the handler once the command returned */
+	pub fn pause_accept(&self) {
+		self.accept_change.set(Some(false));
+	}
+
+	pub fn resume_accept(&self) {
+		self.accept_change.set(Some(true));
+	}
+
+	/* `Some(accepting)` if a command asked to pause or resume accepts */
+	pub fn accept_change(&self) -> Option<bool> {
+		self.accept_change.get()
+	}
+}
+
+impl AdminConsole {
+	pub fn new(listener: Listener, token: Token, config: AdminConfig) -> Self {
+		AdminConsole {
+			listener,
+			token,
+			config,
+			sessions:		HashMap::new(),
+		}
+	}
+
+	pub fn register<H: Handler>(&self, event_loop: &mut EventLoop<H>) -> ::std::io::Result<()> {
+		event_loop.register_opt(&self.listener, self.token, EventSet::readable(), PollOpt::level())
+	}
+
+	pub fn token(&self) -> Token {
+		self.token
+	}
+
+	/* whether `token` is the listener or one of the sessions */
+	pub fn owns(&self, token: Token) -> bool {
+		token == self.token || self.sessions.contains_key(&token)
+	}
+
+	/* session tokens come from the registry so they never collide with a client's */
+	pub fn ready<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, token: Token, events: EventSet, context: &AdminContext) {
+		if token == self.token {
+			self.accept(event_loop, context.registry);
+			return;
+		}
+		let done = match self.sessions.get_mut(&token) {
+			Some(session) => session.ready(events, &self.config, context),
+			None => return,
+		};
+		match self.sessions.get(&token) {
+			Some(session) if !done => {
+				let mut interest = EventSet::readable();
+				if !session.output.is_empty() {
+					interest.insert(EventSet::writable());
+				}
+				if let Err(e) = event_loop.reregister(&session.stream, token, interest, PollOpt::oneshot()) {
+					warn!(target: "admin", "could not re-register {:?}: {:#?}", token, e);
+				}
+			},
+			_ => self.close(event_loop, token),
+		}
+	}
+
+	fn accept<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, registry: &ClientRegistry) {
+		let stream = match self.listener.accept() {
+			Ok(Some(stream)) => stream,
+			Ok(None) => return,
+			Err(e) => {
+				warn!(target: "admin", "error while accepting: {:#?}", e);
+				return;
+			},
+		};
+		if self.sessions.len() >= MAX_SESSIONS {
+			debug!(target: "admin", "too many sessions, closing");
+			let _ = stream.shutdown();
+			return;
+		}
+		let token = registry.next_token();
+		if let Err(e) = event_loop.register_opt(&stream, token, EventSet::readable(), PollOpt::oneshot()) {
+			warn!(target: "admin", "could not register {:?}: {:#?}", token, e);
+			return;
+		}
+		info!(target: "admin", "session {:?} opened from {:?}", token, stream.peer_addr());
+		self.sessions.insert(token, Session {
+			stream,
+			input:			Vec::new(),
+			output:			Vec::new(),
+			authenticated:	self.config.auth.is_none(),
+			failed_auths:	0,
+			closing:		false,
+		});
+	}
+
+	fn close<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, token: Token) {
+		if let Some(session) = self.sessions.remove(&token) {
+			let _ = event_loop.deregister(&session.stream);
+			let _ = session.stream.shutdown();
+			info!(target: "admin", "session {:?} closed", token);
+		}
+	}
+}
+
+impl Session {
+	/* returns true once the session is done with */
+	fn ready(&mut self, events: EventSet, config: &AdminConfig, context: &AdminContext) -> bool {
+		if events.is_error() {
+			return true;
+		}
+		if events.is_readable() && !self.closing {
+			let open = self.read();
+			while let Some(end) = self.input.iter().position(|b| *b == b'\n') {
+				let line: Vec<u8> = self.input.drain(..end + 1).collect();
+				let line = String::from_utf8_lossy(&line[..end]).trim().to_string();
+				if !line.is_empty() {
+					self.execute(&line, config, context);
+				}
+				if self.closing {
+					break;
+				}
+			}
+			if self.input.len() > MAX_LINE {
+				self.reply(Err("line too long".to_string()));
+				self.closing = true;
+			}
+			if !open {
+				self.closing = true;
+			}
+		}
+		if !self.write() {
+			return true;
+		}
+		self.closing && self.output.is_empty()
+	}
+
+	/* false once the other side closed */
+	fn read(&mut self) -> bool {
+		let mut buf = [0; 1024];
+		loop {
+			match self.stream.read(&mut buf[..]) {
+				Ok(0) => return false,
+				Ok(size) => {
+					self.input.extend_from_slice(&buf[..size]);
+					if self.input.len() > MAX_LINE {
+						return true;
+					}
+				},
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(_) => return false,
+			}
+		}
+	}
+
+	/* false if the session broke */
+	fn write(&mut self) -> bool {
+		while !self.output.is_empty() {
+			match self.stream.write(&self.output[..]) {
+				Ok(0) => return false,
+				Ok(size) => { self.output.drain(..size); },
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(_) => return false,
+			}
+		}
+		true
+	}
+
+	fn reply(&mut self, result: CommandResult) {
+		let text = match result {
+			Ok(output) => {
+				let mut text = output;
+				if !text.is_empty() && !text.ends_with('\n') {
+					text.push('\n');
+				}
+				text.push_str("ok\n");
+				text
+			},
+			Err(message) => format!("error: {}\n", message),
+		};
+		self.output.extend_from_slice(text.as_bytes());
+	}
+
+	fn execute(&mut self, line: &str, config: &AdminConfig, context: &AdminContext) {
+		let args: Vec<&str> = line.split_whitespace().collect();
+		let (command, args) = (args[0], &args[1..]);
+		let result = match command {
+			"quit" => {
+				self.closing = true;
+				Ok(String::new())
+			},
+			"help" => Ok(help(config)),
+			"auth" => match config.auth {
+				Some(ref auth) if auth.authenticate(line["auth".len()..].trim()) => {
+					self.authenticated = true;
+					Ok(String::new())
+				},
+				Some(_) => {
+					warn!(target: "admin", "failed authentication");
+					self.failed_auths += 1;
+					if self.failed_auths >= MAX_AUTH_FAILURES {
+						self.closing = true;
+						Err("authentication failed, closing".to_string())
+					} else {
+						Err("authentication failed".to_string())
+					}
+				},
+				None => Ok(String::new()),
+			},
+			_ if !self.authenticated => Err("authenticate first".to_string()),
+			_ => {
+				debug!(target: "admin", "executing {:?}", line);
+				match config.commands.get(command) {
+					Some(custom) => (custom.run)(context, args),
+					None => builtin(command, args, context),
+				}
+			},
+		};
+		self.reply(result);
+	}
+}
+
+fn help(config: &AdminConfig) -> String {
+	let mut lines: Vec<(String, &str)> = BUILTIN_HELP.iter()
+		.filter(|&&(usage, _)| !config.commands.contains_key(usage.split(' ').next().unwrap()))
+		.map(|&(usage, help)| (usage.to_string(), help))
+		.collect();
+	lines.extend(config.commands.iter().map(|(name, command)| (name.clone(), &command.help[..])));
+	let width = lines.iter().map(|l| l.0.len()).max().unwrap_or(0);
+	lines.iter().map(|&(ref usage, help)| format!("{:width$}  {}\n", usage, help, width = width)).collect()
+}
+
+fn builtin(command: &str, args: &[&str], context: &AdminContext) -> CommandResult {
+	match (command, args) {
+		("status", []) => Ok(format!("clients {}\naccepting {}", context.registry.len(), context.accepting)),
+		("list", []) => {
+			let mut clients = context.registry.clients();
+			clients.sort_by_key(|c| c.id().0);
+			Ok(clients.iter().map(|c| format!("{:?} {} {} up {}s queued {} tracing {}\n",
+				c.id(),
+				c.peer_addr().map(|a| a.to_string()).unwrap_or_else(|| "-".to_string()),
+				c.listener(),
+				c.uptime().as_secs(),
+				c.queued_bytes(),
+				if c.is_tracing() { "on" } else { "off" })).collect())
+		},
+		("kick", [token]) => {
+			let client = find_client(context, token)?;
+			client.close();
+			Ok(format!("kicked {:?}", client.id()))
+		},
+		("metrics", []) => Ok(context.metrics.snapshot().to_prometheus()),
+		("trace", [token, state]) => {
+			let enabled = match *state {
+				"on" => true,
+				"off" => false,
+				_ => return Err("expected on or off".to_string()),
+			};
+			let client = find_client(context, token)?;
+			client.set_tracing(enabled);
+			Ok(String::new())
+		},
+		("broadcast", _) if !args.is_empty() => {
+			let opcode = parse_opcode(args[0])?;
+			let body = parse_hex(&args[1..].concat())?;
+			if body.len() > MAX_BODY_SIZE {
+				return Err(format!("at most {} bytes", MAX_BODY_SIZE));
+			}
+			let frame = frame_packet(opcode, &body[..]);
+			let clients = context.registry.clients();
+			for client in &clients {
+				client.append_send(&frame[..]);
+			}
+			Ok(format!("sent to {} clients", clients.len()))
+		},
+		("pause", []) => {
+			context.pause_accept();
+			Ok(String::new())
+		},
+		("resume", []) => {
+			context.resume_accept();
+			Ok(String::new())
+		},
+		(_, _) if BUILTIN_HELP.iter().any(|&(usage, _)| usage.split(' ').next() == Some(command)) =>
+			Err("wrong arguments, see help".to_string()),
+		_ => Err(format!("unknown command {}", command)),
+	}
+}
+
+fn find_client(context: &AdminContext, token: &str) -> Result<Arc<FiestaNetworkClient>, String> {
+	let token = token.parse().map_err(|_| format!("not a token: {}", token))?;
+	context.registry.get(Token(token)).ok_or_else(|| format!("no client with {:?}", Token(token)))
+}
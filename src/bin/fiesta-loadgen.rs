@@ -0,0 +1,410 @@
+/* simulates many clients against a server and reports how it held up:
+ *
+ *   fiesta-loadgen --server <addr> [--clients <n>] [--ramp <per second>]
+ *                  [--duration <seconds>] [--handshake <opcode>]
+ *                  [--hello <opcode>[:<hex body>]]
+ *                  [--move <opcode>] [--move-rate <per second>]
+ *                  [--chat <opcode>] [--chat-rate <per second>] [--chat-text <text>]
+ *                  [--reply <request opcode>=<response opcode>]...
+ *
+ * Every client connects, waits for the `--handshake` packet if one is given,
+ * sends the `--hello` packet and then moves and chats at the given rates.
+ * Movement is sent as four u32 (from x, from y, to x, to y), chat as a u8
+ * length followed by the text. The round trip of a request is the time until
+ * its response arrives on the same client; the response opcode is the request
+ * opcode + 1 unless `--reply` says otherwise. Opcodes may be given in hex.
+ */
+extern crate fiesta_net;
+extern crate mio;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::net::SocketAddr;
+use std::process;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use mio::Token;
+
+use fiesta_net::client::FiestaNetworkClient;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::connector::Connector;
+use fiesta_net::dump;
+use fiesta_net::opcodes;
+use fiesta_net::processing::{PacketProcessingInfo, PacketProcessor};
+use fiesta_net::writer::PacketWriter;
+
+/* requests older than this are counted as unanswered */
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+const TICK: Duration = Duration::from_millis(10);
+
+struct Scenario {
+	server:			SocketAddr,
+	clients:		usize,
+	ramp:			f64,
+	duration:		Duration,
+	handshake:		Option<u16>,
+	hello:			Option<(u16, Vec<u8>)>,
+	movement:		Option<(u16, f64)>,
+	chat:			Option<(u16, f64)>,
+	chat_text:		String,
+	replies:		HashMap<u16, u16>,
+}
+
+struct Bot {
+	client:			Arc<FiestaNetworkClient>,
+	ready:			bool,
+	/* sent requests by response opcode, oldest first */
+	pending:		HashMap<u16, VecDeque<(u16, Instant)>>,
+	next_move:		Instant,
+	next_chat:		Instant,
+	position:		(u32, u32),
+}
+
+#[derive(Default)]
+struct Stats {
+	attempts:		usize,
+	connected:		usize,
+	failed:			usize,
+	connect_us:		Vec<u64>,
+	handshakes:		usize,
+	sent:			BTreeMap<u16, u64>,
+	/* round trips in microseconds by request opcode */
+	round_trips:	BTreeMap<u16, Vec<u64>>,
+	unanswered:		u64,
+}
+
+/* each bot has its own lock, the map is only held to look one up */
+#[derive(Default)]
+struct Bots {
+	known:			HashMap<Token, Arc<Mutex<Bot>>>,
+	/* opcodes received before `connect` returned and the bot was added */
+	early:			HashMap<Token, Vec<u16>>,
+}
+
+struct Shared {
+	scenario:		Scenario,
+	bots:			Mutex<Bots>,
+	stats:			Mutex<Stats>,
+}
+
+struct LoadProcessor(Arc<Shared>);
+
+/* xorshift, good enough to spread the bots */
+struct Random(u64);
+
+impl Random {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	fn fraction(&mut self) -> f64 {
+		(self.next() % 1_000_000) as f64 / 1_000_000.0
+	}
+}
+
+impl Shared {
+	fn send(&self, bot: &mut Bot, packet: PacketWriter) {
+		let opcode = packet.opcode();
+		let reply = self.scenario.replies.get(&opcode).cloned().unwrap_or(opcode.wrapping_add(1));
+		bot.client.append_send(&packet.to_frame()[..]);
+		bot.pending.entry(reply).or_default().push_back((opcode, Instant::now()));
+		*self.stats.lock().unwrap().sent.entry(opcode).or_insert(0) += 1;
+	}
+
+	fn start(&self, bot: &mut Bot) {
+		bot.ready = true;
+		if let Some((opcode, ref body)) = self.scenario.hello {
+			self.send(bot, PacketWriter::new(opcode).write_bytes(&body[..]));
+		}
+	}
+
+	/* the connection is handed to the event loop before `connect` returns, the
+	 * handshake may well arrive first. Whatever did is replayed in order. */
+	fn add(&self, token: Token, mut bot: Bot) {
+		let mut bots = self.bots.lock().unwrap();
+		if self.scenario.handshake.is_none() {
+			self.start(&mut bot);
+		}
+		for opcode in bots.early.remove(&token).unwrap_or_default() {
+			self.answered(&mut bot, opcode);
+		}
+		bots.known.insert(token, Arc::new(Mutex::new(bot)));
+	}
+
+	fn received(&self, token: Token, opcode: u16) {
+		let bot = {
+			let mut bots = self.bots.lock().unwrap();
+			match bots.known.get(&token) {
+				Some(bot) => bot.clone(),
+				None => {
+					bots.early.entry(token).or_default().push(opcode);
+					return;
+				},
+			}
+		};
+		self.answered(&mut bot.lock().unwrap(), opcode);
+	}
+
+	fn answered(&self, bot: &mut Bot, opcode: u16) {
+		if !bot.ready && self.scenario.handshake == Some(opcode) {
+			self.stats.lock().unwrap().handshakes += 1;
+			self.start(bot);
+			return;
+		}
+		let answered = bot.pending.get_mut(&opcode).and_then(|pending| pending.pop_front());
+		if let Some((request, sent)) = answered {
+			let us = sent.elapsed().as_micros() as u64;
+			self.stats.lock().unwrap().round_trips.entry(request).or_default().push(us);
+		}
+	}
+
+	/* sends whatever is due */
+	fn tick(&self, random: &mut Random) {
+		let now = Instant::now();
+		/* the lock of the map is not held while sending, responses keep coming in */
+		let bots: Vec<_> = self.bots.lock().unwrap().known.values().cloned().collect();
+		for bot in &bots {
+			let mut bot = bot.lock().unwrap();
+			if !bot.ready || !bot.client.alive() {
+				continue;
+			}
+			let bot = &mut *bot;
+			if let Some((opcode, rate)) = self.scenario.movement {
+				if now >= bot.next_move {
+					let from = bot.position;
+					let to = (from.0.wrapping_add((random.next() % 21) as u32).wrapping_sub(10),
+						from.1.wrapping_add((random.next() % 21) as u32).wrapping_sub(10));
+					bot.position = to;
+					self.send(bot, PacketWriter::new(opcode)
+						.write_u32(from.0).write_u32(from.1)
+						.write_u32(to.0).write_u32(to.1));
+					bot.next_move += Duration::from_secs_f64(1.0 / rate);
+				}
+			}
+			if let Some((opcode, rate)) = self.scenario.chat {
+				if now >= bot.next_chat {
+					let text = self.scenario.chat_text.as_bytes();
+					let text = &text[..text.len().min(255)];
+					self.send(bot, PacketWriter::new(opcode).write_u8(text.len() as u8).write_bytes(text));
+					bot.next_chat += Duration::from_secs_f64(1.0 / rate);
+				}
+			}
+			for pending in bot.pending.values_mut() {
+				while pending.front().is_some_and(|&(_, sent)| now.duration_since(sent) > REPLY_TIMEOUT) {
+					pending.pop_front();
+					self.stats.lock().unwrap().unanswered += 1;
+				}
+			}
+		}
+	}
+}
+
+impl PacketProcessor for LoadProcessor {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let opcode = info.packet.read().unwrap().header;
+		self.0.received(info.client.id(), opcode);
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(LoadProcessor(self.0.clone()))
+	}
+}
+
+fn usage() -> ! {
+	eprintln!("usage: fiesta-loadgen --server <addr> [--clients <n>] [--ramp <per second>] [--duration <seconds>] \
+		[--handshake <opcode>] [--hello <opcode>[:<hex>]] [--move <opcode>] [--move-rate <hz>] \
+		[--chat <opcode>] [--chat-rate <hz>] [--chat-text <text>] [--reply <opcode>=<opcode>]...");
+	process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+	eprintln!("fiesta-loadgen: {}", message);
+	process::exit(2);
+}
+
+fn parse_opcode(value: &str) -> u16 {
+	opcodes::parse_opcode(value).unwrap_or_else(|e| fail(e))
+}
+
+fn parse_hex(value: &str) -> Vec<u8> {
+	dump::parse_hex(value).unwrap_or_else(|e| fail(e))
+}
+
+fn parse_rate(value: &str) -> f64 {
+	match value.parse::<f64>() {
+		Ok(rate) if rate > 0.0 => rate,
+		_ => fail(format!("not a rate: {}", value)),
+	}
+}
+
+fn parse_args() -> Scenario {
+	let mut server = None;
+	let mut scenario = Scenario {
+		server:			"127.0.0.1:0".parse().unwrap(),
+		clients:		100,
+		ramp:			100.0,
+		duration:		Duration::from_secs(30),
+		handshake:		None,
+		hello:			None,
+		movement:		None,
+		chat:			None,
+		chat_text:		"hello".to_string(),
+		replies:		HashMap::new(),
+	};
+	let mut move_rate = 2.0;
+	let mut chat_rate = 0.2;
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().unwrap_or_else(|| usage());
+		match &arg[..] {
+			"--server" => server = Some(value().parse().unwrap_or_else(|e| fail(format!("bad address: {}", e)))),
+			"--clients" => scenario.clients = value().parse().unwrap_or_else(|_| fail("bad client count".to_string())),
+			"--ramp" => scenario.ramp = parse_rate(&value()),
+			"--duration" => scenario.duration = Duration::from_secs_f64(parse_rate(&value())),
+			"--handshake" => scenario.handshake = Some(parse_opcode(&value())),
+			"--hello" => {
+				let value = value();
+				let mut parts = value.splitn(2, ':');
+				let opcode = parse_opcode(parts.next().unwrap());
+				scenario.hello = Some((opcode, parts.next().map(parse_hex).unwrap_or_default()));
+			},
+			"--move" => scenario.movement = Some((parse_opcode(&value()), 0.0)),
+			"--move-rate" => move_rate = parse_rate(&value()),
+			"--chat" => scenario.chat = Some((parse_opcode(&value()), 0.0)),
+			"--chat-rate" => chat_rate = parse_rate(&value()),
+			"--chat-text" => scenario.chat_text = value(),
+			"--reply" => {
+				let value = value();
+				let mut parts = value.splitn(2, '=');
+				let request = parse_opcode(parts.next().unwrap());
+				let response = parse_opcode(parts.next().unwrap_or_else(|| usage()));
+				scenario.replies.insert(request, response);
+			},
+			_ => usage(),
+		}
+	}
+	scenario.server = server.unwrap_or_else(|| usage());
+	scenario.movement = scenario.movement.map(|(opcode, _)| (opcode, move_rate));
+	scenario.chat = scenario.chat.map(|(opcode, _)| (opcode, chat_rate));
+	scenario
+}
+
+/* the `q` quantile of sorted values */
+fn quantile(sorted: &[u64], q: f64) -> u64 {
+	if sorted.is_empty() {
+		return 0;
+	}
+	let rank = ((sorted.len() as f64) * q).ceil().max(1.0) as usize;
+	sorted[rank.min(sorted.len()) - 1]
+}
+
+fn millis(us: u64) -> String {
+	format!("{:.2}ms", us as f64 / 1000.0)
+}
+
+fn main() {
+	let shared = Arc::new(Shared {
+		scenario:	parse_args(),
+		bots:		Mutex::new(Bots::default()),
+		stats:		Mutex::new(Stats::default()),
+	});
+	let connector = Connector::start(Box::new(LoadProcessor(shared.clone())), ServerConfig::default())
+		.unwrap_or_else(|e| fail(format!("could not start: {}", e)));
+	let connector = Arc::new(connector);
+	let started = Instant::now();
+
+	let ramp = {
+		let shared = shared.clone();
+		let connector = connector.clone();
+		thread::spawn(move || {
+			let scenario = &shared.scenario;
+			let mut random = Random(started.elapsed().as_nanos() as u64 | 1);
+			for i in 0..scenario.clients {
+				let due = started + Duration::from_secs_f64(i as f64 / scenario.ramp);
+				if due > started + scenario.duration {
+					break;
+				}
+				let now = Instant::now();
+				if due > now {
+					thread::sleep(due - now);
+				}
+				shared.stats.lock().unwrap().attempts += 1;
+				let connecting = Instant::now();
+				match connector.connect(&scenario.server) {
+					Ok(client) => {
+						let now = Instant::now();
+						/* spread the first packets over a whole interval */
+						let spread = |rate: Option<(u16, f64)>, random: &mut Random|
+							now + Duration::from_secs_f64(rate.map(|(_, r)| random.fraction() / r).unwrap_or(0.0));
+						let bot = Bot {
+							client:		client.clone(),
+							ready:		false,
+							pending:	HashMap::new(),
+							next_move:	spread(scenario.movement, &mut random),
+							next_chat:	spread(scenario.chat, &mut random),
+							position:	((random.next() % 1000) as u32, (random.next() % 1000) as u32),
+						};
+						{
+							let mut stats = shared.stats.lock().unwrap();
+							stats.connected += 1;
+							stats.connect_us.push(connecting.elapsed().as_micros() as u64);
+						}
+						shared.add(client.id(), bot);
+					},
+					Err(e) => {
+						let mut stats = shared.stats.lock().unwrap();
+						if stats.failed == 0 {
+							eprintln!("fiesta-loadgen: connecting failed: {}", e);
+						}
+						stats.failed += 1;
+					},
+				}
+			}
+		})
+	};
+
+	let mut random = Random(0x9E37_79B9_7F4A_7C15);
+	let mut last_report = Instant::now();
+	while started.elapsed() < shared.scenario.duration {
+		shared.tick(&mut random);
+		if last_report.elapsed() >= Duration::from_secs(5) {
+			last_report = Instant::now();
+			let stats = shared.stats.lock().unwrap();
+			eprintln!("{:>6.1}s connected {} failed {} up {}", started.elapsed().as_secs_f64(),
+				stats.connected, stats.failed, connector.registry().len());
+		}
+		thread::sleep(TICK);
+	}
+	let _ = ramp.join();
+
+	let bots: Vec<_> = shared.bots.lock().unwrap().known.values().cloned().collect();
+	let bots: Vec<_> = bots.iter().map(|bot| bot.lock().unwrap()).collect();
+	let disconnected = bots.iter().filter(|bot| !bot.client.alive()).count();
+	let still_pending: usize = bots.iter().flat_map(|bot| bot.pending.values()).map(|p| p.len()).sum();
+	let mut stats = shared.stats.lock().unwrap();
+	stats.connect_us.sort_unstable();
+	println!("clients      {} attempted, {} connected, {} failed", stats.attempts, stats.connected, stats.failed);
+	println!("connect      p50 {} p99 {}", millis(quantile(&stats.connect_us, 0.5)), millis(quantile(&stats.connect_us, 0.99)));
+	if shared.scenario.handshake.is_some() {
+		println!("handshakes   {}", stats.handshakes);
+	}
+	println!("disconnects  {}", disconnected);
+	println!("unanswered   {} timed out, {} still pending", stats.unanswered, still_pending);
+	println!("opcode        sent  answered       p50       p99       max");
+	let sent = stats.sent.clone();
+	for (opcode, sent) in sent {
+		let round_trips = stats.round_trips.entry(opcode).or_default();
+		round_trips.sort_unstable();
+		println!("0x{:04X} {:>11} {:>9} {:>9} {:>9} {:>9}", opcode, sent, round_trips.len(),
+			millis(quantile(round_trips, 0.5)), millis(quantile(round_trips, 0.99)),
+			millis(round_trips.last().cloned().unwrap_or(0)));
+	}
+	drop(bots);
+
+	connector.shutdown();
+}
@@ -0,0 +1,103 @@
+/* logs the traffic between a client and a server:
+ *
+ *   fiesta-proxy --listen <addr> --upstream <addr> [--key <file>]
+ *                [--c2s] [--s2c] [--seed-opcode <opcode>] [--dump]
+ *                [--drop <opcode>]...
+ *
+ * `--key` loads the XOR key table, `--c2s`/`--s2c` pick the encrypted
+ * directions (client to server by default). Opcodes may be given in hex.
+ */
+extern crate fiesta_net;
+#[macro_use]
+extern crate log;
+
+use std::env;
+use std::process;
+
+use fiesta_net::cipher;
+use fiesta_net::opcodes;
+use fiesta_net::proxy::{self, HookAction, ProxyCipher, ProxyConfig, ProxyHooks};
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+	fn enabled(&self, metadata: &log::LogMetadata) -> bool {
+		metadata.level() <= log::LogLevel::Info
+	}
+
+	fn log(&self, record: &log::LogRecord) {
+		if self.enabled(record.metadata()) {
+			eprintln!("[{}] {}", record.target(), record.args());
+		}
+	}
+}
+
+fn usage() -> ! {
+	eprintln!("usage: fiesta-proxy --listen <addr> --upstream <addr> [--key <file>] [--c2s] [--s2c] \
+		[--seed-opcode <opcode>] [--dump] [--drop <opcode>]...");
+	process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+	eprintln!("fiesta-proxy: {}", message);
+	process::exit(2);
+}
+
+fn parse_opcode(value: &str) -> u16 {
+	opcodes::parse_opcode(value).unwrap_or_else(|e| fail(e))
+}
+
+fn main() {
+	let _ = log::set_logger(|max_level| {
+		max_level.set(log::LogLevelFilter::Info);
+		Box::new(StderrLogger)
+	});
+
+	let mut listen = None;
+	let mut upstream = None;
+	let mut key = None;
+	let mut c2s = false;
+	let mut s2c = false;
+	let mut seed_opcode = None;
+	let mut dump = false;
+	let mut dropped = Vec::new();
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().unwrap_or_else(|| usage());
+		match &arg[..] {
+			"--listen" => listen = Some(value().parse().unwrap_or_else(|e| fail(format!("bad address: {}", e)))),
+			"--upstream" => upstream = Some(value().parse().unwrap_or_else(|e| fail(format!("bad address: {}", e)))),
+			"--key" => key = Some(cipher::load_key(value()).unwrap_or_else(|e| fail(format!("key: {}", e)))),
+			"--c2s" => c2s = true,
+			"--s2c" => s2c = true,
+			"--seed-opcode" => seed_opcode = Some(parse_opcode(&value())),
+			"--dump" => dump = true,
+			"--drop" => dropped.push(parse_opcode(&value())),
+			_ => usage(),
+		}
+	}
+	let (listen, upstream) = match (listen, upstream) {
+		(Some(listen), Some(upstream)) => (listen, upstream),
+		_ => usage(),
+	};
+	let cipher = key.map(|key| ProxyCipher {
+		key,
+		client_to_server:	c2s || !s2c,
+		server_to_client:	s2c,
+		seed_opcode,
+	});
+	let config = ProxyConfig {
+		upstream,
+		cipher,
+		dump,
+	};
+	let mut hooks = ProxyHooks::new();
+	for opcode in dropped {
+		hooks = hooks.on(opcode, |_, _| HookAction::Drop);
+	}
+
+	info!(target: "proxy", "listening on {}, forwarding to {}", listen, upstream);
+	if let Err(e) = proxy::run(&listen, config, hooks) {
+		fail(format!("{}", e));
+	}
+}
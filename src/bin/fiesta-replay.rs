@@ -0,0 +1,106 @@
+/* replays a client from a capture file against a running server:
+ *
+ *   fiesta-replay <capture> --server <addr> [--token <n>] [--realtime]
+ *                 [--speed <factor>] [--settle <ms>] [--ignore <opcode>]...
+ *                 [--key <file>] [--seed-opcode <opcode>] [--no-seed]
+ *   fiesta-replay <capture> --list [--dump]
+ *
+ * Opcodes may be given in hex (0x0C01). Exits with 1 if the responses differ.
+ * `--key` encrypts the packets from the seed the server sends on, which is
+ * left out of the comparison unless `--no-seed` is given.
+ */
+extern crate fiesta_net;
+extern crate mio;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+use mio::Token;
+
+use fiesta_net::capture::{CaptureReader, Direction};
+use fiesta_net::cipher;
+use fiesta_net::dump;
+use fiesta_net::replay::{self, ReplayOptions, Session, Timing};
+
+fn usage() -> ! {
+	eprintln!("usage: fiesta-replay <capture> --server <addr> [--token <n>] [--realtime] \
+		[--speed <factor>] [--settle <ms>] [--ignore <opcode>]... [--key <file>] [--seed-opcode <opcode>] [--no-seed]");
+	eprintln!("       fiesta-replay <capture> --list [--dump]");
+	process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+	eprintln!("fiesta-replay: {}", message);
+	process::exit(2);
+}
+
+fn parse_number(value: &str) -> u64 {
+	let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+		u64::from_str_radix(&value[2..], 16)
+	} else {
+		value.parse()
+	};
+	parsed.unwrap_or_else(|_| fail(format!("not a number: {}", value)))
+}
+
+fn main() {
+	let mut args = env::args().skip(1);
+	let path = match args.next() {
+		Some(path) => path,
+		None => usage(),
+	};
+	let mut server = None;
+	let mut token = None;
+	let mut list = false;
+	let mut dump = false;
+	let mut speed = None;
+	let mut options = ReplayOptions::new();
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().unwrap_or_else(|| usage());
+		match &arg[..] {
+			"--server" => server = Some(value().parse().unwrap_or_else(|e| fail(format!("bad address: {}", e)))),
+			"--token" => token = Some(Token(parse_number(&value()) as usize)),
+			"--realtime" => speed = Some(speed.unwrap_or(1.0)),
+			"--speed" => speed = Some(value().parse().unwrap_or_else(|_| fail("bad speed".to_string()))),
+			"--settle" => options = options.settle(Duration::from_millis(parse_number(&value()))),
+			"--ignore" => options = options.ignore(parse_number(&value()) as u16),
+			"--key" => options = options.key(cipher::load_key(value()).unwrap_or_else(|e| fail(format!("key: {}", e)))),
+			"--seed-opcode" => options = options.seed_opcode(Some(parse_number(&value()) as u16)),
+			"--no-seed" => options = options.seed_opcode(None),
+			"--list" => list = true,
+			"--dump" => dump = true,
+			_ => usage(),
+		}
+	}
+	if let Some(speed) = speed {
+		options = options.timing(Timing::Original { speed });
+	}
+
+	let reader = CaptureReader::open(&path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+	if list {
+		for session in Session::all(reader).unwrap_or_else(|e| fail(format!("{}: {}", path, e))) {
+			let inbound = session.entries.iter().filter(|e| e.direction == Direction::Inbound).count();
+			println!("{:?} {:?}: {} inbound, {} outbound packets",
+				session.token, session.peer, inbound, session.entries.len() - inbound);
+			if dump {
+				for entry in &session.entries {
+					let arrow = if entry.direction == Direction::Inbound { "<-" } else { "->" };
+					println!("{:>12} {} {}", entry.timestamp, arrow, dump::pretty_packet(entry.opcode, &entry.payload[..]));
+				}
+			}
+		}
+		return;
+	}
+
+	let server = match server {
+		Some(server) => server,
+		None => usage(),
+	};
+	let session = Session::load(reader, token).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+	let report = replay::replay_against(&server, &session, &options)
+		.unwrap_or_else(|e| fail(format!("replaying against {}: {}", server, e)));
+	print!("{}", report);
+	if !report.is_match() {
+		process::exit(1);
+	}
+}
@@ -1,25 +1,29 @@
-use std::io::{Error, ErrorKind, Write};
-use mio::buf::*;
+use std::io::{Error, ErrorKind, IoSlice, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pool::{self, PooledBuf};
 
 /* buffer of clients */
 pub const BUFFERSIZE: usize = 4 * 1024;		/* 4 KB should be plenty */
 
+static APPEND_OVERFLOWS: AtomicUsize = AtomicUsize::new(0);
+
 pub trait BinaryReadable {
 	fn read_bytes(&mut self, size: usize) -> Result<Vec<u8>, Error>;
 	fn read_u8(&mut self) -> Result<u8, Error> {
-		let buf = try!(self.read_bytes(1));
+		let buf = self.read_bytes(1)?;
 		let result = buf[0];
 
 		Ok(result)
 	}
 	fn read_i8(&mut self) -> Result<i8, Error> {
-		let buf = try!(self.read_bytes(1));
+		let buf = self.read_bytes(1)?;
 		let result = buf[0] as i8;
 
 		Ok(result)
 	}
 	fn read_u16(&mut self) -> Result<u16, Error> {
-		let buf = try!(self.read_bytes(2));
+		let buf = self.read_bytes(2)?;
 		let result = 
 				(buf[1] as u16) 
 			|	((buf[0] as u16) << 8);
@@ -27,7 +31,7 @@ pub trait BinaryReadable {
 		Ok(result)
 	}
 	fn read_i16(&mut self) -> Result<i16, Error> {
-		let buf = try!(self.read_bytes(2));
+		let buf = self.read_bytes(2)?;
 		let result = 
 				(buf[1] as i16) 
 			|	((buf[0] as i16) << 8);
@@ -35,7 +39,7 @@ pub trait BinaryReadable {
 		Ok(result)
 	}
 	fn read_u32(&mut self) -> Result<u32, Error> {
-		let buf = try!(self.read_bytes(4));
+		let buf = self.read_bytes(4)?;
 		let result = 
 				(buf[3] as u32)
 			|	((buf[2] as u32) << 8)
@@ -45,7 +49,7 @@ pub trait BinaryReadable {
 		Ok(result)
 	}
 	fn read_i32(&mut self) -> Result<i32, Error> {
-		let buf = try!(self.read_bytes(4));
+		let buf = self.read_bytes(4)?;
 		let result = 
 				(buf[3] as i32)
 			|	((buf[2] as i32) << 8)
@@ -55,7 +59,7 @@ pub trait BinaryReadable {
 		Ok(result)
 	}
 	fn read_u64(&mut self) -> Result<u64, Error> {
-		let buf = try!(self.read_bytes(8));
+		let buf = self.read_bytes(8)?;
 		let result = 
 				(buf[7] as u64)
 			|	((buf[6] as u64) << 8)
@@ -69,7 +73,7 @@ pub trait BinaryReadable {
 		Ok(result)
 	}
 	fn read_i64(&mut self) -> Result<i64, Error> {
-		let buf = try!(self.read_bytes(8));
+		let buf = self.read_bytes(8)?;
 		let result = 
 				(buf[7] as i64)
 			|	((buf[6] as i64) << 8)
@@ -88,19 +92,19 @@ pub trait BinaryPeekable {
 	fn peek_bytes(&mut self, offset: usize, size: usize) -> Result<Vec<u8>, Error>;
 
 	fn peek_u8(&mut self, offset: usize) -> Result<u8, Error> {
-		let buf = try!(self.peek_bytes(offset, 1));
+		let buf = self.peek_bytes(offset, 1)?;
 		let result = buf[0];
 
 		Ok(result)
 	}
 	fn peek_i8(&mut self, offset: usize) -> Result<i8, Error> {
-		let buf = try!(self.peek_bytes(offset, 1));
+		let buf = self.peek_bytes(offset, 1)?;
 		let result = buf[0] as i8;
 
 		Ok(result)
 	}
 	fn peek_u16(&mut self, offset: usize) -> Result<u16, Error> {
-		let buf = try!(self.peek_bytes(offset, 2));
+		let buf = self.peek_bytes(offset, 2)?;
 		let result = 
 				(buf[1] as u16) 
 			|	((buf[0] as u16) << 8);
@@ -108,7 +112,7 @@ pub trait BinaryPeekable {
 		Ok(result)
 	}
 	fn peek_i16(&mut self, offset: usize) -> Result<i16, Error> {
-		let buf = try!(self.peek_bytes(offset, 2));
+		let buf = self.peek_bytes(offset, 2)?;
 		let result = 
 				(buf[1] as i16) 
 			|	((buf[0] as i16) << 8);
@@ -116,7 +120,7 @@ pub trait BinaryPeekable {
 		Ok(result)
 	}
 	fn peek_u32(&mut self, offset: usize) -> Result<u32, Error> {
-		let buf = try!(self.peek_bytes(offset, 4));
+		let buf = self.peek_bytes(offset, 4)?;
 		let result = 
 				(buf[3] as u32)
 			|	((buf[2] as u32) << 8)
@@ -126,7 +130,7 @@ pub trait BinaryPeekable {
 		Ok(result)
 	}
 	fn peek_i32(&mut self, offset: usize) -> Result<i32, Error> {
-		let buf = try!(self.peek_bytes(offset, 4));
+		let buf = self.peek_bytes(offset, 4)?;
 		let result = 
 				(buf[3] as i32)
 			|	((buf[2] as i32) << 8)
@@ -136,7 +140,7 @@ pub trait BinaryPeekable {
 		Ok(result)
 	}
 	fn peek_u64(&mut self, offset: usize) -> Result<u64, Error> {
-		let buf = try!(self.peek_bytes(offset, 8));
+		let buf = self.peek_bytes(offset, 8)?;
 		let result = 
 				(buf[7] as u64)
 			|	((buf[6] as u64) << 8)
@@ -150,7 +154,7 @@ pub trait BinaryPeekable {
 		Ok(result)
 	}
 	fn peek_i64(&mut self, offset: usize) -> Result<i64, Error> {
-		let buf = try!(self.peek_bytes(offset, 8));
+		let buf = self.peek_bytes(offset, 8)?;
 		let result = 
 				(buf[7] as i64)
 			|	((buf[6] as i64) << 8)
@@ -165,51 +169,172 @@ pub trait BinaryPeekable {
 	}
 }
 
+/* how often `Buffer::append` had to drop bytes, over all buffers */
+pub fn append_overflows() -> usize {
+	APPEND_OVERFLOWS.load(Ordering::Relaxed)
+}
+
+/* ring buffer; the readable bytes are at most split into two slices. The
+ * storage comes from the buffer pool and goes back to it when dropped. */
 pub struct Buffer {
-	buffer:			Box<RingBuf>,
+	data:			PooledBuf,
+	head:			usize,
 	remaining:		usize,
 }
 
 impl Buffer {
 	pub fn new() -> Self {
-		Buffer {
-			buffer:		Box::new(RingBuf::new(BUFFERSIZE)),
-			remaining:	0,
-		}
+		Buffer::with_capacity(BUFFERSIZE)
 	}
 
+	/* the capacity is rounded up to the next of `pool::SIZE_CLASSES`, e.g. 100
+	 * bytes become 256; see `capacity`. Anything above the largest class is
+	 * allocated exactly. */
 	pub fn with_capacity(capacity: usize) -> Self {
 		Buffer {
-			buffer:		Box::new(RingBuf::new(capacity)),
+			data:		pool::take(capacity),
+			head:		0,
 			remaining:	0,
 		}
 	}
 
+	pub fn capacity(&self) -> usize {
+		self.data.len()
+	}
+
 	pub fn bytes_remaining(&self) -> usize {
 		self.remaining
 	}
 
+	pub fn free_space(&self) -> usize {
+		self.capacity() - self.remaining
+	}
+
+	/* the readable bytes in order, the second slice is empty unless they wrap around */
+	pub fn as_slices(&self) -> (&[u8], &[u8]) {
+		let end = self.head + self.remaining;
+		if end <= self.capacity() {
+			(&self.data[self.head..end], &[])
+		} else {
+			(&self.data[self.head..], &self.data[..end - self.capacity()])
+		}
+	}
+
+	/* the contiguous free space right after the readable bytes */
+	fn free_slice_mut(&mut self) -> &mut [u8] {
+		let capacity = self.capacity();
+		if capacity == 0 {
+			return &mut self.data[..];
+		}
+		let tail = (self.head + self.remaining) % capacity;
+		let len = ::std::cmp::min(self.free_space(), capacity - tail);
+		&mut self.data[tail..tail + len]
+	}
+
+	fn commit_write(&mut self, size: usize) {
+		self.remaining += size;
+	}
+
+	/* copies bytes starting `offset` bytes after the read position into `buf`,
+	 * without consuming them */
+	pub fn copy_out(&self, offset: usize, buf: &mut [u8]) -> usize {
+		if offset >= self.remaining {
+			return 0;
+		}
+		let (first, second) = self.as_slices();
+		let mut copied = 0;
+		let wanted = ::std::cmp::min(buf.len(), self.remaining - offset);
+		while copied < wanted {
+			let position = offset + copied;
+			let (slice, start) = if position < first.len() {
+				(first, position)
+			} else {
+				(second, position - first.len())
+			};
+			let len = ::std::cmp::min(wanted - copied, slice.len() - start);
+			buf[copied..copied + len].copy_from_slice(&slice[start..start + len]);
+			copied += len;
+		}
+		copied
+	}
+
+	/* reads at most `max` bytes from `reader` straight into the buffer, without
+	 * an intermediate copy. Returns Ok(0) at EOF or if the buffer is full. */
+	pub fn fill_from<R: Read>(&mut self, reader: &mut R, max: usize) -> Result<usize, Error> {
+		let size = {
+			let bytes = self.free_slice_mut();
+			let len = ::std::cmp::min(bytes.len(), max);
+			if len == 0 {
+				return Ok(0);
+			}
+			reader.read(&mut bytes[..len])?
+		};
+		self.commit_write(size);
+		Ok(size)
+	}
+
+	/* writes the readable bytes to `writer` with a single vectored write and
+	 * consumes whatever was written */
+	pub fn drain_into<W: Write>(&mut self, writer: &mut W) -> Result<usize, Error> {
+		let size = {
+			let (first, second) = self.as_slices();
+			if first.is_empty() {
+				return Ok(0);
+			}
+			writer.write_vectored(&[IoSlice::new(first), IoSlice::new(second)])?
+		};
+		self.advance_read(size);
+		Ok(size)
+	}
+
+	/* appends as much of `bytes` as fits, returns how much that was */
+	pub fn write_some(&mut self, bytes: &[u8]) -> usize {
+		let mut written = 0;
+		while written < bytes.len() {
+			let len = {
+				let free = self.free_slice_mut();
+				let len = ::std::cmp::min(free.len(), bytes.len() - written);
+				free[..len].copy_from_slice(&bytes[written..written + len]);
+				len
+			};
+			if len == 0 {
+				break;
+			}
+			self.commit_write(len);
+			written += len;
+		}
+		written
+	}
+
 	pub fn append(&mut self, bytes: &[u8]) {
-		self.buffer.write(bytes).unwrap();
-		self.remaining += bytes.len();
-		if self.remaining > BUFFERSIZE {
+		let written = self.write_some(bytes);
+		if written < bytes.len() {
 			// panic!("overwritten some data!!");
-			warn!(target: "networking", "overwritten some data.");
-			self.remaining = BUFFERSIZE;
+			APPEND_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+			warn!(target: "networking", "buffer full, dropped {} bytes.", bytes.len() - written);
 		}
 	}
 
 	pub fn advance_read(&mut self, bytes: usize) {
-		<RingBuf as Buf>::advance(&mut self.buffer, bytes);
+		let bytes = ::std::cmp::min(bytes, self.remaining);
 		self.remaining -= bytes;
+		if self.remaining == 0 {
+			/* keeps the free space contiguous */
+			self.head = 0;
+		} else {
+			self.head = (self.head + bytes) % self.capacity();
+		}
 	}
 
 	pub fn peek_max(&mut self, offset: usize, len: usize, buf: &mut [u8]) -> Result<usize, Error> {
-		self.buffer.mark();
-		<RingBuf as Buf>::advance(&mut self.buffer, offset);
-		let size = self.buffer.read_slice(buf);
-		self.buffer.reset();
-		Ok(size)
+		let len = ::std::cmp::min(len, buf.len());
+		Ok(self.copy_out(offset, &mut buf[..len]))
+	}
+}
+
+impl Default for Buffer {
+	fn default() -> Self {
+		Buffer::new()
 	}
 }
 
@@ -219,34 +344,115 @@ impl BinaryReadable for Buffer {
 			Err(Error::new(ErrorKind::InvalidData, "Not enough data"))
 		} else {
 			let mut buf = vec![0; size];
-
-			if <RingBuf as Buf>::read_slice(&mut self.buffer, &mut buf[..]) != size {
-				Err(Error::new(ErrorKind::InvalidData, "Didn't read as many bytes as specified!"))
-			} else {
-				self.remaining -= size;
-				Ok(buf)
-			}
+			self.copy_out(0, &mut buf[..]);
+			self.advance_read(size);
+			Ok(buf)
 		}
-
 	}
 }
 
 impl BinaryPeekable for Buffer {
 	fn peek_bytes(&mut self, offset: usize, size: usize) -> Result<Vec<u8>, Error> {
-		if <RingBuf as Buf>::remaining(&self.buffer) < size + offset {
+		if self.bytes_remaining() < size + offset {
 			Err(Error::new(ErrorKind::InvalidData, "Not enough data"))
 		} else {
-			self.buffer.mark();
-
 			let mut buf = vec![0; size];
-			<RingBuf as Buf>::advance(&mut self.buffer, offset);
-			if <RingBuf as Buf>::read_slice(&mut self.buffer, buf.as_mut_slice()) != size {
-				self.buffer.reset();
-				Err(Error::new(ErrorKind::InvalidData, "Didn't read as many bytes as specified!"))
-			} else {
-				self.buffer.reset();
-				Ok(buf)
-			}
+			self.copy_out(offset, &mut buf[..]);
+			Ok(buf)
+		}
+	}
+}
+
+/* reads from a borrowed slice; unlike `Buffer` it decodes primitives without
+ * allocating */
+pub struct ByteCursor<'a> {
+	data:			&'a [u8],
+	position:		usize,
+}
+
+impl<'a> ByteCursor<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		ByteCursor {
+			data,
+			position:	0,
+		}
+	}
+
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	pub fn remaining(&self) -> usize {
+		self.data.len() - self.position
+	}
+
+	pub fn set_position(&mut self, position: usize) -> Result<(), Error> {
+		if position > self.data.len() {
+			Err(Error::new(ErrorKind::InvalidData, "position past the end"))
+		} else {
+			self.position = position;
+			Ok(())
+		}
+	}
+
+	/* the next `size` bytes, borrowed from the underlying slice */
+	pub fn read_slice(&mut self, size: usize) -> Result<&'a [u8], Error> {
+		if self.remaining() < size {
+			Err(Error::new(ErrorKind::InvalidData, "Not enough data"))
+		} else {
+			let data = self.data;
+			let result = &data[self.position..self.position + size];
+			self.position += size;
+			Ok(result)
 		}
 	}
-}
\ No newline at end of file
+
+	fn read_array2(&mut self) -> Result<[u8; 2], Error> {
+		let buf = self.read_slice(2)?;
+		Ok([buf[0], buf[1]])
+	}
+
+	fn read_array4(&mut self) -> Result<[u8; 4], Error> {
+		let buf = self.read_slice(4)?;
+		Ok([buf[0], buf[1], buf[2], buf[3]])
+	}
+
+	fn read_array8(&mut self) -> Result<[u8; 8], Error> {
+		let buf = self.read_slice(8)?;
+		Ok([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]])
+	}
+}
+
+/* same byte order as the default implementations, see `BinaryReadable` */
+impl<'a> BinaryReadable for ByteCursor<'a> {
+	fn read_bytes(&mut self, size: usize) -> Result<Vec<u8>, Error> {
+		let buf = self.read_slice(size)?;
+		Ok(buf.to_vec())
+	}
+	fn read_u8(&mut self) -> Result<u8, Error> {
+		let buf = self.read_slice(1)?;
+		Ok(buf[0])
+	}
+	fn read_i8(&mut self) -> Result<i8, Error> {
+		let buf = self.read_slice(1)?;
+		Ok(buf[0] as i8)
+	}
+	fn read_u16(&mut self) -> Result<u16, Error> {
+		Ok(u16::from_be_bytes(self.read_array2()?))
+	}
+	fn read_i16(&mut self) -> Result<i16, Error> {
+		Ok(i16::from_be_bytes(self.read_array2()?))
+	}
+	fn read_u32(&mut self) -> Result<u32, Error> {
+		Ok(u32::from_be_bytes(self.read_array4()?))
+	}
+	fn read_i32(&mut self) -> Result<i32, Error> {
+		Ok(i32::from_be_bytes(self.read_array4()?))
+	}
+	fn read_u64(&mut self) -> Result<u64, Error> {
+		Ok(u64::from_be_bytes(self.read_array8()?))
+	}
+	fn read_i64(&mut self) -> Result<i64, Error> {
+		Ok(i64::from_be_bytes(self.read_array8()?))
+	}
+}
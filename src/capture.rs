@@ -0,0 +1,685 @@
+/* Packet capture.
+ *
+ * A `PacketRecorder` set on a `FiestaNetworkClient` gets every packet the
+ * client receives and sends. The capture file format, all numbers big endian:
+ *
+ *   file header (8 bytes)
+ *     magic         4  "FCAP"
+ *     version       u16  1
+ *     reserved      u16  0
+ *   entry, repeated
+ *     length        u32  size of the entry after this field
+ *     timestamp     u64  microseconds since the unix epoch
+ *     token         u64  the client's `Token`
+ *     direction     u8   0 inbound (client to server), 1 outbound
+ *     address kind  u8   0 none, 4 IPv4, 6 IPv6
+ *     address       0, 4 or 16 bytes, depending on the kind
+ *     port          u16  only present with an address
+ *     opcode        u16
+ *     payload size  u32
+ *     payload       the packet body, without size prefix and opcode
+ *
+ * Readers skip whatever follows the payload inside an entry, so fields can be
+ * appended in later versions, and refuse entries above `MAX_ENTRY_SIZE`.
+ * `export_pcapng` converts a capture for tools like Wireshark.
+ */
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use mio::Token;
+
+use client::{frame_large_packet, FiestaNetworkClient, MAX_WIRE_BODY_SIZE};
+
+pub const MAGIC: &[u8; 4] = b"FCAP";
+pub const VERSION: u16 = 1;
+const FILE_HEADER_SIZE: u64 = 8;
+/* far above any packet, a larger length means a corrupt file */
+pub const MAX_ENTRY_SIZE: usize = 1024 * 1024;
+pub const FLUSH_INTERVAL_MS: u64 = 1000;
+/* entries waiting for the writer thread, more are counted as failed */
+pub const QUEUE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+	/* client to server */
+	Inbound,
+	/* server to client */
+	Outbound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureEntry {
+	/* microseconds since the unix epoch */
+	pub timestamp:		u64,
+	pub token:			Token,
+	pub peer:			Option<SocketAddr>,
+	pub direction:		Direction,
+	pub opcode:			u16,
+	pub payload:		Vec<u8>,
+}
+
+/* which packets are recorded, `None` matches everything */
+#[derive(Debug, Clone, Default)]
+pub struct CaptureFilter {
+	pub tokens:			Option<HashSet<Token>>,
+	pub peers:			Option<HashSet<IpAddr>>,
+	pub opcodes:		Option<HashSet<u16>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+	pub path:			PathBuf,
+	pub filter:			CaptureFilter,
+	/* the file is rotated once it would grow beyond this */
+	pub max_file_size:	Option<u64>,
+	/* rotated files kept next to the current one, as `<path>.1` (newest) and up */
+	pub max_files:		usize,
+	/* entries are buffered for at most this long, `PacketRecorder::flush`
+	 * writes them out right away */
+	pub flush_interval:	Duration,
+}
+
+/* writes capture entries to any `Write` */
+pub struct CaptureWriter<W: Write> {
+	inner:			W,
+	written:		u64,
+}
+
+/* reads capture entries from any `Read` */
+pub struct CaptureReader<R: Read> {
+	inner:			R,
+}
+
+/* records packets of many clients into a rotating capture file. The file
+ * is written by a thread of its own, recording only queues the entry, so
+ * the event loops never wait for the disk. */
+pub struct PacketRecorder {
+	config:			CaptureConfig,
+	filter:			RwLock<CaptureFilter>,
+	jobs:			Option<SyncSender<Job>>,
+	thread:			Option<JoinHandle<()>>,
+	counts:			Arc<Counts>,
+}
+
+enum Job {
+	Entry(CaptureEntry),
+	/* answered once everything queued before it is flushed */
+	Flush(mpsc::Sender<io::Result<()>>),
+}
+
+#[derive(Default)]
+struct Counts {
+	recorded:		AtomicUsize,
+	failed:			AtomicUsize,
+}
+
+/* the writer thread's end */
+struct OpenCapture {
+	config:			CaptureConfig,
+	writer:			Option<CaptureWriter<BufWriter<File>>>,
+	/* when buffered entries have to be flushed, if there are any */
+	flush_at:		Option<Instant>,
+	counts:			Arc<Counts>,
+}
+
+impl Direction {
+	fn to_byte(self) -> u8 {
+		match self {
+			Direction::Inbound => 0,
+			Direction::Outbound => 1,
+		}
+	}
+
+	fn from_byte(byte: u8) -> io::Result<Direction> {
+		match byte {
+			0 => Ok(Direction::Inbound),
+			1 => Ok(Direction::Outbound),
+			_ => Err(Error::new(ErrorKind::InvalidData, format!("invalid direction {}", byte))),
+		}
+	}
+}
+
+impl CaptureEntry {
+	pub fn now(token: Token, peer: Option<SocketAddr>, direction: Direction, opcode: u16, payload: &[u8]) -> Self {
+		CaptureEntry {
+			timestamp:		timestamp_now(),
+			token,
+			peer,
+			direction,
+			opcode,
+			payload:		payload.to_vec(),
+		}
+	}
+
+	/* the packet as it was on the wire */
+	pub fn to_frame(&self) -> Vec<u8> {
+		frame_large_packet(self.opcode, &self.payload[..])
+	}
+
+	fn encode(&self) -> Vec<u8> {
+		let mut data = Vec::with_capacity(self.payload.len() + 48);
+		data.extend_from_slice(&[0; 4]);
+		data.extend_from_slice(&self.timestamp.to_be_bytes());
+		data.extend_from_slice(&(self.token.0 as u64).to_be_bytes());
+		data.push(self.direction.to_byte());
+		match self.peer {
+			None => data.push(0),
+			Some(SocketAddr::V4(addr)) => {
+				data.push(4);
+				data.extend_from_slice(&addr.ip().octets());
+				data.extend_from_slice(&addr.port().to_be_bytes());
+			},
+			Some(SocketAddr::V6(addr)) => {
+				data.push(6);
+				data.extend_from_slice(&addr.ip().octets());
+				data.extend_from_slice(&addr.port().to_be_bytes());
+			},
+		}
+		data.extend_from_slice(&self.opcode.to_be_bytes());
+		data.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+		data.extend_from_slice(&self.payload[..]);
+		let length = (data.len() - 4) as u32;
+		data[..4].copy_from_slice(&length.to_be_bytes());
+		data
+	}
+
+	fn decode(data: &[u8]) -> io::Result<CaptureEntry> {
+		let mut cursor = Cursor { data, position: 0 };
+		let timestamp = u64::from_be_bytes(cursor.array()?);
+		let token = Token(u64::from_be_bytes(cursor.array()?) as usize);
+		let direction = Direction::from_byte(cursor.array::<1>()?[0])?;
+		let peer = match cursor.array::<1>()?[0] {
+			0 => None,
+			4 => {
+				let ip = Ipv4Addr::from(cursor.array::<4>()?);
+				Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes(cursor.array()?)))
+			},
+			6 => {
+				let ip = Ipv6Addr::from(cursor.array::<16>()?);
+				Some(SocketAddr::new(IpAddr::V6(ip), u16::from_be_bytes(cursor.array()?)))
+			},
+			kind => return Err(Error::new(ErrorKind::InvalidData, format!("invalid address kind {}", kind))),
+		};
+		let opcode = u16::from_be_bytes(cursor.array()?);
+		let size = u32::from_be_bytes(cursor.array()?) as usize;
+		if size > MAX_WIRE_BODY_SIZE {
+			return Err(Error::new(ErrorKind::InvalidData, format!("payload of {} bytes, more than a packet holds", size)));
+		}
+		let payload = cursor.slice(size)?.to_vec();
+		Ok(CaptureEntry {
+			timestamp,
+			token,
+			peer,
+			direction,
+			opcode,
+			payload,
+		})
+	}
+}
+
+struct Cursor<'a> {
+	data:			&'a [u8],
+	position:		usize,
+}
+
+impl<'a> Cursor<'a> {
+	fn slice(&mut self, size: usize) -> io::Result<&'a [u8]> {
+		if self.data.len() - self.position < size {
+			return Err(Error::new(ErrorKind::InvalidData, "truncated capture entry"));
+		}
+		let slice = &self.data[self.position..self.position + size];
+		self.position += size;
+		Ok(slice)
+	}
+
+	fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+		let mut array = [0; N];
+		array.copy_from_slice(self.slice(N)?);
+		Ok(array)
+	}
+}
+
+impl CaptureFilter {
+	pub fn all() -> Self {
+		CaptureFilter::default()
+	}
+
+	pub fn token(mut self, token: Token) -> Self {
+		self.tokens.get_or_insert_with(HashSet::new).insert(token);
+		self
+	}
+
+	pub fn peer(mut self, ip: IpAddr) -> Self {
+		self.peers.get_or_insert_with(HashSet::new).insert(ip);
+		self
+	}
+
+	pub fn opcode(mut self, opcode: u16) -> Self {
+		self.opcodes.get_or_insert_with(HashSet::new).insert(opcode);
+		self
+	}
+
+	pub fn matches_client(&self, token: Token, peer: Option<IpAddr>) -> bool {
+		let token_ok = self.tokens.as_ref().is_none_or(|tokens| tokens.contains(&token));
+		let peer_ok = match (self.peers.as_ref(), peer) {
+			(None, _) => true,
+			(Some(peers), Some(ip)) => peers.contains(&ip),
+			(Some(_), None) => false,
+		};
+		token_ok && peer_ok
+	}
+
+	pub fn matches(&self, token: Token, peer: Option<IpAddr>, opcode: u16) -> bool {
+		self.matches_client(token, peer)
+			&& self.opcodes.as_ref().is_none_or(|opcodes| opcodes.contains(&opcode))
+	}
+}
+
+impl CaptureConfig {
+	pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+		CaptureConfig {
+			path:			path.into(),
+			filter:			CaptureFilter::all(),
+			max_file_size:	None,
+			max_files:		0,
+			flush_interval:	Duration::from_millis(FLUSH_INTERVAL_MS),
+		}
+	}
+
+	pub fn filter(mut self, filter: CaptureFilter) -> Self {
+		self.filter = filter;
+		self
+	}
+
+	pub fn rotate(mut self, max_file_size: u64, max_files: usize) -> Self {
+		self.max_file_size = Some(max_file_size);
+		self.max_files = max_files;
+		self
+	}
+
+	pub fn flush_every(mut self, interval: Duration) -> Self {
+		self.flush_interval = interval;
+		self
+	}
+}
+
+impl<W: Write> CaptureWriter<W> {
+	/* writes the file header */
+	pub fn new(mut inner: W) -> io::Result<Self> {
+		inner.write_all(&MAGIC[..])?;
+		inner.write_all(&VERSION.to_be_bytes())?;
+		inner.write_all(&[0, 0])?;
+		Ok(CaptureWriter {
+			inner,
+			written:		FILE_HEADER_SIZE,
+		})
+	}
+
+	pub fn write_entry(&mut self, entry: &CaptureEntry) -> io::Result<()> {
+		let data = entry.encode();
+		self.inner.write_all(&data[..])?;
+		self.written += data.len() as u64;
+		Ok(())
+	}
+
+	/* bytes written including the file header */
+	pub fn written(&self) -> u64 {
+		self.written
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+
+	pub fn into_inner(self) -> W {
+		self.inner
+	}
+}
+
+impl<R: Read> CaptureReader<R> {
+	/* checks the file header */
+	pub fn new(mut inner: R) -> io::Result<Self> {
+		let mut header = [0; FILE_HEADER_SIZE as usize];
+		inner.read_exact(&mut header[..])?;
+		if header[..4] != MAGIC[..] {
+			return Err(Error::new(ErrorKind::InvalidData, "not a capture file"));
+		}
+		let version = u16::from_be_bytes([header[4], header[5]]);
+		if version != VERSION {
+			return Err(Error::new(ErrorKind::InvalidData, format!("unsupported capture version {}", version)));
+		}
+		Ok(CaptureReader {
+			inner,
+		})
+	}
+
+	/* `Ok(None)` at the end of the capture */
+	pub fn read_entry(&mut self) -> io::Result<Option<CaptureEntry>> {
+		let mut length = [0; 4];
+		let mut read = 0;
+		while read < length.len() {
+			match self.inner.read(&mut length[read..])? {
+				0 if read == 0 => return Ok(None),
+				0 => return Err(Error::new(ErrorKind::UnexpectedEof, "truncated capture entry")),
+				size => read += size,
+			}
+		}
+		let length = u32::from_be_bytes(length) as usize;
+		if length > MAX_ENTRY_SIZE {
+			return Err(Error::new(ErrorKind::InvalidData, format!("capture entry of {} bytes, at most {} expected", length, MAX_ENTRY_SIZE)));
+		}
+		let mut data = vec![0; length];
+		self.inner.read_exact(&mut data[..])?;
+		CaptureEntry::decode(&data[..]).map(Some)
+	}
+}
+
+impl CaptureReader<io::BufReader<File>> {
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		CaptureReader::new(io::BufReader::new(File::open(path)?))
+	}
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+	type Item = io::Result<CaptureEntry>;
+
+	fn next(&mut self) -> Option<io::Result<CaptureEntry>> {
+		match self.read_entry() {
+			Ok(Some(entry)) => Some(Ok(entry)),
+			Ok(None) => None,
+			Err(e) => Some(Err(e)),
+		}
+	}
+}
+
+impl PacketRecorder {
+	/* creates (or truncates) the capture file and starts the writer thread */
+	pub fn new(config: CaptureConfig) -> io::Result<Self> {
+		let counts = Arc::new(Counts::default());
+		let capture = OpenCapture {
+			writer:			Some(open_capture(&config.path)?),
+			config:			config.clone(),
+			flush_at:		None,
+			counts:			counts.clone(),
+		};
+		let (jobs, queue) = mpsc::sync_channel(QUEUE_SIZE);
+		let thread = thread::Builder::new()
+			.name("fiesta-capture".to_string())
+			.spawn(move || capture.run(queue))?;
+		Ok(PacketRecorder {
+			filter:			RwLock::new(config.filter.clone()),
+			config,
+			jobs:			Some(jobs),
+			thread:			Some(thread),
+			counts,
+		})
+	}
+
+	pub fn set_filter(&self, filter: CaptureFilter) {
+		*self.filter.write().unwrap() = filter;
+	}
+
+	/* whether any packet of this client could be recorded */
+	pub fn wants_client(&self, token: Token, peer: Option<IpAddr>) -> bool {
+		self.filter.read().unwrap().matches_client(token, peer)
+	}
+
+	pub fn record(&self, client: &FiestaNetworkClient, direction: Direction, opcode: u16, payload: &[u8]) {
+		let token = client.id();
+		let peer = client.peer_addr();
+		if !self.filter.read().unwrap().matches(token, peer.map(|addr| addr.ip()), opcode) {
+			return;
+		}
+		self.queue(CaptureEntry::now(token, peer, direction, opcode, payload));
+	}
+
+	/* writes the entry regardless of the filter */
+	pub fn write(&self, entry: &CaptureEntry) {
+		self.queue(entry.clone());
+	}
+
+	fn queue(&self, entry: CaptureEntry) {
+		let queued = match self.jobs {
+			Some(ref jobs) => jobs.try_send(Job::Entry(entry)),
+			None => return,
+		};
+		match queued {
+			Ok(()) => (),
+			Err(TrySendError::Full(_)) => self.counts.failed("the queue is full"),
+			Err(TrySendError::Disconnected(_)) => self.counts.failed("the writer thread is gone"),
+		}
+	}
+
+	/* waits until the entries recorded so far are written out, the rest is
+	 * written when the recorder is dropped */
+	pub fn flush(&self) -> io::Result<()> {
+		let (done, result) = mpsc::channel();
+		let gone = || Error::other("the capture writer thread is gone");
+		match self.jobs {
+			Some(ref jobs) => jobs.send(Job::Flush(done)).map_err(|_| gone())?,
+			None => return Err(gone()),
+		}
+		result.recv().map_err(|_| gone())?
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.config.path
+	}
+
+	/* entries written so far */
+	pub fn recorded(&self) -> usize {
+		self.counts.recorded.load(Ordering::Relaxed)
+	}
+
+	/* entries lost to write errors or a full queue */
+	pub fn failed(&self) -> usize {
+		self.counts.failed.load(Ordering::Relaxed)
+	}
+}
+
+impl Drop for PacketRecorder {
+	fn drop(&mut self) {
+		/* the thread writes out what is queued once the channel is closed */
+		self.jobs = None;
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl Counts {
+	fn failed(&self, reason: &str) {
+		/* only the first failure is logged, the server keeps running */
+		if self.failed.fetch_add(1, Ordering::Relaxed) == 0 {
+			warn!(target: "capture", "could not record a packet: {}", reason);
+		}
+	}
+}
+
+impl OpenCapture {
+	fn run(mut self, queue: Receiver<Job>) {
+		loop {
+			let job = match self.flush_at {
+				Some(at) => queue.recv_timeout(at.saturating_duration_since(Instant::now())),
+				None => queue.recv().map_err(|_| RecvTimeoutError::Disconnected),
+			};
+			match job {
+				Ok(Job::Entry(entry)) => {
+					match self.write(&entry) {
+						Ok(()) => {
+							self.counts.recorded.fetch_add(1, Ordering::Relaxed);
+						},
+						Err(e) => self.counts.failed(&format!("{} on {}", e, self.config.path.display())),
+					}
+				},
+				Ok(Job::Flush(done)) => {
+					let _ = done.send(self.flush());
+				},
+				Err(RecvTimeoutError::Timeout) => {
+					if let Err(e) = self.flush() {
+						warn!(target: "capture", "could not flush {}: {}", self.config.path.display(), e);
+					}
+				},
+				Err(RecvTimeoutError::Disconnected) => {
+					if let Err(e) = self.flush() {
+						warn!(target: "capture", "could not flush {}: {}", self.config.path.display(), e);
+					}
+					return;
+				},
+			}
+		}
+	}
+
+	fn write(&mut self, entry: &CaptureEntry) -> io::Result<()> {
+		if let (Some(max), Some(current)) = (self.config.max_file_size, self.writer.as_ref()) {
+			let size = entry.encode().len() as u64;
+			if current.written() > FILE_HEADER_SIZE && current.written() + size > max {
+				self.rotate()?;
+			}
+		}
+		match self.writer {
+			Some(ref mut writer) => {
+				writer.write_entry(entry)?;
+				if self.flush_at.is_none() {
+					self.flush_at = Some(Instant::now() + self.config.flush_interval);
+				}
+				Ok(())
+			},
+			None => Err(Error::other("capture file is closed")),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.flush_at = None;
+		match self.writer {
+			Some(ref mut writer) => writer.flush(),
+			None => Ok(()),
+		}
+	}
+
+	fn rotate(&mut self) -> io::Result<()> {
+		if let Some(mut old) = self.writer.take() {
+			old.flush()?;
+		}
+		self.flush_at = None;
+		let path = &self.config.path;
+		if self.config.max_files == 0 {
+			fs::remove_file(path)?;
+		} else {
+			let _ = fs::remove_file(rotated_path(path, self.config.max_files));
+			for i in (1..self.config.max_files).rev() {
+				let from = rotated_path(path, i);
+				if from.exists() {
+					fs::rename(&from, rotated_path(path, i + 1))?;
+				}
+			}
+			fs::rename(path, rotated_path(path, 1))?;
+		}
+		self.writer = Some(open_capture(path)?);
+		debug!(target: "capture", "rotated {}", path.display());
+		Ok(())
+	}
+}
+
+fn open_capture(path: &Path) -> io::Result<CaptureWriter<BufWriter<File>>> {
+	let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+	let mut writer = CaptureWriter::new(BufWriter::new(file))?;
+	/* a valid, empty capture until the first flush */
+	writer.flush()?;
+	Ok(writer)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+	let mut name = path.as_os_str().to_owned();
+	name.push(format!(".{}", index));
+	PathBuf::from(name)
+}
+
+fn timestamp_now() -> u64 {
+	match SystemTime::now().duration_since(UNIX_EPOCH) {
+		Ok(since) => since.as_secs() * 1_000_000 + since.subsec_micros() as u64,
+		Err(_) => 0,
+	}
+}
+
+/* pcapng link type for the packets; the user range is meant for private
+ * encapsulations, tell Wireshark how to dissect it with a DLT_USER entry */
+pub const PCAPNG_LINKTYPE: u16 = 147;
+
+/* converts a capture to pcapng. Every packet becomes an enhanced packet block
+ * holding the frame as it was on the wire (size prefix, opcode, payload), with
+ * the direction in its flags and the client in a comment. */
+pub fn export_pcapng<R: Read, W: Write>(reader: CaptureReader<R>, mut out: W) -> io::Result<usize> {
+	/* section header block */
+	let mut shb = Vec::new();
+	shb.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+	shb.extend_from_slice(&1u16.to_le_bytes());
+	shb.extend_from_slice(&0u16.to_le_bytes());
+	shb.extend_from_slice(&(-1i64).to_le_bytes());
+	write_block(&mut out, 0x0A0D_0D0A, &shb[..])?;
+
+	/* interface description block, timestamps in microseconds by default */
+	let mut idb = Vec::new();
+	idb.extend_from_slice(&PCAPNG_LINKTYPE.to_le_bytes());
+	idb.extend_from_slice(&0u16.to_le_bytes());
+	idb.extend_from_slice(&0u32.to_le_bytes());
+	write_block(&mut out, 0x0000_0001, &idb[..])?;
+
+	let mut count = 0;
+	for entry in reader {
+		let entry = entry?;
+		let frame = entry.to_frame();
+		let mut epb = Vec::with_capacity(frame.len() + 64);
+		epb.extend_from_slice(&0u32.to_le_bytes());
+		epb.extend_from_slice(&((entry.timestamp >> 32) as u32).to_le_bytes());
+		epb.extend_from_slice(&(entry.timestamp as u32).to_le_bytes());
+		epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+		epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+		epb.extend_from_slice(&frame[..]);
+		pad(&mut epb);
+		/* epb_flags: bits 0-1 are the direction, 1 inbound and 2 outbound */
+		let flags: u32 = match entry.direction {
+			Direction::Inbound => 1,
+			Direction::Outbound => 2,
+		};
+		push_option(&mut epb, 2, &flags.to_le_bytes());
+		let comment = match entry.peer {
+			Some(peer) => format!("{:?} {} opcode 0x{:04X}", entry.token, peer, entry.opcode),
+			None => format!("{:?} opcode 0x{:04X}", entry.token, entry.opcode),
+		};
+		push_option(&mut epb, 1, comment.as_bytes());
+		push_option(&mut epb, 0, &[]);
+		write_block(&mut out, 0x0000_0006, &epb[..])?;
+		count += 1;
+	}
+	out.flush()?;
+	Ok(count)
+}
+
+fn pad(data: &mut Vec<u8>) {
+	while !data.len().is_multiple_of(4) {
+		data.push(0);
+	}
+}
+
+fn push_option(data: &mut Vec<u8>, code: u16, value: &[u8]) {
+	data.extend_from_slice(&code.to_le_bytes());
+	data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+	data.extend_from_slice(value);
+	pad(data);
+}
+
+fn write_block<W: Write>(out: &mut W, kind: u32, body: &[u8]) -> io::Result<()> {
+	let length = (body.len() + 12) as u32;
+	out.write_all(&kind.to_le_bytes())?;
+	out.write_all(&length.to_le_bytes())?;
+	out.write_all(body)?;
+	out.write_all(&length.to_le_bytes())
+}
@@ -0,0 +1,155 @@
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use stream::ListenerTag;
+
+/* NC_MISC_SEED_ACK, the packet carrying the seed */
+pub const SEED_OPCODE: u16 = 0x0807;
+
+/* seeds are below this without a key table, the size of the client's */
+pub const SEED_RANGE: usize = 499;
+
+/* the XOR stream cipher of the game protocol. Everything after the size
+ * prefix (opcode and body) is XORed with a key table, continuing where the
+ * previous packet left off and wrapping around at the end of the table. The
+ * starting position is the seed the server hands out on connect. */
+#[derive(Debug, Clone)]
+pub struct XorCipher {
+	key:			Arc<Vec<u8>>,
+	position:		usize,
+}
+
+impl XorCipher {
+	/* `key` must not be empty */
+	pub fn new(key: Arc<Vec<u8>>, seed: u16) -> Self {
+		assert!(!key.is_empty(), "empty XOR key table");
+		let mut cipher = XorCipher {
+			key,
+			position:		0,
+		};
+		cipher.set_seed(seed);
+		cipher
+	}
+
+	pub fn key(&self) -> &Arc<Vec<u8>> {
+		&self.key
+	}
+
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	pub fn set_seed(&mut self, seed: u16) {
+		self.position = seed as usize % self.key.len();
+	}
+
+	/* encrypts or decrypts in place, the operation is its own inverse */
+	pub fn apply(&mut self, data: &mut [u8]) {
+		for byte in data.iter_mut() {
+			*byte ^= self.key[self.position];
+			self.position += 1;
+			if self.position == self.key.len() {
+				self.position = 0;
+			}
+		}
+	}
+
+	/* `apply` to the opcode and body of a packet */
+	pub fn apply_packet(&mut self, header: u16, body: &[u8]) -> (u16, Vec<u8>) {
+		let mut data = Vec::with_capacity(body.len() + 2);
+		data.extend_from_slice(&header.to_be_bytes());
+		data.extend_from_slice(body);
+		self.apply(&mut data[..]);
+		let body = data.split_off(2);
+		(u16::from_be_bytes([data[0], data[1]]), body)
+	}
+}
+
+/* the seed handshake: every accepted client is sent a random seed before
+ * anything else. With a key table the packets of the client are decrypted
+ * from that position on, before the processor sees them. */
+#[derive(Debug, Clone)]
+pub struct SeedConfig {
+	pub opcode:			u16,
+	/* `None` only hands out the seed, see `FiestaNetworkClient::seed` */
+	pub key:			Option<Arc<Vec<u8>>>,
+	/* `None` sends it on every listener */
+	pub listeners:		Option<Vec<ListenerTag>>,
+}
+
+impl SeedConfig {
+	pub fn new() -> Self {
+		SeedConfig {
+			opcode:		SEED_OPCODE,
+			key:		None,
+			listeners:	None,
+		}
+	}
+
+	pub fn opcode(mut self, opcode: u16) -> Self {
+		self.opcode = opcode;
+		self
+	}
+
+	pub fn key(mut self, key: Arc<Vec<u8>>) -> Self {
+		self.key = Some(key);
+		self
+	}
+
+	/* only clients of these listeners get a seed, e.g. not those of the
+	 * listener for other servers */
+	pub fn only_on(mut self, listeners: &[ListenerTag]) -> Self {
+		self.listeners = Some(listeners.to_vec());
+		self
+	}
+
+	pub fn applies_to(&self, listener: ListenerTag) -> bool {
+		self.listeners.as_ref().is_none_or(|listeners| listeners.contains(&listener))
+	}
+
+	/* an empty key table would leave nothing to XOR with */
+	pub fn validate(&self) -> io::Result<()> {
+		match self.key {
+			Some(ref key) if key.is_empty() => Err(Error::new(ErrorKind::InvalidInput, "empty XOR key table")),
+			_ => Ok(()),
+		}
+	}
+
+	/* a fresh seed, a position in the key table */
+	pub fn generate(&self) -> u16 {
+		random_seed(self.key.as_ref().map_or(SEED_RANGE, |key| key.len()))
+	}
+}
+
+impl Default for SeedConfig {
+	fn default() -> Self {
+		SeedConfig::new()
+	}
+}
+
+/* a seed below `limit` (at most 65536). Not cryptographically strong, the
+ * cipher is not either. */
+pub fn random_seed(limit: usize) -> u16 {
+	/* every `RandomState` is keyed differently */
+	let mut hasher = RandomState::new().build_hasher();
+	if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+		hasher.write_u32(now.subsec_nanos());
+	}
+	let limit = limit.clamp(1, 1 << 16) as u64;
+	(hasher.finish() % limit) as u16
+}
+
+/* reads a key table, the raw bytes of the file */
+pub fn load_key<P: AsRef<Path>>(path: P) -> io::Result<Arc<Vec<u8>>> {
+	let mut key = Vec::new();
+	File::open(path)?.read_to_end(&mut key)?;
+	if key.is_empty() {
+		return Err(Error::new(ErrorKind::InvalidData, "empty XOR key table"));
+	}
+	Ok(Arc::new(key))
+}
@@ -1,364 +1,1355 @@
 use std::collections::{HashMap, LinkedList};
-use std::io::{Error, ErrorKind, Read, Write};
-use std::sync::{Mutex, Arc, RwLock, MutexGuard};
-use std::mem::drop;
+use std::fmt;
+use std::mem;
+use std::io::{Error, ErrorKind, Write};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
 use mio::*;
 use mio::tcp::*;
 
+use admin::{AdminConfig, AdminConsole, AdminContext};
 use buffer::*;
+use capture::{Direction, PacketRecorder};
+use cipher::{SeedConfig, XorCipher};
+use config::ServerConfig;
+use dump;
+use http::MetricsEndpoint;
+use limits::*;
+use metrics::{DisconnectReason, Metrics};
+use ratelimit::*;
+use registry::ClientRegistry;
+use server::Balance;
+use pool;
+use shared::SharedBytes;
+use stream::{Listener, ListenerTag, Stream, DEFAULT_LISTENER};
 use super::processing::*;
 
 pub const SERVER_TOKEN: Token = Token(0);
 
+/* the largest body the server reads, larger sizes are taken for 0 */
+pub const MAX_BODY_SIZE: usize = 2048;
+/* the largest body the size prefix can describe */
+pub const MAX_WIRE_BODY_SIZE: usize = 0xFFFF;
+
 pub struct FiestaHandler {
-	listener:		TcpListener,
-	clients:		HashMap<Token, Arc<RwLock<Box<FiestaNetworkClient>>>>,
-	token_count:	usize,
-	processor:		Box<PacketProcessor>,
+	/* with the token each listener is registered with */
+	listeners:		Vec<(Listener, ListenerTag, Token)>,
+	clients:		HashMap<Token, Connection>,
+	processor:		Box<dyn PacketProcessor>,
+	config:			ServerConfig,
+	accept_paused:	bool,
+	/* accepting was stopped by an operator, the pause timer does not resume it */
+	accept_held:	bool,
+	rate_limit_stats:	RateLimitStats,
+	registry:		Arc<ClientRegistry>,
+	/* index of this event loop and channels to all of them, for handing out clients */
+	reactor:		usize,
+	peers:			Vec<Sender<FiestaMessage>>,
+	balance:		Balance,
+	next_reactor:	usize,
+	/* channels to every event loop of the server, including this one */
+	reactors:		Vec<Sender<FiestaMessage>>,
+	/* `/metrics` for Prometheus, see `serve_metrics` */
+	http:			Option<MetricsEndpoint>,
+	/* see `serve_admin` */
+	admin:			Option<AdminConsole>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FiestaTimeout {
+	/* re-register the listener after accepting was paused */
+	ResumeAccept,
+	/* hand packets held back by the rate limiter to the processor */
+	ReleaseDelayed(Token),
+	/* a `/metrics` connection did nothing for too long */
+	HttpIdle(Token),
 }
 
+/* sent to the event loop by `FiestaNetworkClient`s and other event loops */
+#[derive(Debug)]
+pub enum FiestaMessage {
+	/* there is outgoing data queued for the client */
+	Flush(Token),
+	/* the client should be disconnected */
+	Close(Token),
+	/* a client accepted by another event loop, to be served by this one */
+	Adopt(Token, Stream, ListenerTag),
+	/* an outbound connection made by a `Connector`, to be served by this one */
+	Attach(Box<Connection>),
+	/* stop accepting clients until `ResumeAccept` */
+	PauseAccept,
+	ResumeAccept,
+	/* stop the event loop */
+	Shutdown,
+}
+
+/* the part of a client that is shared with the packet processors. It holds no
+ * locks, outgoing data is queued and picked up by the event loop. */
 pub struct FiestaNetworkClient {
-	client:			Mutex<TcpStream>,
-	read_buffer:	Mutex<Buffer>,
-	write_buffer:	Mutex<Buffer>,
-	packet_queue:	Mutex<LinkedList<FiestaPacket>>,
-	is_alive:		Mutex<bool>,
-	interest:		Mutex<EventSet>,
 	id:				Token,
+	peer_addr:		Option<SocketAddr>,
+	listener:		ListenerTag,
+	is_alive:		AtomicBool,
+	interest:		AtomicUsize,
+	flush_pending:	AtomicBool,
+	queued_bytes:	AtomicUsize,
+	/* see `set_send_limit`, `usize::MAX` for no limit */
+	send_limit:		AtomicUsize,
+	overflow_closes:	AtomicBool,
+	/* closed because the send queue overflowed, for the disconnect reason */
+	overflowed:		AtomicBool,
+	outgoing:		mpsc::Sender<Vec<u8>>,
+	notifier:		Option<Sender<FiestaMessage>>,
+	/* `recording` spares the lock while no recorder is set */
+	recording:		AtomicBool,
+	recorder:		RwLock<Option<Arc<PacketRecorder>>>,
+	metrics:		Option<Arc<Metrics>>,
+	/* logs every packet of the client, see `dump::log_packet` */
+	tracing:		AtomicBool,
+	connected_at:	Instant,
+	/* the seed it was sent, see `SeedConfig` */
+	seed:			RwLock<Option<u16>>,
+}
+
+/* the socket and buffers of a client, owned by the event loop thread */
+pub struct Connection {
+	socket:			Stream,
+	read_buffer:	Buffer,
+	write_buffer:	Buffer,
+	outgoing:		mpsc::Receiver<Vec<u8>>,
+	/* an outgoing message that did not fit into the write buffer completely */
+	pending:		Option<(Vec<u8>, usize)>,
+	/* the start of a frame whose end is in a later outgoing message */
+	sent_rest:		Vec<u8>,
+	/* see `set_max_body` */
+	max_body:		usize,
+	packet_queue:	LinkedList<FiestaPacket>,
+	interest:		EventSet,
+	rate_limit:		Option<RateLimitState<FiestaPacket>>,
+	/* why the connection went down, set by whoever noticed first */
+	disconnect_reason:	Option<DisconnectReason>,
+	/* made by us rather than accepted, it does not count against the limits */
+	outbound:		bool,
+	/* decrypts what the client sends, started by `send_seed` */
+	cipher:			Option<XorCipher>,
+	/* see `set_traced` */
+	traced:			bool,
+	client:			Arc<FiestaNetworkClient>,
 }
 
 pub struct FiestaPacket {
 	pub header:			u16,
-	pub data:			Buffer,
+	pub data:			SharedBytes,
 }
 
 impl FiestaNetworkClient {
-	pub fn new(inner_client: TcpStream, id: Token) -> Self {
-		FiestaNetworkClient {
-			client:			Mutex::new(inner_client),
-			read_buffer:	Mutex::new(Buffer::new()),
-			write_buffer:	Mutex::new(Buffer::new()),
-			packet_queue:	Mutex::new(LinkedList::new()),
-			is_alive:		Mutex::new(true),
-			interest:		Mutex::new(EventSet::all()),
-			id:				id
-		}
+	pub fn alive(&self) -> bool {
+		self.is_alive.load(Ordering::Acquire)
 	}
 
-	pub fn can_read_next_packet(&self) -> bool {
-		match self.get_next_size() {
-			Ok(s) => {
-				let guard = self.read_buffer.lock().unwrap();
-				guard.bytes_remaining() >= (s as usize) + 2 + if s > 255 { 2 } else { 1 }
-				/* for the size of the header/size info */
-			},
-			Err(_) => false
-		}
+	pub fn id(&self) -> Token {
+		self.id
 	}
 
-	fn can_read_next_packet_inner(guard: &mut MutexGuard<Buffer>) -> bool {
-		match FiestaNetworkClient::get_next_size_inner(guard) {
-			Ok(s) => {
-				let total_size =
-						s
-					+	2	/* header */
-					+	if s > 255 { 2 } else { 1 };	/* size data */
+	pub fn peer_addr(&self) -> Option<SocketAddr> {
+		self.peer_addr
+	}
 
-				guard.bytes_remaining() >= total_size as usize
-			},
-			Err(_) => false,
-		}
+	pub fn peer_ip(&self) -> Option<IpAddr> {
+		self.peer_addr.map(|addr| addr.ip())
 	}
 
-	pub fn read_next_packet(&self) {
-		let mut read_buffer_guard = self.read_buffer.lock().unwrap();
-		let mut packet_queue_guard = self.packet_queue.lock().unwrap();
+	/* the listener the client connected through */
+	pub fn listener(&self) -> ListenerTag {
+		self.listener
+	}
 
-		FiestaNetworkClient::read_next_packet_inner(&mut read_buffer_guard, &mut packet_queue_guard);
+	pub fn connected_at(&self) -> Instant {
+		self.connected_at
 	}
 
-	fn read_next_packet_inner(
-			read_buffer: &mut MutexGuard<Buffer>, 
-			packet_queue: &mut MutexGuard<LinkedList<FiestaPacket>>) {
+	pub fn uptime(&self) -> Duration {
+		self.connected_at.elapsed()
+	}
 
-		if FiestaNetworkClient::can_read_next_packet_inner(read_buffer) {
-			let size = match FiestaNetworkClient::get_next_size_inner(read_buffer) {
-				Ok(s) => s,
-				Err(_) => return,
-			};
-			let mut packet = FiestaPacket::new(0, size as usize);
+	/* logs all packets of this client to `dump::TRACE_TARGET`, on top of
+	 * the opcodes traced for everyone */
+	pub fn set_tracing(&self, enabled: bool) {
+		self.tracing.store(enabled, Ordering::Relaxed);
+	}
 
-			if size > 255 {
-				read_buffer.advance_read(3);
-			} else {
-				read_buffer.advance_read(1);
-			};
+	pub fn is_tracing(&self) -> bool {
+		self.tracing.load(Ordering::Relaxed)
+	}
+
+	/* the seed the client was sent when it connected, if any */
+	pub fn seed(&self) -> Option<u16> {
+		*self.seed.read().unwrap()
+	}
+
+	/* where the traffic of the client is counted, if anywhere */
+	pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+		self.metrics.as_ref()
+	}
 
-			packet.header = read_buffer.read_u16().unwrap();
-			let body = read_buffer.read_bytes(size as usize).unwrap();
-			packet.data.append(&body[..]);
-			packet_queue.push_back(packet);
+	/* the interest the connection was last registered with */
+	pub fn interest(&self) -> EventSet {
+		let bits = self.interest.load(Ordering::Relaxed);
+		let mut interest = EventSet::none();
+		for flag in &[EventSet::readable(), EventSet::writable(), EventSet::error(), EventSet::hup()] {
+			if bits & flag.bits() != 0 {
+				interest.insert(*flag);
+			}
 		}
+		interest
+	}
+
+	/* bytes handed to `append_send` that have not been written to the socket yet */
+	pub fn queued_bytes(&self) -> usize {
+		self.queued_bytes.load(Ordering::Relaxed)
+	}
+
+	/* bounds `queued_bytes`, a client that does not read its data will not
+	 * hold on to more than `limit.max_bytes`. The limit is not exact when
+	 * several threads send at the same time. */
+	pub fn set_send_limit(&self, limit: &SendQueueLimit) {
+		self.send_limit.store(limit.max_bytes.unwrap_or(usize::MAX), Ordering::Relaxed);
+		self.overflow_closes.store(limit.overflow == SendOverflow::Disconnect, Ordering::Relaxed);
 	}
 
-	fn get_next_size(&self) -> Result<u16, Error> {
-		let mut guard = self.read_buffer.lock().unwrap();
-		FiestaNetworkClient::get_next_size_inner(&mut guard)
+	pub fn append_send(&self, buffer: &[u8]) {
+		if !self.alive() || buffer.is_empty() {
+			return;
+		}
+		let limit = self.send_limit.load(Ordering::Relaxed);
+		if self.queued_bytes().saturating_add(buffer.len()) > limit {
+			self.send_overflowed(buffer.len());
+			return;
+		}
+		self.queued_bytes.fetch_add(buffer.len(), Ordering::Relaxed);
+		if self.outgoing.send(buffer.to_vec()).is_err() {
+			/* the connection is gone already */
+			return;
+		}
+		/* one wakeup is enough until the event loop picked the data up */
+		if !self.flush_pending.swap(true, Ordering::AcqRel) {
+			self.notify(FiestaMessage::Flush(self.id));
+		}
 	}
 
-	fn get_next_size_inner(guard: &mut MutexGuard<Buffer>) -> Result<u16, Error> {
-		if guard.bytes_remaining() < 3 {
-			Err(Error::new(ErrorKind::Other, "to little data left"))
+	fn send_overflowed(&self, bytes: usize) {
+		if let Some(ref metrics) = self.metrics {
+			metrics.send_overflowed();
+		}
+		if self.overflow_closes.load(Ordering::Relaxed) {
+			if !self.overflowed.swap(true, Ordering::AcqRel) {
+				warn!(target: "network", "send queue of {:?} is full, disconnecting", self.id);
+			}
+			self.close();
 		} else {
-			let small_size = try!(guard.peek_u8(0));
-			if small_size > 0 {
-				Ok(small_size as u16)
-			} else {
-				let mut big_size = try!(guard.peek_u16(1));
+			debug!(target: "network", "send queue of {:?} is full, dropped {} bytes", self.id, bytes);
+		}
+	}
 
-				if (big_size as usize) > 2048 {
-					/* this should never actually happen with real data */
-					/* casting 0 here will still let it read 5 bytes (size + header) */
-					big_size = 0;
-				};
+	/* asks the event loop to disconnect the client */
+	pub fn close(&self) {
+		if self.is_alive.swap(false, Ordering::AcqRel) {
+			self.notify(FiestaMessage::Close(self.id));
+		}
+	}
+
+	/* records every packet received and sent from now on, `None` stops */
+	pub fn set_recorder(&self, recorder: Option<Arc<PacketRecorder>>) {
+		let mut current = self.recorder.write().unwrap();
+		self.recording.store(recorder.is_some(), Ordering::Release);
+		*current = recorder;
+	}
+
+	pub fn is_recording(&self) -> bool {
+		self.recording.load(Ordering::Acquire)
+	}
+
+	fn record(&self, direction: Direction, opcode: u16, payload: &[u8]) {
+		if let Some(ref recorder) = *self.recorder.read().unwrap() {
+			recorder.record(self, direction, opcode, payload);
+		}
+	}
+
+	/* counts, records and, if `traced`, traces a packet on its way to the socket */
+	fn sent_packet(&self, opcode: u16, body: &[u8], traced: bool) {
+		if self.is_recording() {
+			self.record(Direction::Outbound, opcode, body);
+		}
+		if let Some(ref metrics) = self.metrics {
+			metrics.sent(opcode, body.len());
+		}
+		if !traced {
+			return;
+		}
+		if self.is_tracing() {
+			dump::log_packet(self.id, Direction::Outbound, opcode, body);
+		} else {
+			dump::trace_packet(self.id, Direction::Outbound, opcode, body);
+		}
+	}
 
-				Ok(big_size)
+	fn notify(&self, message: FiestaMessage) {
+		if let Some(ref notifier) = self.notifier {
+			if let Err(e) = notifier.send(message) {
+				/* picked up with the next event of the client instead */
+				warn!(target: "network", "could not notify the event loop for {:?}: {:?}", self.id, e);
+				self.flush_pending.store(false, Ordering::Release);
 			}
 		}
 	}
 
-	pub fn readable(&self, event_loop: &mut EventLoop<FiestaHandler>, token: Token, disconnect: &mut bool) {
-		let mut buffer = [0; 2048]; /* maybe not allocate this every time again? */
-		let mut inner_client_guard = self.client.lock().unwrap();
-		let mut read_buffer_guard = self.read_buffer.lock().unwrap();
+	fn set_alive(&self, value: bool) {
+		self.is_alive.store(value, Ordering::Release);
+	}
+}
 
-		match inner_client_guard.read(&mut buffer[..]) {
-			Ok(size) if size > 0 => {
-				/* read some data */
-				info!(target: "network", "read {} bytes from {:?}", size, token);
-				read_buffer_guard.append(&buffer[0..size]);
-			},
-			Ok(_) => {
-				/* size == 0 */
-				debug!(target: "network", "read 0 bytes from {:?}", self.id());
-				/* this usually means a disconect */
-				/* no need to deregister, we use oneshot. */
-				// event_loop.deregister(&*inner_client_guard).unwrap();
-				inner_client_guard.shutdown(Shutdown::Both).unwrap();
-				self.set_alive(false);
-				*disconnect = true;
+impl Connection {
+	/* `notifier` wakes the event loop when data is sent from another thread,
+	 * without one the owner has to call `writeable` itself */
+	pub fn new<S: Into<Stream>>(socket: S, id: Token, notifier: Option<Sender<FiestaMessage>>) -> Self {
+		Connection::with_listener(socket.into(), id, DEFAULT_LISTENER, notifier)
+	}
+
+	pub fn with_listener(socket: Stream, id: Token, listener: ListenerTag, notifier: Option<Sender<FiestaMessage>>) -> Self {
+		Connection::with_metrics(socket, id, listener, notifier, None)
+	}
+
+	/* like `with_listener`, the traffic of the client is counted into `metrics` */
+	pub fn with_metrics(
+			socket: Stream,
+			id: Token,
+			listener: ListenerTag,
+			notifier: Option<Sender<FiestaMessage>>,
+			metrics: Option<Arc<Metrics>>) -> Self {
+		let (sender, receiver) = mpsc::channel();
+		let client = FiestaNetworkClient {
+			id,
+			peer_addr:		socket.peer_addr(),
+			listener,
+			is_alive:		AtomicBool::new(true),
+			interest:		AtomicUsize::new(EventSet::all().bits()),
+			flush_pending:	AtomicBool::new(false),
+			queued_bytes:	AtomicUsize::new(0),
+			send_limit:		AtomicUsize::new(usize::MAX),
+			overflow_closes:	AtomicBool::new(false),
+			overflowed:		AtomicBool::new(false),
+			outgoing:		sender,
+			notifier,
+			recording:		AtomicBool::new(false),
+			recorder:		RwLock::new(None),
+			metrics,
+			tracing:		AtomicBool::new(false),
+			connected_at:	Instant::now(),
+			seed:			RwLock::new(None),
+		};
+		Connection {
+			socket,
+			read_buffer:	Buffer::new(),
+			write_buffer:	Buffer::new(),
+			outgoing:		receiver,
+			pending:		None,
+			sent_rest:		Vec::new(),
+			max_body:		MAX_BODY_SIZE,
+			packet_queue:	LinkedList::new(),
+			interest:		EventSet::all(),
+			rate_limit:		None,
+			disconnect_reason:	None,
+			outbound:		false,
+			cipher:			None,
+			traced:			true,
+			client:			Arc::new(client),
+		}
+	}
+
+	/* a connection we made to `socket`'s peer, see `Connector` */
+	pub fn outbound(socket: Stream, id: Token, listener: ListenerTag, notifier: Option<Sender<FiestaMessage>>, metrics: Option<Arc<Metrics>>) -> Self {
+		let mut connection = Connection::with_metrics(socket, id, listener, notifier, metrics);
+		connection.outbound = true;
+		connection
+	}
+
+	pub fn is_outbound(&self) -> bool {
+		self.outbound
+	}
+
+	pub fn client(&self) -> &Arc<FiestaNetworkClient> {
+		&self.client
+	}
+
+	pub fn id(&self) -> Token {
+		self.client.id
+	}
+
+	pub fn socket(&self) -> &Stream {
+		&self.socket
+	}
+
+	pub fn interest(&self) -> EventSet {
+		self.interest
+	}
+
+	/* sends `config.opcode` with `seed` ahead of anything sent afterwards.
+	 * With a key table what the client sends is decrypted from then on. */
+	pub fn send_seed(&mut self, config: &SeedConfig, seed: u16) {
+		*self.client.seed.write().unwrap() = Some(seed);
+		self.cipher = config.key.as_ref().map(|key| XorCipher::new(key.clone(), seed));
+		self.client.append_send(&frame_packet(config.opcode, &seed.to_be_bytes())[..]);
+	}
+
+	pub fn pop_packet(&mut self) -> Option<FiestaPacket> {
+		let packet = self.packet_queue.pop_front().map(|packet| match self.cipher {
+			Some(ref mut cipher) => {
+				let (header, body) = cipher.apply_packet(packet.header, &packet.data[..]);
+				FiestaPacket::from_vec(header, body)
 			},
-			Err(e) => {
-				/* some error while receiving data.. */
-				warn!(target: "network", "error while receiving data: '{:#?}'", e);
-				/* no need to deregister, we use oneshot. */
-				// event_loop.deregister(&*inner_client_guard);
-				inner_client_guard.shutdown(Shutdown::Both).unwrap();
-				self.set_alive(false);
-				*disconnect = true;
+			None => packet,
+		});
+		if let Some(ref packet) = packet {
+			if self.client.is_recording() {
+				self.client.record(Direction::Inbound, packet.header, &packet.data[..]);
+			}
+			if let Some(ref metrics) = self.client.metrics {
+				metrics.received(packet.header, packet.data.len());
+			}
+			if !self.traced {
+				/* the owner traces it */
+			} else if self.client.is_tracing() {
+				dump::log_packet(self.id(), Direction::Inbound, packet.header, &packet.data[..]);
+			} else {
+				dump::trace_packet(self.id(), Direction::Inbound, packet.header, &packet.data[..]);
 			}
 		}
+		packet
+	}
+
+	pub fn can_read_next_packet(&self) -> bool {
+		frame_len_at(&self.read_buffer, 0, self.max_body).is_some()
+	}
+
+	pub fn read_next_packet(&mut self) {
+		read_packets_bounded(&mut self.read_buffer, &mut self.packet_queue, 1, self.max_body);
+	}
+
+	/* off leaves the packets of this connection out of `dump` tracing, for
+	 * connections carrying packets encrypted in a way the connection does not
+	 * know about; their owner traces them once they are decrypted */
+	pub fn set_traced(&mut self, traced: bool) {
+		self.traced = traced;
+	}
 
-		/* this is no longer needed, as it is a mutex, I like to drop it ASAP */
-		drop(inner_client_guard);
-		
-		let mut packet_queue_guard = self.packet_queue.lock().unwrap();
-		while FiestaNetworkClient::can_read_next_packet_inner(&mut read_buffer_guard) {
-			FiestaNetworkClient::read_next_packet_inner(&mut read_buffer_guard, &mut packet_queue_guard);
+	/* reads bodies of up to `max_body` bytes instead of `MAX_BODY_SIZE`, the
+	 * read buffer grows to fit the largest frame. Call it before anything is
+	 * read. */
+	pub fn set_max_body(&mut self, max_body: usize) {
+		let frame = max_body + 5;
+		if self.read_buffer.capacity() < frame && self.read_buffer.bytes_remaining() == 0 {
+			self.read_buffer = Buffer::with_capacity(frame);
 		}
+		self.max_body = max_body;
 	}
 
-	pub fn writeable(&self, event_loop: &mut EventLoop<FiestaHandler>, token: Token, disconnect: &mut bool) {
-		let mut buf = [0; 1024];
-		let mut guard = self.write_buffer.lock().unwrap();
-		match guard.peek_max(0, 1024, &mut buf[..]) {
-			Ok(size) if size > 0	=> {
-				let mut inner_client_guard = self.client.lock().unwrap();
-				match inner_client_guard.write(&buf[0..size]) {
-					Ok(s) if s > 0 => {
-						debug!(target: "network", "wrote {} bytes to {:?}", s, token);
-						guard.advance_read(s);
-					},
-					Ok(_) => {
-						/* size == 0 */
-						warn!(target: "network", "wrote 0 bytes for {:?}, shutting down the socket.", token);
-						/* no need to deregister, we use oneshot. */
-						inner_client_guard.shutdown(Shutdown::Both).unwrap();
-						self.set_alive(false);
-						*disconnect = true;
-					},
-					Err(e) => {
-						/* error while writing */
-						warn!(target: "network", "error while writing to socket ({:?}): {:#?}", token, e);
-						/* no need to deregister, we use oneshot. */
-						inner_client_guard.shutdown(Shutdown::Both).unwrap();
-						self.set_alive(false);
-						*disconnect = true;
+	/* reads until the socket would block or `budget` bytes have been read, the
+	 * budget keeps a single busy client from starving the others. With oneshot
+	 * level triggered registration the client is woken up again right away if
+	 * there is data left. */
+	pub fn readable(&mut self, disconnect: &mut bool, budget: usize) {
+		let token = self.id();
+		let mut total = 0;
+
+		while total < budget {
+			if self.read_buffer.free_space() == 0 {
+				/* only complete frames make room, a single frame always fits */
+				warn!(target: "network", "read buffer of {:?} is full", token);
+				break;
+			}
+			match self.read_buffer.fill_from(&mut self.socket, budget - total) {
+				Ok(size) if size > 0 => {
+					total += size;
+					if let Some(ref metrics) = self.client.metrics {
+						metrics.read_buffer_level(self.read_buffer.bytes_remaining());
 					}
+					read_packets_bounded(&mut self.read_buffer, &mut self.packet_queue, usize::MAX, self.max_body);
+				},
+				Ok(_) => {
+					/* size == 0 */
+					debug!(target: "network", "read 0 bytes from {:?}", token);
+					/* this usually means a disconect */
+					self.set_disconnect_reason(DisconnectReason::PeerClosed);
+					self.shutdown();
+					*disconnect = true;
+					break;
+				},
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+				Err(e) => {
+					/* some error while receiving data.. */
+					warn!(target: "network", "error while receiving data: '{:#?}'", e);
+					self.set_disconnect_reason(DisconnectReason::ReadError);
+					self.shutdown();
+					*disconnect = true;
+					break;
 				}
-			},
-			Ok(_)	=> {
-				/* read 0 bytes from send buffer..  */
-				/* TODO: we might want to unregister it from the loop until new data arrives */
-				let interest = self.interest();
-				let interest = interest ^ EventSet::writable();
-				self.set_interest(interest);
-			},
-			Err(e)		=> {
-				warn!(target: "network", "error while reading from write_buffer ({:?}): {:#?}", token, e);
-				let inner_client_guard = self.client.lock().unwrap();
-				/* no need to deregister, we use oneshot */
-				inner_client_guard.shutdown(Shutdown::Both).unwrap();
-				*disconnect = true;
 			}
-		};
-	}
+		}
 
-	pub fn alive(&self) -> bool {
-		let guard = self.is_alive.lock().unwrap();
-		(*guard).clone()
+		if total > 0 {
+			info!(target: "network", "read {} bytes from {:?}", total, token);
+			if let Some(ref metrics) = self.client.metrics {
+				metrics.read_bytes(total);
+			}
+		}
 	}
 
-	pub fn id(&self) -> Token {
-		self.id
+	/* moves data queued by `FiestaNetworkClient::append_send` into the write
+	 * buffer, as far as it fits */
+	fn pull_outgoing(&mut self) {
+		/* anything sent after this point triggers another wakeup */
+		self.client.flush_pending.store(false, Ordering::Release);
+
+		while self.write_buffer.free_space() > 0 {
+			let (data, offset) = match self.pending.take() {
+				Some(pending) => pending,
+				None => match self.outgoing.try_recv() {
+					Ok(data) => {
+						self.split_outgoing(&data[..]);
+						(data, 0)
+					},
+					Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+				},
+			};
+			let written = self.write_buffer.write_some(&data[offset..]);
+			if offset + written < data.len() {
+				self.pending = Some((data, offset + written));
+			}
+		}
 	}
 
-	fn set_alive(&self, value: bool) {
-		let mut guard = self.is_alive.lock().unwrap();
-		*guard = value;
+	/* hands the packets in `data` to `FiestaNetworkClient::sent_packet`, in
+	 * the order they go out. A message need not end with a frame, the rest is
+	 * kept for the next one. */
+	fn split_outgoing(&mut self, data: &[u8]) {
+		let joined;
+		let bytes = if self.sent_rest.is_empty() {
+			data
+		} else {
+			self.sent_rest.extend_from_slice(data);
+			joined = mem::take(&mut self.sent_rest);
+			&joined[..]
+		};
+		let mut frames = split_frames(bytes).max_body(self.max_body);
+		for (opcode, body) in frames.by_ref() {
+			self.client.sent_packet(opcode, body, self.traced);
+		}
+		self.sent_rest.extend_from_slice(frames.rest());
 	}
 
-	pub fn interest(&self) -> EventSet {
-		let guard = self.interest.lock().unwrap();
-		(*guard).clone()
+	/* writes until there is nothing left to send or the socket would block.
+	 * Writable interest is kept exactly as long as there is data left. */
+	pub fn writeable(&mut self, disconnect: &mut bool) {
+		let token = self.id();
+		let mut total = 0;
+
+		self.pull_outgoing();
+		if let Some(ref metrics) = self.client.metrics {
+			metrics.write_buffer_level(self.write_buffer.bytes_remaining());
+		}
+		while self.write_buffer.bytes_remaining() > 0 {
+			match self.write_buffer.drain_into(&mut self.socket) {
+				Ok(size) if size > 0 => {
+					total += size;
+					self.client.queued_bytes.fetch_sub(size, Ordering::Relaxed);
+					self.pull_outgoing();
+				},
+				Ok(_) => {
+					/* size == 0 */
+					warn!(target: "network", "wrote 0 bytes for {:?}, shutting down the socket.", token);
+					self.set_disconnect_reason(DisconnectReason::WriteError);
+					self.shutdown();
+					*disconnect = true;
+					break;
+				},
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+				Err(e) => {
+					/* error while writing */
+					warn!(target: "network", "error while writing to socket ({:?}): {:#?}", token, e);
+					self.set_disconnect_reason(DisconnectReason::WriteError);
+					self.shutdown();
+					*disconnect = true;
+					break;
+				}
+			}
+		}
+
+		if total > 0 {
+			debug!(target: "network", "wrote {} bytes to {:?}", total, token);
+			if let Some(ref metrics) = self.client.metrics {
+				metrics.wrote_bytes(total);
+			}
+		}
+
+		if self.write_buffer.bytes_remaining() > 0 {
+			self.interest.insert(EventSet::writable());
+		} else {
+			/* nothing left, a `Flush` message turns it back on */
+			self.interest.remove(EventSet::writable());
+		}
+		self.client.interest.store(self.interest.bits(), Ordering::Relaxed);
 	}
 
-	fn set_interest(&self, interest: EventSet) {
-		let mut guard = self.interest.lock().unwrap();
-		*guard = interest;
+	pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+		self.disconnect_reason
 	}
 
-	pub fn append_send(&self, buffer: &[u8]) {
-		let mut guard = self.write_buffer.lock().unwrap();
-		guard.append(buffer);
-		let mut interest_guard = self.interest.lock().unwrap();
-		if !interest_guard.is_writable() {
-			*interest_guard = (*interest_guard) | EventSet::writable();
+	/* keeps the first reason, later ones are usually a consequence of it */
+	pub fn set_disconnect_reason(&mut self, reason: DisconnectReason) {
+		if self.disconnect_reason.is_none() {
+			self.disconnect_reason = Some(reason);
 		}
 	}
+
+	/* shuts the socket down, the handler drops the connection afterwards */
+	pub fn shutdown(&mut self) {
+		let _ = self.socket.shutdown();
+		self.client.set_alive(false);
+	}
+}
+
+impl fmt::Debug for Connection {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Connection({:?}, {:?})", self.id(), self.client.peer_addr())
+	}
 }
 
 impl FiestaHandler {
-	pub fn new(listener: TcpListener, processor: Box<PacketProcessor>) -> FiestaHandler {
+	pub fn new(listener: TcpListener, processor: Box<dyn PacketProcessor>) -> FiestaHandler {
+		FiestaHandler::with_config(listener, processor, ServerConfig::default())
+	}
+
+	pub fn with_config(listener: TcpListener, processor: Box<dyn PacketProcessor>, config: ServerConfig) -> FiestaHandler {
+		let listeners = vec![(Listener::Tcp(listener), DEFAULT_LISTENER)];
+		FiestaHandler::reactor(listeners, processor, config, Arc::new(ClientRegistry::new()), 0)
+	}
+
+	/* one of several event loops sharing `registry`; reactors without
+	 * listeners only serve clients handed to them with `FiestaMessage::Adopt`.
+	 * The caller registers listener `i` with `Token(i)`. */
+	pub fn reactor(
+			listeners: Vec<(Listener, ListenerTag)>,
+			processor: Box<dyn PacketProcessor>,
+			config: ServerConfig,
+			registry: Arc<ClientRegistry>,
+			reactor: usize) -> FiestaHandler {
+		registry.reserve_tokens(listeners.len());
+		let listeners = listeners.into_iter().enumerate().map(|(i, (listener, tag))| (listener, tag, Token(i))).collect();
 		FiestaHandler {
-			listener:			listener,
+			listeners,
 			clients:			HashMap::new(),
-			token_count:		0,
-			processor:			processor,
+			processor,
+			config,
+			accept_paused:		false,
+			accept_held:		false,
+			rate_limit_stats:	RateLimitStats::default(),
+			registry,
+			reactor,
+			peers:				Vec::new(),
+			balance:			Balance::RoundRobin,
+			next_reactor:		0,
+			reactors:			Vec::new(),
+			http:				None,
+			admin:				None,
 		}
 	}
 
+	/* channels to all reactors (indexed like the registry's reactors), accepted
+	 * clients are spread over them according to `balance` */
+	pub fn set_peers(&mut self, peers: Vec<Sender<FiestaMessage>>, balance: Balance) {
+		self.peers = peers;
+		self.balance = balance;
+	}
+
+	/* channels to all event loops of the server, for changes that apply to all
+	 * of them; without them they only apply to this one */
+	pub fn set_reactors(&mut self, reactors: Vec<Sender<FiestaMessage>>) {
+		self.reactors = reactors;
+	}
+
+	/* stops or resumes accepting on every event loop of the server */
+	pub fn set_accepting(&mut self, event_loop: &mut EventLoop<Self>, accepting: bool) {
+		if accepting {
+			self.release_accept(event_loop);
+		} else {
+			self.hold_accept(event_loop);
+		}
+		let message = || if accepting { FiestaMessage::ResumeAccept } else { FiestaMessage::PauseAccept };
+		for (reactor, channel) in self.reactors.iter().enumerate().filter(|&(r, _)| r != self.reactor) {
+			if let Err(e) = channel.send(message()) {
+				warn!(target: "network", "could not reach reactor {}: {:?}", reactor, e);
+			}
+		}
+	}
+
+	pub fn is_accepting(&self) -> bool {
+		!self.accept_paused && !self.accept_held
+	}
+
+	/* adds and registers another listener, at any time. Returns its token,
+	 * which comes from the registry like the clients' tokens. */
+	pub fn listen(&mut self, event_loop: &mut EventLoop<Self>, listener: Listener, tag: ListenerTag) -> Result<Token, Error> {
+		let token = self.registry.next_token();
+		register_listener(event_loop, &listener, token)?;
+		self.listeners.push((listener, tag, token));
+		Ok(token)
+	}
+
+	/* serves the metrics in the Prometheus format over HTTP on `listener`,
+	 * in this event loop. Returns the token of the listener. */
+	pub fn serve_metrics(&mut self, event_loop: &mut EventLoop<Self>, listener: TcpListener) -> Result<Token, Error> {
+		let endpoint = MetricsEndpoint::new(listener, self.registry.next_token(), self.config.metrics.clone())
+			.idle_timeout(self.config.metrics_idle_timeout);
+		endpoint.register(event_loop)?;
+		let token = endpoint.token();
+		self.http = Some(endpoint);
+		Ok(token)
+	}
+
+	/* serves the admin console on `listener`, in this event loop. Returns the
+	 * token of the listener. */
+	pub fn serve_admin(&mut self, event_loop: &mut EventLoop<Self>, listener: Listener, config: AdminConfig) -> Result<Token, Error> {
+		let console = AdminConsole::new(listener, self.registry.next_token(), config);
+		console.register(event_loop)?;
+		let token = console.token();
+		self.admin = Some(console);
+		Ok(token)
+	}
+
+	pub fn listeners(&self) -> Vec<ListenerTag> {
+		self.listeners.iter().map(|&(_, tag, _)| tag).collect()
+	}
+
+	fn listener(&self, token: Token) -> Option<&(Listener, ListenerTag, Token)> {
+		self.listeners.iter().find(|&&(_, _, listener)| listener == token)
+	}
+
+	pub fn registry(&self) -> &Arc<ClientRegistry> {
+		&self.registry
+	}
+
+	pub fn connection_count(&self) -> usize {
+		self.registry.connections().lock().unwrap().total()
+	}
+
+	pub fn client(&self, token: Token) -> Option<Arc<FiestaNetworkClient>> {
+		self.registry.get(token)
+	}
+
 	fn server_ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
-		if events.is_readable() {
+		if events.is_readable() && self.is_accepting() {
+			let (accepted, tag) = match self.listener(token) {
+				Some(&(ref listener, tag, _)) => (listener.accept(), tag),
+				None => return,
+			};
 			/* we may accept a client */
-			match self.listener.accept() {
+			match accepted {
 				Ok(Some(client)) => {
-					/* successfully accepted a client */
-					let token = self.get_next_token();
-					event_loop.register_opt(&client, token, EventSet::all(), PollOpt::oneshot()).unwrap();
-					self.clients.insert(
-						token, 
-						Arc::new(
-							RwLock::new(
-								Box::new(
-									FiestaNetworkClient::new(client, token)))));
-					info!(target: "network", "accepted client with {:?}", token);
+					let ip = client.peer_addr().map(|addr| addr.ip());
+					let checked = {
+						let mut connections = self.registry.connections().lock().unwrap();
+						let checked = connections.check(&self.config.limits, ip);
+						if checked.is_ok() {
+							connections.add(ip);
+						}
+						checked
+					};
+					match checked {
+						Ok(()) => self.hand_out_client(event_loop, client, tag),
+						Err(reason) => {
+							self.config.metrics.rejected(reason);
+							self.reject_client(client, ip, reason)
+						},
+					}
 				},
 				Ok(None) => {
 					/* WOULDBLOCK / EAGAIN */
 					info!(target: "network", "WOULDBLOCK while accepting client.");
 				},
 				Err(e) => {
-					/* unexpected error */
-					panic!("unexpected error: {:#?}", e);
+					/* e.g. EMFILE; the pending connection stays in the backlog, so stop
+					 * polling the listener for a while instead of spinning on it */
+					let pause = self.config.limits.accept_pause_ms;
+					self.config.metrics.accept_failed();
+					warn!(target: "network", "error while accepting client, pausing accepts for {} ms: {:#?}", pause, e);
+					self.pause_accept(event_loop, pause);
 				}
 			}
 		}
 	}
 
-	fn get_next_token(&mut self) -> Token {
-		self.token_count += 1;
-		Token(self.token_count)
+	fn pick_reactor(&mut self) -> usize {
+		if self.peers.len() < 2 {
+			return self.reactor;
+		}
+		match self.balance {
+			Balance::RoundRobin => {
+				let reactor = self.next_reactor % self.peers.len();
+				self.next_reactor = reactor + 1;
+				reactor
+			},
+			Balance::LeastLoaded => self.registry.least_loaded(),
+		}
+	}
+
+	fn hand_out_client(&mut self, event_loop: &mut EventLoop<Self>, client: Stream, tag: ListenerTag) {
+		let token = self.registry.next_token();
+		let reactor = self.pick_reactor();
+		self.registry.assign(reactor);
+		if reactor == self.reactor {
+			self.adopt_client(event_loop, token, client, tag);
+			return;
+		}
+		let ip = client.peer_addr().map(|addr| addr.ip());
+		/* if the other loop can't take it, serve it here */
+		match self.peers[reactor].send(FiestaMessage::Adopt(token, client, tag)) {
+			Ok(()) => debug!(target: "network", "handed {:?} to reactor {}", token, reactor),
+			Err(NotifyError::Full(FiestaMessage::Adopt(token, client, tag)))
+			| Err(NotifyError::Closed(Some(FiestaMessage::Adopt(token, client, tag)))) => {
+				warn!(target: "network", "reactor {} did not take {:?}", reactor, token);
+				self.registry.unassign(reactor);
+				self.registry.assign(self.reactor);
+				self.adopt_client(event_loop, token, client, tag);
+			},
+			Err(e) => {
+				warn!(target: "network", "could not hand {:?} to reactor {}: {:?}", token, reactor, e);
+				self.registry.unassign(reactor);
+				self.registry.connections().lock().unwrap().remove(ip);
+			},
+		}
+	}
+
+	fn adopt_client(&mut self, event_loop: &mut EventLoop<Self>, token: Token, client: Stream, tag: ListenerTag) {
+		if let Err(e) = event_loop.register_opt(&client, token, EventSet::all(), PollOpt::oneshot()) {
+			warn!(target: "network", "could not register client with {:?}: {:#?}", token, e);
+			let ip = client.peer_addr().map(|addr| addr.ip());
+			self.registry.connections().lock().unwrap().remove(ip);
+			self.registry.unassign(self.reactor);
+			let _ = client.shutdown();
+			return;
+		}
+		let metrics = Some(self.config.metrics.clone());
+		let mut connection = Connection::with_metrics(client, token, tag, Some(event_loop.channel()), metrics);
+		connection.client().set_send_limit(&self.config.send_queue);
+		if self.config.capture.is_some() {
+			connection.client().set_recorder(self.config.capture.clone());
+		}
+		if let Some(ref config) = self.config.rate_limits {
+			connection.rate_limit = Some(RateLimitState::new(config));
+		}
+		if let Some(ref config) = self.config.seed {
+			if config.applies_to(tag) {
+				connection.send_seed(config, config.generate());
+			}
+		}
+		self.registry.insert(connection.client().clone());
+		self.clients.insert(token, connection);
+		self.config.metrics.connected();
+		info!(target: "network", "accepted client with {:?} on listener {}", token, tag);
+	}
+
+	fn attach_connection(&mut self, event_loop: &mut EventLoop<Self>, mut connection: Connection) {
+		let token = connection.id();
+		if let Err(e) = event_loop.register_opt(connection.socket(), token, EventSet::all(), PollOpt::oneshot()) {
+			warn!(target: "network", "could not register connection {:?}: {:#?}", token, e);
+			connection.shutdown();
+			return;
+		}
+		connection.client().set_send_limit(&self.config.send_queue);
+		if self.config.capture.is_some() {
+			connection.client().set_recorder(self.config.capture.clone());
+		}
+		self.registry.assign(self.reactor);
+		self.registry.insert(connection.client().clone());
+		info!(target: "network", "connected {:?} to {:?}", token, connection.client().peer_addr());
+		self.clients.insert(token, connection);
+	}
+
+	fn reject_client(&mut self, mut client: Stream, ip: Option<IpAddr>, reason: LimitExceeded) {
+		info!(target: "network", "rejecting client from {:?}: {:?}", ip, reason);
+		if let ExcessPolicy::NotifyAndClose { header, ref body } = self.config.limits.excess_policy {
+			/* best effort, a freshly accepted socket has an empty send buffer */
+			let notified = try_frame_packet(header, body).and_then(|frame| client.write(&frame[..]));
+			if let Err(e) = notified {
+				debug!(target: "network", "could not notify rejected client: {:#?}", e);
+			}
+		}
+		let _ = client.shutdown();
+	}
+
+	fn pause_accept(&mut self, event_loop: &mut EventLoop<Self>, delay: u64) {
+		if self.accept_paused {
+			return;
+		}
+		if !self.accept_held {
+			self.deregister_listeners(event_loop);
+		}
+		match event_loop.timeout_ms(FiestaTimeout::ResumeAccept, delay) {
+			Ok(_) => self.accept_paused = true,
+			Err(e) => {
+				warn!(target: "network", "could not schedule resuming accepts: {:?}", e);
+				self.resume_accept(event_loop);
+			}
+		}
+	}
+
+	fn resume_accept(&mut self, event_loop: &mut EventLoop<Self>) {
+		self.accept_paused = false;
+		if !self.accept_held {
+			self.register_listeners(event_loop);
+			info!(target: "network", "resumed accepting clients.");
+		}
+	}
+
+	fn hold_accept(&mut self, event_loop: &mut EventLoop<Self>) {
+		if self.accept_held {
+			return;
+		}
+		self.accept_held = true;
+		if !self.accept_paused {
+			self.deregister_listeners(event_loop);
+		}
+		info!(target: "network", "stopped accepting clients.");
+	}
+
+	fn release_accept(&mut self, event_loop: &mut EventLoop<Self>) {
+		if !self.accept_held {
+			return;
+		}
+		self.accept_held = false;
+		if !self.accept_paused {
+			self.register_listeners(event_loop);
+			info!(target: "network", "resumed accepting clients.");
+		}
 	}
 
-	fn get_current_token(&self) -> Token {
-		Token(self.token_count)
+	fn deregister_listeners(&mut self, event_loop: &mut EventLoop<Self>) {
+		for &(ref listener, tag, _) in &self.listeners {
+			if let Err(e) = event_loop.deregister(listener) {
+				warn!(target: "network", "could not deregister listener {}: {:#?}", tag, e);
+			}
+		}
+	}
+
+	fn register_listeners(&mut self, event_loop: &mut EventLoop<Self>) {
+		for &(ref listener, tag, token) in &self.listeners {
+			if let Err(e) = register_listener(event_loop, listener, token) {
+				warn!(target: "network", "could not re-register listener {}: {:#?}", tag, e);
+			}
+		}
 	}
 
 	fn client_ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
 		let mut client_disconnect = false;
-		let mut packets_to_process = Vec::new();
-
-		if events.is_readable() {
-			let client = self.clients.get(&token).unwrap();
-			let client_guard = client.read().unwrap();
-			client_guard.readable(event_loop, token, &mut client_disconnect);
-
-			let mut packet_queue_guard = client_guard.packet_queue.lock().unwrap();
-			while !packet_queue_guard.is_empty() {
-				let packet = packet_queue_guard.pop_front().unwrap();
-				packets_to_process.push(
-					Arc::new(
-						RwLock::new(
-							Box::new(
-								PacketProcessingInfo::new(
-									packet,
-									client.clone())))));
+		let mut received = Vec::new();
+
+		{
+			let connection = match self.clients.get_mut(&token) {
+				Some(connection) => connection,
+				None => return,
+			};
+
+			if events.is_readable() {
+				connection.readable(&mut client_disconnect, self.config.read_budget);
+				while let Some(packet) = connection.pop_packet() {
+					received.push(packet);
+				}
 			}
+
+			if events.is_writable() && !client_disconnect {
+				connection.writeable(&mut client_disconnect);
+			}
+		}
+
+		if !received.is_empty() && !self.dispatch_packets(event_loop, token, received) {
+			client_disconnect = true;
+		}
+
+		/* we need to have this down here, because of borrows.. */
+		if client_disconnect {
+			self.remove_client(event_loop, token);
+		} else {
+			self.reregister_client(event_loop, token);
 		}
+	}
 
-		if events.is_writable() {
-			let client = self.clients.get_mut(&token).unwrap();
-			let guard = client.read().unwrap();
-			guard.writeable(event_loop, token, &mut client_disconnect);
+	fn reregister_client(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+		if let Some(connection) = self.clients.get(&token) {
+			if let Err(e) = event_loop.reregister(connection.socket(), token, connection.interest(), PollOpt::oneshot()) {
+				warn!(target: "network", "could not re-register {:?}: {:#?}", token, e);
+			}
 		}
+	}
 
-		for packet in packets_to_process.into_iter() {
-			self.processor.process_packet(packet);
+	/* applies the rate limits and hands the admitted packets to the processor,
+	 * returns false if the client has been disconnected */
+	fn dispatch_packets(&mut self, event_loop: &mut EventLoop<Self>, token: Token, packets: Vec<FiestaPacket>) -> bool {
+		let connection = match self.clients.get_mut(&token) {
+			Some(connection) => connection,
+			None => return false,
 		};
 
-		/* we need to have this down here, because of borrows.. */
+		let packets = match (self.config.rate_limits.as_ref(), connection.rate_limit.as_mut()) {
+			(Some(config), Some(state)) => {
+				let mut stats = RateLimitStats::default();
+				let admission = state.admit(config, packets, |p| p.header, &mut stats);
+				self.rate_limit_stats.merge(&stats);
+				self.config.metrics.rate_limited(&stats);
+
+				if let Some(after) = admission.retry_after {
+					let delay = after.as_secs() * 1000 + after.subsec_millis() as u64;
+					if let Err(e) = event_loop.timeout_ms(FiestaTimeout::ReleaseDelayed(token), delay.max(1)) {
+						warn!(target: "network", "could not schedule delayed packets of {:?}: {:?}", token, e);
+						state.timer_fired();
+					}
+				}
+				if let Some(reason) = admission.disconnect {
+					info!(target: "network", "disconnecting {:?} for exceeding its rate limit: {}", token, reason);
+					connection.set_disconnect_reason(DisconnectReason::RateLimited);
+					connection.shutdown();
+					return false;
+				}
+				admission.allowed
+			},
+			_ => packets,
+		};
+
+		for packet in packets {
+			trace!(target: "network", "dispatching {:?} from {:?}", packet, token);
+			self.processor.process_packet(
+				Arc::new(
+					RwLock::new(
+						Box::new(
+							PacketProcessingInfo::new(
+								packet,
+								connection.client().clone())))));
+		}
+		true
+	}
+
+	fn release_delayed(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+		match self.clients.get_mut(&token) {
+			Some(connection) => {
+				if let Some(ref mut state) = connection.rate_limit {
+					state.timer_fired();
+				}
+			},
+			/* disconnected in the meantime */
+			None => return,
+		}
+		if !self.dispatch_packets(event_loop, token, Vec::new()) {
+			self.remove_client(event_loop, token);
+		}
+	}
+
+	/* outgoing data was queued from another thread */
+	fn flush_client(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+		let mut client_disconnect = false;
+		match self.clients.get_mut(&token) {
+			Some(connection) => connection.writeable(&mut client_disconnect),
+			None => return,
+		}
 		if client_disconnect {
-			self.clients.remove(&token);
-			info!(target: "network", "client {:?} disconnected.", token);
+			self.remove_client(event_loop, token);
 		} else {
-			/* re-register */
-			let client = self.clients.get(&token).unwrap();
-			let client_borrow = client.read().unwrap();
-			let inner_client_guard = client_borrow.client.lock().unwrap();
-			let interest = client_borrow.interest();
-			event_loop.reregister(&*inner_client_guard, token, interest, PollOpt::oneshot()).unwrap();
+			self.reregister_client(event_loop, token);
 		}
 	}
+
+	fn remove_client(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+		if let Some(mut connection) = self.clients.remove(&token) {
+			let _ = event_loop.deregister(connection.socket());
+			connection.shutdown();
+			self.registry.remove(self.reactor, token);
+			if !connection.is_outbound() {
+				self.registry.connections().lock().unwrap().remove(connection.client().peer_ip());
+				self.config.metrics.disconnected(connection.disconnect_reason().unwrap_or(DisconnectReason::Closed));
+			}
+			info!(target: "network", "client {:?} disconnected.", token);
+		}
+	}
+
+	pub fn metrics(&self) -> &Arc<Metrics> {
+		&self.config.metrics
+	}
+
+	pub fn rate_limit_stats(&self) -> RateLimitStats {
+		self.rate_limit_stats
+	}
 }
 
 impl Handler for FiestaHandler {
-	type Timeout = usize;
-	type Message = ();
+	type Timeout = FiestaTimeout;
+	type Message = FiestaMessage;
 
 	fn ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
-		match token {
-			SERVER_TOKEN 	=> self.server_ready(event_loop, SERVER_TOKEN, events),
-			t 				=> self.client_ready(event_loop, t, events),
+		if self.listener(token).is_some() {
+			self.server_ready(event_loop, token, events);
+		} else if self.http.as_ref().is_some_and(|http| http.owns(token)) {
+			let registry = self.registry.clone();
+			self.http.as_mut().unwrap().ready(event_loop, token, events, &registry);
+		} else if self.admin.as_ref().is_some_and(|admin| admin.owns(token)) {
+			let accept_change = {
+				let context = AdminContext::new(&self.registry, &self.config.metrics, self.is_accepting());
+				self.admin.as_mut().unwrap().ready(event_loop, token, events, &context);
+				context.accept_change()
+			};
+			if let Some(accepting) = accept_change {
+				self.set_accepting(event_loop, accepting);
+			}
+		} else {
+			self.client_ready(event_loop, token, events);
+		}
+	}
+
+	fn notify(&mut self, event_loop: &mut EventLoop<Self>, message: FiestaMessage) {
+		match message {
+			FiestaMessage::Flush(token) => self.flush_client(event_loop, token),
+			FiestaMessage::Close(token) => {
+				/* send whatever is still buffered, then drop the client */
+				let mut client_disconnect = false;
+				if let Some(connection) = self.clients.get_mut(&token) {
+					let reason = if connection.client().overflowed.load(Ordering::Acquire) {
+						DisconnectReason::SendQueueFull
+					} else {
+						DisconnectReason::Closed
+					};
+					connection.set_disconnect_reason(reason);
+					connection.writeable(&mut client_disconnect);
+				}
+				self.remove_client(event_loop, token);
+			},
+			FiestaMessage::Adopt(token, client, tag) => self.adopt_client(event_loop, token, client, tag),
+			FiestaMessage::Attach(connection) => self.attach_connection(event_loop, *connection),
+			FiestaMessage::PauseAccept => self.hold_accept(event_loop),
+			FiestaMessage::ResumeAccept => self.release_accept(event_loop),
+			FiestaMessage::Shutdown => event_loop.shutdown(),
+		}
+	}
+
+	fn timeout(&mut self, event_loop: &mut EventLoop<Self>, timeout: FiestaTimeout) {
+		match timeout {
+			FiestaTimeout::ResumeAccept => self.resume_accept(event_loop),
+			FiestaTimeout::ReleaseDelayed(token) => self.release_delayed(event_loop, token),
+			FiestaTimeout::HttpIdle(token) => {
+				if let Some(ref mut http) = self.http {
+					http.idle(event_loop, token);
+				}
+			},
 		}
 	}
 }
 
 impl FiestaPacket {
-	pub fn new(header: u16, size: usize) -> Self {
+	pub fn new(header: u16, data: SharedBytes) -> Self {
 		FiestaPacket {
-			header:			header,
-			data:			Buffer::with_capacity(size),
+			header,
+			data,
 		}
 	}
-}
\ No newline at end of file
+
+	pub fn from_vec(header: u16, data: Vec<u8>) -> Self {
+		FiestaPacket::new(header, SharedBytes::from_vec(data))
+	}
+
+	/* copies the body into a pooled buffer */
+	pub fn from_slice(header: u16, data: &[u8]) -> Self {
+		FiestaPacket::new(header, SharedBytes::copy_from_slice(data))
+	}
+
+	pub fn cursor(&self) -> ByteCursor<'_> {
+		self.data.cursor()
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		frame_packet(self.header, &self.data[..])
+	}
+}
+
+/* how every event loop polls the listeners of the server. Level triggered,
+ * one readable event accepts one client and the rest of the backlog keeps
+ * the listener readable. */
+pub fn register_listener<H: Handler>(event_loop: &mut EventLoop<H>, listener: &Listener, token: Token) -> Result<(), Error> {
+	event_loop.register_opt(listener, token, EventSet::readable(), PollOpt::level())
+}
+
+/* (body size, length of the size prefix) of a frame starting with `prefix`.
+ * A leading 0 means the size follows as u16, which is also how empty bodies
+ * are sent. */
+fn frame_size_of(prefix: [u8; 3], max_body: usize) -> (u16, usize) {
+	let small_size = prefix[0];
+	if small_size > 0 {
+		(small_size as u16, 1)
+	} else {
+		let mut big_size = ((prefix[1] as u16) << 8) | (prefix[2] as u16);
+
+		if (big_size as usize) > max_body {
+			/* this should never actually happen with real data */
+			/* casting 0 here will still let it read 5 bytes (size + header) */
+			big_size = 0;
+		};
+
+		(big_size, 3)
+	}
+}
+
+/* `frame_size_of` the frame starting `offset` bytes into the buffer */
+fn frame_size_at(buffer: &Buffer, offset: usize, max_body: usize) -> Result<(u16, usize), Error> {
+	let mut prefix = [0; 3];
+	if buffer.copy_out(offset, &mut prefix[..]) < 3 {
+		Err(Error::other("to little data left"))
+	} else {
+		Ok(frame_size_of(prefix, max_body))
+	}
+}
+
+/* the length of the frame starting at `offset`, if it has been received completely */
+fn frame_len_at(buffer: &Buffer, offset: usize, max_body: usize) -> Option<usize> {
+	match frame_size_at(buffer, offset, max_body) {
+		Ok((size, prefix_len)) => {
+			let total_size =
+					size as usize
+				+	2	/* header */
+				+	prefix_len;	/* size data */
+
+			if buffer.bytes_remaining() >= offset + total_size {
+				Some(total_size)
+			} else {
+				None
+			}
+		},
+		Err(_) => None,
+	}
+}
+
+/* decodes up to `max` complete frames from the buffer into `packet_queue`.
+ * The frames are copied out of the ring buffer once, in one piece, and every
+ * packet body is a slice of that copy. Returns the amount of packets. */
+pub fn read_packets(read_buffer: &mut Buffer, packet_queue: &mut LinkedList<FiestaPacket>, max: usize) -> usize {
+	read_packets_bounded(read_buffer, packet_queue, max, MAX_BODY_SIZE)
+}
+
+/* like `read_packets`, with bodies of up to `max_body` bytes */
+pub fn read_packets_bounded(read_buffer: &mut Buffer, packet_queue: &mut LinkedList<FiestaPacket>, max: usize, max_body: usize) -> usize {
+	let mut frames = Vec::new();
+	let mut total = 0;
+	while frames.len() < max {
+		match frame_len_at(read_buffer, total, max_body) {
+			Some(len) => {
+				frames.push((total, len));
+				total += len;
+			},
+			None => break,
+		}
+	}
+	if frames.is_empty() {
+		return 0;
+	}
+
+	let mut batch = pool::take(total);
+	read_buffer.copy_out(0, &mut batch[..total]);
+	read_buffer.advance_read(total);
+	let batch = SharedBytes::from_pooled(batch, total);
+
+	for &(start, len) in &frames {
+		let prefix_len = if batch[start] == 0 { 3 } else { 1 };
+		let header_start = start + prefix_len;
+		let header = ((batch[header_start] as u16) << 8) | (batch[header_start + 1] as u16);
+		packet_queue.push_back(FiestaPacket::new(header, batch.slice(header_start + 2, start + len)));
+	}
+	frames.len()
+}
+
+/* splits whole frames off the front of `bytes` the way `read_packets` does,
+ * yielding (header, body). Stops at the first incomplete frame. */
+pub fn split_frames(bytes: &[u8]) -> FrameIter<'_> {
+	FrameIter {
+		bytes,
+		max_body:	MAX_BODY_SIZE,
+	}
+}
+
+pub struct FrameIter<'a> {
+	bytes:			&'a [u8],
+	max_body:		usize,
+}
+
+impl<'a> FrameIter<'a> {
+	/* see `read_packets_bounded` */
+	pub fn max_body(mut self, max_body: usize) -> Self {
+		self.max_body = max_body;
+		self
+	}
+
+	/* what is left after the complete frames */
+	pub fn rest(&self) -> &'a [u8] {
+		self.bytes
+	}
+}
+
+impl<'a> Iterator for FrameIter<'a> {
+	type Item = (u16, &'a [u8]);
+
+	fn next(&mut self) -> Option<(u16, &'a [u8])> {
+		if self.bytes.len() < 3 {
+			return None;
+		}
+		let (size, prefix_len) = frame_size_of([self.bytes[0], self.bytes[1], self.bytes[2]], self.max_body);
+		let total = prefix_len + 2 + size as usize;
+		if self.bytes.len() < total {
+			return None;
+		}
+		let header = ((self.bytes[prefix_len] as u16) << 8) | (self.bytes[prefix_len + 1] as u16);
+		let body = &self.bytes[prefix_len + 2..total];
+		self.bytes = &self.bytes[total..];
+		Some((header, body))
+	}
+}
+
+/* encodes a packet the same way `read_packets` decodes it, the body must
+ * not be larger than `MAX_BODY_SIZE` */
+pub fn frame_packet(header: u16, body: &[u8]) -> Vec<u8> {
+	assert!(body.len() <= MAX_BODY_SIZE, "packet body of {} bytes, at most {} are read", body.len(), MAX_BODY_SIZE);
+	frame_large_packet(header, body)
+}
+
+/* `frame_packet` for bodies that are not known to fit, e.g. from a config */
+pub fn try_frame_packet(header: u16, body: &[u8]) -> Result<Vec<u8>, Error> {
+	if body.len() > MAX_BODY_SIZE {
+		return Err(Error::new(ErrorKind::InvalidInput, format!("packet body of {} bytes, at most {} are read", body.len(), MAX_BODY_SIZE)));
+	}
+	Ok(frame_large_packet(header, body))
+}
+
+/* `frame_packet` for peers that read larger bodies, see
+ * `Connection::set_max_body`; at most `MAX_WIRE_BODY_SIZE` bytes */
+pub fn frame_large_packet(header: u16, body: &[u8]) -> Vec<u8> {
+	assert!(body.len() <= MAX_WIRE_BODY_SIZE, "packet body of {} bytes does not fit the size prefix", body.len());
+	let mut result = Vec::with_capacity(body.len() + 5);
+	if body.is_empty() || body.len() > 255 {
+		result.push(0);
+		result.push((body.len() >> 8) as u8);
+		result.push(body.len() as u8);
+	} else {
+		result.push(body.len() as u8);
+	}
+	result.push((header >> 8) as u8);
+	result.push(header as u8);
+	result.extend_from_slice(body);
+	result
+}
@@ -0,0 +1,57 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use admin::AdminConfig;
+use capture::PacketRecorder;
+use cipher::SeedConfig;
+use http::IDLE_TIMEOUT_MS;
+use limits::{ConnectionLimits, SendQueueLimit};
+use metrics::Metrics;
+use ratelimit::RateLimitConfig;
+
+/* default amount of bytes read from a single client per readiness event */
+pub const READ_BUDGET: usize = 64 * 1024;
+
+/* settings of a `FiestaHandler` */
+#[derive(Clone)]
+pub struct ServerConfig {
+	pub limits:			ConnectionLimits,
+	/* applied to every client, see `FiestaNetworkClient::set_send_limit` */
+	pub send_queue:		SendQueueLimit,
+	/* `None` disables rate limiting */
+	pub rate_limits:	Option<RateLimitConfig>,
+	/* fairness budget, see `Connection::readable` */
+	pub read_budget:	usize,
+	/* set on every accepted client, see `FiestaNetworkClient::set_recorder` */
+	pub capture:		Option<Arc<PacketRecorder>>,
+	/* shared by all reactors, clones of the config count into the same metrics */
+	pub metrics:		Arc<Metrics>,
+	/* serves `/metrics` for Prometheus on this address, e.g. 127.0.0.1:9100
+	 * to keep it local; `None` disables it */
+	pub metrics_addr:	Option<SocketAddr>,
+	/* connections to it that read or write nothing for this long are closed */
+	pub metrics_idle_timeout:	Duration,
+	/* the admin console, see `admin`; `None` disables it */
+	pub admin:			Option<AdminConfig>,
+	/* sends accepted clients their seed, see `SeedConfig`; `None` leaves it
+	 * to the processor */
+	pub seed:			Option<SeedConfig>,
+}
+
+impl Default for ServerConfig {
+	fn default() -> Self {
+		ServerConfig {
+			limits:			ConnectionLimits::default(),
+			send_queue:		SendQueueLimit::default(),
+			rate_limits:	None,
+			read_budget:	READ_BUDGET,
+			capture:		None,
+			metrics:		Arc::new(Metrics::new()),
+			metrics_addr:	None,
+			metrics_idle_timeout:	Duration::from_millis(IDLE_TIMEOUT_MS),
+			admin:			None,
+			seed:			None,
+		}
+	}
+}
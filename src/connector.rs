@@ -0,0 +1,125 @@
+/* Outbound connections, served by an event loop of their own. What they
+ * receive goes to a `PacketProcessor` like the packets of accepted clients,
+ * and they are written to through the same `FiestaNetworkClient`. */
+use std::io::{self, Error};
+use std::net::{self, SocketAddr};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use mio::*;
+use mio::tcp::TcpStream;
+
+use client::{Connection, FiestaHandler, FiestaMessage, FiestaNetworkClient};
+use config::ServerConfig;
+use metrics::Metrics;
+use processing::PacketProcessor;
+use registry::ClientRegistry;
+use stream::{ListenerTag, Stream};
+
+/* what `FiestaNetworkClient::listener` says for connections made by `connect` */
+pub const OUTBOUND: ListenerTag = ListenerTag("outbound");
+
+/* default for `Connector::connect_timeout` */
+pub const CONNECT_TIMEOUT_MS: u64 = 5000;
+
+pub struct Connector {
+	channel:		Sender<FiestaMessage>,
+	registry:		Arc<ClientRegistry>,
+	metrics:		Arc<Metrics>,
+	thread:			JoinHandle<io::Result<()>>,
+	timeout:		Duration,
+}
+
+impl Connector {
+	/* starts the event loop; `config.limits` do not apply to outbound
+	 * connections, the listeners in it are not bound */
+	pub fn start(processor: Box<dyn PacketProcessor>, config: ServerConfig) -> io::Result<Connector> {
+		let registry = Arc::new(ClientRegistry::new());
+		let metrics = config.metrics.clone();
+		let (channel_tx, channel_rx) = mpsc::channel();
+		let thread = {
+			let registry = registry.clone();
+			(thread::Builder::new()
+				.name("fiesta-connector".to_string())
+				.spawn(move || -> io::Result<()> {
+					let mut event_loop = match EventLoop::new() {
+						Ok(event_loop) => event_loop,
+						Err(e) => {
+							let _ = channel_tx.send(None);
+							return Err(e);
+						},
+					};
+					let _ = channel_tx.send(Some(event_loop.channel()));
+					let mut handler = FiestaHandler::reactor(Vec::new(), processor, config, registry, 0);
+					event_loop.run(&mut handler)
+				}))?
+		};
+		let channel = match channel_rx.recv() {
+			Ok(Some(channel)) => channel,
+			_ => {
+				return match thread.join() {
+					Ok(Err(e)) => Err(e),
+					_ => Err(Error::other("could not create the event loop")),
+				};
+			},
+		};
+		Ok(Connector {
+			channel,
+			registry,
+			metrics,
+			thread,
+			timeout:	Duration::from_millis(CONNECT_TIMEOUT_MS),
+		})
+	}
+
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/* connects to `addr`, blocking the calling thread until the connection is
+	 * established. The client can be sent to right away. */
+	pub fn connect(&self, addr: &SocketAddr) -> io::Result<Arc<FiestaNetworkClient>> {
+		self.connect_tagged(addr, OUTBOUND)
+	}
+
+	/* like `connect`, `tag` tells the processor which kind of link it is */
+	pub fn connect_tagged(&self, addr: &SocketAddr, tag: ListenerTag) -> io::Result<Arc<FiestaNetworkClient>> {
+		let stream = net::TcpStream::connect_timeout(addr, self.timeout)?;
+		stream.set_nonblocking(true)?;
+		let _ = stream.set_nodelay(true);
+		let stream = unsafe { TcpStream::from_raw_fd(stream.into_raw_fd()) };
+
+		let token = self.registry.next_token();
+		let connection = Connection::outbound(Stream::Tcp(stream), token, tag, Some(self.channel.clone()), Some(self.metrics.clone()));
+		let client = connection.client().clone();
+		match self.channel.send(FiestaMessage::Attach(Box::new(connection))) {
+			Ok(()) => Ok(client),
+			Err(e) => Err(Error::other(format!("could not hand {:?} to the event loop: {:?}", token, e))),
+		}
+	}
+
+	/* the connections that are still up */
+	pub fn registry(&self) -> &Arc<ClientRegistry> {
+		&self.registry
+	}
+
+	pub fn metrics(&self) -> &Arc<Metrics> {
+		&self.metrics
+	}
+
+	pub fn shutdown(&self) {
+		if let Err(e) = self.channel.send(FiestaMessage::Shutdown) {
+			warn!(target: "network", "could not stop the connector: {:?}", e);
+		}
+	}
+
+	pub fn join(self) -> io::Result<()> {
+		match self.thread.join() {
+			Ok(result) => result,
+			Err(_) => Err(Error::other("the connector panicked")),
+		}
+	}
+}
@@ -0,0 +1,292 @@
+/* Hex dumps of raw bytes and packets. Opcodes with a registered
+ * `PacketSchema` are annotated with their field names and decoded values.
+ * Tracing is switched on per opcode and logs to the `packets` target. */
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use mio::Token;
+
+use buffer::Buffer;
+use capture::Direction;
+use client::FiestaPacket;
+use opcodes;
+use reader::PacketReader;
+
+/* bytes shown per line of a dump */
+pub const DUMP_WIDTH: usize = 16;
+
+/* the `log` target traced packets go to */
+pub const TRACE_TARGET: &str = "packets";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+	U8,
+	I8,
+	Bool,
+	U16,
+	I16,
+	U32,
+	I32,
+	U64,
+	I64,
+	/* a fixed size, zero padded string */
+	String(usize),
+	Bytes(usize),
+	/* whatever is left of the body */
+	Rest,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+	pub name:			&'static str,
+	pub kind:			FieldKind,
+}
+
+/* the layout of a packet body, fields in wire order */
+#[derive(Debug, Clone)]
+pub struct PacketSchema {
+	pub name:			&'static str,
+	pub fields:			Vec<Field>,
+}
+
+/* a decoded field of a dumped packet */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+	pub name:			&'static str,
+	pub offset:			usize,
+	pub size:			usize,
+	pub value:			String,
+}
+
+static SCHEMAS: RwLock<Option<HashMap<u16, Arc<PacketSchema>>>> = RwLock::new(None);
+static TRACED: RwLock<Option<HashSet<u16>>> = RwLock::new(None);
+/* spares the lock on every packet while nothing is traced */
+static TRACING: AtomicBool = AtomicBool::new(false);
+static TRACE_ALL: AtomicBool = AtomicBool::new(false);
+
+impl PacketSchema {
+	pub fn new(name: &'static str) -> Self {
+		PacketSchema {
+			name,
+			fields:		Vec::new(),
+		}
+	}
+
+	pub fn field(mut self, name: &'static str, kind: FieldKind) -> Self {
+		self.fields.push(Field { name, kind });
+		self
+	}
+
+	/* decodes the body field by field, stopping at the first field that does
+	 * not fit; that one is annotated with the error */
+	pub fn annotate(&self, opcode: u16, body: &[u8]) -> Vec<Annotation> {
+		let mut reader = PacketReader::from_slice(opcode, body);
+		let mut annotations = Vec::with_capacity(self.fields.len());
+		for field in &self.fields {
+			let offset = reader.position();
+			let value = match field.kind {
+				FieldKind::U8 => reader.read_u8().map(|v| v.to_string()),
+				FieldKind::I8 => reader.read_i8().map(|v| v.to_string()),
+				FieldKind::Bool => reader.read_bool().map(|v| v.to_string()),
+				FieldKind::U16 => reader.read_u16().map(|v| format!("{} (0x{:04X})", v, v)),
+				FieldKind::I16 => reader.read_i16().map(|v| v.to_string()),
+				FieldKind::U32 => reader.read_u32().map(|v| format!("{} (0x{:08X})", v, v)),
+				FieldKind::I32 => reader.read_i32().map(|v| v.to_string()),
+				FieldKind::U64 => reader.read_u64().map(|v| v.to_string()),
+				FieldKind::I64 => reader.read_i64().map(|v| v.to_string()),
+				FieldKind::String(size) => reader.read_string(size).map(|v| format!("{:?}", v)),
+				FieldKind::Bytes(size) => reader.read_bytes(size).map(hex_bytes),
+				FieldKind::Rest => {
+					let remaining = reader.remaining();
+					reader.read_bytes(remaining).map(hex_bytes)
+				},
+			};
+			let failed = value.is_err();
+			annotations.push(Annotation {
+				name:		field.name,
+				offset,
+				size:		reader.position() - offset,
+				value:		value.unwrap_or_else(|e| format!("<{}>", e.kind)),
+			});
+			if failed {
+				break;
+			}
+		}
+		annotations
+	}
+}
+
+/* registers the schema used to annotate dumps of `opcode`, replacing any
+ * earlier one */
+pub fn register_schema(opcode: u16, schema: PacketSchema) {
+	let mut schemas = SCHEMAS.write().unwrap();
+	schemas.get_or_insert_with(HashMap::new).insert(opcode, Arc::new(schema));
+}
+
+pub fn schema(opcode: u16) -> Option<Arc<PacketSchema>> {
+	let schemas = SCHEMAS.read().unwrap();
+	schemas.as_ref().and_then(|schemas| schemas.get(&opcode).cloned())
+}
+
+/* a classic offset / hex / ASCII dump, one line per 16 bytes:
+ *
+ *     0000  48 65 6c 6c 6f 00 01 02  03 04 05 06 07 08 09 0a  Hello...........
+ */
+pub fn hex_dump(data: &[u8]) -> String {
+	let mut out = String::with_capacity((data.len() / DUMP_WIDTH + 1) * 76);
+	for (line, chunk) in data.chunks(DUMP_WIDTH).enumerate() {
+		if line > 0 {
+			out.push('\n');
+		}
+		let _ = write!(out, "{:04x} ", line * DUMP_WIDTH);
+		for i in 0..DUMP_WIDTH {
+			if i == DUMP_WIDTH / 2 {
+				out.push(' ');
+			}
+			match chunk.get(i) {
+				Some(byte) => { let _ = write!(out, " {:02x}", byte); },
+				None => out.push_str("   "),
+			}
+		}
+		out.push_str("  ");
+		for byte in chunk {
+			out.push(if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' });
+		}
+	}
+	out
+}
+
+/* the unread contents of a buffer, without consuming them */
+pub fn buffer_dump(buffer: &Buffer) -> String {
+	let (first, second) = buffer.as_slices();
+	if second.is_empty() {
+		hex_dump(first)
+	} else {
+		let mut data = Vec::with_capacity(first.len() + second.len());
+		data.extend_from_slice(first);
+		data.extend_from_slice(second);
+		hex_dump(&data[..])
+	}
+}
+
+/* a header line, the annotated fields if the opcode has a schema, then the
+ * hex dump of the body:
+ *
+ *     0x0C01 NC_USER_LOGIN_REQ, 20 bytes
+ *       0000  name        "admin"
+ *       0010  password    ...
+ *     0000  61 64 6d 69 6e 00 ...
+ */
+pub fn pretty_packet(opcode: u16, body: &[u8]) -> String {
+	let mut out = String::new();
+	let schema = schema(opcode);
+	let _ = write!(out, "0x{:04X}", opcode);
+	if let Some(name) = packet_name(opcode, schema.as_deref()) {
+		let _ = write!(out, " {}", name);
+	}
+	let _ = write!(out, ", {} bytes", body.len());
+	if let Some(ref schema) = schema {
+		let annotations = schema.annotate(opcode, body);
+		let width = annotations.iter().map(|a| a.name.len()).max().unwrap_or(0);
+		let mut end = 0;
+		for annotation in &annotations {
+			let _ = write!(out, "\n  {:04x}  {:width$}  {}", annotation.offset, annotation.name, annotation.value, width = width);
+			end = annotation.offset + annotation.size;
+		}
+		if end < body.len() && schema.fields.len() == annotations.len() {
+			let _ = write!(out, "\n  {:04x}  {} bytes not covered by the schema", end, body.len() - end);
+		}
+	}
+	if !body.is_empty() {
+		out.push('\n');
+		out.push_str(&hex_dump(body));
+	}
+	out
+}
+
+/* the name from `opcodes`, or else the one of the schema */
+fn packet_name(opcode: u16, schema: Option<&PacketSchema>) -> Option<&'static str> {
+	opcodes::name(opcode).or_else(|| schema.map(|schema| schema.name))
+}
+
+/* bytes written as hex digits without separators, e.g. "0a0b" */
+pub fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+	if !value.is_ascii() || !value.len().is_multiple_of(2) {
+		return Err(format!("not hex: {}", value));
+	}
+	(0..value.len()).step_by(2)
+		.map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| format!("not hex: {}", value)))
+		.collect()
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+	let mut out = String::with_capacity(bytes.len() * 3);
+	for (i, byte) in bytes.iter().enumerate() {
+		if i > 0 {
+			out.push(' ');
+		}
+		let _ = write!(out, "{:02x}", byte);
+	}
+	out
+}
+
+/* logs packets with `opcode` to `TRACE_TARGET`, or stops doing so */
+pub fn trace_opcode(opcode: u16, enabled: bool) {
+	let mut traced = TRACED.write().unwrap();
+	let traced = traced.get_or_insert_with(HashSet::new);
+	if enabled {
+		traced.insert(opcode);
+	} else {
+		traced.remove(&opcode);
+	}
+	TRACING.store(!traced.is_empty() || TRACE_ALL.load(Ordering::Relaxed), Ordering::Release);
+}
+
+/* logs every packet, on top of the opcodes switched on one by one */
+pub fn trace_all(enabled: bool) {
+	let traced = TRACED.read().unwrap();
+	TRACE_ALL.store(enabled, Ordering::Relaxed);
+	TRACING.store(enabled || traced.as_ref().is_some_and(|t| !t.is_empty()), Ordering::Release);
+}
+
+/* whether any opcode is traced at all, a cheap check for the hot paths */
+pub fn is_tracing() -> bool {
+	TRACING.load(Ordering::Acquire)
+}
+
+pub fn is_traced(opcode: u16) -> bool {
+	if !is_tracing() {
+		return false;
+	}
+	TRACE_ALL.load(Ordering::Relaxed) || TRACED.read().unwrap().as_ref().is_some_and(|t| t.contains(&opcode))
+}
+
+/* logs a packet received from or sent to `token` if its opcode is traced */
+pub fn trace_packet(token: Token, direction: Direction, opcode: u16, body: &[u8]) {
+	if is_traced(opcode) {
+		log_packet(token, direction, opcode, body);
+	}
+}
+
+/* logs a packet to `TRACE_TARGET` whether its opcode is traced or not */
+pub fn log_packet(token: Token, direction: Direction, opcode: u16, body: &[u8]) {
+	let arrow = match direction {
+		Direction::Inbound => "<-",
+		Direction::Outbound => "->",
+	};
+	info!(target: TRACE_TARGET, "{:?} {} {}", token, arrow, pretty_packet(opcode, body));
+}
+
+/* `{:?}` is a one line summary, `{:#?}` the full `pretty_packet` dump */
+impl fmt::Debug for FiestaPacket {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if f.alternate() {
+			return f.write_str(&pretty_packet(self.header, &self.data[..]));
+		}
+		match packet_name(self.header, schema(self.header).as_deref()) {
+			Some(name) => write!(f, "FiestaPacket(0x{:04X} {}, {} bytes)", self.header, name, self.data.len()),
+			None => write!(f, "FiestaPacket(0x{:04X}, {} bytes)", self.header, self.data.len()),
+		}
+	}
+}
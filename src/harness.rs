@@ -0,0 +1,153 @@
+use std::collections::{LinkedList, VecDeque};
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use mio::Token;
+
+use buffer::Buffer;
+use client::{frame_packet, read_packets, Connection, FiestaNetworkClient, FiestaPacket};
+use memory::{self, MemoryStream};
+use processing::{PacketProcessingInfo, PacketProcessor};
+use stream::{ListenerTag, Stream, DEFAULT_LISTENER};
+
+/* runs a `PacketProcessor` against a fake client over a `MemoryStream`.
+ * Nothing happens in the background: `run` reads what was injected, hands
+ * the packets to the processor and writes its answers, on the calling thread.
+ *
+ *     let mut harness = Harness::new(Box::new(MyProcessor::new()));
+ *     harness.inject(0x0C01, &[1, 2]);
+ *     harness.run();
+ *     harness.assert_sent(0x0C02, &[0]);
+ */
+pub struct Harness {
+	connection:		Connection,
+	remote:			MemoryStream,
+	processor:		Box<dyn PacketProcessor>,
+	/* answers that were written but not looked at yet */
+	sent:			Buffer,
+	sent_packets:	VecDeque<FiestaPacket>,
+	disconnected:	bool,
+}
+
+impl Harness {
+	pub fn new(processor: Box<dyn PacketProcessor>) -> Self {
+		Harness::on_listener(processor, DEFAULT_LISTENER)
+	}
+
+	/* the client looks like it connected through `listener` */
+	pub fn on_listener(processor: Box<dyn PacketProcessor>, listener: ListenerTag) -> Self {
+		let (local, remote) = memory::pair();
+		Harness {
+			connection:		Connection::with_listener(Stream::Memory(local), Token(1), listener, None),
+			remote,
+			processor,
+			sent:			Buffer::with_capacity(64 * 1024),
+			sent_packets:	VecDeque::new(),
+			disconnected:	false,
+		}
+	}
+
+	pub fn client(&self) -> &Arc<FiestaNetworkClient> {
+		self.connection.client()
+	}
+
+	/* raw bytes as the client would send them, need not be whole frames.
+	 * After a disconnect they are dropped. */
+	pub fn inject_bytes(&mut self, bytes: &[u8]) {
+		if self.disconnected {
+			return;
+		}
+		self.remote.write_all(bytes).unwrap();
+	}
+
+	pub fn inject(&mut self, header: u16, body: &[u8]) {
+		self.inject_bytes(&frame_packet(header, body)[..]);
+	}
+
+	pub fn inject_packet<P: Into<FiestaPacket>>(&mut self, packet: P) {
+		let packet = packet.into();
+		self.inject_bytes(&packet.to_bytes()[..]);
+	}
+
+	/* the client closes its end, the next `run` sees the disconnect */
+	pub fn hang_up(&mut self) {
+		self.remote.shutdown();
+	}
+
+	/* reads everything injected so far, processes the complete packets and
+	 * writes what the processor sent. Returns the amount of packets processed. */
+	pub fn run(&mut self) -> usize {
+		if self.disconnected {
+			return 0;
+		}
+		let mut disconnect = false;
+		let mut processed = 0;
+		self.connection.readable(&mut disconnect, usize::MAX);
+		while let Some(packet) = self.connection.pop_packet() {
+			let info = PacketProcessingInfo::new(packet, self.connection.client().clone());
+			self.processor.process_packet(Arc::new(RwLock::new(Box::new(info))));
+			processed += 1;
+		}
+		if !disconnect {
+			/* like the event loop, a closed client still gets its buffered data */
+			self.connection.writeable(&mut disconnect);
+		}
+		if disconnect || !self.connection.client().alive() {
+			self.connection.shutdown();
+			self.disconnected = true;
+		}
+		processed
+	}
+
+	/* whether the client was disconnected, by the processor or `hang_up` */
+	pub fn is_disconnected(&self) -> bool {
+		self.disconnected
+	}
+
+	/* the raw bytes sent to the client since the last call; these are not
+	 * seen by `next_sent` and the other packet based methods */
+	pub fn sent_bytes(&mut self) -> Vec<u8> {
+		self.remote.read_available()
+	}
+
+	fn collect_sent(&mut self) {
+		let mut bytes = &self.remote.read_available()[..];
+		let mut packets = LinkedList::new();
+		while !bytes.is_empty() {
+			let written = self.sent.write_some(bytes);
+			bytes = &bytes[written..];
+			read_packets(&mut self.sent, &mut packets, usize::MAX);
+		}
+		self.sent_packets.extend(packets);
+	}
+
+	/* the oldest packet sent to the client that was not looked at yet */
+	pub fn next_sent(&mut self) -> Option<FiestaPacket> {
+		self.collect_sent();
+		self.sent_packets.pop_front()
+	}
+
+	/* all packets sent to the client that were not looked at yet */
+	pub fn sent_packets(&mut self) -> Vec<FiestaPacket> {
+		self.collect_sent();
+		self.sent_packets.drain(..).collect()
+	}
+
+	/* panics unless the next packet sent to the client is `header` with `body` */
+	pub fn assert_sent(&mut self, header: u16, body: &[u8]) {
+		match self.next_sent() {
+			Some(packet) => {
+				assert!(packet.header == header,
+					"expected packet 0x{:04X}, got 0x{:04X}", header, packet.header);
+				assert!(&packet.data[..] == body,
+					"packet 0x{:04X}: expected body {:?}, got {:?}", header, body, &packet.data[..]);
+			},
+			None => panic!("expected packet 0x{:04X}, nothing was sent", header),
+		}
+	}
+
+	pub fn assert_nothing_sent(&mut self) {
+		if let Some(packet) = self.next_sent() {
+			panic!("expected nothing to be sent, got packet 0x{:04X} {:?}", packet.header, &packet.data[..]);
+		}
+	}
+}
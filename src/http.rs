@@ -0,0 +1,240 @@
+/* A tiny HTTP server for Prometheus, registered in the event loop of a
+ * `FiestaHandler`. It answers `GET /metrics` and closes every connection
+ * after one response, or once it was idle for too long. */
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use mio::*;
+use mio::tcp::*;
+
+use client::FiestaTimeout;
+use metrics::Metrics;
+use registry::ClientRegistry;
+
+/* scrapers send small requests, anything bigger is refused */
+const MAX_REQUEST: usize = 8 * 1024;
+/* connections served at the same time, more are closed right away */
+const MAX_CONNECTIONS: usize = 16;
+
+pub const METRICS_PATH: &str = "/metrics";
+/* how long a connection may go without reading or writing anything, so
+ * stalled scrapers do not hold on to `MAX_CONNECTIONS` */
+pub const IDLE_TIMEOUT_MS: u64 = 10_000;
+
+struct HttpConnection {
+	socket:			TcpStream,
+	request:		Vec<u8>,
+	/* the response and how much of it was written */
+	response:		Option<(Vec<u8>, usize)>,
+	/* closes the connection unless it makes progress before */
+	idle:			Option<Timeout>,
+}
+
+pub struct MetricsEndpoint {
+	listener:		TcpListener,
+	token:			Token,
+	metrics:		Arc<Metrics>,
+	connections:	HashMap<Token, HttpConnection>,
+	idle_timeout:	Duration,
+}
+
+impl MetricsEndpoint {
+	pub fn new(listener: TcpListener, token: Token, metrics: Arc<Metrics>) -> Self {
+		MetricsEndpoint {
+			listener,
+			token,
+			metrics,
+			connections:	HashMap::new(),
+			idle_timeout:	Duration::from_millis(IDLE_TIMEOUT_MS),
+		}
+	}
+
+	pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+		self.idle_timeout = timeout;
+		self
+	}
+
+	pub fn register<H: Handler>(&self, event_loop: &mut EventLoop<H>) -> ::std::io::Result<()> {
+		event_loop.register_opt(&self.listener, self.token, EventSet::readable(), PollOpt::level())
+	}
+
+	pub fn token(&self) -> Token {
+		self.token
+	}
+
+	/* whether `token` is the listener or one of the connections */
+	pub fn owns(&self, token: Token) -> bool {
+		token == self.token || self.connections.contains_key(&token)
+	}
+
+	/* connection tokens come from `registry` so they never collide with a client's */
+	pub fn ready<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token, events: EventSet, registry: &ClientRegistry)
+			where H: Handler<Timeout = FiestaTimeout> {
+		if token == self.token {
+			self.accept(event_loop, registry);
+			return;
+		}
+		let done = match self.connections.get_mut(&token) {
+			Some(connection) => connection.ready(events, &self.metrics),
+			None => return,
+		};
+		if done {
+			self.close(event_loop, token);
+			return;
+		}
+		self.restart_idle(event_loop, token);
+		if let Some(connection) = self.connections.get(&token) {
+			let interest = if connection.response.is_some() { EventSet::writable() } else { EventSet::readable() };
+			if let Err(e) = event_loop.reregister(&connection.socket, token, interest, PollOpt::oneshot()) {
+				warn!(target: "http", "could not re-register {:?}: {:#?}", token, e);
+			}
+		}
+	}
+
+	/* `FiestaTimeout::HttpIdle` fired for `token` */
+	pub fn idle<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, token: Token) {
+		if let Some(connection) = self.connections.get_mut(&token) {
+			connection.idle = None;
+			debug!(target: "http", "{:?} was idle for {:?}, closing", token, self.idle_timeout);
+		}
+		self.close(event_loop, token);
+	}
+
+	fn restart_idle<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token)
+			where H: Handler<Timeout = FiestaTimeout> {
+		let connection = match self.connections.get_mut(&token) {
+			Some(connection) => connection,
+			None => return,
+		};
+		if let Some(idle) = connection.idle.take() {
+			event_loop.clear_timeout(idle);
+		}
+		let delay = self.idle_timeout.as_secs() * 1000 + self.idle_timeout.subsec_millis() as u64;
+		match event_loop.timeout_ms(FiestaTimeout::HttpIdle(token), delay.max(1)) {
+			Ok(idle) => connection.idle = Some(idle),
+			Err(e) => warn!(target: "http", "could not set the idle timeout of {:?}: {:?}", token, e),
+		}
+	}
+
+	fn accept<H>(&mut self, event_loop: &mut EventLoop<H>, registry: &ClientRegistry)
+			where H: Handler<Timeout = FiestaTimeout> {
+		let socket = match self.listener.accept() {
+			Ok(Some(socket)) => socket,
+			Ok(None) => return,
+			Err(e) => {
+				warn!(target: "http", "error while accepting: {:#?}", e);
+				return;
+			},
+		};
+		if self.connections.len() >= MAX_CONNECTIONS {
+			debug!(target: "http", "too many connections, closing");
+			let _ = socket.shutdown(Shutdown::Both);
+			return;
+		}
+		let token = registry.next_token();
+		if let Err(e) = event_loop.register_opt(&socket, token, EventSet::readable(), PollOpt::oneshot()) {
+			warn!(target: "http", "could not register {:?}: {:#?}", token, e);
+			return;
+		}
+		self.connections.insert(token, HttpConnection {
+			socket,
+			request:	Vec::new(),
+			response:	None,
+			idle:		None,
+		});
+		self.restart_idle(event_loop, token);
+	}
+
+	fn close<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, token: Token) {
+		if let Some(connection) = self.connections.remove(&token) {
+			if let Some(idle) = connection.idle {
+				event_loop.clear_timeout(idle);
+			}
+			let _ = event_loop.deregister(&connection.socket);
+			let _ = connection.socket.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+impl HttpConnection {
+	/* returns true once the connection is done with */
+	fn ready(&mut self, events: EventSet, metrics: &Metrics) -> bool {
+		if events.is_error() {
+			return true;
+		}
+		if self.response.is_none() {
+			if !self.read() {
+				return true;
+			}
+			if let Some(response) = self.respond(metrics) {
+				self.response = Some((response, 0));
+			}
+		}
+		match self.response {
+			Some(_) => self.write(),
+			None => events.is_hup(),
+		}
+	}
+
+	/* false if the client went away */
+	fn read(&mut self) -> bool {
+		let mut buf = [0; 1024];
+		loop {
+			match self.socket.read(&mut buf[..]) {
+				Ok(0) => return false,
+				Ok(size) => {
+					self.request.extend_from_slice(&buf[..size]);
+					if self.request.len() > MAX_REQUEST {
+						return true;
+					}
+				},
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(_) => return false,
+			}
+		}
+	}
+
+	/* `None` while the request is not complete yet */
+	fn respond(&self, metrics: &Metrics) -> Option<Vec<u8>> {
+		if self.request.len() > MAX_REQUEST {
+			return Some(response("431 Request Header Fields Too Large", "text/plain", b"request too large\n"));
+		}
+		let end = self.request.windows(4).position(|w| w == b"\r\n\r\n")?;
+		let head = String::from_utf8_lossy(&self.request[..end]);
+		let mut parts = head.lines().next().unwrap_or("").split(' ');
+		let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+		let path = target.split('?').next().unwrap_or("");
+		Some(match (method, path) {
+			("GET", METRICS_PATH) => {
+				let body = metrics.snapshot().to_prometheus();
+				response("200 OK", "text/plain; version=0.0.4; charset=utf-8", body.as_bytes())
+			},
+			(_, METRICS_PATH) => response("405 Method Not Allowed", "text/plain", b"only GET is supported\n"),
+			_ => response("404 Not Found", "text/plain", b"not found\n"),
+		})
+	}
+
+	/* true once everything was written */
+	fn write(&mut self) -> bool {
+		let (ref data, ref mut written) = *self.response.as_mut().unwrap();
+		while *written < data.len() {
+			match self.socket.write(&data[*written..]) {
+				Ok(0) => return true,
+				Ok(size) => *written += size,
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => return false,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(_) => return true,
+			}
+		}
+		true
+	}
+}
+
+fn response(status: &str, content_type: &str, body: &[u8]) -> Vec<u8> {
+	let mut response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+		status, content_type, body.len()).into_bytes();
+	response.extend_from_slice(body);
+	response
+}
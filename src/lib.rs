@@ -1,15 +1,48 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+/* `stream` borrows sockets through their raw file descriptors, and Unix
+ * sockets are part of `Stream` itself */
+#[cfg(not(unix))]
+compile_error!("fiesta-net only supports unix platforms");
+
 #[macro_use]
 extern crate log;
 extern crate mio;
 extern crate chan;
 extern crate threadpool;
+extern crate libc;
 
-mod buffer;
-mod client;
-mod processing;
+pub mod admin;
+pub mod buffer;
+pub mod capture;
+pub mod cipher;
+pub mod client;
+pub mod config;
+pub mod connector;
+pub mod dump;
+pub mod harness;
+pub mod http;
+pub mod limits;
+pub mod links;
+pub mod memory;
+pub mod metrics;
+pub mod opcodes;
+pub mod packets;
+pub mod pool;
+pub mod processing;
+pub mod proxy;
+pub mod ratelimit;
+pub mod reader;
+pub mod registry;
+pub mod replay;
+pub mod rpc;
+pub mod schema;
+pub mod server;
+pub mod shared;
+pub mod stream;
+pub mod tickets;
+pub mod writer;
 
 #[test]
 fn it_works() {
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::net::IpAddr;
+
+use client::MAX_BODY_SIZE;
+
+/* default for `SendQueueLimit::max_bytes` */
+pub const SEND_QUEUE_LIMIT: usize = 1024 * 1024;
+
+/* what to do with a connection that would exceed one of the limits */
+#[derive(Clone)]
+pub enum ExcessPolicy {
+	/* close the socket right after accepting it */
+	Refuse,
+	/* send a single packet (e.g. "server full") and close afterwards */
+	NotifyAndClose {
+		header:		u16,
+		body:		Vec<u8>,
+	},
+}
+
+#[derive(Clone)]
+pub struct ConnectionLimits {
+	/* maximum amount of connected clients, `None` for no limit */
+	pub max_connections:	Option<usize>,
+	/* maximum amount of connected clients per remote address */
+	pub max_per_ip:			Option<usize>,
+	pub excess_policy:		ExcessPolicy,
+	/* how long accepting is paused after an accept error (e.g. EMFILE) */
+	pub accept_pause_ms:	u64,
+}
+
+/* what `append_send` does with data that does not fit into the send queue */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOverflow {
+	/* drop the data, the client stays connected */
+	Drop,
+	/* drop the data and disconnect the client */
+	Disconnect,
+}
+
+/* bounds the bytes queued for a client that have not been written yet, e.g.
+ * because it stopped reading */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendQueueLimit {
+	/* `None` for no limit */
+	pub max_bytes:		Option<usize>,
+	pub overflow:		SendOverflow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitExceeded {
+	MaxConnections,
+	MaxPerIp,
+}
+
+/* keeps track of how many clients are connected, in total and per address */
+pub struct ConnectionTracker {
+	total:			usize,
+	per_ip:			HashMap<IpAddr, usize>,
+}
+
+impl ConnectionLimits {
+	/* a rejected client could not read a larger `NotifyAndClose` body */
+	pub fn validate(&self) -> io::Result<()> {
+		match self.excess_policy {
+			ExcessPolicy::NotifyAndClose { ref body, .. } if body.len() > MAX_BODY_SIZE => Err(Error::new(ErrorKind::InvalidInput,
+				format!("the excess notification has {} bytes, at most {} are read", body.len(), MAX_BODY_SIZE))),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl Default for ConnectionLimits {
+	fn default() -> Self {
+		ConnectionLimits {
+			max_connections:	None,
+			max_per_ip:			None,
+			excess_policy:		ExcessPolicy::Refuse,
+			accept_pause_ms:	1000,
+		}
+	}
+}
+
+impl Default for SendQueueLimit {
+	fn default() -> Self {
+		SendQueueLimit {
+			max_bytes:		Some(SEND_QUEUE_LIMIT),
+			overflow:		SendOverflow::Disconnect,
+		}
+	}
+}
+
+impl ConnectionTracker {
+	pub fn new() -> Self {
+		ConnectionTracker {
+			total:		0,
+			per_ip:		HashMap::new(),
+		}
+	}
+
+	pub fn total(&self) -> usize {
+		self.total
+	}
+
+	pub fn connections_from(&self, ip: &IpAddr) -> usize {
+		match self.per_ip.get(ip) {
+			Some(count) => *count,
+			None => 0,
+		}
+	}
+
+	/* checks whether one more connection from `ip` would still be within `limits` */
+	pub fn check(&self, limits: &ConnectionLimits, ip: Option<IpAddr>) -> Result<(), LimitExceeded> {
+		if let Some(max) = limits.max_connections {
+			if self.total >= max {
+				return Err(LimitExceeded::MaxConnections);
+			}
+		}
+		if let (Some(max), Some(ip)) = (limits.max_per_ip, ip) {
+			if self.connections_from(&ip) >= max {
+				return Err(LimitExceeded::MaxPerIp);
+			}
+		}
+		Ok(())
+	}
+
+	pub fn add(&mut self, ip: Option<IpAddr>) {
+		self.total += 1;
+		if let Some(ip) = ip {
+			*self.per_ip.entry(ip).or_insert(0) += 1;
+		}
+	}
+
+	pub fn remove(&mut self, ip: Option<IpAddr>) {
+		if self.total > 0 {
+			self.total -= 1;
+		}
+		if let Some(ip) = ip {
+			let now_empty = match self.per_ip.get_mut(&ip) {
+				Some(count) => {
+					*count -= 1;
+					*count == 0
+				},
+				None => false,
+			};
+			if now_empty {
+				self.per_ip.remove(&ip);
+			}
+		}
+	}
+}
+
+impl Default for ConnectionTracker {
+	fn default() -> Self {
+		ConnectionTracker::new()
+	}
+}
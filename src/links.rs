@@ -0,0 +1,467 @@
+/* Named links to other servers that are kept up: made with a `Connector`
+ * and made again whenever they go down, so a zone server survives restarts
+ * of its world server:
+ *
+ *     let links = (LinkManager::new(connector)
+ *         .link(Link::new("world", world_addr).handshake(|link| announce(link)))
+ *         .start())?;
+ *     let events = links.subscribe();
+ *     links.send("world", &frame)?;
+ *
+ * Failed connects are retried after `Backoff` delays, and so is a link that
+ * went down before it was up for `STABLE_UPTIME_MS`. Every link connects on a
+ * thread of its own, a peer that does not answer holds up no other link. The
+ * handshake runs on every (re)connect, the link only counts as up once it
+ * succeeded. What is sent while a link is down is queued or rejected, see
+ * `WhenDown`. */
+use std::collections::{HashMap, VecDeque};
+use std::error;
+use std::fmt;
+use std::io::{self, Error};
+use std::net::SocketAddr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use cipher::random_seed;
+use client::FiestaNetworkClient;
+use connector::{Connector, OUTBOUND};
+use stream::ListenerTag;
+
+/* how often the manager checks whether its links are still up */
+pub const CHECK_INTERVAL_MS: u64 = 50;
+
+/* a link that went down before it was up this long counts as a failed
+ * connect, a peer that accepts and closes right away is not hammered */
+pub const STABLE_UPTIME_MS: u64 = 5000;
+
+/* the delay before the next connect after `failures` failed ones in a row:
+ * `initial`, multiplied by `factor` for every further failure up to `max`.
+ * Up to `jitter` of it is taken off at random, so servers that lost the same
+ * peer do not all come back at once. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+	pub initial:		Duration,
+	pub max:			Duration,
+	pub factor:			u32,
+	/* between 0 and 1 */
+	pub jitter:			f64,
+}
+
+/* what `RunningLinks::send` does while the link is down */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhenDown {
+	/* keeps up to this many frames and sends them once the link is back up */
+	Queue(usize),
+	Reject,
+}
+
+#[derive(Clone)]
+pub enum LinkEvent {
+	/* connected and through the handshake */
+	Up {
+		name:		String,
+		link:		Arc<FiestaNetworkClient>,
+	},
+	Down {
+		name:		String,
+	},
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+	/* no link of that name */
+	Unknown(String),
+	/* the link is down and `WhenDown::Reject` */
+	Down(String),
+	/* the link is down and its queue is full */
+	QueueFull(String),
+}
+
+/* runs on every (re)connect before the link counts as up; an error closes
+ * the connection and counts as a failed connect */
+pub type Handshake = dyn Fn(&Arc<FiestaNetworkClient>) -> io::Result<()> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Link {
+	name:			String,
+	addr:			SocketAddr,
+	tag:			ListenerTag,
+	handshake:		Option<Arc<Handshake>>,
+}
+
+pub struct LinkManager {
+	connector:		Connector,
+	links:			Vec<Link>,
+	backoff:		Backoff,
+	when_down:		WhenDown,
+}
+
+/* a started manager, it connects on a thread of its own */
+pub struct RunningLinks {
+	state:			Arc<LinksState>,
+	thread:			JoinHandle<io::Result<()>>,
+}
+
+struct LinksState {
+	links:			Mutex<HashMap<String, LinkState>>,
+	/* signalled when there is something for the manager thread to do */
+	wake:			Condvar,
+	subscribers:	Mutex<Vec<Sender<LinkEvent>>>,
+	backoff:		Backoff,
+	when_down:		WhenDown,
+	running:		AtomicBool,
+}
+
+struct LinkState {
+	link:			Link,
+	client:			Option<Arc<FiestaNetworkClient>>,
+	/* failed connects in a row, including links that did not stay up */
+	failures:		u32,
+	retry_at:		Instant,
+	/* a connect is in flight */
+	connecting:		bool,
+	queue:			VecDeque<Vec<u8>>,
+}
+
+impl Backoff {
+	pub fn delay(&self, failures: u32) -> Duration {
+		let mut delay = self.initial;
+		for _ in 1..failures {
+			if delay >= self.max {
+				break;
+			}
+			delay = delay.checked_mul(self.factor).unwrap_or(self.max);
+		}
+		let delay = delay.min(self.max);
+		let jitter = self.jitter.clamp(0.0, 1.0) * random_seed(1 << 16) as f64 / (1 << 16) as f64;
+		delay.mul_f64(1.0 - jitter)
+	}
+}
+
+impl Default for Backoff {
+	fn default() -> Self {
+		Backoff {
+			initial:	Duration::from_millis(100),
+			max:		Duration::from_secs(30),
+			factor:		2,
+			jitter:		0.5,
+		}
+	}
+}
+
+impl Link {
+	/* the connection is tagged `OUTBOUND` */
+	pub fn new<S: Into<String>>(name: S, addr: SocketAddr) -> Self {
+		Link {
+			name:		name.into(),
+			addr,
+			tag:		OUTBOUND,
+			handshake:	None,
+		}
+	}
+
+	/* tells the processor which kind of link a connection is */
+	pub fn tag(mut self, tag: ListenerTag) -> Self {
+		self.tag = tag;
+		self
+	}
+
+	pub fn handshake<F>(mut self, handshake: F) -> Self
+			where F: Fn(&Arc<FiestaNetworkClient>) -> io::Result<()> + Send + Sync + 'static {
+		self.handshake = Some(Arc::new(handshake));
+		self
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+}
+
+impl LinkManager {
+	/* the manager owns `connector` and stops it with the links */
+	pub fn new(connector: Connector) -> Self {
+		LinkManager {
+			connector,
+			links:		Vec::new(),
+			backoff:	Backoff::default(),
+			when_down:	WhenDown::Queue(1024),
+		}
+	}
+
+	pub fn link(mut self, link: Link) -> Self {
+		self.links.push(link);
+		self
+	}
+
+	pub fn backoff(mut self, backoff: Backoff) -> Self {
+		self.backoff = backoff;
+		self
+	}
+
+	pub fn when_down(mut self, when_down: WhenDown) -> Self {
+		self.when_down = when_down;
+		self
+	}
+
+	/* starts the manager thread, which connects every link right away */
+	pub fn start(self) -> io::Result<RunningLinks> {
+		let state = Arc::new(LinksState {
+			links:			Mutex::new(HashMap::new()),
+			wake:			Condvar::new(),
+			subscribers:	Mutex::new(Vec::new()),
+			backoff:		self.backoff,
+			when_down:		self.when_down,
+			running:		AtomicBool::new(true),
+		});
+		for link in self.links {
+			state.add(link);
+		}
+		let thread = {
+			let state = state.clone();
+			let connector = Arc::new(self.connector);
+			(thread::Builder::new()
+				.name("fiesta-links".to_string())
+				.spawn(move || -> io::Result<()> {
+					state.run(&connector);
+					connector.shutdown();
+					/* the connect threads are done, nothing else holds it */
+					match Arc::try_unwrap(connector) {
+						Ok(connector) => connector.join(),
+						Err(_) => Ok(()),
+					}
+				}))?
+		};
+		Ok(RunningLinks {
+			state,
+			thread,
+		})
+	}
+}
+
+impl RunningLinks {
+	/* adds or replaces a link, replacing closes the old connection */
+	pub fn add(&self, link: Link) {
+		self.state.add(link);
+		self.state.wake.notify_all();
+	}
+
+	/* the connection of `name` if it is up */
+	pub fn get(&self, name: &str) -> Option<Arc<FiestaNetworkClient>> {
+		let links = self.state.links.lock().unwrap();
+		links.get(name).and_then(|state| state.client.clone()).filter(|client| client.alive())
+	}
+
+	pub fn is_up(&self, name: &str) -> bool {
+		self.get(name).is_some()
+	}
+
+	/* sends a framed packet over `name`, or queues it while the link is down */
+	pub fn send(&self, name: &str, frame: &[u8]) -> Result<(), LinkError> {
+		let mut links = self.state.links.lock().unwrap();
+		let state = match links.get_mut(name) {
+			Some(state) => state,
+			None => return Err(LinkError::Unknown(name.to_string())),
+		};
+		match state.client {
+			Some(ref client) if client.alive() => {
+				client.append_send(frame);
+				Ok(())
+			},
+			_ => match self.state.when_down {
+				WhenDown::Queue(max) if state.queue.len() < max => {
+					state.queue.push_back(frame.to_vec());
+					Ok(())
+				},
+				WhenDown::Queue(_) => Err(LinkError::QueueFull(name.to_string())),
+				WhenDown::Reject => Err(LinkError::Down(name.to_string())),
+			},
+		}
+	}
+
+	/* gets every event from now on */
+	pub fn subscribe(&self) -> Receiver<LinkEvent> {
+		let (sender, receiver) = mpsc::channel();
+		self.state.subscribers.lock().unwrap().push(sender);
+		receiver
+	}
+
+	/* stops reconnecting and closes the links; use `join` to wait for it */
+	pub fn shutdown(&self) {
+		self.state.running.store(false, Ordering::Release);
+		self.state.wake.notify_all();
+	}
+
+	pub fn join(self) -> io::Result<()> {
+		match self.thread.join() {
+			Ok(result) => result,
+			Err(_) => Err(Error::other("the link manager panicked")),
+		}
+	}
+}
+
+impl LinksState {
+	fn add(&self, link: Link) {
+		let replaced = self.links.lock().unwrap().insert(link.name.clone(), LinkState {
+			link,
+			client:		None,
+			failures:	0,
+			retry_at:	Instant::now(),
+			connecting:	false,
+			queue:		VecDeque::new(),
+		});
+		if let Some(client) = replaced.and_then(|state| state.client) {
+			client.close();
+		}
+	}
+
+	fn run(self: &Arc<Self>, connector: &Arc<Connector>) {
+		let mut connects: Vec<JoinHandle<()>> = Vec::new();
+		while self.running.load(Ordering::Acquire) {
+			let mut events = Vec::new();
+			for link in self.check(&mut events) {
+				let (state, connector, attempt) = (self.clone(), connector.clone(), link.clone());
+				let spawned = thread::Builder::new()
+					.name(format!("fiesta-link-{}", link.name))
+					.spawn(move || {
+						let result = connect(&connector, &attempt);
+						let mut events = Vec::new();
+						state.connected(attempt, result, &mut events);
+						state.publish(events);
+					});
+				match spawned {
+					Ok(thread) => connects.push(thread),
+					Err(e) => self.connected(link, Err(e), &mut events),
+				}
+			}
+			self.publish(events);
+			connects.retain(|thread| !thread.is_finished());
+
+			let links = self.links.lock().unwrap();
+			if self.running.load(Ordering::Acquire) {
+				let _ = self.wake.wait_timeout(links, Duration::from_millis(CHECK_INTERVAL_MS)).unwrap();
+			}
+		}
+		for thread in connects {
+			let _ = thread.join();
+		}
+		for state in self.links.lock().unwrap().values() {
+			if let Some(ref client) = state.client {
+				client.close();
+			}
+		}
+	}
+
+	/* notices links that went down, returns those due for a connect */
+	fn check(&self, events: &mut Vec<LinkEvent>) -> Vec<Link> {
+		let now = Instant::now();
+		let mut links = self.links.lock().unwrap();
+		let mut due = Vec::new();
+		for state in links.values_mut() {
+			let uptime = match state.client {
+				Some(ref client) if !client.alive() => client.uptime(),
+				_ => {
+					if state.client.is_none() && !state.connecting && state.retry_at <= now {
+						state.connecting = true;
+						due.push(state.link.clone());
+					}
+					continue;
+				},
+			};
+			state.client = None;
+			events.push(LinkEvent::Down { name: state.link.name.clone() });
+			if uptime >= Duration::from_millis(STABLE_UPTIME_MS) {
+				info!(target: "links", "link {} to {} is down", state.link.name, state.link.addr);
+				state.failures = 0;
+				state.retry_at = now;
+			} else {
+				state.failures = state.failures.saturating_add(1);
+				let delay = self.backoff.delay(state.failures);
+				state.retry_at = now + delay;
+				warn!(target: "links", "link {} to {} went down after {:?} ({} failures in a row), retrying in {:?}",
+					state.link.name, state.link.addr, uptime, state.failures, delay);
+			}
+		}
+		due
+	}
+
+	fn connected(&self, link: Link, result: io::Result<Arc<FiestaNetworkClient>>, events: &mut Vec<LinkEvent>) {
+		let mut links = self.links.lock().unwrap();
+		/* the link may have been replaced by `add` in the meantime */
+		let state = match links.get_mut(&link.name) {
+			Some(state) if state.connecting && state.link.addr == link.addr && state.client.is_none() => state,
+			_ => {
+				if let Ok(client) = result {
+					client.close();
+				}
+				return;
+			},
+		};
+		state.connecting = false;
+		match result {
+			Ok(client) => {
+				info!(target: "links", "link {} to {} is up", link.name, link.addr);
+				for frame in state.queue.drain(..) {
+					client.append_send(&frame[..]);
+				}
+				state.client = Some(client.clone());
+				events.push(LinkEvent::Up { name: link.name, link: client });
+			},
+			Err(e) => {
+				state.failures = state.failures.saturating_add(1);
+				let delay = self.backoff.delay(state.failures);
+				state.retry_at = Instant::now() + delay;
+				warn!(target: "links", "could not connect link {} to {} ({} failures in a row), retrying in {:?}: {}",
+					link.name, link.addr, state.failures, delay, e);
+			},
+		}
+	}
+
+	fn publish(&self, events: Vec<LinkEvent>) {
+		if events.is_empty() {
+			return;
+		}
+		let mut subscribers = self.subscribers.lock().unwrap();
+		for event in events {
+			subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+		}
+	}
+}
+
+/* connects and runs the handshake */
+fn connect(connector: &Connector, link: &Link) -> io::Result<Arc<FiestaNetworkClient>> {
+	let client = connector.connect_tagged(&link.addr, link.tag)?;
+	if let Some(ref handshake) = link.handshake {
+		if let Err(e) = handshake(&client) {
+			client.close();
+			return Err(Error::new(e.kind(), format!("handshake failed: {}", e)));
+		}
+	}
+	Ok(client)
+}
+
+impl fmt::Display for LinkError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			LinkError::Unknown(ref name) => write!(f, "no link named {}", name),
+			LinkError::Down(ref name) => write!(f, "link {} is down", name),
+			LinkError::QueueFull(ref name) => write!(f, "link {} is down and its queue is full", name),
+		}
+	}
+}
+
+impl error::Error for LinkError {
+	fn description(&self) -> &str {
+		match *self {
+			LinkError::Unknown(_) => "no such link",
+			LinkError::Down(_) => "link is down",
+			LinkError::QueueFull(_) => "link queue is full",
+		}
+	}
+}
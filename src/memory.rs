@@ -0,0 +1,115 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/* one direction of a `MemoryStream` pair */
+struct Pipe {
+	data:			VecDeque<u8>,
+	/* `None` for no limit */
+	capacity:		Option<usize>,
+	writer_closed:	bool,
+	reader_closed:	bool,
+}
+
+/* an in-memory duplex stream standing in for a socket. Reads of an empty pipe
+ * fail with `WouldBlock` until the other end is closed, then they return 0. */
+pub struct MemoryStream {
+	incoming:		Arc<Mutex<Pipe>>,
+	outgoing:		Arc<Mutex<Pipe>>,
+}
+
+/* two connected ends without a limit on buffered data */
+pub fn pair() -> (MemoryStream, MemoryStream) {
+	pair_with_capacity(None)
+}
+
+/* two connected ends, each direction buffering at most `capacity` bytes so
+ * writes can come up short like they do on a full socket */
+pub fn pair_with_capacity(capacity: Option<usize>) -> (MemoryStream, MemoryStream) {
+	let a = Arc::new(Mutex::new(Pipe::new(capacity)));
+	let b = Arc::new(Mutex::new(Pipe::new(capacity)));
+	(MemoryStream { incoming: a.clone(), outgoing: b.clone() },
+	 MemoryStream { incoming: b, outgoing: a })
+}
+
+impl Pipe {
+	fn new(capacity: Option<usize>) -> Self {
+		Pipe {
+			data:			VecDeque::new(),
+			capacity,
+			writer_closed:	false,
+			reader_closed:	false,
+		}
+	}
+}
+
+impl MemoryStream {
+	/* bytes written by the other end that have not been read yet */
+	pub fn available(&self) -> usize {
+		self.incoming.lock().unwrap().data.len()
+	}
+
+	/* takes everything the other end wrote so far */
+	pub fn read_available(&mut self) -> Vec<u8> {
+		let mut incoming = self.incoming.lock().unwrap();
+		incoming.data.drain(..).collect()
+	}
+
+	/* both directions; the other end reads the buffered data and then EOF */
+	pub fn shutdown(&self) {
+		self.outgoing.lock().unwrap().writer_closed = true;
+		self.incoming.lock().unwrap().reader_closed = true;
+	}
+
+	pub fn is_closed(&self) -> bool {
+		let incoming = self.incoming.lock().unwrap();
+		incoming.writer_closed || incoming.reader_closed
+	}
+}
+
+impl Read for MemoryStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let mut incoming = self.incoming.lock().unwrap();
+		if incoming.data.is_empty() {
+			return if incoming.writer_closed || incoming.reader_closed {
+				Ok(0)
+			} else {
+				Err(Error::new(ErrorKind::WouldBlock, "no data available"))
+			};
+		}
+		let size = cmp::min(buf.len(), incoming.data.len());
+		for (dst, src) in buf.iter_mut().zip(incoming.data.drain(..size)) {
+			*dst = src;
+		}
+		Ok(size)
+	}
+}
+
+impl Write for MemoryStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let mut outgoing = self.outgoing.lock().unwrap();
+		if outgoing.reader_closed || outgoing.writer_closed {
+			return Err(Error::new(ErrorKind::BrokenPipe, "the other end is closed"));
+		}
+		let size = match outgoing.capacity {
+			Some(capacity) => cmp::min(buf.len(), capacity.saturating_sub(outgoing.data.len())),
+			None => buf.len(),
+		};
+		if size == 0 && !buf.is_empty() {
+			return Err(Error::new(ErrorKind::WouldBlock, "pipe is full"));
+		}
+		outgoing.data.extend(&buf[..size]);
+		Ok(size)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Drop for MemoryStream {
+	fn drop(&mut self) {
+		self.shutdown();
+	}
+}
@@ -0,0 +1,440 @@
+/* Counters kept by the handler, the clients and the worker pool. Everything
+ * is updated with atomics (or a lock taken only for rare events), `snapshot`
+ * copies it all into plain structs. */
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use buffer;
+use limits::LimitExceeded;
+use opcodes;
+use ratelimit::RateLimitStats;
+
+/* upper bounds of the latency histogram buckets in microseconds, a last
+ * bucket takes everything slower */
+pub const LATENCY_BUCKETS_US: [u64; 12] = [
+	50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DisconnectReason {
+	/* the client closed the connection */
+	PeerClosed,
+	ReadError,
+	WriteError,
+	RateLimited,
+	/* more was sent than `SendQueueLimit` allows */
+	SendQueueFull,
+	/* closed through `FiestaNetworkClient::close` */
+	Closed,
+}
+
+pub struct Metrics {
+	connected:				AtomicUsize,
+	accepted:				AtomicU64,
+	accept_errors:			AtomicU64,
+	rejected:				Mutex<HashMap<LimitExceeded, u64>>,
+	disconnects:			Mutex<HashMap<DisconnectReason, u64>>,
+	bytes_in:				AtomicU64,
+	bytes_out:				AtomicU64,
+	opcodes:				RwLock<HashMap<u16, Arc<OpcodeCounters>>>,
+	read_buffer_high:		AtomicUsize,
+	write_buffer_high:		AtomicUsize,
+	send_overflows:			AtomicU64,
+	rate_dropped:			AtomicU64,
+	rate_delayed:			AtomicU64,
+	rate_disconnects:		AtomicU64,
+	queue_depth:			AtomicUsize,
+	queue_high:				AtomicUsize,
+}
+
+#[derive(Default)]
+struct OpcodeCounters {
+	packets_in:				AtomicU64,
+	bytes_in:				AtomicU64,
+	packets_out:			AtomicU64,
+	bytes_out:				AtomicU64,
+	latency:				Histogram,
+}
+
+#[derive(Default)]
+struct Histogram {
+	buckets:				[AtomicU64; 13],
+	count:					AtomicU64,
+	sum_us:					AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+	/* `LATENCY_BUCKETS_US`, the counts have one more entry for the rest */
+	pub bounds_us:			Vec<u64>,
+	pub counts:				Vec<u64>,
+	pub count:				u64,
+	pub sum_us:				u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpcodeSnapshot {
+	pub packets_in:			u64,
+	/* payload bytes, without the frame header */
+	pub bytes_in:			u64,
+	pub packets_out:		u64,
+	pub bytes_out:			u64,
+	/* time the processor spent on the packet */
+	pub latency:			HistogramSnapshot,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+	pub connected:			usize,
+	pub accepted:			u64,
+	/* accept calls that failed, e.g. with EMFILE */
+	pub accept_errors:		u64,
+	pub rejected:			HashMap<LimitExceeded, u64>,
+	pub disconnects:		HashMap<DisconnectReason, u64>,
+	/* bytes read from and written to the sockets */
+	pub bytes_in:			u64,
+	pub bytes_out:			u64,
+	pub opcodes:			BTreeMap<u16, OpcodeSnapshot>,
+	pub read_buffer_high:	usize,
+	pub write_buffer_high:	usize,
+	/* process wide, see `buffer::append_overflows` */
+	pub append_overflows:	usize,
+	/* writes dropped because the send queue of a client was full */
+	pub send_overflows:		u64,
+	/* packets dropped and delayed, and clients disconnected by rate limiting */
+	pub rate_limited:		RateLimitStats,
+	/* packets waiting for a worker of the `PacketProcessingThreadPool` */
+	pub queue_depth:		usize,
+	pub queue_high:			usize,
+}
+
+impl DisconnectReason {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			DisconnectReason::PeerClosed => "peer_closed",
+			DisconnectReason::ReadError => "read_error",
+			DisconnectReason::WriteError => "write_error",
+			DisconnectReason::RateLimited => "rate_limited",
+			DisconnectReason::SendQueueFull => "send_queue_full",
+			DisconnectReason::Closed => "closed",
+		}
+	}
+}
+
+impl Metrics {
+	pub fn new() -> Self {
+		Metrics {
+			connected:			AtomicUsize::new(0),
+			accepted:			AtomicU64::new(0),
+			accept_errors:		AtomicU64::new(0),
+			rejected:			Mutex::new(HashMap::new()),
+			disconnects:		Mutex::new(HashMap::new()),
+			bytes_in:			AtomicU64::new(0),
+			bytes_out:			AtomicU64::new(0),
+			opcodes:			RwLock::new(HashMap::new()),
+			read_buffer_high:	AtomicUsize::new(0),
+			write_buffer_high:	AtomicUsize::new(0),
+			send_overflows:		AtomicU64::new(0),
+			rate_dropped:		AtomicU64::new(0),
+			rate_delayed:		AtomicU64::new(0),
+			rate_disconnects:	AtomicU64::new(0),
+			queue_depth:		AtomicUsize::new(0),
+			queue_high:			AtomicUsize::new(0),
+		}
+	}
+
+	/* a client was accepted and is being served */
+	pub fn connected(&self) {
+		self.accepted.fetch_add(1, Ordering::Relaxed);
+		self.connected.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn disconnected(&self, reason: DisconnectReason) {
+		self.connected.fetch_sub(1, Ordering::Relaxed);
+		*self.disconnects.lock().unwrap().entry(reason).or_insert(0) += 1;
+	}
+
+	pub fn rejected(&self, reason: LimitExceeded) {
+		*self.rejected.lock().unwrap().entry(reason).or_insert(0) += 1;
+	}
+
+	pub fn accept_failed(&self) {
+		self.accept_errors.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn read_bytes(&self, bytes: usize) {
+		self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	pub fn wrote_bytes(&self, bytes: usize) {
+		self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	/* a packet with a body of `bytes` was received */
+	pub fn received(&self, opcode: u16, bytes: usize) {
+		let counters = self.opcode(opcode);
+		counters.packets_in.fetch_add(1, Ordering::Relaxed);
+		counters.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	pub fn sent(&self, opcode: u16, bytes: usize) {
+		let counters = self.opcode(opcode);
+		counters.packets_out.fetch_add(1, Ordering::Relaxed);
+		counters.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	/* how long the processor took for a packet */
+	pub fn processed(&self, opcode: u16, elapsed: Duration) {
+		self.opcode(opcode).latency.observe(elapsed);
+	}
+
+	pub fn read_buffer_level(&self, bytes: usize) {
+		self.read_buffer_high.fetch_max(bytes, Ordering::Relaxed);
+	}
+
+	pub fn write_buffer_level(&self, bytes: usize) {
+		self.write_buffer_high.fetch_max(bytes, Ordering::Relaxed);
+	}
+
+	/* a write did not fit into the send queue of a client */
+	pub fn send_overflowed(&self) {
+		self.send_overflows.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/* what the rate limits did to a batch of packets */
+	pub fn rate_limited(&self, stats: &RateLimitStats) {
+		self.rate_dropped.fetch_add(stats.dropped, Ordering::Relaxed);
+		self.rate_delayed.fetch_add(stats.delayed, Ordering::Relaxed);
+		self.rate_disconnects.fetch_add(stats.disconnects, Ordering::Relaxed);
+	}
+
+	pub fn enqueued(&self) {
+		let depth = self.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+		self.queue_high.fetch_max(depth, Ordering::Relaxed);
+	}
+
+	pub fn dequeued(&self) {
+		self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+	}
+
+	pub fn snapshot(&self) -> MetricsSnapshot {
+		let opcodes = self.opcodes.read().unwrap().iter()
+			.map(|(opcode, counters)| (*opcode, counters.snapshot()))
+			.collect();
+		MetricsSnapshot {
+			connected:			self.connected.load(Ordering::Relaxed),
+			accepted:			self.accepted.load(Ordering::Relaxed),
+			accept_errors:		self.accept_errors.load(Ordering::Relaxed),
+			rejected:			self.rejected.lock().unwrap().clone(),
+			disconnects:		self.disconnects.lock().unwrap().clone(),
+			bytes_in:			self.bytes_in.load(Ordering::Relaxed),
+			bytes_out:			self.bytes_out.load(Ordering::Relaxed),
+			opcodes,
+			read_buffer_high:	self.read_buffer_high.load(Ordering::Relaxed),
+			write_buffer_high:	self.write_buffer_high.load(Ordering::Relaxed),
+			append_overflows:	buffer::append_overflows(),
+			send_overflows:		self.send_overflows.load(Ordering::Relaxed),
+			rate_limited:		RateLimitStats {
+				dropped:		self.rate_dropped.load(Ordering::Relaxed),
+				delayed:		self.rate_delayed.load(Ordering::Relaxed),
+				disconnects:	self.rate_disconnects.load(Ordering::Relaxed),
+			},
+			queue_depth:		self.queue_depth.load(Ordering::Relaxed),
+			queue_high:			self.queue_high.load(Ordering::Relaxed),
+		}
+	}
+
+	/* the write lock is only taken the first time an opcode is seen */
+	fn opcode(&self, opcode: u16) -> Arc<OpcodeCounters> {
+		if let Some(counters) = self.opcodes.read().unwrap().get(&opcode) {
+			return counters.clone();
+		}
+		self.opcodes.write().unwrap().entry(opcode).or_default().clone()
+	}
+}
+
+impl Default for Metrics {
+	fn default() -> Self {
+		Metrics::new()
+	}
+}
+
+impl OpcodeCounters {
+	fn snapshot(&self) -> OpcodeSnapshot {
+		OpcodeSnapshot {
+			packets_in:		self.packets_in.load(Ordering::Relaxed),
+			bytes_in:		self.bytes_in.load(Ordering::Relaxed),
+			packets_out:	self.packets_out.load(Ordering::Relaxed),
+			bytes_out:		self.bytes_out.load(Ordering::Relaxed),
+			latency:		self.latency.snapshot(),
+		}
+	}
+}
+
+impl Histogram {
+	fn observe(&self, elapsed: Duration) {
+		let us = elapsed.as_micros().min(u64::MAX as u128) as u64;
+		let bucket = LATENCY_BUCKETS_US.iter().position(|bound| us <= *bound).unwrap_or(LATENCY_BUCKETS_US.len());
+		self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+		self.count.fetch_add(1, Ordering::Relaxed);
+		self.sum_us.fetch_add(us, Ordering::Relaxed);
+	}
+
+	fn snapshot(&self) -> HistogramSnapshot {
+		HistogramSnapshot {
+			bounds_us:		LATENCY_BUCKETS_US.to_vec(),
+			counts:			self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect(),
+			count:			self.count.load(Ordering::Relaxed),
+			sum_us:			self.sum_us.load(Ordering::Relaxed),
+		}
+	}
+}
+
+impl HistogramSnapshot {
+	pub fn mean_us(&self) -> Option<f64> {
+		if self.count == 0 {
+			None
+		} else {
+			Some(self.sum_us as f64 / self.count as f64)
+		}
+	}
+
+	/* the upper bound of the bucket the `q` quantile falls into, `None` if
+	 * nothing was observed or it is in the last, unbounded bucket */
+	pub fn quantile(&self, q: f64) -> Option<u64> {
+		if self.count == 0 {
+			return None;
+		}
+		let rank = ((self.count as f64) * q.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+		let mut seen = 0;
+		for (i, count) in self.counts.iter().enumerate() {
+			seen += count;
+			if seen >= rank {
+				return self.bounds_us.get(i).cloned();
+			}
+		}
+		None
+	}
+}
+
+impl MetricsSnapshot {
+	pub fn packets_in(&self) -> u64 {
+		self.opcodes.values().map(|o| o.packets_in).sum()
+	}
+
+	pub fn packets_out(&self) -> u64 {
+		self.opcodes.values().map(|o| o.packets_out).sum()
+	}
+
+	pub fn disconnects_total(&self) -> u64 {
+		self.disconnects.values().sum()
+	}
+}
+
+fn limit_name(reason: LimitExceeded) -> &'static str {
+	match reason {
+		LimitExceeded::MaxConnections => "max_connections",
+		LimitExceeded::MaxPerIp => "max_per_ip",
+	}
+}
+
+/* `# HELP` and `# TYPE` lines of a metric */
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/* `opcode="0x0807",name="NC_MISC_SEED_ACK"`, without `name` if it is unknown */
+fn opcode_labels(opcode: u16) -> String {
+	match opcodes::name(opcode) {
+		/* `{:?}` escapes quotes and backslashes the way the format wants them */
+		Some(name) => format!("opcode=\"0x{:04X}\",name={:?}", opcode, name),
+		None => format!("opcode=\"0x{:04X}\"", opcode),
+	}
+}
+
+impl MetricsSnapshot {
+	/* the Prometheus text exposition format (version 0.0.4), every metric is
+	 * prefixed with `fiesta_`. Opcodes are labelled in hex, and with their
+	 * name if `opcodes` knows it */
+	pub fn to_prometheus(&self) -> String {
+		let mut out = String::new();
+		describe(&mut out, "fiesta_connected_clients", "gauge", "Clients currently connected.");
+		let _ = writeln!(out, "fiesta_connected_clients {}", self.connected);
+		describe(&mut out, "fiesta_accepted_total", "counter", "Clients accepted.");
+		let _ = writeln!(out, "fiesta_accepted_total {}", self.accepted);
+		describe(&mut out, "fiesta_accept_errors_total", "counter", "Failed accept calls.");
+		let _ = writeln!(out, "fiesta_accept_errors_total {}", self.accept_errors);
+
+		describe(&mut out, "fiesta_rejected_total", "counter", "Clients rejected by a connection limit.");
+		let mut rejected: Vec<_> = self.rejected.iter().map(|(r, n)| (limit_name(*r), *n)).collect();
+		rejected.sort();
+		for (reason, count) in rejected {
+			let _ = writeln!(out, "fiesta_rejected_total{{reason=\"{}\"}} {}", reason, count);
+		}
+		describe(&mut out, "fiesta_disconnects_total", "counter", "Clients disconnected, by reason.");
+		let mut disconnects: Vec<_> = self.disconnects.iter().collect();
+		disconnects.sort();
+		for (reason, count) in disconnects {
+			let _ = writeln!(out, "fiesta_disconnects_total{{reason=\"{}\"}} {}", reason.name(), count);
+		}
+
+		describe(&mut out, "fiesta_received_bytes_total", "counter", "Bytes read from client sockets.");
+		let _ = writeln!(out, "fiesta_received_bytes_total {}", self.bytes_in);
+		describe(&mut out, "fiesta_sent_bytes_total", "counter", "Bytes written to client sockets.");
+		let _ = writeln!(out, "fiesta_sent_bytes_total {}", self.bytes_out);
+
+		describe(&mut out, "fiesta_packets_received_total", "counter", "Packets received, by opcode.");
+		for (opcode, stats) in self.opcodes.iter().filter(|&(_, s)| s.packets_in > 0) {
+			let _ = writeln!(out, "fiesta_packets_received_total{{{}}} {}", opcode_labels(*opcode), stats.packets_in);
+		}
+		describe(&mut out, "fiesta_packet_received_bytes_total", "counter", "Payload bytes received, by opcode.");
+		for (opcode, stats) in self.opcodes.iter().filter(|&(_, s)| s.packets_in > 0) {
+			let _ = writeln!(out, "fiesta_packet_received_bytes_total{{{}}} {}", opcode_labels(*opcode), stats.bytes_in);
+		}
+		describe(&mut out, "fiesta_packets_sent_total", "counter", "Packets sent, by opcode.");
+		for (opcode, stats) in self.opcodes.iter().filter(|&(_, s)| s.packets_out > 0) {
+			let _ = writeln!(out, "fiesta_packets_sent_total{{{}}} {}", opcode_labels(*opcode), stats.packets_out);
+		}
+		describe(&mut out, "fiesta_packet_sent_bytes_total", "counter", "Payload bytes sent, by opcode.");
+		for (opcode, stats) in self.opcodes.iter().filter(|&(_, s)| s.packets_out > 0) {
+			let _ = writeln!(out, "fiesta_packet_sent_bytes_total{{{}}} {}", opcode_labels(*opcode), stats.bytes_out);
+		}
+
+		describe(&mut out, "fiesta_read_buffer_high_water_bytes", "gauge", "Fullest a client read buffer has been.");
+		let _ = writeln!(out, "fiesta_read_buffer_high_water_bytes {}", self.read_buffer_high);
+		describe(&mut out, "fiesta_write_buffer_high_water_bytes", "gauge", "Fullest a client write buffer has been.");
+		let _ = writeln!(out, "fiesta_write_buffer_high_water_bytes {}", self.write_buffer_high);
+		describe(&mut out, "fiesta_buffer_append_overflows_total", "counter", "Appends that dropped bytes because a buffer was full.");
+		let _ = writeln!(out, "fiesta_buffer_append_overflows_total {}", self.append_overflows);
+		describe(&mut out, "fiesta_send_queue_overflows_total", "counter", "Writes dropped because a client send queue was full.");
+		let _ = writeln!(out, "fiesta_send_queue_overflows_total {}", self.send_overflows);
+		describe(&mut out, "fiesta_rate_limited_total", "counter", "Packets dropped or delayed and clients disconnected by rate limiting.");
+		let _ = writeln!(out, "fiesta_rate_limited_total{{outcome=\"dropped\"}} {}", self.rate_limited.dropped);
+		let _ = writeln!(out, "fiesta_rate_limited_total{{outcome=\"delayed\"}} {}", self.rate_limited.delayed);
+		let _ = writeln!(out, "fiesta_rate_limited_total{{outcome=\"disconnected\"}} {}", self.rate_limited.disconnects);
+		describe(&mut out, "fiesta_worker_queue_depth", "gauge", "Packets waiting for a worker.");
+		let _ = writeln!(out, "fiesta_worker_queue_depth {}", self.queue_depth);
+		describe(&mut out, "fiesta_worker_queue_high_water", "gauge", "Most packets that waited for a worker at once.");
+		let _ = writeln!(out, "fiesta_worker_queue_high_water {}", self.queue_high);
+
+		describe(&mut out, "fiesta_packet_processing_seconds", "histogram", "Time the processor spent on a packet, by opcode.");
+		for (opcode, stats) in self.opcodes.iter().filter(|&(_, s)| s.latency.count > 0) {
+			let latency = &stats.latency;
+			let labels = opcode_labels(*opcode);
+			let mut cumulative = 0;
+			for (bound, count) in latency.bounds_us.iter().zip(latency.counts.iter()) {
+				cumulative += count;
+				let _ = writeln!(out, "fiesta_packet_processing_seconds_bucket{{{},le=\"{}\"}} {}",
+					labels, *bound as f64 / 1e6, cumulative);
+			}
+			let _ = writeln!(out, "fiesta_packet_processing_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, latency.count);
+			let _ = writeln!(out, "fiesta_packet_processing_seconds_sum{{{}}} {}", labels, latency.sum_us as f64 / 1e6);
+			let _ = writeln!(out, "fiesta_packet_processing_seconds_count{{{}}} {}", labels, latency.count);
+		}
+		out
+	}
+}
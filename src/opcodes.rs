@@ -0,0 +1,125 @@
+/* Names of opcodes, for logs, dumps and metrics. An opcode is the department
+ * in its upper six bits and the command in the lower ten; names follow the
+ * client's `NC_<DEPARTMENT>_<COMMAND>` convention. `WELL_KNOWN` is always
+ * there, applications add their own with `register`, which wins over it. */
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use schema::Origin;
+
+/* what is known about an opcode */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeInfo {
+	pub opcode:			u16,
+	pub name:			&'static str,
+	/* which side sends it */
+	pub origin:			Origin,
+	/* the size of the body, if it never changes */
+	pub size:			Option<usize>,
+}
+
+/* formats as `0x0807 NC_MISC_SEED_ACK`, or `0x0807` if the name is unknown */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Named(pub u16);
+
+static REGISTERED: RwLock<Option<HashMap<u16, OpcodeInfo>>> = RwLock::new(None);
+
+macro_rules! opcodes {
+	($($opcode:expr, $name:expr, $origin:ident, $size:expr;)*) => {
+		&[$(OpcodeInfo {
+			opcode:		$opcode,
+			name:		$name,
+			origin:		Origin::$origin,
+			size:		$size,
+		}),*]
+	};
+}
+
+/* sorted by opcode */
+pub const WELL_KNOWN: &[OpcodeInfo] = opcodes! {
+	0x0804, "NC_MISC_HEARTBEAT_REQ", Server, Some(0);
+	0x0805, "NC_MISC_HEARTBEAT_ACK", Client, Some(0);
+	0x0807, "NC_MISC_SEED_ACK", Server, Some(2);
+	0x0C06, "NC_USER_LOGIN_REQ", Client, Some(34);
+	0x0C09, "NC_USER_LOGINFAIL_ACK", Server, Some(2);
+	0x0C0A, "NC_USER_LOGIN_ACK", Server, None;
+	0x0C0B, "NC_USER_WORLDSELECT_REQ", Client, Some(1);
+	0x0C0C, "NC_USER_WORLDSELECT_ACK", Server, Some(83);
+	0x0C0F, "NC_USER_WILLLOGIN_REQ", Client, None;
+	0x0C18, "NC_USER_NORMALLOGOUT_CMD", Client, Some(0);
+	0x0C1B, "NC_USER_WORLD_STATUS_REQ", Client, Some(0);
+	0x0C1C, "NC_USER_WORLD_STATUS_ACK", Server, None;
+	0x0C65, "NC_USER_CLIENT_VERSION_CHECK_REQ", Client, None;
+	0x0C67, "NC_USER_CLIENT_RIGHTVERSION_CHECK_ACK", Server, Some(0);
+	0x0C68, "NC_USER_CLIENT_WRONGVERSION_CHECK_ACK", Server, Some(0);
+	0x1001, "NC_CHAR_LOGIN_REQ", Client, Some(1);
+	0x1003, "NC_CHAR_LOGIN_ACK", Server, None;
+	0x1004, "NC_CHAR_LOGINFAIL_ACK", Server, Some(2);
+	0x1401, "NC_AVATAR_CREATE_REQ", Client, None;
+	0x1403, "NC_AVATAR_CREATESUCC_ACK", Server, None;
+	0x1404, "NC_AVATAR_CREATEFAIL_ACK", Server, Some(2);
+	0x1407, "NC_AVATAR_ERASE_REQ", Client, Some(1);
+	0x1408, "NC_AVATAR_ERASESUCC_ACK", Server, Some(1);
+	0x1801, "NC_MAP_LOGIN_REQ", Client, None;
+	0x1803, "NC_MAP_LOGINCOMPLETE_CMD", Client, Some(0);
+	0x1804, "NC_MAP_LOGINFAIL_ACK", Server, Some(2);
+	0x1C06, "NC_BRIEFINFO_LOGINCHARACTER_CMD", Server, None;
+	0x1C0E, "NC_BRIEFINFO_BRIEFINFODELETE_CMD", Server, Some(2);
+	0x2001, "NC_ACT_CHAT_REQ", Client, None;
+	0x2002, "NC_ACT_SOMEONECHAT_CMD", Server, None;
+	0x2012, "NC_ACT_STOP_REQ", Client, Some(8);
+	0x2017, "NC_ACT_MOVEWALK_CMD", Client, Some(16);
+	0x2018, "NC_ACT_SOMEONEMOVEWALK_CMD", Server, None;
+	0x2019, "NC_ACT_MOVERUN_CMD", Client, Some(16);
+	0x201A, "NC_ACT_SOMEONEMOVERUN_CMD", Server, None;
+	0x2401, "NC_BAT_TARGETTING_REQ", Client, Some(2);
+	0x3007, "NC_ITEM_DROP_REQ", Client, None;
+	0x3009, "NC_ITEM_PICK_REQ", Client, Some(2);
+};
+
+/* adds or replaces the entry of `info.opcode` */
+pub fn register(info: OpcodeInfo) {
+	REGISTERED.write().unwrap().get_or_insert_with(HashMap::new).insert(info.opcode, info);
+}
+
+pub fn info(opcode: u16) -> Option<OpcodeInfo> {
+	let registered = REGISTERED.read().unwrap().as_ref().and_then(|r| r.get(&opcode).cloned());
+	registered.or_else(|| {
+		WELL_KNOWN.binary_search_by_key(&opcode, |info| info.opcode).ok().map(|i| WELL_KNOWN[i])
+	})
+}
+
+pub fn name(opcode: u16) -> Option<&'static str> {
+	info(opcode).map(|info| info.name)
+}
+
+/* an opcode as operators type it, in hex (0x0C01) or decimal */
+pub fn parse_opcode(value: &str) -> Result<u16, String> {
+	let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+		u16::from_str_radix(&value[2..], 16)
+	} else {
+		value.parse()
+	};
+	parsed.map_err(|_| format!("not an opcode: {}", value))
+}
+
+/* every known opcode, well-known or registered, by opcode */
+pub fn known() -> Vec<OpcodeInfo> {
+	let mut all: HashMap<u16, OpcodeInfo> = WELL_KNOWN.iter().map(|info| (info.opcode, *info)).collect();
+	if let Some(ref registered) = *REGISTERED.read().unwrap() {
+		all.extend(registered.iter().map(|(opcode, info)| (*opcode, *info)));
+	}
+	let mut all: Vec<OpcodeInfo> = all.into_values().collect();
+	all.sort_by_key(|info| info.opcode);
+	all
+}
+
+impl fmt::Display for Named {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match name(self.0) {
+			Some(name) => write!(f, "0x{:04X} {}", self.0, name),
+			None => write!(f, "0x{:04X}", self.0),
+		}
+	}
+}
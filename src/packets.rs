@@ -0,0 +1,167 @@
+/* Typed packets. Their structs and codecs are generated by the build script
+ * from the schema files in `schemas/`, see `schema` for the format:
+ *
+ *     let packet = NcUserLoginReq { username: "admin".into(), ..Default::default() }.to_packet();
+ *     let request = NcUserLoginReq::from_packet(&packet)?;
+ *
+ * `defs::register()` puts every generated packet into the opcode registry
+ * and its layout into the dump annotations. */
+use std::io;
+
+use client::FiestaPacket;
+use dump::{self, PacketSchema};
+use opcodes::{self, OpcodeInfo};
+use reader::{DecodeError, PacketReader};
+use schema::{Origin, Scalar};
+use writer::PacketWriter;
+
+/* the packets generated from the `.fps` files in `schemas/` */
+pub mod defs {
+	include!(concat!(env!("OUT_DIR"), "/packets.rs"));
+}
+
+/* a value that can be read from and written to a packet body */
+pub trait Codec: Sized {
+	fn decode(reader: &mut PacketReader) -> Result<Self, DecodeError>;
+	fn encode(&self, writer: PacketWriter) -> PacketWriter;
+}
+
+pub trait Packet: Codec {
+	const OPCODE: u16;
+	const NAME: &'static str;
+	const ORIGIN: Origin;
+	/* the size of the body, if it never changes */
+	const SIZE: Option<usize>;
+
+	/* the layout for `dump::register_schema` */
+	fn dump_schema() -> PacketSchema;
+
+	/* decodes the body of `packet`, which has to be used up completely */
+	fn from_packet(packet: &FiestaPacket) -> Result<Self, DecodeError> {
+		let mut reader = PacketReader::new(packet);
+		if packet.header != Self::OPCODE {
+			return Err(reader.invalid(format!("expected {} (0x{:04X})", Self::NAME, Self::OPCODE)));
+		}
+		let value = Self::decode(&mut reader)?;
+		reader.expect_end()?;
+		Ok(value)
+	}
+
+	fn to_packet(&self) -> FiestaPacket {
+		self.encode(PacketWriter::new(Self::OPCODE)).finish()
+	}
+
+	/* the framed packet, ready for `FiestaNetworkClient::append_send` */
+	fn to_frame(&self) -> Vec<u8> {
+		self.encode(PacketWriter::new(Self::OPCODE)).to_frame()
+	}
+
+	/* `to_frame` for packets whose body may be larger than `MAX_BODY_SIZE` */
+	fn try_to_frame(&self) -> io::Result<Vec<u8>> {
+		self.encode(PacketWriter::new(Self::OPCODE)).try_to_frame()
+	}
+}
+
+/* registers `P` with `opcodes`, replacing whatever was registered for its
+ * opcode, and its layout for annotated dumps */
+pub fn register<P: Packet>() {
+	opcodes::register(OpcodeInfo {
+		opcode:		P::OPCODE,
+		name:		P::NAME,
+		origin:		P::ORIGIN,
+		size:		P::SIZE,
+	});
+	dump::register_schema(P::OPCODE, P::dump_schema());
+}
+
+macro_rules! scalar_codec {
+	($ty:ty, $read:ident, $write:ident) => {
+		impl Codec for $ty {
+			fn decode(reader: &mut PacketReader) -> Result<Self, DecodeError> {
+				reader.$read()
+			}
+
+			fn encode(&self, writer: PacketWriter) -> PacketWriter {
+				writer.$write(*self)
+			}
+		}
+	};
+}
+
+scalar_codec!(u8, read_u8, write_u8);
+scalar_codec!(i8, read_i8, write_i8);
+scalar_codec!(bool, read_bool, write_bool);
+scalar_codec!(u16, read_u16, write_u16);
+scalar_codec!(i16, read_i16, write_i16);
+scalar_codec!(u32, read_u32, write_u32);
+scalar_codec!(i32, read_i32, write_i32);
+scalar_codec!(u64, read_u64, write_u64);
+scalar_codec!(i64, read_i64, write_i64);
+
+/* The helpers below are called by the generated code. */
+
+pub fn decode_array<T: Codec>(reader: &mut PacketReader, count: usize) -> Result<Vec<T>, DecodeError> {
+	/* the count comes from the peer, every element takes at least a byte */
+	let mut items = Vec::with_capacity(count.min(reader.remaining()));
+	for _ in 0..count {
+		items.push(T::decode(reader)?);
+	}
+	Ok(items)
+}
+
+pub fn decode_rest<T: Codec>(reader: &mut PacketReader) -> Result<Vec<T>, DecodeError> {
+	let mut items = Vec::new();
+	while reader.remaining() > 0 {
+		items.push(T::decode(reader)?);
+	}
+	Ok(items)
+}
+
+/* exactly `count` items, cut off or padded with defaults */
+pub fn encode_array<T: Codec + Default>(writer: PacketWriter, items: &[T], count: usize) -> PacketWriter {
+	let mut writer = encode_items(writer, &items[..items.len().min(count)]);
+	for _ in items.len()..count {
+		writer = T::default().encode(writer);
+	}
+	writer
+}
+
+pub fn encode_items<T: Codec>(writer: PacketWriter, items: &[T]) -> PacketWriter {
+	items.iter().fold(writer, |writer, item| item.encode(writer))
+}
+
+/* the count as `prefix`, then as many items as fit in it */
+pub fn encode_prefixed_array<T: Codec>(writer: PacketWriter, items: &[T], prefix: Scalar) -> PacketWriter {
+	let (writer, count) = write_count(writer, items.len(), prefix);
+	encode_items(writer, &items[..count])
+}
+
+/* exactly `size` bytes, cut off or zero padded */
+pub fn encode_fixed_bytes(writer: PacketWriter, bytes: &[u8], size: usize) -> PacketWriter {
+	let len = bytes.len().min(size);
+	let writer = writer.write_bytes(&bytes[..len]);
+	writer.write_bytes(&vec![0; size - len])
+}
+
+pub fn encode_prefixed_bytes(writer: PacketWriter, bytes: &[u8], prefix: Scalar) -> PacketWriter {
+	let (writer, len) = write_count(writer, bytes.len(), prefix);
+	writer.write_bytes(&bytes[..len])
+}
+
+/* writes `count`, capped to what `prefix` holds, and returns the count written */
+fn write_count(writer: PacketWriter, count: usize, prefix: Scalar) -> (PacketWriter, usize) {
+	match prefix {
+		Scalar::U16 => {
+			let count = count.min(u16::MAX as usize);
+			(writer.write_u16(count as u16), count)
+		},
+		Scalar::U32 => {
+			let count = count.min(u32::MAX as usize);
+			(writer.write_u32(count as u32), count)
+		},
+		_ => {
+			let count = count.min(u8::MAX as usize);
+			(writer.write_u8(count as u8), count)
+		},
+	}
+}
@@ -0,0 +1,189 @@
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/* buffers are handed out in these sizes; anything bigger is allocated directly */
+pub const SIZE_CLASSES: [usize; 6] = [64, 256, 1024, 4096, 16384, 65536];
+/* default amount of bytes kept in free buffers, over all size classes */
+pub const MAX_FREE_BYTES: usize = 8 * 1024 * 1024;
+
+static POOL: BufferPool = BufferPool {
+	classes: [
+		SizeClass::new(SIZE_CLASSES[0]),
+		SizeClass::new(SIZE_CLASSES[1]),
+		SizeClass::new(SIZE_CLASSES[2]),
+		SizeClass::new(SIZE_CLASSES[3]),
+		SizeClass::new(SIZE_CLASSES[4]),
+		SizeClass::new(SIZE_CLASSES[5]),
+	],
+	oversized:		AtomicUsize::new(0),
+	free_bytes:		AtomicUsize::new(0),
+	max_free_bytes:	AtomicUsize::new(MAX_FREE_BYTES),
+};
+
+struct BufferPool {
+	classes:		[SizeClass; 6],
+	oversized:		AtomicUsize,
+	free_bytes:		AtomicUsize,
+	max_free_bytes:	AtomicUsize,
+}
+
+struct SizeClass {
+	size:			usize,
+	free:			Mutex<Vec<Vec<u8>>>,
+	allocated:		AtomicUsize,
+	reused:			AtomicUsize,
+	returned:		AtomicUsize,
+	discarded:		AtomicUsize,
+}
+
+/* a buffer taken from the pool, it goes back when dropped */
+pub struct PooledBuf {
+	data:			Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassStats {
+	pub size:			usize,
+	/* buffers currently waiting in the pool */
+	pub free:			usize,
+	/* buffers that had to be allocated because the pool was empty */
+	pub allocated:		usize,
+	pub reused:			usize,
+	pub returned:		usize,
+	/* returned while the pool held `max_free_bytes` already */
+	pub discarded:		usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+	pub classes:		Vec<ClassStats>,
+	/* requests bigger than the largest size class */
+	pub oversized:		usize,
+	/* bytes held in free buffers, over all classes */
+	pub free_bytes:		usize,
+}
+
+impl SizeClass {
+	const fn new(size: usize) -> Self {
+		SizeClass {
+			size,
+			free:			Mutex::new(Vec::new()),
+			allocated:		AtomicUsize::new(0),
+			reused:			AtomicUsize::new(0),
+			returned:		AtomicUsize::new(0),
+			discarded:		AtomicUsize::new(0),
+		}
+	}
+
+	fn stats(&self) -> ClassStats {
+		ClassStats {
+			size:			self.size,
+			free:			self.free.lock().unwrap().len(),
+			allocated:		self.allocated.load(Ordering::Relaxed),
+			reused:			self.reused.load(Ordering::Relaxed),
+			returned:		self.returned.load(Ordering::Relaxed),
+			discarded:		self.discarded.load(Ordering::Relaxed),
+		}
+	}
+}
+
+impl BufferPool {
+	fn class_for(&self, size: usize) -> Option<&SizeClass> {
+		self.classes.iter().find(|c| c.size >= size)
+	}
+
+	fn take(&self, size: usize) -> Vec<u8> {
+		match self.class_for(size) {
+			Some(class) => {
+				let reused = class.free.lock().unwrap().pop();
+				match reused {
+					Some(data) => {
+						self.free_bytes.fetch_sub(data.len(), Ordering::Relaxed);
+						class.reused.fetch_add(1, Ordering::Relaxed);
+						data
+					},
+					None => {
+						class.allocated.fetch_add(1, Ordering::Relaxed);
+						vec![0; class.size]
+					},
+				}
+			},
+			None => {
+				self.oversized.fetch_add(1, Ordering::Relaxed);
+				vec![0; size]
+			},
+		}
+	}
+
+	fn give_back(&self, data: Vec<u8>) {
+		/* only buffers that came out of a size class go back into it */
+		if let Some(class) = self.classes.iter().find(|c| c.size == data.len()) {
+			/* reserve the bytes first, concurrent returns cannot overshoot */
+			let len = data.len();
+			let held = self.free_bytes.fetch_add(len, Ordering::Relaxed) + len;
+			if held <= self.max_free_bytes.load(Ordering::Relaxed) {
+				class.free.lock().unwrap().push(data);
+				class.returned.fetch_add(1, Ordering::Relaxed);
+			} else {
+				self.free_bytes.fetch_sub(len, Ordering::Relaxed);
+				class.discarded.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+	}
+}
+
+/* a buffer of at least `size` bytes; its contents are unspecified */
+pub fn take(size: usize) -> PooledBuf {
+	PooledBuf {
+		data:		POOL.take(size),
+	}
+}
+
+pub fn stats() -> PoolStats {
+	PoolStats {
+		classes:		POOL.classes.iter().map(|c| c.stats()).collect(),
+		oversized:		POOL.oversized.load(Ordering::Relaxed),
+		free_bytes:		POOL.free_bytes.load(Ordering::Relaxed),
+	}
+}
+
+/* how many bytes of free buffers are kept, over all size classes; buffers
+ * returned beyond that are freed. Lowering it frees nothing right away. */
+pub fn set_max_free_bytes(max_free_bytes: usize) {
+	POOL.max_free_bytes.store(max_free_bytes, Ordering::Relaxed);
+}
+
+impl PooledBuf {
+	/* wraps a buffer that did not come from the pool; it is only kept when
+	 * dropped if its length happens to match a size class */
+	pub fn from_vec(data: Vec<u8>) -> Self {
+		PooledBuf {
+			data,
+		}
+	}
+}
+
+impl Deref for PooledBuf {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.data[..]
+	}
+}
+
+impl DerefMut for PooledBuf {
+	fn deref_mut(&mut self) -> &mut [u8] {
+		&mut self.data[..]
+	}
+}
+
+impl Drop for PooledBuf {
+	fn drop(&mut self) {
+		let data = mem::take(&mut self.data);
+		if !data.is_empty() {
+			POOL.give_back(data);
+		}
+	}
+}
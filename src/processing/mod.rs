@@ -1,5 +1,6 @@
 // TMP
 mod packetproc;
+pub mod router;
 pub mod traits;
 
 
@@ -8,6 +9,10 @@ pub mod traits;
 pub use self::traits::{
 	PacketProcessor,
 };
+pub use self::router::{
+	PacketRouter,
+	RouteHandler,
+};
 // TMP
 pub use self::packetproc::{
 	PacketProcessingThreadPool,
@@ -1,27 +1,27 @@
 use std::thread::{JoinHandle, Builder};
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use chan::{Receiver, Sender, async};
 use client::*;
-
-pub trait PacketProcessor: Send + 'static {
-	  fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>);
-	  fn clone(&self) -> Box<PacketProcessor>;
-}
+use metrics::Metrics;
+use super::traits::PacketProcessor;
 
 pub struct PacketProcessingThreadPool {
 	  thread_handles:					Arc<RwLock<Vec<JoinHandle<()>>>>,
 	  packet_receiver:				Receiver<Arc<RwLock<Box<PacketProcessingInfo>>>>,
 	  packet_sender:					Sender<Arc<RwLock<Box<PacketProcessingInfo>>>>,
-	  processor:						Box<PacketProcessor>,
+	  processor:						Box<dyn PacketProcessor>,
+	  /* queue depth and per-opcode processing time */
+	  metrics:						Option<Arc<Metrics>>,
 }
 
 pub struct PacketProcessingInfo {
 	  pub packet:			Arc<RwLock<FiestaPacket>>,
-	  pub client:			Arc<RwLock<Box<FiestaNetworkClient>>>,
+	  pub client:			Arc<FiestaNetworkClient>,
 }
 
 impl PacketProcessingInfo {
-	  pub fn new(packet: FiestaPacket, client: Arc<RwLock<Box<FiestaNetworkClient>>>) -> Self {
+	  pub fn new(packet: FiestaPacket, client: Arc<FiestaNetworkClient>) -> Self {
 		    PacketProcessingInfo {
 			      packet:		Arc::new(RwLock::new(packet)),
 			      client:		client.clone(),
@@ -33,7 +33,11 @@ impl PacketProcessingInfo {
 // unsafe impl Send for PacketProcessingInfo { }
 
 impl PacketProcessingThreadPool {
-	  pub fn new(threads: usize, processor: Box<PacketProcessor>) -> PacketProcessingThreadPool {
+	  pub fn new(threads: usize, processor: Box<dyn PacketProcessor>) -> PacketProcessingThreadPool {
+		    PacketProcessingThreadPool::with_metrics(threads, processor, None)
+	  }
+
+	  pub fn with_metrics(threads: usize, processor: Box<dyn PacketProcessor>, metrics: Option<Arc<Metrics>>) -> PacketProcessingThreadPool {
 		    let (s, r) = async();
 
 		    let mut result = PacketProcessingThreadPool {
@@ -41,6 +45,7 @@ impl PacketProcessingThreadPool {
 			      packet_receiver:			r,
 			      packet_sender:				s,
 			      processor:					processor.clone(),
+			      metrics,
 		    };
 		    for i in 0..threads {
 			      result.start_new_thread(i);
@@ -53,12 +58,22 @@ impl PacketProcessingThreadPool {
 	  pub fn start_new_thread(&mut self, id: usize) {
 		    let rec = self.packet_receiver.clone();
 		    let mut processor = self.processor.clone();
+		    let metrics = self.metrics.clone();
 
 		    let handle = Builder::new()
 			      .name(format!("WRKR {}", id))
 			      .spawn(move || {
 				        for packet in rec.iter() {
-					          processor.process_packet(packet);
+					          match metrics {
+						            Some(ref metrics) => {
+							              metrics.dequeued();
+							              let opcode = packet.read().unwrap().packet.read().unwrap().header;
+							              let started = Instant::now();
+							              processor.process_packet(packet);
+							              metrics.processed(opcode, started.elapsed());
+						            },
+						            None => processor.process_packet(packet),
+					          }
 				        }
 			      }).unwrap();
 		    let mut handles = self.thread_handles.write().unwrap();
@@ -73,16 +88,20 @@ impl Clone for PacketProcessingThreadPool {
 			      packet_receiver:		self.packet_receiver.clone(),
 			      packet_sender:			self.packet_sender.clone(),
 			      processor:	   			self.processor.clone(),
+			      metrics:					self.metrics.clone(),
 		    }
 	  }
 }
 
 impl PacketProcessor for PacketProcessingThreadPool {
 	  fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		    if let Some(ref metrics) = self.metrics {
+			      metrics.enqueued();
+		    }
 		    self.packet_sender.send(info);
 	  }
 
-	  fn clone(&self) -> Box<PacketProcessor> {
-		    Box::new(<PacketProcessingThreadPool as Clone>::clone(&self))
+	  fn clone(&self) -> Box<dyn PacketProcessor> {
+		    Box::new(<PacketProcessingThreadPool as Clone>::clone(self))
 	  }
 }
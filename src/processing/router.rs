@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use opcodes::Named;
+use stream::ListenerTag;
+use super::packetproc::PacketProcessingInfo;
+use super::traits::PacketProcessor;
+
+pub type RouteHandler = dyn Fn(&PacketProcessingInfo) + Send + Sync;
+
+#[derive(Clone)]
+struct Route {
+	handler:		Arc<RouteHandler>,
+	/* `None` accepts the opcode on every listener */
+	listeners:		Option<Vec<ListenerTag>>,
+}
+
+/* dispatches packets to a handler per opcode. Routes can be restricted to
+ * some listeners, e.g. zone server opcodes to the internal one. */
+#[derive(Clone)]
+pub struct PacketRouter {
+	routes:			HashMap<u16, Route>,
+	fallback:		Option<Arc<RouteHandler>>,
+}
+
+impl PacketRouter {
+	pub fn new() -> Self {
+		PacketRouter {
+			routes:		HashMap::new(),
+			fallback:	None,
+		}
+	}
+
+	pub fn route<F>(mut self, opcode: u16, handler: F) -> Self
+			where F: Fn(&PacketProcessingInfo) + Send + Sync + 'static {
+		self.routes.insert(opcode, Route {
+			handler:	Arc::new(handler),
+			listeners:	None,
+		});
+		self
+	}
+
+	/* only clients that connected through one of `listeners` reach `handler` */
+	pub fn route_on<F>(mut self, opcode: u16, listeners: &[ListenerTag], handler: F) -> Self
+			where F: Fn(&PacketProcessingInfo) + Send + Sync + 'static {
+		self.routes.insert(opcode, Route {
+			handler:	Arc::new(handler),
+			listeners:	Some(listeners.to_vec()),
+		});
+		self
+	}
+
+	/* gets the packets without a route */
+	pub fn fallback<F>(mut self, handler: F) -> Self
+			where F: Fn(&PacketProcessingInfo) + Send + Sync + 'static {
+		self.fallback = Some(Arc::new(handler));
+		self
+	}
+
+	/* whether a packet with `opcode` from `listener` would be handled */
+	pub fn allows(&self, opcode: u16, listener: ListenerTag) -> bool {
+		match self.routes.get(&opcode) {
+			Some(&Route { listeners: Some(ref listeners), .. }) => listeners.contains(&listener),
+			Some(_) => true,
+			None => self.fallback.is_some(),
+		}
+	}
+}
+
+impl Default for PacketRouter {
+	fn default() -> Self {
+		PacketRouter::new()
+	}
+}
+
+impl PacketProcessor for PacketRouter {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let opcode = info.packet.read().unwrap().header;
+		let listener = info.client.listener();
+		match self.routes.get(&opcode) {
+			Some(route) => {
+				if let Some(ref listeners) = route.listeners {
+					if !listeners.contains(&listener) {
+						warn!(target: "network", "dropped packet {} from {:?}, not allowed on listener {}",
+							Named(opcode), info.client.id(), listener);
+						return;
+					}
+				}
+				(route.handler)(&info);
+			},
+			None => match self.fallback {
+				Some(ref fallback) => fallback(&info),
+				None => debug!(target: "network", "no route for packet {} from {:?}", Named(opcode), info.client.id()),
+			},
+		}
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(<PacketRouter as Clone>::clone(self))
+	}
+}
@@ -0,0 +1,341 @@
+/* A logging man-in-the-middle proxy. Every client that connects gets its own
+ * connection to the upstream server; packets are framed, decrypted, logged,
+ * passed through the hooks and encrypted again for the other side. Both legs
+ * use `Connection`, so the framing is the server's, except that bodies are
+ * not limited to `MAX_BODY_SIZE`: whatever either side sends passes through.
+ * The legs see the packets encrypted, `dump` tracing happens here instead. */
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use mio::*;
+use mio::tcp::*;
+
+use capture::Direction;
+use cipher::XorCipher;
+use client::{frame_large_packet, Connection, FiestaPacket, MAX_WIRE_BODY_SIZE};
+use config::READ_BUDGET;
+use dump::{self, pretty_packet};
+use opcodes::Named;
+
+const LISTENER: Token = Token(0);
+
+/* which directions are encrypted, and how the seed is learned */
+#[derive(Debug, Clone)]
+pub struct ProxyCipher {
+	pub key:				Arc<Vec<u8>>,
+	pub client_to_server:	bool,
+	pub server_to_client:	bool,
+	/* server packet carrying the seed (u16) for the client to server
+	 * direction, e.g. NC_MISC_SEED_ACK; `None` starts at offset 0 */
+	pub seed_opcode:		Option<u16>,
+}
+
+#[derive(Clone)]
+pub struct ProxyConfig {
+	pub upstream:			SocketAddr,
+	pub cipher:				Option<ProxyCipher>,
+	/* dump the payloads, annotated where a schema is registered */
+	pub dump:				bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+	Forward,
+	Drop,
+}
+
+/* sees a decrypted packet before it is forwarded and may change it */
+pub type ProxyHook = dyn Fn(Direction, &mut FiestaPacket) -> HookAction + Send;
+
+/* hooks per opcode, plus ones that see every packet */
+#[derive(Default)]
+pub struct ProxyHooks {
+	opcodes:		HashMap<u16, Vec<Box<ProxyHook>>>,
+	all:			Vec<Box<ProxyHook>>,
+}
+
+/* one side of a proxied session */
+struct Leg {
+	connection:		Connection,
+	/* the other side of the session */
+	peer:			Token,
+	/* what this leg receives: inbound from the client, outbound from the server */
+	direction:		Direction,
+	decrypt:		Option<XorCipher>,
+	encrypt:		Option<XorCipher>,
+}
+
+pub struct ProxyHandler {
+	listener:		TcpListener,
+	config:			ProxyConfig,
+	hooks:			ProxyHooks,
+	legs:			HashMap<Token, Leg>,
+	next_token:		usize,
+}
+
+impl ProxyHooks {
+	pub fn new() -> Self {
+		ProxyHooks::default()
+	}
+
+	pub fn on<F>(mut self, opcode: u16, hook: F) -> Self
+			where F: Fn(Direction, &mut FiestaPacket) -> HookAction + Send + 'static {
+		self.opcodes.entry(opcode).or_default().push(Box::new(hook));
+		self
+	}
+
+	pub fn on_all<F>(mut self, hook: F) -> Self
+			where F: Fn(Direction, &mut FiestaPacket) -> HookAction + Send + 'static {
+		self.all.push(Box::new(hook));
+		self
+	}
+
+	fn run(&self, direction: Direction, packet: &mut FiestaPacket) -> HookAction {
+		for hook in &self.all {
+			if hook(direction, packet) == HookAction::Drop {
+				return HookAction::Drop;
+			}
+		}
+		/* picked after the catch-all hooks ran, which may have changed the opcode */
+		if let Some(hooks) = self.opcodes.get(&packet.header) {
+			for hook in hooks {
+				if hook(direction, packet) == HookAction::Drop {
+					return HookAction::Drop;
+				}
+			}
+		}
+		HookAction::Forward
+	}
+}
+
+impl ProxyHandler {
+	/* the listener has to be registered with `Token(0)` */
+	pub fn new(listener: TcpListener, config: ProxyConfig, hooks: ProxyHooks) -> Self {
+		ProxyHandler {
+			listener,
+			config,
+			hooks,
+			legs:			HashMap::new(),
+			next_token:		LISTENER.0 + 1,
+		}
+	}
+
+	pub fn sessions(&self) -> usize {
+		self.legs.len() / 2
+	}
+
+	fn accept(&mut self, event_loop: &mut EventLoop<Self>) {
+		let client = match self.listener.accept() {
+			Ok(Some(client)) => client,
+			Ok(None) => return,
+			Err(e) => {
+				warn!(target: "proxy", "error while accepting client: {:#?}", e);
+				return;
+			},
+		};
+		let server = match TcpStream::connect(&self.config.upstream) {
+			Ok(server) => server,
+			Err(e) => {
+				warn!(target: "proxy", "could not connect to {}: {:#?}", self.config.upstream, e);
+				let _ = client.shutdown(Shutdown::Both);
+				return;
+			},
+		};
+		let client_token = Token(self.next_token);
+		let server_token = Token(self.next_token + 1);
+		self.next_token += 2;
+
+		let cipher = |enabled: bool| match self.config.cipher {
+			Some(ref cipher) if enabled => Some(XorCipher::new(cipher.key.clone(), 0)),
+			_ => None,
+		};
+		let (c2s, s2c) = match self.config.cipher {
+			Some(ref cipher) => (cipher.client_to_server, cipher.server_to_client),
+			None => (false, false),
+		};
+		let leg_connection = |socket: TcpStream, token: Token| {
+			let mut connection = Connection::new(socket, token, None);
+			connection.set_max_body(MAX_WIRE_BODY_SIZE);
+			connection.set_traced(false);
+			connection
+		};
+		let client_leg = Leg {
+			connection:		leg_connection(client, client_token),
+			peer:			server_token,
+			direction:		Direction::Inbound,
+			decrypt:		cipher(c2s),
+			encrypt:		cipher(s2c),
+		};
+		let server_leg = Leg {
+			connection:		leg_connection(server, server_token),
+			peer:			client_token,
+			direction:		Direction::Outbound,
+			decrypt:		cipher(s2c),
+			encrypt:		cipher(c2s),
+		};
+		for leg in &[&client_leg, &server_leg] {
+			let token = leg.connection.id();
+			if let Err(e) = event_loop.register_opt(leg.connection.socket(), token, EventSet::all(), PollOpt::oneshot()) {
+				warn!(target: "proxy", "could not register {:?}: {:#?}", token, e);
+			}
+		}
+		info!(target: "proxy", "session {:?} from {:?} to {}", client_token,
+			client_leg.connection.client().peer_addr(), self.config.upstream);
+		self.legs.insert(client_token, client_leg);
+		self.legs.insert(server_token, server_leg);
+	}
+
+	fn leg_ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
+		let mut disconnect = false;
+		let mut packets = Vec::new();
+		let (peer, direction) = match self.legs.get_mut(&token) {
+			Some(leg) => {
+				if events.is_readable() {
+					leg.connection.readable(&mut disconnect, READ_BUDGET);
+					while let Some(packet) = leg.connection.pop_packet() {
+						packets.push(packet);
+					}
+				}
+				if events.is_writable() && !disconnect {
+					leg.connection.writeable(&mut disconnect);
+				}
+				if events.is_hup() || events.is_error() {
+					disconnect = true;
+				}
+				(leg.peer, leg.direction)
+			},
+			None => return,
+		};
+
+		for packet in packets {
+			self.forward(token, peer, direction, packet);
+		}
+
+		let mut peer_disconnect = false;
+		if let Some(leg) = self.legs.get_mut(&peer) {
+			leg.connection.writeable(&mut peer_disconnect);
+		}
+		if disconnect || peer_disconnect {
+			self.close_session(event_loop, token);
+		} else {
+			self.reregister(event_loop, token);
+			self.reregister(event_loop, peer);
+		}
+	}
+
+	/* decrypts, logs and hooks a packet read from `from`, then queues it on `to` */
+	fn forward(&mut self, from: Token, to: Token, direction: Direction, packet: FiestaPacket) {
+		let mut packet = match self.legs.get_mut(&from).and_then(|leg| leg.decrypt.as_mut()) {
+			Some(cipher) => {
+				let (header, body) = cipher.apply_packet(packet.header, &packet.data[..]);
+				FiestaPacket::from_vec(header, body)
+			},
+			None => packet,
+		};
+		dump::trace_packet(from, direction, packet.header, &packet.data[..]);
+
+		let arrow = match direction {
+			Direction::Inbound => "C->S",
+			Direction::Outbound => "S->C",
+		};
+		if self.config.dump {
+			info!(target: "proxy", "{:?} {} {}", from, arrow, pretty_packet(packet.header, &packet.data[..]));
+		} else {
+			info!(target: "proxy", "{:?} {} {} {} bytes", from, arrow, Named(packet.header), packet.data.len());
+		}
+
+		if direction == Direction::Outbound {
+			self.learn_seed(to, from, &packet);
+		}
+		if self.hooks.run(direction, &mut packet) == HookAction::Drop {
+			info!(target: "proxy", "{:?} {} {} dropped by a hook", from, arrow, Named(packet.header));
+			return;
+		}
+
+		if let Some(leg) = self.legs.get_mut(&to) {
+			let frame = match leg.encrypt {
+				Some(ref mut cipher) => {
+					let (header, body) = cipher.apply_packet(packet.header, &packet.data[..]);
+					frame_large_packet(header, &body[..])
+				},
+				None => frame_large_packet(packet.header, &packet.data[..]),
+			};
+			leg.connection.client().append_send(&frame[..]);
+		}
+	}
+
+	/* the seed the server sends starts the client to server cipher, for the
+	 * client's encryption as well as for ours towards the server */
+	fn learn_seed(&mut self, client: Token, server: Token, packet: &FiestaPacket) {
+		let seed_opcode = match self.config.cipher {
+			Some(ProxyCipher { seed_opcode: Some(opcode), client_to_server: true, .. }) => opcode,
+			_ => return,
+		};
+		if packet.header != seed_opcode {
+			return;
+		}
+		let seed = match packet.reader().read_u16() {
+			Ok(seed) => seed,
+			Err(e) => {
+				warn!(target: "proxy", "{}", e);
+				return;
+			},
+		};
+		info!(target: "proxy", "{:?} seed {}", client, seed);
+		if let Some(cipher) = self.legs.get_mut(&client).and_then(|leg| leg.decrypt.as_mut()) {
+			cipher.set_seed(seed);
+		}
+		if let Some(cipher) = self.legs.get_mut(&server).and_then(|leg| leg.encrypt.as_mut()) {
+			cipher.set_seed(seed);
+		}
+	}
+
+	fn reregister(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+		if let Some(leg) = self.legs.get(&token) {
+			let socket = leg.connection.socket();
+			if let Err(e) = event_loop.reregister(socket, token, leg.connection.interest(), PollOpt::oneshot()) {
+				warn!(target: "proxy", "could not re-register {:?}: {:#?}", token, e);
+			}
+		}
+	}
+
+	fn close_session(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+		let peer = match self.legs.get(&token) {
+			Some(leg) => leg.peer,
+			None => return,
+		};
+		for token in &[token, peer] {
+			if let Some(mut leg) = self.legs.remove(token) {
+				/* whatever the closing side sent last still goes out */
+				let mut disconnect = false;
+				leg.connection.writeable(&mut disconnect);
+				let _ = event_loop.deregister(leg.connection.socket());
+				leg.connection.shutdown();
+			}
+		}
+		info!(target: "proxy", "session {:?} closed", token);
+	}
+}
+
+impl Handler for ProxyHandler {
+	type Timeout = ();
+	type Message = ();
+
+	fn ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
+		if token == LISTENER {
+			self.accept(event_loop);
+		} else {
+			self.leg_ready(event_loop, token, events);
+		}
+	}
+}
+
+/* binds `listen` and proxies to `config.upstream` until the loop fails */
+pub fn run(listen: &SocketAddr, config: ProxyConfig, hooks: ProxyHooks) -> io::Result<()> {
+	let listener = TcpListener::bind(listen)?;
+	let mut event_loop = EventLoop::new()?;
+	event_loop.register_opt(&listener, LISTENER, EventSet::readable(), PollOpt::level())?;
+	let mut handler = ProxyHandler::new(listener, config, hooks);
+	event_loop.run(&mut handler)
+}
@@ -0,0 +1,308 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+/* refills at `rate` tokens per second, holds at most `burst` tokens. A
+ * bucket that never refills would hold delayed packets back forever, so the
+ * rate must be positive. */
+#[derive(Debug, Clone, Copy)]
+pub struct BucketConfig {
+	pub rate:		f64,
+	pub burst:		f64,
+}
+
+pub struct TokenBucket {
+	config:			BucketConfig,
+	tokens:			f64,
+	last_refill:	Instant,
+}
+
+/* what happens to a packet that exceeds one of the limits */
+#[derive(Debug, Clone)]
+pub enum RateLimitAction {
+	/* silently discard the packet */
+	Drop,
+	/* hold the packet back until the buckets allow it, keeping at most
+	 * `max_queued` packets per client; anything beyond that is dropped */
+	Delay {
+		max_queued:		usize,
+	},
+	/* kick the client, logging the reason */
+	Disconnect(String),
+}
+
+#[derive(Clone)]
+pub struct RateLimitConfig {
+	pub per_connection:		Option<BucketConfig>,
+	pub action:				RateLimitAction,
+	groups:					Vec<BucketConfig>,
+	opcode_groups:			HashMap<u16, usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+	Allow,
+	/* the packet exceeded a limit, a token will be available after the duration */
+	Exceeded(Duration),
+}
+
+/* per client state, created from a `RateLimitConfig` */
+pub struct RateLimiter {
+	per_connection:		Option<TokenBucket>,
+	groups:				Vec<TokenBucket>,
+}
+
+/* how many packets were affected by rate limiting */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+	pub dropped:		u64,
+	pub delayed:		u64,
+	pub disconnects:	u64,
+}
+
+impl BucketConfig {
+	pub fn new(rate: f64, burst: f64) -> Self {
+		assert!(rate > 0.0, "bucket rate of {} tokens per second, it must be positive", rate);
+		BucketConfig {
+			rate,
+			burst,
+		}
+	}
+}
+
+impl TokenBucket {
+	pub fn new(config: BucketConfig, now: Instant) -> Self {
+		TokenBucket {
+			config,
+			tokens:			config.burst,
+			last_refill:	now,
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		if now > self.last_refill {
+			let elapsed = now.duration_since(self.last_refill);
+			let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+			self.tokens = (self.tokens + elapsed * self.config.rate).min(self.config.burst);
+			self.last_refill = now;
+		}
+	}
+
+	/* how long it takes until a whole token is available */
+	pub fn wait_time(&mut self, now: Instant) -> Duration {
+		self.refill(now);
+		if self.tokens >= 1.0 || self.config.rate <= 0.0 {
+			Duration::from_millis(0)
+		} else {
+			let secs = (1.0 - self.tokens) / self.config.rate;
+			Duration::from_millis((secs * 1000.0).ceil() as u64)
+		}
+	}
+
+	pub fn has_token(&mut self, now: Instant) -> bool {
+		self.refill(now);
+		self.tokens >= 1.0
+	}
+
+	fn take(&mut self) {
+		self.tokens -= 1.0;
+	}
+}
+
+impl RateLimitConfig {
+	pub fn new(action: RateLimitAction) -> Self {
+		RateLimitConfig {
+			per_connection:		None,
+			action,
+			groups:				Vec::new(),
+			opcode_groups:		HashMap::new(),
+		}
+	}
+
+	pub fn limit_connection(mut self, bucket: BucketConfig) -> Self {
+		self.per_connection = Some(bucket);
+		self
+	}
+
+	pub fn limit_opcode(self, opcode: u16, bucket: BucketConfig) -> Self {
+		self.limit_group(&[opcode], bucket)
+	}
+
+	/* all opcodes of a group share a single bucket */
+	pub fn limit_group(mut self, opcodes: &[u16], bucket: BucketConfig) -> Self {
+		let group = self.groups.len();
+		self.groups.push(bucket);
+		for opcode in opcodes {
+			self.opcode_groups.insert(*opcode, group);
+		}
+		self
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.per_connection.is_none() && self.groups.is_empty()
+	}
+
+	/* catches buckets filled in by hand, `BucketConfig::new` checks the rest */
+	pub fn validate(&self) -> io::Result<()> {
+		for bucket in self.per_connection.iter().chain(self.groups.iter()) {
+			if bucket.rate.is_nan() || bucket.rate <= 0.0 {
+				return Err(Error::new(ErrorKind::InvalidInput, format!("bucket rate of {} tokens per second, it must be positive", bucket.rate)));
+			}
+		}
+		Ok(())
+	}
+
+	pub fn limiter(&self) -> RateLimiter {
+		let now = Instant::now();
+		RateLimiter {
+			per_connection:		self.per_connection.map(|c| TokenBucket::new(c, now)),
+			groups:				self.groups.iter().map(|c| TokenBucket::new(*c, now)).collect(),
+		}
+	}
+}
+
+impl RateLimitStats {
+	pub fn merge(&mut self, other: &RateLimitStats) {
+		self.dropped += other.dropped;
+		self.delayed += other.delayed;
+		self.disconnects += other.disconnects;
+	}
+}
+
+impl RateLimiter {
+	/* takes a token from every bucket the opcode is subject to, if all of them have one */
+	pub fn check(&mut self, config: &RateLimitConfig, opcode: u16, now: Instant) -> Verdict {
+		let mut wait = Duration::from_millis(0);
+		let mut allowed = true;
+
+		if let Some(ref mut bucket) = self.per_connection {
+			if !bucket.has_token(now) {
+				allowed = false;
+				wait = wait.max(bucket.wait_time(now));
+			}
+		}
+		let group = config.opcode_groups.get(&opcode).cloned();
+		if let Some(group) = group {
+			let bucket = &mut self.groups[group];
+			if !bucket.has_token(now) {
+				allowed = false;
+				wait = wait.max(bucket.wait_time(now));
+			}
+		}
+
+		if !allowed {
+			return Verdict::Exceeded(wait);
+		}
+		if let Some(ref mut bucket) = self.per_connection {
+			bucket.take();
+		}
+		if let Some(group) = group {
+			self.groups[group].take();
+		}
+		Verdict::Allow
+	}
+}
+
+/* the rate limiting state of a single client, including packets held back by
+ * `RateLimitAction::Delay` */
+pub struct RateLimitState<T> {
+	limiter:			RateLimiter,
+	delayed:			VecDeque<T>,
+	timer_pending:		bool,
+}
+
+pub struct Admission<T> {
+	/* packets that may be processed now, in order */
+	pub allowed:		Vec<T>,
+	/* a release timer should be scheduled for delayed packets */
+	pub retry_after:	Option<Duration>,
+	/* the client exceeded its limits and should be disconnected */
+	pub disconnect:		Option<String>,
+}
+
+impl<T> RateLimitState<T> {
+	pub fn new(config: &RateLimitConfig) -> Self {
+		RateLimitState {
+			limiter:		config.limiter(),
+			delayed:		VecDeque::new(),
+			timer_pending:	false,
+		}
+	}
+
+	pub fn delayed_count(&self) -> usize {
+		self.delayed.len()
+	}
+
+	/* must be called when the timer requested through `Admission::retry_after` fired */
+	pub fn timer_fired(&mut self) {
+		self.timer_pending = false;
+	}
+
+	pub fn admit<F>(&mut self, config: &RateLimitConfig, incoming: Vec<T>, opcode: F, stats: &mut RateLimitStats) -> Admission<T>
+			where F: Fn(&T) -> u16 {
+		let now = Instant::now();
+		let mut admission = Admission {
+			allowed:		Vec::with_capacity(incoming.len()),
+			retry_after:	None,
+			disconnect:		None,
+		};
+
+		match config.action {
+			RateLimitAction::Drop => {
+				for packet in incoming {
+					match self.limiter.check(config, opcode(&packet), now) {
+						Verdict::Allow => admission.allowed.push(packet),
+						Verdict::Exceeded(_) => stats.dropped += 1,
+					}
+				}
+			},
+			RateLimitAction::Disconnect(ref reason) => {
+				for packet in incoming {
+					match self.limiter.check(config, opcode(&packet), now) {
+						Verdict::Allow => admission.allowed.push(packet),
+						Verdict::Exceeded(_) => {
+							stats.disconnects += 1;
+							admission.disconnect = Some(reason.clone());
+							break;
+						},
+					}
+				}
+			},
+			RateLimitAction::Delay { max_queued } => {
+				/* new packets queue up behind the ones already waiting, to keep the order */
+				let mut queued = 0;
+				for packet in incoming {
+					if self.delayed.len() >= max_queued {
+						stats.dropped += 1;
+					} else {
+						self.delayed.push_back(packet);
+						queued += 1;
+					}
+				}
+
+				let mut wait = None;
+				let limiter = &mut self.limiter;
+				while let Some(verdict) = self.delayed.front().map(|p| limiter.check(config, opcode(p), now)) {
+					match verdict {
+						Verdict::Allow => admission.allowed.push(self.delayed.pop_front().unwrap()),
+						Verdict::Exceeded(after) => {
+							wait = Some(after);
+							break;
+						},
+					}
+				}
+
+				/* the newest packets are at the back of the queue */
+				stats.delayed += queued.min(self.delayed.len()) as u64;
+				if !self.timer_pending {
+					if let Some(after) = wait {
+						self.timer_pending = true;
+						admission.retry_after = Some(after);
+					}
+				}
+			},
+		}
+
+		admission
+	}
+}
@@ -0,0 +1,209 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use buffer::ByteCursor;
+use client::FiestaPacket;
+
+/* a cursor over the body of a `FiestaPacket`. Reads are not destructive, so a
+ * decoder can `seek` back and try another layout. */
+pub struct PacketReader<'a> {
+	opcode:			u16,
+	cursor:			ByteCursor<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+	/* a read needed more bytes than were left */
+	UnexpectedEnd {
+		wanted:			usize,
+		remaining:		usize,
+	},
+	/* `expect_end` found bytes that were not decoded */
+	TrailingBytes(usize),
+	/* `seek`/`skip` to a position past the end of the body, `usize::MAX` if
+	 * the position does not even fit */
+	OutOfBounds(usize),
+	/* the bytes were there but made no sense, e.g. a bad enum value */
+	Invalid(String),
+}
+
+/* where decoding failed: the opcode of the packet and the offset in its body */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+	pub opcode:			u16,
+	pub offset:			usize,
+	pub kind:			DecodeErrorKind,
+}
+
+impl<'a> PacketReader<'a> {
+	pub fn new(packet: &'a FiestaPacket) -> Self {
+		PacketReader::from_slice(packet.header, &packet.data[..])
+	}
+
+	pub fn from_slice(opcode: u16, body: &'a [u8]) -> Self {
+		PacketReader {
+			opcode,
+			cursor:		ByteCursor::new(body),
+		}
+	}
+
+	pub fn opcode(&self) -> u16 {
+		self.opcode
+	}
+
+	pub fn position(&self) -> usize {
+		self.cursor.position()
+	}
+
+	pub fn remaining(&self) -> usize {
+		self.cursor.remaining()
+	}
+
+	pub fn len(&self) -> usize {
+		self.cursor.position() + self.cursor.remaining()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn seek(&mut self, position: usize) -> Result<(), DecodeError> {
+		if position > self.len() {
+			Err(self.error(DecodeErrorKind::OutOfBounds(position)))
+		} else {
+			self.cursor.set_position(position).unwrap();
+			Ok(())
+		}
+	}
+
+	/* `size` may come from the peer, so it can be anything */
+	pub fn skip(&mut self, size: usize) -> Result<(), DecodeError> {
+		match self.position().checked_add(size) {
+			Some(position) => self.seek(position),
+			None => Err(self.error(DecodeErrorKind::OutOfBounds(usize::MAX))),
+		}
+	}
+
+	pub fn rewind(&mut self) {
+		self.cursor.set_position(0).unwrap();
+	}
+
+	/* fails if the body has bytes left that were not decoded */
+	pub fn expect_end(&self) -> Result<(), DecodeError> {
+		match self.remaining() {
+			0 => Ok(()),
+			trailing => Err(self.error(DecodeErrorKind::TrailingBytes(trailing))),
+		}
+	}
+
+	/* an error at the current position, for checks done by the caller */
+	pub fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+		DecodeError {
+			opcode:		self.opcode,
+			offset:		self.position(),
+			kind,
+		}
+	}
+
+	pub fn invalid<S: Into<String>>(&self, message: S) -> DecodeError {
+		self.error(DecodeErrorKind::Invalid(message.into()))
+	}
+
+	fn check(&self, wanted: usize) -> Result<(), DecodeError> {
+		let remaining = self.remaining();
+		if remaining < wanted {
+			Err(self.error(DecodeErrorKind::UnexpectedEnd {
+				wanted,
+				remaining,
+			}))
+		} else {
+			Ok(())
+		}
+	}
+
+	pub fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], DecodeError> {
+		self.check(size)?;
+		Ok(self.cursor.read_slice(size).unwrap())
+	}
+
+	/* a fixed size, zero padded string */
+	pub fn read_string(&mut self, size: usize) -> Result<String, DecodeError> {
+		let bytes = self.read_bytes(size)?;
+		let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+		Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+		let bytes = self.read_bytes(1)?;
+		Ok(bytes[0])
+	}
+
+	pub fn read_i8(&mut self) -> Result<i8, DecodeError> {
+		let bytes = self.read_bytes(1)?;
+		Ok(bytes[0] as i8)
+	}
+
+	pub fn read_bool(&mut self) -> Result<bool, DecodeError> {
+		Ok(self.read_u8()? != 0)
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16, DecodeError> {
+		let bytes = self.read_bytes(2)?;
+		Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+	}
+
+	pub fn read_i16(&mut self) -> Result<i16, DecodeError> {
+		Ok(self.read_u16()? as i16)
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+		let bytes = self.read_bytes(4)?;
+		Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+
+	pub fn read_i32(&mut self) -> Result<i32, DecodeError> {
+		Ok(self.read_u32()? as i32)
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+		let bytes = self.read_bytes(8)?;
+		Ok(u64::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]))
+	}
+
+	pub fn read_i64(&mut self) -> Result<i64, DecodeError> {
+		Ok(self.read_u64()? as i64)
+	}
+}
+
+impl FiestaPacket {
+	pub fn reader(&self) -> PacketReader<'_> {
+		PacketReader::new(self)
+	}
+}
+
+impl fmt::Display for DecodeErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			DecodeErrorKind::UnexpectedEnd { wanted, remaining } =>
+				write!(f, "wanted {} bytes but only {} are left", wanted, remaining),
+			DecodeErrorKind::TrailingBytes(trailing) => write!(f, "{} trailing bytes", trailing),
+			DecodeErrorKind::OutOfBounds(position) => write!(f, "position {} is out of bounds", position),
+			DecodeErrorKind::Invalid(ref message) => write!(f, "{}", message),
+		}
+	}
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "decoding packet 0x{:04X} failed at offset {}: {}", self.opcode, self.offset, self.kind)
+	}
+}
+
+impl error::Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+	fn from(e: DecodeError) -> io::Error {
+		io::Error::new(io::ErrorKind::InvalidData, e)
+	}
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use mio::Token;
+
+use client::{FiestaNetworkClient, SERVER_TOKEN};
+use limits::ConnectionTracker;
+
+/* all clients of a server, shared by every event loop (reactor) it runs.
+ * Tokens are unique across the reactors. */
+pub struct ClientRegistry {
+	clients:		RwLock<HashMap<Token, Arc<FiestaNetworkClient>>>,
+	next_token:		AtomicUsize,
+	connections:	Mutex<ConnectionTracker>,
+	loads:			Vec<AtomicUsize>,
+}
+
+impl ClientRegistry {
+	pub fn new() -> Self {
+		ClientRegistry::with_reactors(1)
+	}
+
+	pub fn with_reactors(reactors: usize) -> Self {
+		ClientRegistry {
+			clients:		RwLock::new(HashMap::new()),
+			next_token:		AtomicUsize::new(SERVER_TOKEN.0 + 1),
+			connections:	Mutex::new(ConnectionTracker::new()),
+			loads:			(0..reactors.max(1)).map(|_| AtomicUsize::new(0)).collect(),
+		}
+	}
+
+	/* tokens below this are reserved for listeners and other non-client sockets */
+	pub fn reserve_tokens(&self, below: usize) {
+		let mut current = self.next_token.load(Ordering::Relaxed);
+		while current < below {
+			match self.next_token.compare_exchange(current, below, Ordering::AcqRel, Ordering::Relaxed) {
+				Ok(_) => break,
+				Err(actual) => current = actual,
+			}
+		}
+	}
+
+	pub fn next_token(&self) -> Token {
+		Token(self.next_token.fetch_add(1, Ordering::Relaxed))
+	}
+
+	pub fn reactors(&self) -> usize {
+		self.loads.len()
+	}
+
+	/* the amount of clients served by a reactor */
+	pub fn load(&self, reactor: usize) -> usize {
+		self.loads[reactor].load(Ordering::Relaxed)
+	}
+
+	pub fn least_loaded(&self) -> usize {
+		(0..self.reactors()).min_by_key(|r| self.load(*r)).unwrap_or(0)
+	}
+
+	/* counts a client towards a reactor's load as soon as it is handed out,
+	 * before the reactor got to `insert` it */
+	pub fn assign(&self, reactor: usize) {
+		self.loads[reactor].fetch_add(1, Ordering::Relaxed);
+	}
+
+	/* undoes `assign` for a client that never made it into the registry */
+	pub fn unassign(&self, reactor: usize) {
+		self.loads[reactor].fetch_sub(1, Ordering::Relaxed);
+	}
+
+	pub fn insert(&self, client: Arc<FiestaNetworkClient>) {
+		self.clients.write().unwrap().insert(client.id(), client);
+	}
+
+	pub fn remove(&self, reactor: usize, token: Token) -> Option<Arc<FiestaNetworkClient>> {
+		let removed = self.clients.write().unwrap().remove(&token);
+		if removed.is_some() {
+			self.unassign(reactor);
+		}
+		removed
+	}
+
+	pub fn get(&self, token: Token) -> Option<Arc<FiestaNetworkClient>> {
+		self.clients.read().unwrap().get(&token).cloned()
+	}
+
+	pub fn len(&self) -> usize {
+		self.clients.read().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/* a snapshot of all connected clients */
+	pub fn clients(&self) -> Vec<Arc<FiestaNetworkClient>> {
+		self.clients.read().unwrap().values().cloned().collect()
+	}
+
+	/* connection counts used for the `ConnectionLimits`, shared by all reactors */
+	pub fn connections(&self) -> &Mutex<ConnectionTracker> {
+		&self.connections
+	}
+}
+
+impl Default for ClientRegistry {
+	fn default() -> Self {
+		ClientRegistry::new()
+	}
+}
@@ -0,0 +1,385 @@
+/* Replays the inbound packets of a captured client and compares what comes
+ * back with what was recorded, see `capture` for the file format. Captures
+ * hold decrypted packets; against a server that hands out seeds they are
+ * encrypted again from the seed on, given the key table. */
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use mio::Token;
+
+use capture::{CaptureEntry, CaptureReader, Direction};
+use cipher::{XorCipher, SEED_OPCODE};
+use client::{frame_large_packet, split_frames};
+use harness::Harness;
+use processing::PacketProcessor;
+
+/* how long `replay_against` waits for the seed before sending anything */
+pub const SEED_TIMEOUT_MS: u64 = 5000;
+
+/* one client's part of a capture */
+#[derive(Debug, Clone)]
+pub struct Session {
+	pub token:			Token,
+	pub peer:			Option<SocketAddr>,
+	/* in capture order, both directions */
+	pub entries:		Vec<CaptureEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+	/* send everything right away */
+	Immediate,
+	/* wait as long as the client did between packets, divided by the speed */
+	Original {
+		speed:		f64,
+	},
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+	pub timing:			Timing,
+	/* opcodes left out of the comparison, e.g. ones carrying the time */
+	pub ignore:			HashSet<u16>,
+	/* a live server is done answering once it was quiet for this long */
+	pub settle:			Duration,
+	/* the packet the server sends the seed with, it is left out of the
+	 * comparison since every connection gets another seed */
+	pub seed_opcode:	Option<u16>,
+	/* encrypts the replayed packets from the seed on, like the game client */
+	pub key:			Option<Arc<Vec<u8>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+	pub opcode:			u16,
+	pub payload:		Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+	/* recorded, but not sent this time */
+	Missing {
+		index:			usize,
+		opcode:			u16,
+	},
+	/* sent this time, but not recorded */
+	Unexpected {
+		index:			usize,
+		opcode:			u16,
+	},
+	/* `offset` is the first differing byte of the payload */
+	Mismatch {
+		index:			usize,
+		expected:		u16,
+		actual:			u16,
+		offset:			Option<usize>,
+	},
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+	pub token:			Token,
+	/* inbound packets replayed */
+	pub replayed:		usize,
+	pub expected:		Vec<Packet>,
+	pub received:		Vec<Packet>,
+	pub differences:	Vec<Difference>,
+}
+
+impl Session {
+	/* every client in the capture, by token */
+	pub fn all<R: Read>(reader: CaptureReader<R>) -> io::Result<Vec<Session>> {
+		let mut sessions: BTreeMap<usize, Session> = BTreeMap::new();
+		for entry in reader {
+			let entry = entry?;
+			sessions.entry(entry.token.0)
+				.or_insert_with(|| Session {
+					token:		entry.token,
+					peer:		entry.peer,
+					entries:	Vec::new(),
+				})
+				.entries.push(entry);
+		}
+		Ok(sessions.into_values().collect())
+	}
+
+	/* the client with `token`, or the first one in the capture */
+	pub fn load<R: Read>(reader: CaptureReader<R>, token: Option<Token>) -> io::Result<Session> {
+		let sessions = Session::all(reader)?;
+		let session = match token {
+			Some(token) => sessions.into_iter().find(|s| s.token == token),
+			None => sessions.into_iter().next(),
+		};
+		session.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no such client in the capture"))
+	}
+
+	pub fn inbound(&self) -> Vec<&CaptureEntry> {
+		self.entries.iter().filter(|e| e.direction == Direction::Inbound).collect()
+	}
+
+	pub fn outbound(&self) -> Vec<Packet> {
+		self.entries.iter()
+			.filter(|e| e.direction == Direction::Outbound)
+			.map(|e| Packet { opcode: e.opcode, payload: e.payload.clone() })
+			.collect()
+	}
+}
+
+impl ReplayOptions {
+	pub fn new() -> Self {
+		ReplayOptions {
+			timing:		Timing::Immediate,
+			ignore:		HashSet::new(),
+			settle:		Duration::from_millis(500),
+			seed_opcode:	Some(SEED_OPCODE),
+			key:		None,
+		}
+	}
+
+	pub fn timing(mut self, timing: Timing) -> Self {
+		self.timing = timing;
+		self
+	}
+
+	pub fn ignore(mut self, opcode: u16) -> Self {
+		self.ignore.insert(opcode);
+		self
+	}
+
+	pub fn settle(mut self, settle: Duration) -> Self {
+		self.settle = settle;
+		self
+	}
+
+	/* `None` if the server does not hand out seeds */
+	pub fn seed_opcode(mut self, opcode: Option<u16>) -> Self {
+		self.seed_opcode = opcode;
+		self
+	}
+
+	pub fn key(mut self, key: Arc<Vec<u8>>) -> Self {
+		self.key = Some(key);
+		self
+	}
+
+	/* waits before sending `entry` if the timing asks for it */
+	fn wait(&self, previous: Option<&CaptureEntry>, entry: &CaptureEntry) {
+		if let (Timing::Original { speed }, Some(previous)) = (self.timing, previous) {
+			let micros = entry.timestamp.saturating_sub(previous.timestamp) as f64 / speed.max(0.001);
+			thread::sleep(Duration::from_micros(micros as u64));
+		}
+	}
+}
+
+impl Default for ReplayOptions {
+	fn default() -> Self {
+		ReplayOptions::new()
+	}
+}
+
+impl ReplayReport {
+	fn new(session: &Session, replayed: usize, received: Vec<Packet>, options: &ReplayOptions) -> Self {
+		let expected = without_seed(session.outbound(), options.seed_opcode);
+		let received = without_seed(received, options.seed_opcode);
+		let differences = diff(&expected[..], &received[..], &options.ignore);
+		ReplayReport {
+			token:			session.token,
+			replayed,
+			expected,
+			received,
+			differences,
+		}
+	}
+
+	pub fn is_match(&self) -> bool {
+		self.differences.is_empty()
+	}
+}
+
+/* feeds the session into `processor` through a `Harness`. The processor has to
+ * answer synchronously, e.g. not through a `PacketProcessingThreadPool`. */
+pub fn replay_into(processor: Box<dyn PacketProcessor>, session: &Session, options: &ReplayOptions) -> ReplayReport {
+	let mut harness = Harness::new(processor);
+	let mut received = Vec::new();
+	let mut previous = None;
+	let inbound = session.inbound();
+	for entry in &inbound {
+		options.wait(previous, entry);
+		harness.inject(entry.opcode, &entry.payload[..]);
+		harness.run();
+		received.extend(harness.sent_packets().into_iter().map(|p| Packet {
+			opcode:		p.header,
+			payload:	p.data.to_vec(),
+		}));
+		previous = Some(*entry);
+	}
+	ReplayReport::new(session, inbound.len(), received, options)
+}
+
+/* the seed is the first packet a client is sent, it is no response */
+fn without_seed(mut packets: Vec<Packet>, seed_opcode: Option<u16>) -> Vec<Packet> {
+	if let Some(opcode) = seed_opcode {
+		if let Some(index) = packets.iter().position(|p| p.opcode == opcode) {
+			packets.remove(index);
+		}
+	}
+	packets
+}
+
+/* connects to a running server as the recorded client. With a key table the
+ * packets are encrypted from the seed the server sends on. */
+pub fn replay_against(addr: &SocketAddr, session: &Session, options: &ReplayOptions) -> io::Result<ReplayReport> {
+	match options.key {
+		Some(ref key) if key.is_empty() => return Err(io::Error::new(ErrorKind::InvalidInput, "empty XOR key table")),
+		Some(_) if options.seed_opcode.is_none() => return Err(io::Error::new(ErrorKind::InvalidInput, "a key table needs the seed opcode")),
+		_ => {},
+	}
+	let seed_opcode = options.seed_opcode;
+	let mut stream = TcpStream::connect(addr)?;
+	let _ = stream.set_nodelay(true);
+	let mut reader = stream.try_clone()?;
+	reader.set_read_timeout(Some(options.settle))?;
+
+	let done = Arc::new(AtomicBool::new(false));
+	let (seed_sender, seeds) = mpsc::channel();
+	let receiver = {
+		let done = done.clone();
+		thread::spawn(move || -> io::Result<Vec<Packet>> {
+			let mut seed_sender = Some(seed_sender);
+			let mut packets = Vec::new();
+			let mut pending = Vec::new();
+			let mut buf = [0; 4096];
+			loop {
+				match reader.read(&mut buf[..]) {
+					Ok(0) => break,
+					Ok(size) => {
+						pending.extend_from_slice(&buf[..size]);
+						let rest = {
+							let mut frames = split_frames(&pending[..]);
+							for (opcode, payload) in &mut frames {
+								if Some(opcode) == seed_opcode && payload.len() >= 2 {
+									if let Some(sender) = seed_sender.take() {
+										let _ = sender.send(u16::from_be_bytes([payload[0], payload[1]]));
+									}
+								}
+								packets.push(Packet { opcode, payload: payload.to_vec() });
+							}
+							frames.rest().len()
+						};
+						let consumed = pending.len() - rest;
+						pending.drain(..consumed);
+					},
+					Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+						/* quiet for `settle` after the last packet went out */
+						if done.load(Ordering::Acquire) {
+							break;
+						}
+					},
+					Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+					Err(e) => return Err(e),
+				}
+			}
+			Ok(packets)
+		})
+	};
+
+	let mut cipher = match options.key {
+		Some(ref key) => match seeds.recv_timeout(Duration::from_millis(SEED_TIMEOUT_MS)) {
+			Ok(seed) => Some(XorCipher::new(key.clone(), seed)),
+			Err(_) => {
+				let _ = stream.shutdown(Shutdown::Both);
+				return Err(io::Error::new(ErrorKind::TimedOut, "the server sent no seed"));
+			},
+		},
+		None => None,
+	};
+	let mut previous = None;
+	let inbound = session.inbound();
+	for entry in &inbound {
+		options.wait(previous, entry);
+		let frame = match cipher {
+			Some(ref mut cipher) => {
+				let (header, body) = cipher.apply_packet(entry.opcode, &entry.payload[..]);
+				frame_large_packet(header, &body[..])
+			},
+			None => frame_large_packet(entry.opcode, &entry.payload[..]),
+		};
+		stream.write_all(&frame[..])?;
+		previous = Some(*entry);
+	}
+	done.store(true, Ordering::Release);
+	let received = match receiver.join() {
+		Ok(received) => received?,
+		Err(_) => return Err(io::Error::other("receiver panicked")),
+	};
+	let _ = stream.shutdown(Shutdown::Both);
+	Ok(ReplayReport::new(session, inbound.len(), received, options))
+}
+
+/* compares the packets in order, skipping ignored opcodes on both sides */
+pub fn diff(expected: &[Packet], received: &[Packet], ignore: &HashSet<u16>) -> Vec<Difference> {
+	let expected: Vec<&Packet> = expected.iter().filter(|p| !ignore.contains(&p.opcode)).collect();
+	let received: Vec<&Packet> = received.iter().filter(|p| !ignore.contains(&p.opcode)).collect();
+	let mut differences = Vec::new();
+	for index in 0..expected.len().max(received.len()) {
+		match (expected.get(index), received.get(index)) {
+			(Some(e), Some(r)) => {
+				if e != r {
+					differences.push(Difference::Mismatch {
+						index,
+						expected:	e.opcode,
+						actual:		r.opcode,
+						offset:		first_difference(&e.payload[..], &r.payload[..]),
+					});
+				}
+			},
+			(Some(e), None) => differences.push(Difference::Missing { index, opcode: e.opcode }),
+			(None, Some(r)) => differences.push(Difference::Unexpected { index, opcode: r.opcode }),
+			(None, None) => {},
+		}
+	}
+	differences
+}
+
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+	match a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+		Some(offset) => Some(offset),
+		None if a.len() != b.len() => Some(a.len().min(b.len())),
+		None => None,
+	}
+}
+
+impl fmt::Display for Difference {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Difference::Missing { index, opcode } =>
+				write!(f, "#{}: expected 0x{:04X}, nothing was sent", index, opcode),
+			Difference::Unexpected { index, opcode } =>
+				write!(f, "#{}: unexpected 0x{:04X}", index, opcode),
+			Difference::Mismatch { index, expected, actual, offset } if expected != actual =>
+				write!(f, "#{}: expected 0x{:04X}, got 0x{:04X} (payload differs at {:?})", index, expected, actual, offset),
+			Difference::Mismatch { index, expected, offset, .. } =>
+				write!(f, "#{}: 0x{:04X} payload differs at offset {}", index, expected, offset.unwrap_or(0)),
+		}
+	}
+}
+
+impl fmt::Display for ReplayReport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "{:?}: replayed {} packets, expected {} responses, received {}",
+			self.token, self.replayed, self.expected.len(), self.received.len())?;
+		for difference in &self.differences {
+			writeln!(f, "  {}", difference)?;
+		}
+		if self.is_match() {
+			writeln!(f, "  responses match")?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,474 @@
+/* Request/response calls between servers, over links accepted by a
+ * `FiestaServer` or made with a `Connector`. A call is a packet whose body
+ * starts with a u32 request ID, the response carries the same ID back:
+ *
+ *     impl Request for NcTicketCheckReq { type Response = NcTicketCheckAck; }
+ *
+ *     // the side answering serves an `RpcProcessor`
+ *     RpcProcessor::new(rpc.clone())
+ *         .handle(|request: NcTicketCheckReq, reply: Responder<NcTicketCheckAck>| reply.send(&check(&request)))
+ *
+ *     // the side asking needs one too, it picks up the responses
+ *     let ack = rpc.call(&link, &request, Duration::from_secs(5)).wait();
+ *
+ * Every call ends, with the response, `RpcError::Timeout` or, if the link
+ * goes down first, `RpcError::Disconnected`. Callbacks run on the thread
+ * that handled the response or noticed the failure, keep them short. The
+ * IDs of calls that timed out are kept for `EXPIRED_KEEP_MS`, a late
+ * response to one of them is dropped with a warning. Any other packet that answers no
+ * call in flight goes on like any other packet, with a warning if it has
+ * the opcode of a response. */
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+use mio::Token;
+
+use client::{FiestaNetworkClient, FiestaPacket};
+use opcodes::Named;
+use packets::Packet;
+use processing::{PacketProcessingInfo, PacketProcessor};
+use reader::{DecodeError, PacketReader};
+use writer::PacketWriter;
+
+/* how often pending calls are checked for timeouts and dropped links */
+pub const SWEEP_INTERVAL_MS: u64 = 25;
+/* how long the IDs of timed out calls are kept to recognize late responses */
+pub const EXPIRED_KEEP_MS: u64 = 60_000;
+/* and how many of them at most, the oldest go first */
+pub const MAX_EXPIRED: usize = 4096;
+
+/* a packet answered by a `Response` */
+pub trait Request: Packet {
+	type Response: Packet;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+	/* no response within the timeout of the call */
+	Timeout,
+	/* the link went down before the response arrived */
+	Disconnected,
+	/* the response did not decode */
+	Decode(DecodeError),
+}
+
+/* the caller's side; clones share the pending calls */
+#[derive(Clone)]
+pub struct Rpc {
+	state:			Arc<RpcState>,
+}
+
+/* a call in flight; `wait` for it or use it as a `Future` */
+pub struct RpcCall<T> {
+	slot:			Arc<CallSlot<T>>,
+}
+
+/* answers one request, from any thread and at any time */
+pub struct Responder<T> {
+	link:			Arc<FiestaNetworkClient>,
+	id:				u32,
+	response:		PhantomData<fn(&T)>,
+}
+
+pub type RpcHandler = dyn Fn(&Arc<FiestaNetworkClient>, &FiestaPacket) + Send + Sync;
+
+/* answers requests with handlers per opcode and completes the calls made
+ * through its `Rpc`; everything else goes to the fallback */
+pub struct RpcProcessor {
+	rpc:			Rpc,
+	handlers:		HashMap<u16, Arc<RpcHandler>>,
+	fallback:		Option<Box<dyn PacketProcessor>>,
+}
+
+type Completion = Box<dyn FnOnce(Result<&FiestaPacket, RpcError>) + Send>;
+
+struct PendingCall {
+	link:			Arc<FiestaNetworkClient>,
+	response:		u16,
+	deadline:		Instant,
+	complete:		Completion,
+}
+
+/* a call that timed out, its response may still arrive */
+struct ExpiredCall {
+	link:			Token,
+	response:		u16,
+	at:				Instant,
+}
+
+struct RpcState {
+	pending:		Mutex<HashMap<u32, PendingCall>>,
+	expired:		Mutex<HashMap<u32, ExpiredCall>>,
+	/* the opcodes of every response asked for so far */
+	responses:		RwLock<HashSet<u16>>,
+	late:			AtomicU32,
+	next_id:		AtomicU32,
+}
+
+/* one thread times out the calls of every `Rpc`; it stops when the last one
+ * is dropped and the next `Rpc` starts it again */
+struct Sweeper {
+	states:			Vec<Weak<RpcState>>,
+	running:		bool,
+}
+
+static SWEEPER: Mutex<Sweeper> = Mutex::new(Sweeper { states: Vec::new(), running: false });
+
+struct CallSlot<T> {
+	state:			Mutex<CallState<T>>,
+	done:			Condvar,
+}
+
+struct CallState<T> {
+	result:			Option<Result<T, RpcError>>,
+	/* of the task polling the call as a `Future` */
+	waker:			Option<Waker>,
+}
+
+impl Rpc {
+	/* the calls are timed out by a thread shared with all other `Rpc`s */
+	pub fn new() -> Self {
+		let state = Arc::new(RpcState {
+			pending:		Mutex::new(HashMap::new()),
+			expired:		Mutex::new(HashMap::new()),
+			responses:		RwLock::new(HashSet::new()),
+			late:			AtomicU32::new(0),
+			next_id:		AtomicU32::new(1),
+		});
+		let mut sweeper = SWEEPER.lock().unwrap();
+		sweeper.states.push(Arc::downgrade(&state));
+		if !sweeper.running {
+			let spawned = thread::Builder::new()
+				.name("fiesta-rpc".to_string())
+				.spawn(sweep_while_used);
+			match spawned {
+				Ok(_) => sweeper.running = true,
+				Err(e) => error!(target: "rpc", "could not start the timeout thread, calls will not time out: {:?}", e),
+			}
+		}
+		Rpc { state }
+	}
+
+	/* sends `request` over `link`, `callback` gets the response or the error */
+	pub fn call_with<R, F>(&self, link: &Arc<FiestaNetworkClient>, request: &R, timeout: Duration, callback: F)
+			where R: Request, F: FnOnce(Result<R::Response, RpcError>) + Send + 'static {
+		if !link.alive() {
+			callback(Err(RpcError::Disconnected));
+			return;
+		}
+		let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+		if !self.state.responses.read().unwrap().contains(&R::Response::OPCODE) {
+			self.state.responses.write().unwrap().insert(R::Response::OPCODE);
+		}
+		let complete = move |result: Result<&FiestaPacket, RpcError>| {
+			callback(result.and_then(|packet| decode::<R::Response>(packet).map(|(_, response)| response).map_err(RpcError::Decode)));
+		};
+		/* before sending, the response may come back before `append_send` returns */
+		self.state.pending.lock().unwrap().insert(id, PendingCall {
+			link:		link.clone(),
+			response:	R::Response::OPCODE,
+			deadline:	Instant::now() + timeout,
+			complete:	Box::new(complete),
+		});
+		debug!(target: "rpc", "call #{} {} to {:?}", id, Named(R::OPCODE), link.id());
+		link.append_send(&encode(R::OPCODE, id, request)[..]);
+	}
+
+	pub fn call<R>(&self, link: &Arc<FiestaNetworkClient>, request: &R, timeout: Duration) -> RpcCall<R::Response>
+			where R: Request, R::Response: Send + 'static {
+		let slot = Arc::new(CallSlot {
+			state:		Mutex::new(CallState { result: None, waker: None }),
+			done:		Condvar::new(),
+		});
+		let completed = slot.clone();
+		self.call_with(link, request, timeout, move |result| completed.complete(result));
+		RpcCall { slot }
+	}
+
+	/* calls waiting for their response */
+	pub fn pending(&self) -> usize {
+		self.state.pending.lock().unwrap().len()
+	}
+
+	/* responses that arrived after their call timed out */
+	pub fn late_responses(&self) -> usize {
+		self.state.late.load(Ordering::Relaxed) as usize
+	}
+
+	/* completes the call `packet` answers, or drops it if it answers a call
+	 * that timed out; false if it answers no call at all, from that link
+	 * and with that opcode */
+	pub fn complete(&self, link: &FiestaNetworkClient, packet: &FiestaPacket) -> bool {
+		let id = match PacketReader::new(packet).read_u32() {
+			Ok(id) => id,
+			Err(_) => return false,
+		};
+		let call = {
+			let mut pending = self.state.pending.lock().unwrap();
+			match pending.get(&id) {
+				Some(call) if call.link.id() == link.id() && call.response == packet.header => pending.remove(&id),
+				_ => None,
+			}
+		};
+		if let Some(call) = call {
+			(call.complete)(Ok(packet));
+			return true;
+		}
+
+		let expired = {
+			let mut expired = self.state.expired.lock().unwrap();
+			match expired.get(&id) {
+				Some(call) if call.link == link.id() && call.response == packet.header => expired.remove(&id),
+				_ => None,
+			}
+		};
+		match expired {
+			Some(call) => {
+				self.state.late.fetch_add(1, Ordering::Relaxed);
+				warn!(target: "rpc", "late response #{} {} from {:?}, {:?} after the call timed out",
+					id, Named(packet.header), link.id(), call.at.elapsed());
+				true
+			},
+			None => {
+				if self.state.responses.read().unwrap().contains(&packet.header) {
+					warn!(target: "rpc", "response #{} {} from {:?} answers no call", id, Named(packet.header), link.id());
+				}
+				false
+			},
+		}
+	}
+}
+
+impl Default for Rpc {
+	fn default() -> Self {
+		Rpc::new()
+	}
+}
+
+impl RpcState {
+	/* fails the calls that timed out or whose link went down */
+	fn sweep(&self, now: Instant) {
+		let failed: Vec<(u32, PendingCall, RpcError)> = {
+			let mut pending = self.pending.lock().unwrap();
+			let ids: Vec<u32> = pending.iter()
+				.filter(|&(_, call)| !call.link.alive() || call.deadline <= now)
+				.map(|(id, _)| *id)
+				.collect();
+			ids.into_iter().filter_map(|id| pending.remove(&id).map(|call| (id, call))).map(|(id, call)| {
+				let error = if call.link.alive() { RpcError::Timeout } else { RpcError::Disconnected };
+				(id, call, error)
+			}).collect()
+		};
+		{
+			let mut expired = self.expired.lock().unwrap();
+			let keep = Duration::from_millis(EXPIRED_KEEP_MS);
+			expired.retain(|_, call| now.duration_since(call.at) < keep);
+			/* a link that went down sends nothing any more */
+			for &(id, ref call, ref error) in &failed {
+				if *error == RpcError::Timeout {
+					if expired.len() >= MAX_EXPIRED {
+						let oldest = expired.iter().min_by_key(|&(_, call)| call.at).map(|(id, _)| *id);
+						if let Some(oldest) = oldest {
+							expired.remove(&oldest);
+						}
+					}
+					expired.insert(id, ExpiredCall {
+						link:		call.link.id(),
+						response:	call.response,
+						at:			now,
+					});
+				}
+			}
+		}
+		for (_, call, error) in failed {
+			debug!(target: "rpc", "call to {:?} failed: {}", call.link.id(), error);
+			(call.complete)(Err(error));
+		}
+	}
+}
+
+fn sweep_while_used() {
+	loop {
+		thread::sleep(Duration::from_millis(SWEEP_INTERVAL_MS));
+		let states: Vec<Arc<RpcState>> = {
+			let mut sweeper = SWEEPER.lock().unwrap();
+			sweeper.states.retain(|state| state.strong_count() > 0);
+			if sweeper.states.is_empty() {
+				sweeper.running = false;
+				return;
+			}
+			sweeper.states.iter().filter_map(|state| state.upgrade()).collect()
+		};
+		let now = Instant::now();
+		for state in states {
+			state.sweep(now);
+		}
+	}
+}
+
+impl<T> CallSlot<T> {
+	fn complete(&self, result: Result<T, RpcError>) {
+		let mut state = self.state.lock().unwrap();
+		state.result = Some(result);
+		if let Some(waker) = state.waker.take() {
+			waker.wake();
+		}
+		self.done.notify_all();
+	}
+}
+
+impl<T> RpcCall<T> {
+	/* blocks until the call ended, which it does by its timeout at the latest */
+	pub fn wait(self) -> Result<T, RpcError> {
+		let mut state = self.slot.state.lock().unwrap();
+		loop {
+			if let Some(result) = state.result.take() {
+				return result;
+			}
+			state = self.slot.done.wait(state).unwrap();
+		}
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.slot.state.lock().unwrap().result.is_some()
+	}
+}
+
+impl<T> Future for RpcCall<T> {
+	type Output = Result<T, RpcError>;
+
+	fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+		let mut state = self.slot.state.lock().unwrap();
+		match state.result.take() {
+			Some(result) => Poll::Ready(result),
+			None => {
+				state.waker = Some(context.waker().clone());
+				Poll::Pending
+			},
+		}
+	}
+}
+
+impl<T: Packet> Responder<T> {
+	pub fn link(&self) -> &Arc<FiestaNetworkClient> {
+		&self.link
+	}
+
+	pub fn id(&self) -> u32 {
+		self.id
+	}
+
+	pub fn send(self, response: &T) {
+		self.link.append_send(&encode(T::OPCODE, self.id, response)[..]);
+	}
+}
+
+impl RpcProcessor {
+	pub fn new(rpc: Rpc) -> Self {
+		RpcProcessor {
+			rpc,
+			handlers:	HashMap::new(),
+			fallback:	None,
+		}
+	}
+
+	/* answers requests of type `R`; the handler may keep the responder and
+	 * answer later */
+	pub fn handle<R, F>(mut self, handler: F) -> Self
+			where R: Request, F: Fn(R, Responder<R::Response>) + Send + Sync + 'static {
+		self.handlers.insert(R::OPCODE, Arc::new(move |link: &Arc<FiestaNetworkClient>, packet: &FiestaPacket| {
+			match decode::<R>(packet) {
+				Ok((id, request)) => handler(request, Responder {
+					link:		link.clone(),
+					id,
+					response:	PhantomData,
+				}),
+				Err(e) => warn!(target: "rpc", "bad request from {:?}: {}", link.id(), e),
+			}
+		}));
+		self
+	}
+
+	/* gets the packets that are neither requests nor responses to a call in
+	 * flight */
+	pub fn fallback(mut self, processor: Box<dyn PacketProcessor>) -> Self {
+		self.fallback = Some(processor);
+		self
+	}
+
+	pub fn rpc(&self) -> &Rpc {
+		&self.rpc
+	}
+}
+
+impl PacketProcessor for RpcProcessor {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let unhandled = {
+			let info = info.read().unwrap();
+			let packet = info.packet.read().unwrap();
+			if self.rpc.complete(&info.client, &packet) {
+				None
+			} else if let Some(handler) = self.handlers.get(&packet.header) {
+				handler(&info.client, &packet);
+				None
+			} else {
+				Some((packet.header, info.client.id()))
+			}
+		};
+		if let Some((opcode, token)) = unhandled {
+			match self.fallback {
+				Some(ref mut fallback) => fallback.process_packet(info),
+				None => debug!(target: "rpc", "no handler for {} from {:?}", Named(opcode), token),
+			}
+		}
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(RpcProcessor {
+			rpc:		self.rpc.clone(),
+			handlers:	self.handlers.clone(),
+			fallback:	self.fallback.as_ref().map(|fallback| PacketProcessor::clone(&**fallback)),
+		})
+	}
+}
+
+impl fmt::Display for RpcError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			RpcError::Timeout => write!(f, "no response in time"),
+			RpcError::Disconnected => write!(f, "the link went down"),
+			RpcError::Decode(ref e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl error::Error for RpcError {
+	fn description(&self) -> &str {
+		match *self {
+			RpcError::Timeout => "no response in time",
+			RpcError::Disconnected => "the link went down",
+			RpcError::Decode(_) => "the response did not decode",
+		}
+	}
+}
+
+/* the framed packet: the request ID, then the body of `value` */
+fn encode<P: Packet>(opcode: u16, id: u32, value: &P) -> Vec<u8> {
+	value.encode(PacketWriter::new(opcode).write_u32(id)).to_frame()
+}
+
+fn decode<P: Packet>(packet: &FiestaPacket) -> Result<(u32, P), DecodeError> {
+	let mut reader = PacketReader::new(packet);
+	let id = reader.read_u32()?;
+	let value = P::decode(&mut reader)?;
+	reader.expect_end()?;
+	Ok((id, value))
+}
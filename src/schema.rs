@@ -0,0 +1,678 @@
+/* Packet layouts written down in schema files, and the Rust code generated
+ * from them. A schema file lists structs and packets, one field per line:
+ *
+ *     # a world in the server list
+ *     struct WorldStatus {
+ *         id:         u8
+ *         name:       string[16]
+ *         status:     u8
+ *     }
+ *
+ *     packet NC_USER_LOGIN_ACK 0x0C0A server {
+ *         worlds:     WorldStatus[u8]
+ *     }
+ *
+ * `packet` takes the name, the opcode and which side sends it: `client`,
+ * `server` or `both`. Field types are `u8`, `i8`, `bool`, `u16`, `i16`,
+ * `u32`, `i32`, `u64`, `i64`, `string[..]`, `bytes[..]` and arrays of any of
+ * them or of a struct that is never empty, `type[..]`. The length in
+ * brackets is either a number of elements (bytes for strings), an integer
+ * type for a count sent right before the elements, or empty for whatever is
+ * left of the body; the last one only for the last field of a packet.
+ *
+ * This file only uses `std` so the build script can include it. */
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt::{self, Write};
+
+/* which side sends a packet */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Origin {
+	Client,
+	Server,
+	Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+	U8,
+	I8,
+	Bool,
+	U16,
+	I16,
+	U32,
+	I32,
+	U64,
+	I64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Length {
+	Fixed(usize),
+	/* a count of this type comes first */
+	Prefixed(Scalar),
+	/* up to the end of the body */
+	Rest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+	Scalar(Scalar),
+	/* zero padded, or cut off, to its length */
+	String(Length),
+	Bytes(Length),
+	Struct(String),
+	Array(Box<Type>, Length),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDef {
+	pub name:			String,
+	pub ty:				Type,
+	pub line:			usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructDef {
+	pub name:			String,
+	pub fields:			Vec<FieldDef>,
+	pub line:			usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketDef {
+	/* as the protocol calls it, e.g. `NC_USER_LOGIN_REQ` */
+	pub name:			String,
+	pub opcode:			u16,
+	pub origin:			Origin,
+	pub fields:			Vec<FieldDef>,
+	pub line:			usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+	pub structs:		Vec<StructDef>,
+	pub packets:		Vec<PacketDef>,
+}
+
+/* what is wrong with a schema, and on which line */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+	pub line:			usize,
+	pub message:		String,
+}
+
+enum Block {
+	Struct(StructDef),
+	Packet(PacketDef),
+}
+
+const KEYWORDS: &[&str] = &[
+	"as", "box", "break", "const", "continue", "crate", "else", "enum", "extern",
+	"false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+	"move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+	"super", "trait", "true", "type", "unsafe", "use", "where", "while",
+	"abstract", "async", "await", "become", "do", "dyn", "final", "macro",
+	"override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+impl Scalar {
+	fn parse(name: &str) -> Option<Scalar> {
+		match name {
+			"u8" => Some(Scalar::U8),
+			"i8" => Some(Scalar::I8),
+			"bool" => Some(Scalar::Bool),
+			"u16" => Some(Scalar::U16),
+			"i16" => Some(Scalar::I16),
+			"u32" => Some(Scalar::U32),
+			"i32" => Some(Scalar::I32),
+			"u64" => Some(Scalar::U64),
+			"i64" => Some(Scalar::I64),
+			_ => None,
+		}
+	}
+
+	/* the Rust type, which is also the schema name */
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Scalar::U8 => "u8",
+			Scalar::I8 => "i8",
+			Scalar::Bool => "bool",
+			Scalar::U16 => "u16",
+			Scalar::I16 => "i16",
+			Scalar::U32 => "u32",
+			Scalar::I32 => "i32",
+			Scalar::U64 => "u64",
+			Scalar::I64 => "i64",
+		}
+	}
+
+	pub fn size(&self) -> usize {
+		match *self {
+			Scalar::U8 | Scalar::I8 | Scalar::Bool => 1,
+			Scalar::U16 | Scalar::I16 => 2,
+			Scalar::U32 | Scalar::I32 => 4,
+			Scalar::U64 | Scalar::I64 => 8,
+		}
+	}
+
+	/* `dump::FieldKind` has a variant of the same name for every scalar */
+	fn field_kind(&self) -> &'static str {
+		match *self {
+			Scalar::U8 => "U8",
+			Scalar::I8 => "I8",
+			Scalar::Bool => "Bool",
+			Scalar::U16 => "U16",
+			Scalar::I16 => "I16",
+			Scalar::U32 => "U32",
+			Scalar::I32 => "I32",
+			Scalar::U64 => "U64",
+			Scalar::I64 => "I64",
+		}
+	}
+}
+
+impl Schema {
+	pub fn parse(source: &str) -> Result<Schema, SchemaError> {
+		let mut schema = Schema::default();
+		let mut block = None;
+		for (index, line) in source.lines().enumerate() {
+			let number = index + 1;
+			let line = match line.find('#') {
+				Some(comment) => &line[..comment],
+				None => line,
+			}.trim();
+			if line.is_empty() {
+				continue;
+			}
+			if line == "}" {
+				match block.take() {
+					Some(Block::Struct(def)) => schema.structs.push(def),
+					Some(Block::Packet(def)) => schema.packets.push(def),
+					None => return Err(SchemaError::new(number, "`}` without a block")),
+				}
+				continue;
+			}
+			match block {
+				Some(Block::Struct(ref mut def)) => def.fields.push(parse_field(number, line)?),
+				Some(Block::Packet(ref mut def)) => def.fields.push(parse_field(number, line)?),
+				None => block = Some(parse_header(number, line)?),
+			}
+		}
+		match block {
+			Some(Block::Struct(def)) => Err(SchemaError::new(def.line, format!("struct {} is not closed", def.name))),
+			Some(Block::Packet(def)) => Err(SchemaError::new(def.line, format!("packet {} is not closed", def.name))),
+			None => {
+				schema.check()?;
+				Ok(schema)
+			},
+		}
+	}
+
+	/* adds the definitions of another file; line numbers in errors refer to
+	 * the file the definition came from */
+	pub fn merge(&mut self, other: Schema) -> Result<(), SchemaError> {
+		self.structs.extend(other.structs);
+		self.packets.extend(other.packets);
+		self.check()
+	}
+
+	pub fn packet(&self, opcode: u16) -> Option<&PacketDef> {
+		self.packets.iter().find(|def| def.opcode == opcode)
+	}
+
+	pub fn find_struct(&self, name: &str) -> Option<&StructDef> {
+		self.structs.iter().find(|def| def.name == name)
+	}
+
+	fn check(&self) -> Result<(), SchemaError> {
+		let mut types = HashSet::new();
+		for def in &self.structs {
+			if !types.insert(def.name.clone()) {
+				return Err(SchemaError::new(def.line, format!("struct {} is defined twice", def.name)));
+			}
+		}
+		let mut opcodes = HashMap::new();
+		for def in &self.packets {
+			if let Some(other) = opcodes.insert(def.opcode, &def.name) {
+				return Err(SchemaError::new(def.line, format!("{} has the opcode 0x{:04X} of {}", def.name, def.opcode, other)));
+			}
+			if !types.insert(type_name(&def.name)) {
+				return Err(SchemaError::new(def.line, format!("{} is defined twice, or as a struct {}", def.name, type_name(&def.name))));
+			}
+		}
+		/* before the fields, `min_size` only ends without cycles */
+		for def in &self.structs {
+			self.check_cycle(def, &mut Vec::new())?;
+		}
+		for def in &self.structs {
+			self.check_fields(&def.fields, false)?;
+		}
+		for def in &self.packets {
+			self.check_fields(&def.fields, true)?;
+		}
+		Ok(())
+	}
+
+	/* a struct may not contain itself, not even in an array */
+	fn check_cycle<'a>(&'a self, def: &'a StructDef, path: &mut Vec<&'a str>) -> Result<(), SchemaError> {
+		if path.contains(&&def.name[..]) {
+			path.push(&def.name);
+			return Err(SchemaError::new(def.line, format!("struct {} contains itself: {}", def.name, path.join(" -> "))));
+		}
+		path.push(&def.name);
+		for field in &def.fields {
+			let inner = match field.ty {
+				Type::Struct(ref name) => name,
+				Type::Array(ref inner, _) => match **inner {
+					Type::Struct(ref name) => name,
+					_ => continue,
+				},
+				_ => continue,
+			};
+			/* unknown types are reported by `check_fields` */
+			if let Some(inner) = self.find_struct(inner) {
+				self.check_cycle(inner, path)?;
+			}
+		}
+		path.pop();
+		Ok(())
+	}
+
+	fn check_fields(&self, fields: &[FieldDef], packet: bool) -> Result<(), SchemaError> {
+		let mut names = HashSet::new();
+		for (index, field) in fields.iter().enumerate() {
+			if !names.insert(&field.name) {
+				return Err(SchemaError::new(field.line, format!("field {} is defined twice", field.name)));
+			}
+			let (name, length) = match field.ty {
+				Type::Struct(ref name) => (Some(name), None),
+				Type::Array(ref inner, length) => match **inner {
+					Type::Struct(ref name) => (Some(name), Some(length)),
+					_ => (None, Some(length)),
+				},
+				Type::String(length) | Type::Bytes(length) => (None, Some(length)),
+				Type::Scalar(_) => (None, None),
+			};
+			if let Some(name) = name {
+				if self.find_struct(name).is_none() {
+					return Err(SchemaError::new(field.line, format!("unknown type {}", name)));
+				}
+				/* decoding would never get past them */
+				if length.is_some() && self.min_size(&Type::Struct(name.clone())) == 0 {
+					return Err(SchemaError::new(field.line, format!("arrays of {} are not supported, it can be empty", name)));
+				}
+			}
+			if length == Some(Length::Rest) && (!packet || index + 1 != fields.len()) {
+				return Err(SchemaError::new(field.line, "`[]` is only allowed for the last field of a packet"));
+			}
+		}
+		Ok(())
+	}
+
+	/* Rust code for every struct and packet: a struct with public fields and
+	 * an implementation of `packets::Codec`, plus `packets::Packet` for
+	 * packets, and a `register` function registering all packets. `krate` is
+	 * the path of this crate as seen from the generated code, `""` inside it
+	 * and e.g. `"::fiesta_net"` in a build script of another crate. */
+	pub fn generate(&self, krate: &str) -> String {
+		let mut out = String::new();
+		for def in &self.structs {
+			self.generate_type(&mut out, krate, &def.name, &def.fields);
+		}
+		for def in &self.packets {
+			let name = type_name(&def.name);
+			self.generate_type(&mut out, krate, &name, &def.fields);
+			let origin = match def.origin {
+				Origin::Client => "Client",
+				Origin::Server => "Server",
+				Origin::Both => "Both",
+			};
+			let _ = writeln!(out, "impl {}::packets::Packet for {} {{", krate, name);
+			let _ = writeln!(out, "\tconst OPCODE: u16 = 0x{:04X};", def.opcode);
+			let _ = writeln!(out, "\tconst NAME: &'static str = {:?};", def.name);
+			let _ = writeln!(out, "\tconst ORIGIN: {}::schema::Origin = {}::schema::Origin::{};", krate, krate, origin);
+			let _ = writeln!(out, "\tconst SIZE: Option<usize> = {:?};", self.fixed_size(&def.fields));
+			let _ = writeln!(out);
+			let _ = writeln!(out, "\tfn dump_schema() -> {}::dump::PacketSchema {{", krate);
+			let _ = write!(out, "\t\t{}::dump::PacketSchema::new({:?})", krate, def.name);
+			let mut fields = Vec::new();
+			self.dump_fields(&def.fields, "", &mut fields);
+			for (name, kind) in fields {
+				let _ = write!(out, "\n\t\t\t.field({:?}, {}::dump::FieldKind::{})", name, krate, kind);
+			}
+			let _ = writeln!(out);
+			let _ = writeln!(out, "\t}}");
+			let _ = writeln!(out, "}}");
+			let _ = writeln!(out);
+		}
+		let _ = writeln!(out, "/* registers every packet of the schema, see `packets::register` */");
+		let _ = writeln!(out, "pub fn register() {{");
+		for def in &self.packets {
+			let _ = writeln!(out, "\t{}::packets::register::<{}>();", krate, type_name(&def.name));
+		}
+		let _ = writeln!(out, "}}");
+		out
+	}
+
+	fn generate_type(&self, out: &mut String, krate: &str, name: &str, fields: &[FieldDef]) {
+		let codec = format!("{}::packets", krate);
+		let reader = format!("{}::reader", krate);
+		let writer = format!("{}::writer::PacketWriter", krate);
+
+		let _ = writeln!(out, "#[derive(Debug, Clone, Default, PartialEq)]");
+		let _ = writeln!(out, "pub struct {} {{", name);
+		for field in fields {
+			let _ = writeln!(out, "\tpub {}: {},", field.name, rust_type(&field.ty));
+		}
+		let _ = writeln!(out, "}}");
+		let _ = writeln!(out);
+
+		let _ = writeln!(out, "impl {}::Codec for {} {{", codec, name);
+		let reader_name = if fields.is_empty() { "_reader" } else { "reader" };
+		let _ = writeln!(out, "\tfn decode({}: &mut {}::PacketReader) -> Result<Self, {}::DecodeError> {{", reader_name, reader, reader);
+		let _ = writeln!(out, "\t\tOk({} {{", name);
+		for field in fields {
+			let _ = writeln!(out, "\t\t\t{}: {},", field.name, decode_expr(&codec, &field.ty));
+		}
+		let _ = writeln!(out, "\t\t}})");
+		let _ = writeln!(out, "\t}}");
+		let _ = writeln!(out);
+		let _ = writeln!(out, "\tfn encode(&self, writer: {}) -> {} {{", writer, writer);
+		for (index, field) in fields.iter().enumerate() {
+			let expr = encode_expr(krate, &field.ty, &format!("self.{}", field.name));
+			if index + 1 == fields.len() {
+				let _ = writeln!(out, "\t\t{}", expr);
+			} else {
+				let _ = writeln!(out, "\t\tlet writer = {};", expr);
+			}
+		}
+		if fields.is_empty() {
+			let _ = writeln!(out, "\t\twriter");
+		}
+		let _ = writeln!(out, "\t}}");
+		let _ = writeln!(out, "}}");
+		let _ = writeln!(out);
+	}
+
+	/* the size of the fields, if it does not depend on their values */
+	pub fn fixed_size(&self, fields: &[FieldDef]) -> Option<usize> {
+		fields.iter().map(|field| self.type_size(&field.ty)).sum()
+	}
+
+	fn type_size(&self, ty: &Type) -> Option<usize> {
+		match *ty {
+			Type::Scalar(scalar) => Some(scalar.size()),
+			Type::String(Length::Fixed(size)) | Type::Bytes(Length::Fixed(size)) => Some(size),
+			Type::Struct(ref name) => self.find_struct(name).and_then(|def| self.fixed_size(&def.fields)),
+			Type::Array(ref inner, Length::Fixed(count)) => self.type_size(inner).map(|size| size * count),
+			Type::String(_) | Type::Bytes(_) | Type::Array(..) => None,
+		}
+	}
+
+	/* the fewest bytes a value of `ty` takes */
+	fn min_size(&self, ty: &Type) -> usize {
+		match *ty {
+			Type::Scalar(scalar) | Type::String(Length::Prefixed(scalar)) | Type::Bytes(Length::Prefixed(scalar)) => scalar.size(),
+			Type::String(Length::Fixed(size)) | Type::Bytes(Length::Fixed(size)) => size,
+			Type::Struct(ref name) => match self.find_struct(name) {
+				Some(def) => def.fields.iter().map(|field| self.min_size(&field.ty)).sum(),
+				None => 0,
+			},
+			Type::Array(ref inner, Length::Fixed(count)) => self.min_size(inner) * count,
+			Type::Array(_, Length::Prefixed(scalar)) => scalar.size(),
+			Type::String(Length::Rest) | Type::Bytes(Length::Rest) | Type::Array(_, Length::Rest) => 0,
+		}
+	}
+
+	/* flattens the fields into `dump::FieldKind`s; returns false once a field
+	 * of variable size was covered with `Rest`, after which nothing is known
+	 * about the offsets */
+	fn dump_fields(&self, fields: &[FieldDef], prefix: &str, out: &mut Vec<(String, String)>) -> bool {
+		for field in fields {
+			let name = format!("{}{}", prefix, field.name);
+			if !self.dump_type(&field.ty, &name, out) {
+				return false;
+			}
+		}
+		true
+	}
+
+	fn dump_type(&self, ty: &Type, name: &str, out: &mut Vec<(String, String)>) -> bool {
+		match *ty {
+			Type::Scalar(scalar) => out.push((name.to_string(), scalar.field_kind().to_string())),
+			Type::String(Length::Fixed(size)) => out.push((name.to_string(), format!("String({})", size))),
+			Type::Bytes(Length::Fixed(size)) => out.push((name.to_string(), format!("Bytes({})", size))),
+			Type::Struct(ref inner) => {
+				let fields = &self.find_struct(inner).unwrap().fields;
+				return self.dump_fields(fields, &format!("{}.", name), out);
+			},
+			Type::Array(ref inner, Length::Fixed(count)) => {
+				for index in 0..count {
+					if !self.dump_type(inner, &format!("{}[{}]", name, index), out) {
+						return false;
+					}
+				}
+			},
+			Type::String(length) | Type::Bytes(length) | Type::Array(_, length) => {
+				if let Length::Prefixed(scalar) = length {
+					out.push((format!("{}.len", name), scalar.field_kind().to_string()));
+				}
+				out.push((name.to_string(), "Rest".to_string()));
+				return false;
+			},
+		}
+		true
+	}
+}
+
+impl SchemaError {
+	fn new<S: Into<String>>(line: usize, message: S) -> Self {
+		SchemaError {
+			line,
+			message:	message.into(),
+		}
+	}
+}
+
+impl fmt::Display for SchemaError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+impl error::Error for SchemaError {
+	fn description(&self) -> &str {
+		&self.message
+	}
+}
+
+/* the Rust name of a packet: `NC_USER_LOGIN_REQ` becomes `NcUserLoginReq` */
+pub fn type_name(packet: &str) -> String {
+	let mut name = String::with_capacity(packet.len());
+	for word in packet.split('_').filter(|word| !word.is_empty()) {
+		let mut chars = word.chars();
+		if let Some(first) = chars.next() {
+			name.extend(first.to_uppercase());
+			name.extend(chars.flat_map(|c| c.to_lowercase()));
+		}
+	}
+	name
+}
+
+fn parse_header(line: usize, text: &str) -> Result<Block, SchemaError> {
+	let words: Vec<&str> = text.split_whitespace().collect();
+	match &words[..] {
+		["struct", name, "{"] => {
+			check_identifier(line, name)?;
+			Ok(Block::Struct(StructDef {
+				name:		name.to_string(),
+				fields:		Vec::new(),
+				line,
+			}))
+		},
+		["packet", name, opcode, origin, "{"] => {
+			check_identifier(line, name)?;
+			let opcode = match parse_number(opcode) {
+				Some(opcode) if opcode <= 0xFFFF => opcode as u16,
+				_ => return Err(SchemaError::new(line, format!("bad opcode {:?}", opcode))),
+			};
+			let origin = match *origin {
+				"client" => Origin::Client,
+				"server" => Origin::Server,
+				"both" => Origin::Both,
+				other => return Err(SchemaError::new(line, format!("expected client, server or both, not {:?}", other))),
+			};
+			Ok(Block::Packet(PacketDef {
+				name:		name.to_string(),
+				opcode,
+				origin,
+				fields:		Vec::new(),
+				line,
+			}))
+		},
+		_ => Err(SchemaError::new(line, "expected `struct <name> {` or `packet <name> <opcode> <client|server|both> {`")),
+	}
+}
+
+fn parse_field(line: usize, text: &str) -> Result<FieldDef, SchemaError> {
+	let colon = match text.find(':') {
+		Some(colon) => colon,
+		None => return Err(SchemaError::new(line, "expected `<name>: <type>`")),
+	};
+	let name = text[..colon].trim();
+	check_identifier(line, name)?;
+	Ok(FieldDef {
+		name:		name.to_string(),
+		ty:			parse_type(line, text[colon + 1..].trim())?,
+		line,
+	})
+}
+
+fn parse_type(line: usize, text: &str) -> Result<Type, SchemaError> {
+	let (base, length) = match text.find('[') {
+		Some(open) if text.ends_with(']') => {
+			let length = text[open + 1..text.len() - 1].trim();
+			let length = if length.is_empty() {
+				Length::Rest
+			} else if let Some(count) = parse_number(length) {
+				Length::Fixed(count as usize)
+			} else {
+				match Scalar::parse(length) {
+					Some(Scalar::U8) => Length::Prefixed(Scalar::U8),
+					Some(Scalar::U16) => Length::Prefixed(Scalar::U16),
+					Some(Scalar::U32) => Length::Prefixed(Scalar::U32),
+					_ => return Err(SchemaError::new(line, format!("bad length {:?}, expected a number, u8, u16, u32 or nothing", length))),
+				}
+			};
+			(text[..open].trim(), Some(length))
+		},
+		Some(_) => return Err(SchemaError::new(line, format!("bad type {:?}", text))),
+		None => (text, None),
+	};
+	let base = match (base, length) {
+		("string", Some(length)) => return Ok(Type::String(length)),
+		("bytes", Some(length)) => return Ok(Type::Bytes(length)),
+		("string", None) | ("bytes", None) => {
+			return Err(SchemaError::new(line, format!("{} needs a length, e.g. {}[16]", base, base)));
+		},
+		(base, _) if base.contains('[') => {
+			return Err(SchemaError::new(line, format!("arrays of {} are not supported", base)));
+		},
+		(base, _) => match Scalar::parse(base) {
+			Some(scalar) => Type::Scalar(scalar),
+			None => {
+				check_identifier(line, base)?;
+				Type::Struct(base.to_string())
+			},
+		},
+	};
+	Ok(match length {
+		Some(length) => Type::Array(Box::new(base), length),
+		None => base,
+	})
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+	if text.starts_with("0x") || text.starts_with("0X") {
+		u64::from_str_radix(&text[2..], 16).ok()
+	} else {
+		text.parse().ok()
+	}
+}
+
+fn check_identifier(line: usize, name: &str) -> Result<(), SchemaError> {
+	let mut chars = name.chars();
+	let valid = match chars.next() {
+		Some(first) => (first.is_ascii_alphabetic() || first == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+		None => false,
+	};
+	if !valid {
+		Err(SchemaError::new(line, format!("{:?} is not a valid name", name)))
+	} else if KEYWORDS.contains(&name) {
+		Err(SchemaError::new(line, format!("{} is a Rust keyword, pick another name", name)))
+	} else {
+		Ok(())
+	}
+}
+
+fn rust_type(ty: &Type) -> String {
+	match *ty {
+		Type::Scalar(scalar) => scalar.name().to_string(),
+		Type::String(_) => "String".to_string(),
+		Type::Bytes(_) => "Vec<u8>".to_string(),
+		Type::Struct(ref name) => name.clone(),
+		Type::Array(ref inner, _) => format!("Vec<{}>", rust_type(inner)),
+	}
+}
+
+/* reads a count of type `scalar` */
+fn read_count(scalar: Scalar) -> String {
+	format!("reader.read_{}()? as usize", scalar.name())
+}
+
+fn decode_expr(codec: &str, ty: &Type) -> String {
+	match *ty {
+		Type::Scalar(scalar) => format!("reader.read_{}()?", scalar.name()),
+		Type::Struct(_) => format!("{}::Codec::decode(reader)?", codec),
+		Type::String(length) => match length {
+			Length::Fixed(size) => format!("reader.read_string({})?", size),
+			Length::Prefixed(scalar) => format!("{{ let size = {}; reader.read_string(size)? }}", read_count(scalar)),
+			Length::Rest => "{ let size = reader.remaining(); reader.read_string(size)? }".to_string(),
+		},
+		Type::Bytes(length) => match length {
+			Length::Fixed(size) => format!("reader.read_bytes({})?.to_vec()", size),
+			Length::Prefixed(scalar) => format!("{{ let size = {}; reader.read_bytes(size)?.to_vec() }}", read_count(scalar)),
+			Length::Rest => "{ let size = reader.remaining(); reader.read_bytes(size)?.to_vec() }".to_string(),
+		},
+		Type::Array(_, length) => match length {
+			Length::Fixed(count) => format!("{}::decode_array(reader, {})?", codec, count),
+			Length::Prefixed(scalar) => format!("{{ let count = {}; {}::decode_array(reader, count)? }}", read_count(scalar), codec),
+			Length::Rest => format!("{}::decode_rest(reader)?", codec),
+		},
+	}
+}
+
+fn encode_expr(krate: &str, ty: &Type, value: &str) -> String {
+	let codec = format!("{}::packets", krate);
+	let prefix = |scalar: Scalar| format!("{}::schema::Scalar::{:?}", krate, scalar);
+	match *ty {
+		Type::Scalar(scalar) => format!("writer.write_{}({})", scalar.name(), value),
+		Type::Struct(_) => format!("{}::Codec::encode(&{}, writer)", codec, value),
+		Type::String(length) => match length {
+			Length::Fixed(size) => format!("writer.write_string(&{}, {})", value, size),
+			Length::Prefixed(scalar) => format!("{}::encode_prefixed_bytes(writer, {}.as_bytes(), {})", codec, value, prefix(scalar)),
+			Length::Rest => format!("writer.write_bytes({}.as_bytes())", value),
+		},
+		Type::Bytes(length) => match length {
+			Length::Fixed(size) => format!("{}::encode_fixed_bytes(writer, &{}, {})", codec, value, size),
+			Length::Prefixed(scalar) => format!("{}::encode_prefixed_bytes(writer, &{}, {})", codec, value, prefix(scalar)),
+			Length::Rest => format!("writer.write_bytes(&{})", value),
+		},
+		Type::Array(_, length) => match length {
+			Length::Fixed(count) => format!("{}::encode_array(writer, &{}, {})", codec, value, count),
+			Length::Prefixed(scalar) => format!("{}::encode_prefixed_array(writer, &{}, {})", codec, value, prefix(scalar)),
+			Length::Rest => format!("{}::encode_items(writer, &{})", codec, value),
+		},
+	}
+}
@@ -0,0 +1,324 @@
+use std::io::{self, Error};
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use libc;
+use mio::*;
+use mio::tcp::*;
+
+use client::{register_listener, FiestaHandler, FiestaMessage};
+use config::ServerConfig;
+use metrics::Metrics;
+use processing::PacketProcessor;
+use registry::ClientRegistry;
+use stream::{ListenAddr, Listener, ListenerTag, DEFAULT_LISTENER};
+
+const BACKLOG: usize = 1024;
+
+/* how the acceptor picks the event loop serving a new client */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+	RoundRobin,
+	/* the loop with the fewest connected clients */
+	LeastLoaded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReactorMode {
+	/* one event loop accepts and serves every client */
+	Single,
+	/* the first loop accepts and hands clients to all `reactors` loops */
+	Acceptor {
+		reactors:	usize,
+		balance:	Balance,
+	},
+	/* every loop has its own SO_REUSEPORT listeners, the kernel spreads clients.
+	 * Unix listeners can't share their path, they are served by the first loop. */
+	ReusePort {
+		reactors:	usize,
+	},
+}
+
+/* runs one or more `FiestaHandler`s sharing a processor and a `ClientRegistry` */
+pub struct FiestaServer {
+	listeners:		Vec<(ListenAddr, ListenerTag)>,
+	processor:		Box<dyn PacketProcessor>,
+	config:			ServerConfig,
+	mode:			ReactorMode,
+}
+
+/* a started server, its event loops run on their own threads */
+pub struct RunningServer {
+	/* the bound TCP listeners, in the order they were added */
+	addrs:			Vec<(ListenerTag, SocketAddr)>,
+	metrics_addr:	Option<SocketAddr>,
+	registry:		Arc<ClientRegistry>,
+	metrics:		Arc<Metrics>,
+	channels:		Vec<Sender<FiestaMessage>>,
+	threads:		Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl FiestaServer {
+	/* `addr` is tagged `DEFAULT_LISTENER`, more can be added with `listen` */
+	pub fn new(addr: SocketAddr, processor: Box<dyn PacketProcessor>) -> Self {
+		FiestaServer {
+			listeners:		vec![(ListenAddr::Tcp(addr), DEFAULT_LISTENER)],
+			processor,
+			config:			ServerConfig::default(),
+			mode:			ReactorMode::Single,
+		}
+	}
+
+	pub fn listen<A: Into<ListenAddr>>(mut self, tag: ListenerTag, addr: A) -> Self {
+		self.listeners.push((addr.into(), tag));
+		self
+	}
+
+	pub fn config(mut self, config: ServerConfig) -> Self {
+		self.config = config;
+		self
+	}
+
+	pub fn mode(mut self, mode: ReactorMode) -> Self {
+		self.mode = mode;
+		self
+	}
+
+	fn reactors(&self) -> usize {
+		match self.mode {
+			ReactorMode::Single => 1,
+			ReactorMode::Acceptor { reactors, .. } | ReactorMode::ReusePort { reactors } => reactors.max(1),
+		}
+	}
+
+	/* binds the listener(s) and starts the event loops */
+	pub fn start(self) -> io::Result<RunningServer> {
+		self.config.limits.validate()?;
+		if let Some(ref rate_limits) = self.config.rate_limits {
+			rate_limits.validate()?;
+		}
+		if let Some(ref seed) = self.config.seed {
+			seed.validate()?;
+		}
+		if let Some(ref admin) = self.config.admin {
+			admin.validate()?;
+		}
+		let reactors = self.reactors();
+		let registry = Arc::new(ClientRegistry::with_reactors(reactors));
+		/* only the acceptor hands clients to the other loops */
+		let balance = match self.mode {
+			ReactorMode::Acceptor { balance, .. } => Some(balance),
+			_ => None,
+		};
+
+		/* bind everything up front so errors are reported to the caller */
+		let mut listeners = Vec::with_capacity(reactors);
+		/* port 0 is resolved by the first loop, the others share its port */
+		let mut addrs = self.listeners.clone();
+		for reactor in 0..reactors {
+			let mut bound = Vec::new();
+			for &mut (ref mut addr, tag) in &mut addrs {
+				let listener = match (self.mode, &*addr) {
+					(ReactorMode::ReusePort { .. }, ListenAddr::Tcp(addr)) => Listener::Tcp(bind_reuseport(addr)?),
+					_ if reactor == 0 => Listener::bind(addr)?,
+					_ => continue,
+				};
+				if let Some(local) = listener.local_addr() {
+					*addr = ListenAddr::Tcp(local);
+				}
+				bound.push((listener, tag));
+			}
+			listeners.push(bound);
+		}
+		let local_addrs = addrs.iter().filter_map(|&(ref addr, tag)| match *addr {
+			ListenAddr::Tcp(addr) => Some((tag, addr)),
+			ListenAddr::Unix(..) => None,
+		}).collect();
+		let mut metrics_listener = match self.config.metrics_addr {
+			Some(ref addr) => Some(TcpListener::bind(addr)?),
+			None => None,
+		};
+		let metrics_addr = match metrics_listener {
+			Some(ref listener) => Some(listener.local_addr()?),
+			None => None,
+		};
+		let mut admin_listener = match self.config.admin {
+			Some(ref admin) => Some(Listener::bind(&admin.addr)?),
+			None => None,
+		};
+
+		let (channel_tx, channel_rx) = mpsc::channel();
+		let mut peer_txs = Vec::with_capacity(reactors);
+		let mut threads = Vec::with_capacity(reactors);
+		for (reactor, listeners) in listeners.into_iter().enumerate() {
+			let (peer_tx, peer_rx) = mpsc::channel::<Vec<Sender<FiestaMessage>>>();
+			peer_txs.push(peer_tx);
+			let channel_tx = channel_tx.clone();
+			let processor = self.processor.clone();
+			let config = self.config.clone();
+			let registry = registry.clone();
+			/* the first event loop serves `/metrics` */
+			let metrics_listener = metrics_listener.take();
+			let admin_listener = admin_listener.take();
+			let thread = (thread::Builder::new()
+				.name(format!("fiesta-reactor-{}", reactor))
+				.spawn(move || -> io::Result<()> {
+					let mut event_loop = match EventLoop::new() {
+						Ok(event_loop) => event_loop,
+						Err(e) => {
+							let _ = channel_tx.send((reactor, None));
+							return Err(e);
+						},
+					};
+					let _ = channel_tx.send((reactor, Some(event_loop.channel())));
+					for (i, listener) in listeners.iter().map(|l| &l.0).enumerate() {
+						register_listener(&mut event_loop, listener, Token(i))?;
+					}
+					let admin_config = config.admin.clone();
+					let mut handler = FiestaHandler::reactor(listeners, processor, config, registry, reactor);
+					if let Some(listener) = metrics_listener {
+						handler.serve_metrics(&mut event_loop, listener)?;
+					}
+					if let (Some(listener), Some(admin)) = (admin_listener, admin_config) {
+						handler.serve_admin(&mut event_loop, listener, admin)?;
+					}
+					/* the peers are known once every loop has been created */
+					match peer_rx.recv() {
+						Ok(peers) => {
+							if let Some(balance) = balance {
+								handler.set_peers(peers.clone(), balance);
+							}
+							handler.set_reactors(peers);
+						},
+						Err(_) => return Ok(()),
+					}
+					event_loop.run(&mut handler)
+				}))?;
+			threads.push(thread);
+		}
+		drop(channel_tx);
+
+		let mut channels: Vec<Option<Sender<FiestaMessage>>> = (0..reactors).map(|_| None).collect();
+		for _ in 0..reactors {
+			match channel_rx.recv() {
+				Ok((reactor, channel)) => channels[reactor] = channel,
+				Err(_) => break,
+			}
+		}
+		let channels = if channels.iter().all(|c| c.is_some()) {
+			channels.into_iter().map(|c| c.unwrap()).collect::<Vec<_>>()
+		} else {
+			/* dropping the peer senders stops the loops that did start */
+			mem::drop(peer_txs);
+			for thread in threads {
+				let _ = thread.join();
+			}
+			return Err(Error::other("could not create all event loops"));
+		};
+		for peer_tx in peer_txs {
+			let _ = peer_tx.send(channels.clone());
+		}
+
+		for &(ref addr, tag) in &addrs {
+			info!(target: "network", "listener {} on {} with {} event loop(s)", tag, addr, reactors);
+		}
+		if let Some(addr) = metrics_addr {
+			info!(target: "network", "metrics on http://{}/metrics", addr);
+		}
+		if let Some(ref admin) = self.config.admin {
+			info!(target: "network", "admin console on {}", admin.addr);
+		}
+		Ok(RunningServer {
+			addrs:			local_addrs,
+			metrics_addr,
+			registry,
+			metrics:		self.config.metrics.clone(),
+			channels,
+			threads,
+		})
+	}
+
+	/* starts the server and blocks until all event loops stopped */
+	pub fn run(self) -> io::Result<()> {
+		self.start()?.join()
+	}
+}
+
+impl RunningServer {
+	/* the address of the listener given to `FiestaServer::new`, with the port
+	 * the system picked if it was bound to port 0 */
+	pub fn local_addr(&self) -> Option<SocketAddr> {
+		self.listener_addr(DEFAULT_LISTENER)
+	}
+
+	/* the first TCP listener tagged `tag` */
+	pub fn listener_addr(&self, tag: ListenerTag) -> Option<SocketAddr> {
+		self.addrs.iter().find(|&&(t, _)| t == tag).map(|&(_, addr)| addr)
+	}
+
+	/* where `/metrics` is served, see `ServerConfig::metrics_addr` */
+	pub fn metrics_addr(&self) -> Option<SocketAddr> {
+		self.metrics_addr
+	}
+
+	pub fn registry(&self) -> &Arc<ClientRegistry> {
+		&self.registry
+	}
+
+	/* shared by all event loops, see `ServerConfig::metrics` */
+	pub fn metrics(&self) -> &Arc<Metrics> {
+		&self.metrics
+	}
+
+	/* channels to the event loops, indexed like the registry's reactors */
+	pub fn channels(&self) -> &[Sender<FiestaMessage>] {
+		&self.channels[..]
+	}
+
+	/* asks every event loop to stop; use `join` to wait for them */
+	pub fn shutdown(&self) {
+		for channel in &self.channels {
+			if let Err(e) = channel.send(FiestaMessage::Shutdown) {
+				warn!(target: "network", "could not stop event loop: {:?}", e);
+			}
+		}
+	}
+
+	pub fn join(self) -> io::Result<()> {
+		let mut result = Ok(());
+		for thread in self.threads {
+			match thread.join() {
+				Ok(Ok(())) => {},
+				Ok(Err(e)) => result = Err(e),
+				Err(_) => result = Err(Error::other("event loop panicked")),
+			}
+		}
+		result
+	}
+}
+
+/* a listener that shares its port with the other event loops' listeners */
+fn bind_reuseport(addr: &SocketAddr) -> io::Result<TcpListener> {
+	let socket = match *addr {
+		SocketAddr::V4(..) => TcpSocket::v4()?,
+		SocketAddr::V6(..) => TcpSocket::v6()?,
+	};
+	socket.set_reuseaddr(true)?;
+	let enable: libc::c_int = 1;
+	let result = unsafe {
+		libc::setsockopt(
+			socket.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_REUSEPORT,
+			&enable as *const libc::c_int as *const libc::c_void,
+			mem::size_of::<libc::c_int>() as libc::socklen_t)
+	};
+	if result != 0 {
+		return Err(Error::last_os_error());
+	}
+	socket.bind(addr)?;
+	socket.listen(BACKLOG)
+}
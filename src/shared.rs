@@ -0,0 +1,96 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use buffer::ByteCursor;
+use pool::{self, PooledBuf};
+
+/* a cheaply clonable, reference counted slice of received bytes. All packets
+ * decoded from one read share the same allocation. */
+#[derive(Clone)]
+pub struct SharedBytes {
+	data:			Arc<PooledBuf>,
+	start:			usize,
+	end:			usize,
+}
+
+impl SharedBytes {
+	pub fn new() -> Self {
+		SharedBytes::from_vec(Vec::new())
+	}
+
+	pub fn from_vec(data: Vec<u8>) -> Self {
+		let len = data.len();
+		SharedBytes::from_pooled(PooledBuf::from_vec(data), len)
+	}
+
+	/* the first `len` bytes of a pooled buffer, which returns to the pool
+	 * once the last slice of it is dropped */
+	pub fn from_pooled(data: PooledBuf, len: usize) -> Self {
+		assert!(len <= data.len(), "length past the end of the buffer");
+		SharedBytes {
+			data:		Arc::new(data),
+			start:		0,
+			end:		len,
+		}
+	}
+
+	/* copies `data` into a pooled buffer */
+	pub fn copy_from_slice(data: &[u8]) -> Self {
+		let mut buf = pool::take(data.len());
+		buf[..data.len()].copy_from_slice(data);
+		SharedBytes::from_pooled(buf, data.len())
+	}
+
+	pub fn len(&self) -> usize {
+		self.end - self.start
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+
+	pub fn as_slice(&self) -> &[u8] {
+		&self.data[self.start..self.end]
+	}
+
+	/* a sub slice sharing the same allocation, `start` and `end` are relative */
+	pub fn slice(&self, start: usize, end: usize) -> SharedBytes {
+		assert!(start <= end && end <= self.len(), "slice out of bounds");
+		SharedBytes {
+			data:		self.data.clone(),
+			start:		self.start + start,
+			end:		self.start + end,
+		}
+	}
+
+	pub fn cursor(&self) -> ByteCursor<'_> {
+		ByteCursor::new(self.as_slice())
+	}
+}
+
+impl Default for SharedBytes {
+	fn default() -> Self {
+		SharedBytes::new()
+	}
+}
+
+impl Deref for SharedBytes {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		self.as_slice()
+	}
+}
+
+impl From<Vec<u8>> for SharedBytes {
+	fn from(data: Vec<u8>) -> Self {
+		SharedBytes::from_vec(data)
+	}
+}
+
+impl fmt::Debug for SharedBytes {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "SharedBytes[{}]", self.len())
+	}
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, IoSlice, Read, Write};
+use std::mem::ManuallyDrop;
+use std::net::{self, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net as unix_net;
+use std::path::{Path, PathBuf};
+use mio::*;
+use mio::tcp::{self, TcpListener, TcpStream};
+use mio::unix::{UnixListener, UnixStream};
+
+use memory::MemoryStream;
+
+/* names the listener a client came in on, e.g. "game" or "zone" */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerTag(pub &'static str);
+
+/* the tag of listeners that were not given one */
+pub const DEFAULT_LISTENER: ListenerTag = ListenerTag("default");
+
+/* where a listener is bound */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+	/* IPv4 or IPv6 */
+	Tcp(SocketAddr),
+	Unix(PathBuf),
+}
+
+pub enum Listener {
+	Tcp(TcpListener),
+	Unix(UnixListener),
+}
+
+/* the socket of a client */
+pub enum Stream {
+	Tcp(TcpStream),
+	Unix(UnixStream),
+	/* not pollable, the owner drives the connection itself */
+	Memory(MemoryStream),
+}
+
+impl ListenerTag {
+	pub fn name(&self) -> &'static str {
+		self.0
+	}
+}
+
+impl fmt::Display for ListenerTag {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl fmt::Display for ListenAddr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ListenAddr::Tcp(ref addr) => write!(f, "{}", addr),
+			ListenAddr::Unix(ref path) => write!(f, "unix:{}", path.display()),
+		}
+	}
+}
+
+impl From<SocketAddr> for ListenAddr {
+	fn from(addr: SocketAddr) -> Self {
+		ListenAddr::Tcp(addr)
+	}
+}
+
+impl Listener {
+	pub fn bind(addr: &ListenAddr) -> io::Result<Listener> {
+		match *addr {
+			ListenAddr::Tcp(ref addr) => Ok(Listener::Tcp(TcpListener::bind(addr)?)),
+			ListenAddr::Unix(ref path) => Listener::bind_unix(path),
+		}
+	}
+
+	/* a socket file left behind by an earlier run is replaced */
+	pub fn bind_unix(path: &Path) -> io::Result<Listener> {
+		if let Ok(metadata) = fs::symlink_metadata(path) {
+			if metadata.file_type().is_socket() {
+				fs::remove_file(path)?;
+			}
+		}
+		Ok(Listener::Unix(UnixListener::bind(path)?))
+	}
+
+	pub fn accept(&self) -> io::Result<Option<Stream>> {
+		match *self {
+			Listener::Tcp(ref listener) => listener.accept().map(|s| s.map(Stream::Tcp)),
+			Listener::Unix(ref listener) => listener.accept().map(|s| s.map(Stream::Unix)),
+		}
+	}
+
+	pub fn local_addr(&self) -> Option<SocketAddr> {
+		match *self {
+			Listener::Tcp(ref listener) => listener.local_addr().ok(),
+			Listener::Unix(..) => None,
+		}
+	}
+}
+
+impl Stream {
+	/* `None` for Unix sockets and memory streams */
+	pub fn peer_addr(&self) -> Option<SocketAddr> {
+		match *self {
+			Stream::Tcp(ref stream) => stream.peer_addr().ok(),
+			Stream::Unix(..) | Stream::Memory(..) => None,
+		}
+	}
+
+	pub fn shutdown(&self) -> io::Result<()> {
+		match *self {
+			Stream::Tcp(ref stream) => stream.shutdown(tcp::Shutdown::Both),
+			Stream::Unix(ref stream) => {
+				/* mio's `UnixStream` has no `shutdown` */
+				let stream = ManuallyDrop::new(unsafe { unix_net::UnixStream::from_raw_fd(stream.as_raw_fd()) });
+				stream.shutdown(net::Shutdown::Both)
+			},
+			Stream::Memory(ref stream) => {
+				stream.shutdown();
+				Ok(())
+			},
+		}
+	}
+}
+
+impl From<TcpStream> for Stream {
+	fn from(stream: TcpStream) -> Self {
+		Stream::Tcp(stream)
+	}
+}
+
+impl From<UnixStream> for Stream {
+	fn from(stream: UnixStream) -> Self {
+		Stream::Unix(stream)
+	}
+}
+
+impl From<MemoryStream> for Stream {
+	fn from(stream: MemoryStream) -> Self {
+		Stream::Memory(stream)
+	}
+}
+
+impl Read for Stream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match *self {
+			Stream::Tcp(ref mut stream) => stream.read(buf),
+			Stream::Unix(ref mut stream) => stream.read(buf),
+			Stream::Memory(ref mut stream) => stream.read(buf),
+		}
+	}
+}
+
+impl Write for Stream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match *self {
+			Stream::Tcp(ref mut stream) => stream.write(buf),
+			Stream::Unix(ref mut stream) => stream.write(buf),
+			Stream::Memory(ref mut stream) => stream.write(buf),
+		}
+	}
+
+	/* mio's streams only implement plain writes, go through std for `writev` */
+	fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+		/* borrows the descriptor, `ManuallyDrop` keeps it from being closed */
+		match *self {
+			Stream::Tcp(ref stream) => {
+				let stream = ManuallyDrop::new(unsafe { net::TcpStream::from_raw_fd(stream.as_raw_fd()) });
+				(&*stream).write_vectored(bufs)
+			},
+			Stream::Unix(ref stream) => {
+				let stream = ManuallyDrop::new(unsafe { unix_net::UnixStream::from_raw_fd(stream.as_raw_fd()) });
+				(&*stream).write_vectored(bufs)
+			},
+			Stream::Memory(ref mut stream) => stream.write_vectored(bufs),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match *self {
+			Stream::Tcp(ref mut stream) => stream.flush(),
+			Stream::Unix(ref mut stream) => stream.flush(),
+			Stream::Memory(ref mut stream) => stream.flush(),
+		}
+	}
+}
+
+impl AsRawFd for Listener {
+	fn as_raw_fd(&self) -> RawFd {
+		match *self {
+			Listener::Tcp(ref listener) => listener.as_raw_fd(),
+			Listener::Unix(ref listener) => listener.as_raw_fd(),
+		}
+	}
+}
+
+impl Evented for Stream {
+	fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+		match *self {
+			Stream::Tcp(ref stream) => stream.register(selector, token, interest, opts),
+			Stream::Unix(ref stream) => stream.register(selector, token, interest, opts),
+			Stream::Memory(..) => Ok(()),
+		}
+	}
+
+	fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+		match *self {
+			Stream::Tcp(ref stream) => stream.reregister(selector, token, interest, opts),
+			Stream::Unix(ref stream) => stream.reregister(selector, token, interest, opts),
+			Stream::Memory(..) => Ok(()),
+		}
+	}
+
+	fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+		match *self {
+			Stream::Tcp(ref stream) => stream.deregister(selector),
+			Stream::Unix(ref stream) => stream.deregister(selector),
+			Stream::Memory(..) => Ok(()),
+		}
+	}
+}
+
+impl Evented for Listener {
+	fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+		match *self {
+			Listener::Tcp(ref listener) => listener.register(selector, token, interest, opts),
+			Listener::Unix(ref listener) => listener.register(selector, token, interest, opts),
+		}
+	}
+
+	fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+		match *self {
+			Listener::Tcp(ref listener) => listener.reregister(selector, token, interest, opts),
+			Listener::Unix(ref listener) => listener.reregister(selector, token, interest, opts),
+		}
+	}
+
+	fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+		match *self {
+			Listener::Tcp(ref listener) => listener.deregister(selector),
+			Listener::Unix(ref listener) => listener.deregister(selector),
+		}
+	}
+}
+
+impl fmt::Debug for Stream {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Stream::Tcp(ref stream) => write!(f, "Tcp({:?})", stream.peer_addr().ok()),
+			Stream::Unix(ref stream) => write!(f, "Unix({})", stream.as_raw_fd()),
+			Stream::Memory(..) => write!(f, "Memory"),
+		}
+	}
+}
@@ -0,0 +1,460 @@
+/* Tickets for handing a player from one server to another. The client
+ * disconnects on a map change and connects to another zone server, which
+ * has to trust the session it brings along:
+ *
+ *     // the server the player leaves
+ *     let token = tickets.issue(&client, session)?;
+ *     // ... tell the client where to go and send it the token
+ *
+ *     // the server the player arrives at
+ *     let session = tickets.redeem(&client, &token)?;
+ *
+ * A ticket is good for one redeem, from the address it was issued for and
+ * until it expires. Tickets are kept in a `TicketStore`: a `MemoryStore` when
+ * both ends share it, or a `LinkStore` reaching the server that keeps them
+ * over an inter-server link, which `serve`s its store.
+ *
+ * `issue` and `redeem` block until the store answered, up to a
+ * `LinkStore::timeout`. Call them from a worker pool, or use `issue_with` and
+ * `redeem_with`, which return right away and call back with the result. */
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use client::{FiestaNetworkClient, MAX_BODY_SIZE};
+use packets::defs::{NcTicketPutAck, NcTicketPutReq, NcTicketTakeAck, NcTicketTakeReq, TicketData};
+use rpc::{Request, Responder, Rpc, RpcError, RpcProcessor};
+
+/* default for `Tickets::ttl` */
+pub const TICKET_TTL_MS: u64 = 30000;
+
+/* default for `LinkStore::timeout` */
+pub const STORE_TIMEOUT_MS: u64 = 5000;
+
+/* the largest session a ticket carries. NC_TICKET_TAKE_ACK has to fit into
+ * `MAX_BODY_SIZE`: the request ID (4), `found` (1), the token (16), an IPv6
+ * address with its size (17), `ttl_ms` (4) and the session's size (2). */
+pub const MAX_SESSION_SIZE: usize = MAX_BODY_SIZE - 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TicketToken(pub [u8; 16]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+	pub token:			TicketToken,
+	/* where the player has to connect from */
+	pub ip:				IpAddr,
+	pub expires:		Instant,
+	/* whatever the receiving server needs to know about the session */
+	pub session:		Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TicketError {
+	/* never issued, or already redeemed */
+	Unknown,
+	Expired,
+	/* redeemed from another address than it was issued for */
+	WrongAddress {
+		expected:	IpAddr,
+		actual:		IpAddr,
+	},
+	/* the client has no IP address, e.g. it came through a Unix socket */
+	NoAddress,
+	/* the store could not be reached */
+	Store(String),
+	/* the session is larger than `MAX_SESSION_SIZE` */
+	TooLarge(usize),
+}
+
+pub type StoreCallback<T> = Box<dyn FnOnce(Result<T, TicketError>) + Send>;
+
+/* where tickets are kept between `issue` and `redeem` */
+pub trait TicketStore: Send + Sync {
+	fn put(&self, ticket: Ticket) -> Result<(), TicketError>;
+
+	/* removes the ticket, no token is ever taken twice */
+	fn take(&self, token: &TicketToken) -> Result<Option<Ticket>, TicketError>;
+
+	/* like `put`, without waiting for the result; stores that block override
+	 * these */
+	fn put_with(&self, ticket: Ticket, done: StoreCallback<()>) {
+		done(self.put(ticket));
+	}
+
+	fn take_with(&self, token: &TicketToken, done: StoreCallback<Option<Ticket>>) {
+		done(self.take(token));
+	}
+}
+
+/* issues and redeems tickets */
+#[derive(Clone)]
+pub struct Tickets {
+	store:			Arc<dyn TicketStore>,
+	ttl:			Duration,
+}
+
+/* keeps tickets in memory, dropping expired ones as new ones come in */
+pub struct MemoryStore {
+	tickets:		Mutex<HashMap<TicketToken, Ticket>>,
+}
+
+/* the store of another server, reached through `rpc` calls over a link.
+ * `put` and `take` block until the answer, up to the timeout: use them from
+ * a worker pool only, never on the thread that handles the packets of the
+ * link. `put_with` and `take_with` do not block. */
+pub struct LinkStore {
+	rpc:			Rpc,
+	link:			Arc<dyn Fn() -> Option<Arc<FiestaNetworkClient>> + Send + Sync>,
+	timeout:		Duration,
+}
+
+impl Request for NcTicketPutReq {
+	type Response = NcTicketPutAck;
+}
+
+impl Request for NcTicketTakeReq {
+	type Response = NcTicketTakeAck;
+}
+
+impl TicketToken {
+	/* 128 bits from the system's CSPRNG, a token must not be guessable */
+	pub fn generate() -> io::Result<Self> {
+		let mut token = [0; 16];
+		File::open("/dev/urandom")?.read_exact(&mut token)?;
+		Ok(TicketToken(token))
+	}
+
+	pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+		if bytes.len() != 16 {
+			return None;
+		}
+		let mut token = [0; 16];
+		token.copy_from_slice(bytes);
+		Some(TicketToken(token))
+	}
+}
+
+impl Ticket {
+	pub fn expired(&self) -> bool {
+		self.expires <= Instant::now()
+	}
+
+	fn to_data(&self) -> TicketData {
+		let ip = match self.ip {
+			IpAddr::V4(ip) => ip.octets().to_vec(),
+			IpAddr::V6(ip) => ip.octets().to_vec(),
+		};
+		let ttl = self.expires.saturating_duration_since(Instant::now());
+		TicketData {
+			token:		self.token.0.to_vec(),
+			ip,
+			ttl_ms:		ttl.as_millis().min(u32::MAX as u128) as u32,
+			session:	self.session.clone(),
+		}
+	}
+
+	fn from_data(data: TicketData) -> Option<Self> {
+		let ip = match data.ip.len() {
+			4 => IpAddr::V4(Ipv4Addr::new(data.ip[0], data.ip[1], data.ip[2], data.ip[3])),
+			16 => {
+				let mut octets = [0; 16];
+				octets.copy_from_slice(&data.ip[..]);
+				IpAddr::V6(Ipv6Addr::from(octets))
+			},
+			_ => return None,
+		};
+		Some(Ticket {
+			token:		TicketToken::from_slice(&data.token[..])?,
+			ip,
+			expires:	Instant::now() + Duration::from_millis(data.ttl_ms as u64),
+			session:	data.session,
+		})
+	}
+}
+
+impl Tickets {
+	pub fn new(store: Arc<dyn TicketStore>) -> Self {
+		Tickets {
+			store,
+			ttl:		Duration::from_millis(TICKET_TTL_MS),
+		}
+	}
+
+	/* how long a ticket can be redeemed after it was issued */
+	pub fn ttl(mut self, ttl: Duration) -> Self {
+		self.ttl = ttl;
+		self
+	}
+
+	/* a ticket for the player on `client`, to be redeemed from the same address */
+	pub fn issue(&self, client: &FiestaNetworkClient, session: Vec<u8>) -> Result<TicketToken, TicketError> {
+		match client.peer_addr() {
+			Some(addr) => self.issue_for(addr.ip(), session),
+			None => Err(TicketError::NoAddress),
+		}
+	}
+
+	pub fn issue_for(&self, ip: IpAddr, session: Vec<u8>) -> Result<TicketToken, TicketError> {
+		let ticket = self.ticket(ip, session)?;
+		let token = ticket.token;
+		self.store.put(ticket)?;
+		debug!(target: "tickets", "issued {} for {}", token, ip);
+		Ok(token)
+	}
+
+	/* like `issue`, `callback` gets the token once the store has the ticket */
+	pub fn issue_with<F>(&self, client: &FiestaNetworkClient, session: Vec<u8>, callback: F)
+			where F: FnOnce(Result<TicketToken, TicketError>) + Send + 'static {
+		let ip = match client.peer_ip() {
+			Some(ip) => ip,
+			None => return callback(Err(TicketError::NoAddress)),
+		};
+		let ticket = match self.ticket(ip, session) {
+			Ok(ticket) => ticket,
+			Err(e) => return callback(Err(e)),
+		};
+		let token = ticket.token;
+		self.store.put_with(ticket, Box::new(move |result: Result<(), TicketError>| {
+			if result.is_ok() {
+				debug!(target: "tickets", "issued {} for {}", token, ip);
+			}
+			callback(result.map(|_| token));
+		}));
+	}
+
+	fn ticket(&self, ip: IpAddr, session: Vec<u8>) -> Result<Ticket, TicketError> {
+		if session.len() > MAX_SESSION_SIZE {
+			return Err(TicketError::TooLarge(session.len()));
+		}
+		let token = TicketToken::generate().map_err(|e| TicketError::Store(format!("no random token: {}", e)))?;
+		Ok(Ticket {
+			token,
+			ip,
+			expires:	Instant::now() + self.ttl,
+			session,
+		})
+	}
+
+	/* checks and uses up the ticket the player on `client` brought, returns
+	 * its session */
+	pub fn redeem(&self, client: &FiestaNetworkClient, token: &TicketToken) -> Result<Vec<u8>, TicketError> {
+		match client.peer_addr() {
+			Some(addr) => self.redeem_from(addr.ip(), token),
+			None => Err(TicketError::NoAddress),
+		}
+	}
+
+	/* a failed redeem uses the ticket up as well */
+	pub fn redeem_from(&self, ip: IpAddr, token: &TicketToken) -> Result<Vec<u8>, TicketError> {
+		check(ip, token, self.store.take(token)?)
+	}
+
+	/* like `redeem`, `callback` gets the session */
+	pub fn redeem_with<F>(&self, client: &FiestaNetworkClient, token: &TicketToken, callback: F)
+			where F: FnOnce(Result<Vec<u8>, TicketError>) + Send + 'static {
+		let ip = match client.peer_ip() {
+			Some(ip) => ip,
+			None => return callback(Err(TicketError::NoAddress)),
+		};
+		let token = *token;
+		self.store.take_with(&token, Box::new(move |result: Result<Option<Ticket>, TicketError>| {
+			callback(result.and_then(|ticket| check(ip, &token, ticket)));
+		}));
+	}
+}
+
+/* whether the ticket taken for `token` may be redeemed from `ip` */
+fn check(ip: IpAddr, token: &TicketToken, ticket: Option<Ticket>) -> Result<Vec<u8>, TicketError> {
+	let ticket = match ticket {
+		Some(ticket) => ticket,
+		None => return Err(TicketError::Unknown),
+	};
+	if ticket.expired() {
+		return Err(TicketError::Expired);
+	}
+	if ticket.ip != ip {
+		warn!(target: "tickets", "{} was issued for {}, redeemed from {}", token, ticket.ip, ip);
+		return Err(TicketError::WrongAddress { expected: ticket.ip, actual: ip });
+	}
+	debug!(target: "tickets", "redeemed {} from {}", token, ip);
+	Ok(ticket.session)
+}
+
+impl MemoryStore {
+	pub fn new() -> Self {
+		MemoryStore {
+			tickets:	Mutex::new(HashMap::new()),
+		}
+	}
+
+	/* tickets issued and neither redeemed nor dropped yet */
+	pub fn len(&self) -> usize {
+		self.tickets.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl Default for MemoryStore {
+	fn default() -> Self {
+		MemoryStore::new()
+	}
+}
+
+impl TicketStore for MemoryStore {
+	fn put(&self, ticket: Ticket) -> Result<(), TicketError> {
+		let mut tickets = self.tickets.lock().unwrap();
+		tickets.retain(|_, ticket| !ticket.expired());
+		tickets.insert(ticket.token, ticket);
+		Ok(())
+	}
+
+	fn take(&self, token: &TicketToken) -> Result<Option<Ticket>, TicketError> {
+		Ok(self.tickets.lock().unwrap().remove(token))
+	}
+}
+
+impl LinkStore {
+	/* `link` gives the link to the server keeping the tickets, if it is up;
+	 * `RunningLinks::get` fits */
+	pub fn new<F>(rpc: Rpc, link: F) -> Self
+			where F: Fn() -> Option<Arc<FiestaNetworkClient>> + Send + Sync + 'static {
+		LinkStore {
+			rpc,
+			link:		Arc::new(link),
+			timeout:	Duration::from_millis(STORE_TIMEOUT_MS),
+		}
+	}
+
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	fn link(&self) -> Result<Arc<FiestaNetworkClient>, TicketError> {
+		(self.link)().ok_or_else(|| TicketError::Store("the link to the ticket store is down".to_string()))
+	}
+}
+
+impl TicketStore for LinkStore {
+	fn put(&self, ticket: Ticket) -> Result<(), TicketError> {
+		let request = NcTicketPutReq { ticket: ticket.to_data() };
+		stored(self.rpc.call(&self.link()?, &request, self.timeout).wait()?)
+	}
+
+	fn take(&self, token: &TicketToken) -> Result<Option<Ticket>, TicketError> {
+		let request = NcTicketTakeReq { token: token.0.to_vec() };
+		taken(self.rpc.call(&self.link()?, &request, self.timeout).wait()?)
+	}
+
+	fn put_with(&self, ticket: Ticket, done: StoreCallback<()>) {
+		let link = match self.link() {
+			Ok(link) => link,
+			Err(e) => return done(Err(e)),
+		};
+		let request = NcTicketPutReq { ticket: ticket.to_data() };
+		self.rpc.call_with(&link, &request, self.timeout, move |result| {
+			done(result.map_err(TicketError::from).and_then(stored));
+		});
+	}
+
+	fn take_with(&self, token: &TicketToken, done: StoreCallback<Option<Ticket>>) {
+		let link = match self.link() {
+			Ok(link) => link,
+			Err(e) => return done(Err(e)),
+		};
+		let request = NcTicketTakeReq { token: token.0.to_vec() };
+		self.rpc.call_with(&link, &request, self.timeout, move |result| {
+			done(result.map_err(TicketError::from).and_then(taken));
+		});
+	}
+}
+
+fn stored(ack: NcTicketPutAck) -> Result<(), TicketError> {
+	if ack.stored {
+		Ok(())
+	} else {
+		Err(TicketError::Store("the ticket was not stored".to_string()))
+	}
+}
+
+fn taken(ack: NcTicketTakeAck) -> Result<Option<Ticket>, TicketError> {
+	if !ack.found {
+		return Ok(None);
+	}
+	match Ticket::from_data(ack.ticket) {
+		Some(ticket) => Ok(Some(ticket)),
+		None => Err(TicketError::Store("malformed ticket".to_string())),
+	}
+}
+
+/* answers the calls of `LinkStore`s with `store` */
+pub fn serve(processor: RpcProcessor, store: Arc<dyn TicketStore>) -> RpcProcessor {
+	let taking = store.clone();
+	processor
+		.handle(move |request: NcTicketPutReq, reply: Responder<NcTicketPutAck>| {
+			let stored = match Ticket::from_data(request.ticket) {
+				Some(ticket) => store.put(ticket).is_ok(),
+				None => false,
+			};
+			reply.send(&NcTicketPutAck { stored });
+		})
+		.handle(move |request: NcTicketTakeReq, reply: Responder<NcTicketTakeAck>| {
+			let ticket = TicketToken::from_slice(&request.token[..])
+				.and_then(|token| taking.take(&token).unwrap_or(None));
+			reply.send(&match ticket {
+				Some(ticket) => NcTicketTakeAck { found: true, ticket: ticket.to_data() },
+				None => NcTicketTakeAck::default(),
+			});
+		})
+}
+
+impl From<RpcError> for TicketError {
+	fn from(e: RpcError) -> Self {
+		TicketError::Store(e.to_string())
+	}
+}
+
+impl fmt::Display for TicketToken {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for byte in &self.0 {
+			write!(f, "{:02x}", byte)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for TicketError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			TicketError::Unknown => write!(f, "unknown or already redeemed ticket"),
+			TicketError::Expired => write!(f, "the ticket expired"),
+			TicketError::WrongAddress { expected, actual } =>
+				write!(f, "the ticket was issued for {}, not {}", expected, actual),
+			TicketError::NoAddress => write!(f, "the client has no IP address"),
+			TicketError::Store(ref e) => write!(f, "ticket store: {}", e),
+			TicketError::TooLarge(size) => write!(f, "a session of {} bytes, at most {} fit into a ticket", size, MAX_SESSION_SIZE),
+		}
+	}
+}
+
+impl error::Error for TicketError {
+	fn description(&self) -> &str {
+		match *self {
+			TicketError::Unknown => "unknown ticket",
+			TicketError::Expired => "expired ticket",
+			TicketError::WrongAddress { .. } => "ticket redeemed from the wrong address",
+			TicketError::NoAddress => "client without an IP address",
+			TicketError::Store(_) => "ticket store failed",
+			TicketError::TooLarge(_) => "session too large for a ticket",
+		}
+	}
+}
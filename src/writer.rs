@@ -0,0 +1,117 @@
+use std::io;
+
+use client::{frame_packet, try_frame_packet, FiestaPacket};
+
+/* builds the body of a packet, the counterpart of `PacketReader`. Numbers
+ * are written big endian like the reader expects them.
+ *
+ *     let packet = PacketWriter::new(0x0C01)
+ *         .write_string("admin", 18)
+ *         .write_u16(3)
+ *         .finish();
+ */
+#[derive(Debug, Clone)]
+pub struct PacketWriter {
+	opcode:			u16,
+	body:			Vec<u8>,
+}
+
+impl PacketWriter {
+	pub fn new(opcode: u16) -> Self {
+		PacketWriter::with_capacity(opcode, 64)
+	}
+
+	pub fn with_capacity(opcode: u16, capacity: usize) -> Self {
+		PacketWriter {
+			opcode,
+			body:		Vec::with_capacity(capacity),
+		}
+	}
+
+	pub fn opcode(&self) -> u16 {
+		self.opcode
+	}
+
+	pub fn len(&self) -> usize {
+		self.body.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.body.is_empty()
+	}
+
+	pub fn body(&self) -> &[u8] {
+		&self.body[..]
+	}
+
+	pub fn write_bytes(mut self, bytes: &[u8]) -> Self {
+		self.body.extend_from_slice(bytes);
+		self
+	}
+
+	/* a fixed size string, cut off or zero padded to `size` bytes */
+	pub fn write_string(mut self, value: &str, size: usize) -> Self {
+		let bytes = value.as_bytes();
+		let len = bytes.len().min(size);
+		self.body.extend_from_slice(&bytes[..len]);
+		self.body.resize(self.body.len() + size - len, 0);
+		self
+	}
+
+	pub fn write_u8(mut self, value: u8) -> Self {
+		self.body.push(value);
+		self
+	}
+
+	pub fn write_i8(self, value: i8) -> Self {
+		self.write_u8(value as u8)
+	}
+
+	pub fn write_bool(self, value: bool) -> Self {
+		self.write_u8(value as u8)
+	}
+
+	pub fn write_u16(self, value: u16) -> Self {
+		self.write_bytes(&value.to_be_bytes())
+	}
+
+	pub fn write_i16(self, value: i16) -> Self {
+		self.write_u16(value as u16)
+	}
+
+	pub fn write_u32(self, value: u32) -> Self {
+		self.write_bytes(&value.to_be_bytes())
+	}
+
+	pub fn write_i32(self, value: i32) -> Self {
+		self.write_u32(value as u32)
+	}
+
+	pub fn write_u64(self, value: u64) -> Self {
+		self.write_bytes(&value.to_be_bytes())
+	}
+
+	pub fn write_i64(self, value: i64) -> Self {
+		self.write_u64(value as u64)
+	}
+
+	/* the framed packet, ready for `FiestaNetworkClient::append_send` */
+	pub fn to_frame(&self) -> Vec<u8> {
+		frame_packet(self.opcode, &self.body[..])
+	}
+
+	/* `to_frame` for bodies that may be larger than `MAX_BODY_SIZE` */
+	pub fn try_to_frame(&self) -> io::Result<Vec<u8>> {
+		try_frame_packet(self.opcode, &self.body[..])
+	}
+
+	pub fn finish(self) -> FiestaPacket {
+		FiestaPacket::from_vec(self.opcode, self.body)
+	}
+}
+
+impl From<PacketWriter> for FiestaPacket {
+	fn from(writer: PacketWriter) -> FiestaPacket {
+		writer.finish()
+	}
+}
@@ -0,0 +1,171 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::env;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use fiesta_net::admin::{AdminConfig, SharedSecret, MAX_AUTH_FAILURES};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::server::{FiestaServer, RunningServer};
+use fiesta_net::stream::ListenAddr;
+use common::{any_port, free_addr, wait_for, Ignore};
+
+struct Console {
+	reader:		BufReader<Box<dyn Read>>,
+	writer:		Box<dyn Write>,
+}
+
+impl Console {
+	fn connect(path: &std::path::Path) -> Self {
+		let stream = UnixStream::connect(path).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		Console { reader: BufReader::new(Box::new(stream.try_clone().unwrap())), writer: Box::new(stream) }
+	}
+
+	fn connect_tcp(addr: SocketAddr) -> Self {
+		let stream = TcpStream::connect(addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		Console { reader: BufReader::new(Box::new(stream.try_clone().unwrap())), writer: Box::new(stream) }
+	}
+
+	/* true once the console closed the session */
+	fn is_closed(&mut self) -> bool {
+		let mut rest = String::new();
+		self.reader.read_line(&mut rest).unwrap() == 0
+	}
+
+	/* the output of the command, `Err` with the message if it failed */
+	fn run(&mut self, line: &str) -> Result<String, String> {
+		self.writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+		let mut output = String::new();
+		loop {
+			let mut line = String::new();
+			assert!(self.reader.read_line(&mut line).unwrap() > 0, "console closed");
+			if line == "ok\n" {
+				return Ok(output);
+			}
+			if let Some(message) = line.strip_prefix("error: ") {
+				return Err(message.trim_end().to_string());
+			}
+			output.push_str(&line);
+		}
+	}
+}
+
+#[test]
+fn console_commands() {
+	let path = env::temp_dir().join(format!("fiesta-admin-{}.sock", std::process::id()));
+	let admin = AdminConfig::new(ListenAddr::Unix(path.clone()))
+		.auth(SharedSecret("s3cret".to_string()))
+		.command("echo", "repeats its arguments", |_, args| Ok(args.join(" ")));
+	let config = ServerConfig {
+		admin:		Some(admin),
+		..ServerConfig::default()
+	};
+	let server = FiestaServer::new(any_port(), Box::new(Ignore)).config(config).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let registry = server.registry().clone();
+
+	let mut console = Console::connect(&path);
+	assert_eq!(console.run("list"), Err("authenticate first".to_string()));
+	assert_eq!(console.run("auth wrong"), Err("authentication failed".to_string()));
+	assert_eq!(console.run("auth s3cret"), Ok(String::new()));
+	assert_eq!(console.run("echo a b"), Ok("a b\n".to_string()));
+	assert!(console.run("help").unwrap().contains("echo"));
+	assert_eq!(console.run("frobnicate"), Err("unknown command frobnicate".to_string()));
+
+	let mut client = TcpStream::connect(addr).unwrap();
+	client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	wait_for(|| registry.len() == 1);
+	let token = registry.clients()[0].id();
+	let list = console.run("list").unwrap();
+	assert!(list.starts_with(&format!("{:?} 127.0.0.1:", token)), "{}", list);
+	assert!(list.contains("tracing off"));
+	assert_eq!(console.run(&format!("trace {} on", token.0)), Ok(String::new()));
+	assert!(registry.get(token).unwrap().is_tracing());
+
+	assert_eq!(console.run("broadcast 0x0C01 aabb"), Ok("sent to 1 clients\n".to_string()));
+	let mut frame = [0; 5];
+	client.read_exact(&mut frame[..]).unwrap();
+	assert_eq!(&frame[..], &frame_packet(0x0C01, &[0xaa, 0xbb])[..]);
+	let large = "00".repeat(2049);
+	assert_eq!(console.run(&format!("broadcast 0x0C01 {}", large)), Err("at most 2048 bytes".to_string()));
+
+	assert!(console.run("metrics").unwrap().contains("fiesta_connected_clients 1\n"));
+	assert_eq!(console.run(&format!("kick {}", token.0)), Ok(format!("kicked {:?}\n", token)));
+	assert_eq!(client.read(&mut frame[..]).unwrap(), 0);
+	wait_for(|| registry.is_empty());
+	assert!(console.run("kick 12345").is_err());
+
+	assert_eq!(console.run("pause"), Ok(String::new()));
+	assert_eq!(console.run("status"), Ok("clients 0\naccepting false\n".to_string()));
+	let _waiting = TcpStream::connect(addr).unwrap();
+	thread::sleep(Duration::from_millis(100));
+	assert!(registry.is_empty());
+	assert_eq!(console.run("resume"), Ok(String::new()));
+	wait_for(|| registry.len() == 1);
+
+	assert_eq!(console.run("quit"), Ok(String::new()));
+	server.shutdown();
+	server.join().unwrap();
+	let _ = std::fs::remove_file(&path);
+}
+
+fn serve(admin: AdminConfig) -> std::io::Result<RunningServer> {
+	let config = ServerConfig {
+		admin:		Some(admin),
+		..ServerConfig::default()
+	};
+	FiestaServer::new(any_port(), Box::new(Ignore)).config(config).start()
+}
+
+fn socket_path(name: &str) -> PathBuf {
+	env::temp_dir().join(format!("fiesta-admin-{}-{}.sock", name, std::process::id()))
+}
+
+#[test]
+fn closes_the_session_after_failed_logins() {
+	let path = socket_path("auth");
+	let server = serve(AdminConfig::new(ListenAddr::Unix(path.clone())).auth(SharedSecret("s3cret".to_string()))).unwrap();
+
+	let mut console = Console::connect(&path);
+	for _ in 1..MAX_AUTH_FAILURES {
+		assert_eq!(console.run("auth guess"), Err("authentication failed".to_string()));
+	}
+	assert_eq!(console.run("auth guess"), Err("authentication failed, closing".to_string()));
+	assert!(console.is_closed());
+
+	/* a new session starts over */
+	let mut console = Console::connect(&path);
+	assert_eq!(console.run("auth guess"), Err("authentication failed".to_string()));
+	assert_eq!(console.run("auth s3cret"), Ok(String::new()));
+	assert_eq!(console.run("status"), Ok("clients 0\naccepting true\n".to_string()));
+
+	server.shutdown();
+	server.join().unwrap();
+	let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn needs_an_authenticator_beyond_loopback() {
+	let public: SocketAddr = "0.0.0.0:0".parse().unwrap();
+	let refused = serve(AdminConfig::new(public)).err().unwrap();
+	assert_eq!(refused.kind(), ErrorKind::InvalidInput);
+	assert!(AdminConfig::new(public).auth(SharedSecret("s3cret".to_string())).validate().is_ok());
+	assert!(AdminConfig::new(ListenAddr::Unix(socket_path("open"))).validate().is_ok());
+
+	/* on loopback every session is let in */
+	let addr = free_addr();
+	let server = serve(AdminConfig::new(addr)).unwrap();
+	let mut console = Console::connect_tcp(addr);
+	assert_eq!(console.run("status"), Ok("clients 0\naccepting true\n".to_string()));
+
+	server.shutdown();
+	server.join().unwrap();
+}
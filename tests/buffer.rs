@@ -0,0 +1,188 @@
+extern crate fiesta_net;
+
+use std::io::{self, Cursor, IoSlice, Write};
+use fiesta_net::buffer::*;
+
+/* a socket that takes at most `limit` bytes per call */
+struct Trickle {
+	written:	Vec<u8>,
+	limit:		usize,
+}
+
+impl Write for Trickle {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let len = buf.len().min(self.limit);
+		self.written.extend_from_slice(&buf[..len]);
+		Ok(len)
+	}
+
+	fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+		let mut written = 0;
+		for buf in bufs {
+			written += self.write(&buf[..self.limit.saturating_sub(written).min(buf.len())])?;
+		}
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fn counting(len: usize) -> Vec<u8> {
+	(0..len).map(|i| i as u8).collect()
+}
+
+/* `data` stored so that its first `split` bytes sit at the end of the
+ * storage and the rest wraps around to the front */
+fn wrapped(data: &[u8], split: usize) -> Buffer {
+	let mut buffer = Buffer::with_capacity(64);
+	let capacity = buffer.capacity();
+	buffer.append(&vec![0xFF; capacity - split][..]);
+	buffer.append(&data[..split]);
+	/* the emptied buffer would start over at the front */
+	buffer.advance_read(capacity - split);
+	buffer.append(&data[split..]);
+	assert_eq!(buffer.bytes_remaining(), data.len());
+	buffer
+}
+
+#[test]
+fn starts_empty() {
+	let mut buffer = Buffer::with_capacity(64);
+	assert_eq!((buffer.bytes_remaining(), buffer.free_space()), (0, buffer.capacity()));
+	assert_eq!(buffer.as_slices(), (&[][..], &[][..]));
+	let mut out = [0; 4];
+	assert_eq!(buffer.peek_max(0, 4, &mut out[..]).unwrap(), 0);
+	let mut sink = Trickle { written: Vec::new(), limit: 100 };
+	assert_eq!(buffer.drain_into(&mut sink).unwrap(), 0);
+	assert!(sink.written.is_empty());
+
+	/* the size class, never less than asked for */
+	assert!(Buffer::with_capacity(100).capacity() >= 100);
+	assert_eq!(Buffer::new().capacity(), BUFFERSIZE);
+}
+
+#[test]
+fn fills_up_to_the_capacity() {
+	let mut buffer = Buffer::with_capacity(64);
+	let data = counting(74);
+	buffer.append(&data[..]);
+	assert_eq!((buffer.bytes_remaining(), buffer.free_space()), (64, 0));
+	buffer.append(b"dropped");
+	assert_eq!(buffer.as_slices(), (&data[..64], &[][..]));
+
+	/* a full buffer does not read at all */
+	let mut source = Cursor::new(vec![1; 8]);
+	assert_eq!(buffer.fill_from(&mut source, 8).unwrap(), 0);
+	assert_eq!(source.position(), 0);
+
+	/* emptied, the next write starts at the front again */
+	buffer.advance_read(64);
+	assert_eq!(buffer.bytes_remaining(), 0);
+	buffer.append(b"abc");
+	assert_eq!(buffer.as_slices(), (&b"abc"[..], &[][..]));
+}
+
+#[test]
+fn wraps_around() {
+	let data = counting(8);
+	let mut buffer = wrapped(&data[..], 3);
+	assert_eq!(buffer.free_space(), buffer.capacity() - 8);
+	assert_eq!(buffer.as_slices(), (&data[..3], &data[3..]));
+
+	/* copies across the end of the storage, from any offset */
+	for offset in 0..8 {
+		let mut out = [0; 8];
+		assert_eq!(buffer.peek_max(offset, 8, &mut out[..]).unwrap(), 8 - offset);
+		assert_eq!(&out[..8 - offset], &data[offset..]);
+	}
+	let mut out = [0; 2];
+	assert_eq!(buffer.peek_max(2, 2, &mut out[..]).unwrap(), 2);
+	assert_eq!(out, [2, 3]);
+	assert_eq!(buffer.peek_max(8, 2, &mut out[..]).unwrap(), 0);
+	assert_eq!(buffer.peek_u16(2).unwrap(), 0x0203);
+	assert_eq!(buffer.read_bytes(5).unwrap(), &data[..5]);
+	assert_eq!(buffer.as_slices(), (&data[5..], &[][..]));
+}
+
+#[test]
+fn drains_both_halves() {
+	let data = counting(8);
+	let mut buffer = wrapped(&data[..], 3);
+
+	/* a partial write only consumes what was written */
+	let mut sink = Trickle { written: Vec::new(), limit: 5 };
+	assert_eq!(buffer.drain_into(&mut sink).unwrap(), 5);
+	assert_eq!(buffer.as_slices(), (&data[5..], &[][..]));
+	assert_eq!(buffer.drain_into(&mut sink).unwrap(), 3);
+	assert_eq!(sink.written, data);
+	assert_eq!(buffer.bytes_remaining(), 0);
+}
+
+#[test]
+fn fills_into_the_wrapped_free_space() {
+	let mut buffer = wrapped(&[0xFF; 4][..], 4);
+	let capacity = buffer.capacity();
+	assert_eq!(buffer.free_space(), capacity - 4);
+
+	/* the free space starts at the front, `max` caps a single read */
+	let mut source = Cursor::new(counting(capacity));
+	assert_eq!(buffer.fill_from(&mut source, 6).unwrap(), 6);
+	assert_eq!(buffer.bytes_remaining(), 10);
+	assert_eq!(buffer.as_slices(), (&[0xFF; 4][..], &counting(6)[..]));
+	let filled = buffer.fill_from(&mut source, capacity).unwrap();
+	assert_eq!(filled, capacity - 10);
+	assert_eq!(buffer.free_space(), 0);
+	assert_eq!(buffer.fill_from(&mut source, capacity).unwrap(), 0);
+
+	/* at EOF */
+	let mut buffer = Buffer::with_capacity(64);
+	assert_eq!(buffer.fill_from(&mut Cursor::new(Vec::new()), 64).unwrap(), 0);
+}
+
+#[test]
+fn cursor_reads_like_the_buffer() {
+	let data = [0x12, 0x34, 0xFF, 0xFE, 0x01, 0x02, 0x03, 0x04, 0x80, 0, 0, 0, 0, 0, 0, 1, 0xAA, 0xBB];
+	let mut cursor = ByteCursor::new(&data[..]);
+	assert_eq!(cursor.read_u16().unwrap(), 0x1234);
+	assert_eq!(cursor.read_i16().unwrap(), -2);
+	assert_eq!(cursor.read_u32().unwrap(), 0x01020304);
+	assert_eq!(cursor.read_i64().unwrap(), i64::MIN + 1);
+	assert_eq!(cursor.position(), 16);
+
+	let mut buffer = Buffer::with_capacity(64);
+	buffer.fill_from(&mut Cursor::new(data.to_vec()), 64).unwrap();
+	let mut cursor = ByteCursor::new(&data[..]);
+	assert_eq!(cursor.read_u64().unwrap(), buffer.read_u64().unwrap());
+	assert_eq!(cursor.read_i32().unwrap(), buffer.read_i32().unwrap());
+	assert_eq!(cursor.read_u8().unwrap(), buffer.read_u8().unwrap());
+	assert_eq!(cursor.read_i8().unwrap(), buffer.read_i8().unwrap());
+	assert_eq!(cursor.read_bytes(4).unwrap(), buffer.read_bytes(4).unwrap());
+	assert_eq!(cursor.remaining(), 0);
+}
+
+#[test]
+fn cursor_stays_in_bounds() {
+	let data = [1, 2, 3, 4, 5];
+	let mut cursor = ByteCursor::new(&data[..]);
+	/* slices borrow the data, not the cursor */
+	let first = cursor.read_slice(2).unwrap();
+	let second = cursor.read_slice(2).unwrap();
+	assert_eq!((first, second), (&[1, 2][..], &[3, 4][..]));
+
+	/* a short read fails without moving */
+	assert!(cursor.read_u16().is_err());
+	assert!(cursor.read_slice(2).is_err());
+	assert_eq!(cursor.position(), 4);
+	assert_eq!(cursor.read_u8().unwrap(), 5);
+	assert!(cursor.read_u8().is_err());
+	assert_eq!(cursor.read_slice(0).unwrap(), &[][..]);
+
+	assert!(cursor.set_position(6).is_err());
+	assert_eq!(cursor.position(), 5);
+	cursor.set_position(5).unwrap();
+	assert_eq!(cursor.remaining(), 0);
+	cursor.set_position(1).unwrap();
+	assert_eq!(cursor.read_u32().unwrap(), 0x02030405);
+}
@@ -0,0 +1,123 @@
+extern crate fiesta_net;
+extern crate mio;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use fiesta_net::capture::*;
+use fiesta_net::client::frame_packet;
+use fiesta_net::harness::Harness;
+use common::{Answer, Ignore};
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+	env::temp_dir().join(format!("fiesta-capture-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn records_both_directions() {
+	let path = temp_path("both");
+	let recorder = Arc::new(PacketRecorder::new(CaptureConfig::new(&path).filter(CaptureFilter::all().opcode(0x10).opcode(0x11))).unwrap());
+	let mut harness = Harness::new(Box::new(Answer));
+	harness.client().set_recorder(Some(recorder.clone()));
+	harness.inject(0x10, &[1, 2, 3]);
+	harness.inject(0x20, &[4]);
+	harness.run();
+	recorder.flush().unwrap();
+	assert_eq!(recorder.recorded(), 2);
+
+	let entries: Vec<CaptureEntry> = CaptureReader::open(&path).unwrap().map(|e| e.unwrap()).collect();
+	assert_eq!(entries.len(), 2);
+	assert_eq!((entries[0].direction, entries[0].opcode, &entries[0].payload[..]), (Direction::Inbound, 0x10, &[1, 2, 3][..]));
+	assert_eq!((entries[1].direction, entries[1].opcode, &entries[1].payload[..]), (Direction::Outbound, 0x11, &[1, 2, 3][..]));
+	assert_eq!(entries[0].token, harness.client().id());
+
+	let mut pcapng = Vec::new();
+	assert_eq!(export_pcapng(CaptureReader::open(&path).unwrap(), &mut pcapng).unwrap(), 2);
+	assert_eq!(&pcapng[..4], &[0x0A, 0x0D, 0x0D, 0x0A]);
+	assert_eq!(pcapng.len() % 4, 0);
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rotates_files() {
+	let path = temp_path("rotate");
+	let recorder = PacketRecorder::new(CaptureConfig::new(&path).rotate(200, 2)).unwrap();
+	for i in 0..10u64 {
+		recorder.write(&CaptureEntry::now(mio::Token(i as usize), None, Direction::Inbound, 1, &[0; 60]));
+	}
+	recorder.flush().unwrap();
+	let rotated = |i: usize| { let mut p = path.clone().into_os_string(); p.push(format!(".{}", i)); p };
+	let count = |p: &std::ffi::OsStr| CaptureReader::open(p).unwrap().count();
+	/* 2 entries of 88 bytes fit next to the 8 byte header */
+	assert_eq!(count(path.as_os_str()), 2);
+	assert_eq!(count(&rotated(1)), 2);
+	assert_eq!(count(&rotated(2)), 2);
+	assert!(fs::metadata(rotated(3)).is_err());
+	let last = CaptureReader::open(&path).unwrap().last().unwrap().unwrap();
+	assert_eq!(last.token, mio::Token(9));
+	for p in &[path.clone().into_os_string(), rotated(1), rotated(2)] {
+		fs::remove_file(p).unwrap();
+	}
+}
+
+#[test]
+fn records_frames_split_across_sends() {
+	let path = temp_path("split");
+	let recorder = Arc::new(PacketRecorder::new(CaptureConfig::new(&path)).unwrap());
+	let mut harness = Harness::new(Box::new(Ignore));
+	harness.client().set_recorder(Some(recorder.clone()));
+	let mut bytes = frame_packet(0x21, &[1; 300]);
+	bytes.extend_from_slice(&frame_packet(0x22, &[2, 3])[..]);
+	/* the second send ends inside the first frame, the third one inside the second */
+	for part in &[&bytes[..1], &bytes[1..200], &bytes[200..305], &bytes[305..]] {
+		harness.client().append_send(part);
+	}
+	harness.run();
+	assert_eq!(harness.sent_bytes(), bytes);
+	recorder.flush().unwrap();
+
+	let entries: Vec<(u16, Vec<u8>)> = CaptureReader::open(&path).unwrap().map(|e| { let e = e.unwrap(); (e.opcode, e.payload) }).collect();
+	assert_eq!(entries, vec![(0x21, vec![1; 300]), (0x22, vec![2, 3])]);
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn flushes_on_an_interval() {
+	let path = temp_path("flush");
+	let recorder = PacketRecorder::new(CaptureConfig::new(&path).flush_every(Duration::from_millis(200))).unwrap();
+	let entry = |i: usize| CaptureEntry::now(mio::Token(i), None, Direction::Inbound, 1, &[0; 8]);
+	let count = || CaptureReader::open(&path).unwrap().count();
+	recorder.write(&entry(0));
+	recorder.write(&entry(1));
+	assert_eq!(count(), 0);
+
+	/* the writer thread flushes them without waiting for more entries */
+	std::thread::sleep(Duration::from_millis(400));
+	assert_eq!(count(), 2);
+	recorder.write(&entry(2));
+	recorder.write(&entry(3));
+	assert_eq!(count(), 2);
+	recorder.flush().unwrap();
+	assert_eq!((count(), recorder.recorded()), (4, 4));
+	recorder.write(&entry(4));
+
+	/* nothing is lost when the recorder goes away */
+	drop(recorder);
+	assert_eq!(count(), 5);
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn refuses_oversized_entries() {
+	let mut file = Vec::new();
+	file.extend_from_slice(&MAGIC[..]);
+	file.extend_from_slice(&[0, 1, 0, 0]);
+	file.extend_from_slice(&u32::MAX.to_be_bytes());
+	let mut reader = CaptureReader::new(io::Cursor::new(file)).unwrap();
+	let error = reader.read_entry().unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
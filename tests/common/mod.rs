@@ -0,0 +1,107 @@
+/* helpers shared by the integration tests, each test file uses some of them */
+#![allow(dead_code)]
+
+extern crate mio;
+
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use fiesta_net::client::{frame_packet, FiestaHandler, SERVER_TOKEN};
+use fiesta_net::config::ServerConfig;
+use fiesta_net::processing::{PacketProcessingInfo, PacketProcessor};
+use self::mio::{EventLoop, EventSet, PollOpt};
+use self::mio::tcp::TcpListener;
+
+/* answers every packet with opcode + 1 and the same body */
+pub struct Answer;
+
+impl PacketProcessor for Answer {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let packet = info.packet.read().unwrap();
+		info.client.append_send(&frame_packet(packet.header + 1, &packet.data[..]));
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(Answer)
+	}
+}
+
+pub struct Ignore;
+
+impl PacketProcessor for Ignore {
+	fn process_packet(&mut self, _: Arc<RwLock<Box<PacketProcessingInfo>>>) {}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(Ignore)
+	}
+}
+
+/* opcode, body and listener tag of every packet received */
+pub type Received = Arc<Mutex<Vec<(u16, Vec<u8>, &'static str)>>>;
+
+#[derive(Clone)]
+pub struct Collect(pub Received);
+
+impl Collect {
+	pub fn new() -> Self {
+		Collect(Arc::new(Mutex::new(Vec::new())))
+	}
+
+	pub fn has_body(&self, body: &[u8]) -> bool {
+		self.0.lock().unwrap().iter().any(|(_, received, _)| &received[..] == body)
+	}
+}
+
+impl PacketProcessor for Collect {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let packet = info.packet.read().unwrap();
+		self.0.lock().unwrap().push((packet.header, packet.data.to_vec(), info.client.listener().0));
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(Clone::clone(self))
+	}
+}
+
+pub fn wait_for<F: Fn() -> bool>(condition: F) {
+	let deadline = Instant::now() + Duration::from_secs(5);
+	while !condition() {
+		assert!(Instant::now() < deadline, "timed out");
+		thread::sleep(Duration::from_millis(10));
+	}
+}
+
+/* let the system pick the port, ask the listener which one it took */
+pub fn any_port() -> SocketAddr {
+	"127.0.0.1:0".parse().unwrap()
+}
+
+/* an address nothing listens on, for now */
+pub fn free_addr() -> SocketAddr {
+	TcpListener::bind(&any_port()).unwrap().local_addr().unwrap()
+}
+
+/* runs a handler on its own event loop until the test process exits; the
+ * loop starts once `start` returns, see `serve` */
+pub fn serve_after<F: FnOnce() + Send + 'static>(processor: Box<dyn PacketProcessor>, config: ServerConfig, start: F) -> SocketAddr {
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || {
+		let listener = TcpListener::bind(&any_port()).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut event_loop = EventLoop::new().unwrap();
+		event_loop.register_opt(&listener, SERVER_TOKEN, EventSet::readable(), PollOpt::level()).unwrap();
+		let mut handler = FiestaHandler::with_config(listener, processor, config);
+		sender.send(addr).unwrap();
+		start();
+		event_loop.run(&mut handler).unwrap();
+	});
+	receiver.recv().unwrap()
+}
+
+pub fn serve(processor: Box<dyn PacketProcessor>, config: ServerConfig) -> SocketAddr {
+	serve_after(processor, config, || ())
+}
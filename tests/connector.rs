@@ -0,0 +1,59 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::io::ErrorKind;
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::connector::{Connector, OUTBOUND};
+use fiesta_net::reader::PacketReader;
+use fiesta_net::server::FiestaServer;
+use fiesta_net::writer::PacketWriter;
+use common::{any_port, wait_for, Answer, Collect};
+
+#[test]
+fn writes_what_the_reader_reads() {
+	let packet = PacketWriter::new(0x0C01)
+		.write_u8(7)
+		.write_i16(-2)
+		.write_u32(0xDEADBEEF)
+		.write_string("fiesta", 8)
+		.write_bool(true)
+		.finish();
+	assert_eq!(packet.header, 0x0C01);
+	assert_eq!(packet.data.len(), 1 + 2 + 4 + 8 + 1);
+
+	let mut reader = PacketReader::new(&packet);
+	assert_eq!(reader.read_u8().unwrap(), 7);
+	assert_eq!(reader.read_i16().unwrap(), -2);
+	assert_eq!(reader.read_u32().unwrap(), 0xDEADBEEF);
+	assert_eq!(reader.read_string(8).unwrap(), "fiesta");
+	assert!(reader.read_bool().unwrap());
+	reader.expect_end().unwrap();
+
+	let frame = PacketWriter::new(0x0C01).write_bytes(&[1, 2, 3]).to_frame();
+	assert_eq!(frame, frame_packet(0x0C01, &[1, 2, 3]));
+	let writer = PacketWriter::new(0x0C01).write_bytes(&[0; 2049]);
+	assert_eq!(writer.try_to_frame().unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn talks_to_a_server() {
+	let server = FiestaServer::new(any_port(), Box::new(Answer)).start().unwrap();
+	let addr = server.local_addr().unwrap();
+
+	let collect = Collect::new();
+	let received = collect.0.clone();
+	let connector = Connector::start(Box::new(collect), ServerConfig::default()).unwrap();
+	let client = connector.connect(&addr).unwrap();
+	client.append_send(&PacketWriter::new(0x0C01).write_u16(0x1234).to_frame());
+
+	wait_for(|| !received.lock().unwrap().is_empty());
+	assert_eq!(received.lock().unwrap()[0], (0x0C02, vec![0x12, 0x34], OUTBOUND.0));
+	assert_eq!(connector.registry().len(), 1);
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
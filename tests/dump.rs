@@ -0,0 +1,81 @@
+extern crate fiesta_net;
+
+use fiesta_net::buffer::Buffer;
+use fiesta_net::client::FiestaPacket;
+use fiesta_net::dump::{self, FieldKind, PacketSchema};
+
+#[test]
+fn hex_dump_layout() {
+	let data: Vec<u8> = (0x41..0x41 + 20).collect();
+	let dumped = dump::hex_dump(&data[..]);
+	let lines: Vec<&str> = dumped.lines().collect();
+	assert_eq!(lines.len(), 2);
+	assert_eq!(lines[0], "0000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  ABCDEFGHIJKLMNOP");
+	assert_eq!(lines[1], "0010  51 52 53 54                                       QRST");
+}
+
+#[test]
+fn buffer_dump_does_not_consume() {
+	let mut buffer = Buffer::with_capacity(64);
+	buffer.append(&[0x00, 0x7f, b'h', b'i']);
+	assert_eq!(dump::buffer_dump(&buffer), dump::hex_dump(&[0x00, 0x7f, b'h', b'i']));
+	assert_eq!(buffer.bytes_remaining(), 4);
+}
+
+#[test]
+fn annotates_registered_schema() {
+	dump::register_schema(0x7E01, PacketSchema::new("TEST_LOGIN")
+		.field("name", FieldKind::String(8))
+		.field("level", FieldKind::U16));
+	let mut body = b"admin\0\0\0".to_vec();
+	body.extend_from_slice(&[0x00, 0x2a, 0xff]);
+
+	let pretty = dump::pretty_packet(0x7E01, &body[..]);
+	let lines: Vec<&str> = pretty.lines().collect();
+	assert_eq!(lines[0], "0x7E01 TEST_LOGIN, 11 bytes");
+	assert_eq!(lines[1], "  0000  name   \"admin\"");
+	assert_eq!(lines[2], "  0008  level  42 (0x002A)");
+	assert_eq!(lines[3], "  000a  1 bytes not covered by the schema");
+	assert!(lines[4].starts_with("0000  61 64 6d 69 6e"));
+}
+
+#[test]
+fn annotation_stops_at_short_body() {
+	dump::register_schema(0x7E02, PacketSchema::new("TEST_SHORT")
+		.field("id", FieldKind::U32)
+		.field("flag", FieldKind::Bool));
+	let annotations = dump::schema(0x7E02).unwrap().annotate(0x7E02, &[1, 2]);
+	assert_eq!(annotations.len(), 1);
+	assert_eq!(annotations[0].name, "id");
+	assert_eq!(annotations[0].value, "<wanted 4 bytes but only 2 are left>");
+}
+
+#[test]
+fn debug_output() {
+	let packet = FiestaPacket::from_vec(0x7E03, vec![1, 2, 3]);
+	assert_eq!(format!("{:?}", packet), "FiestaPacket(0x7E03, 3 bytes)");
+	assert_eq!(format!("{:#?}", packet), dump::pretty_packet(0x7E03, &[1, 2, 3]));
+
+	dump::register_schema(0x7E04, PacketSchema::new("TEST_NAMED"));
+	let packet = FiestaPacket::from_vec(0x7E04, vec![]);
+	assert_eq!(format!("{:?}", packet), "FiestaPacket(0x7E04 TEST_NAMED, 0 bytes)");
+}
+
+#[test]
+fn tracing_per_opcode() {
+	assert!(!dump::is_traced(0x7E05));
+	dump::trace_opcode(0x7E05, true);
+	assert!(dump::is_traced(0x7E05));
+	assert!(!dump::is_traced(0x7E06));
+	dump::trace_opcode(0x7E05, false);
+	assert!(!dump::is_traced(0x7E05));
+}
+
+#[test]
+fn parses_hex_bytes() {
+	assert_eq!(dump::parse_hex("0a0B"), Ok(vec![0x0A, 0x0B]));
+	assert_eq!(dump::parse_hex(""), Ok(vec![]));
+	assert_eq!(dump::parse_hex("abc"), Err("not hex: abc".to_string()));
+	assert!(dump::parse_hex("zz").is_err());
+	assert!(dump::parse_hex("ä0").is_err());
+}
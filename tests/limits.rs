@@ -0,0 +1,245 @@
+extern crate fiesta_net;
+extern crate libc;
+
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::limits::*;
+use fiesta_net::metrics::{DisconnectReason, Metrics};
+use fiesta_net::processing::{PacketProcessingInfo, PacketProcessor};
+use fiesta_net::server::FiestaServer;
+use common::{any_port, serve, serve_after, wait_for, Answer, Ignore};
+
+/* `pauses_accepting_after_an_accept_error` runs out of file descriptors for
+ * the whole process, no other test may run meanwhile */
+static FDS: RwLock<()> = RwLock::new(());
+
+/* answers every packet with 16 MB the client never reads */
+struct Flood;
+
+impl PacketProcessor for Flood {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let frame = frame_packet(0x0C02, &[7; 2000]);
+		for _ in 0..8 * 1024 {
+			info.client.append_send(&frame);
+		}
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(Flood)
+	}
+}
+
+fn flood(overflow: SendOverflow) -> (TcpStream, Arc<Metrics>) {
+	let config = ServerConfig {
+		send_queue:		SendQueueLimit { max_bytes: Some(256 * 1024), overflow },
+		..ServerConfig::default()
+	};
+	let metrics = config.metrics.clone();
+	let mut stream = TcpStream::connect(serve(Box::new(Flood), config)).unwrap();
+	stream.write_all(&frame_packet(0x0C01, b"go")[..]).unwrap();
+	(stream, metrics)
+}
+
+#[test]
+fn drops_what_does_not_fit_into_the_send_queue() {
+	let _fds = FDS.read().unwrap();
+	let (mut stream, metrics) = flood(SendOverflow::Drop);
+
+	/* whatever fit is delivered, the rest is dropped and the client stays */
+	let expected = frame_packet(0x0C02, &[7; 2000]);
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut received = 0;
+	let mut frame = vec![0; expected.len()];
+	while received < 256 * 1024 / expected.len() {
+		stream.read_exact(&mut frame[..]).unwrap();
+		assert!(frame == expected);
+		received += 1;
+	}
+	assert!(kept(stream));
+	assert!(metrics.snapshot().send_overflows > 0);
+}
+
+#[test]
+fn disconnects_when_the_send_queue_is_full() {
+	let _fds = FDS.read().unwrap();
+	let (stream, metrics) = flood(SendOverflow::Disconnect);
+	/* at most what fit into the queue, then the server hangs up */
+	let rest = rest_of(stream);
+	assert!(rest.len() <= 256 * 1024, "{}", rest.len());
+	wait_for(|| metrics.snapshot().disconnects.get(&DisconnectReason::SendQueueFull) == Some(&1));
+}
+
+#[test]
+fn tracks_connections_in_total_and_per_address() {
+	let limits = ConnectionLimits {
+		max_connections:	Some(3),
+		max_per_ip:			Some(2),
+		..ConnectionLimits::default()
+	};
+	let a: IpAddr = "10.0.0.1".parse().unwrap();
+	let b: IpAddr = "10.0.0.2".parse().unwrap();
+	let mut tracker = ConnectionTracker::new();
+	tracker.add(Some(a));
+	tracker.add(Some(a));
+	assert_eq!(tracker.check(&limits, Some(a)), Err(LimitExceeded::MaxPerIp));
+	assert_eq!(tracker.check(&limits, Some(b)), Ok(()));
+	/* a client without an address only counts towards the total */
+	assert_eq!(tracker.check(&limits, None), Ok(()));
+	tracker.add(None);
+	assert_eq!(tracker.check(&limits, Some(b)), Err(LimitExceeded::MaxConnections));
+
+	tracker.remove(Some(a));
+	assert_eq!((tracker.total(), tracker.connections_from(&a)), (2, 1));
+	assert_eq!(tracker.check(&limits, Some(a)), Ok(()));
+	tracker.remove(Some(a));
+	tracker.remove(None);
+	assert_eq!((tracker.total(), tracker.connections_from(&a)), (0, 0));
+	tracker.remove(None);
+	assert_eq!(tracker.total(), 0);
+	assert_eq!(ConnectionTracker::new().check(&ConnectionLimits::default(), Some(a)), Ok(()));
+}
+
+fn limited(limits: ConnectionLimits) -> SocketAddr {
+	let config = ServerConfig {
+		limits,
+		..ServerConfig::default()
+	};
+	serve(Box::new(Ignore), config)
+}
+
+/* what the server sent until it closed the connection */
+fn rest_of(mut stream: TcpStream) -> Vec<u8> {
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut rest = Vec::new();
+	stream.read_to_end(&mut rest).unwrap();
+	rest
+}
+
+/* the server kept the connection, it neither wrote nor closed */
+fn kept(mut stream: TcpStream) -> bool {
+	stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+	match stream.read(&mut [0; 16]) {
+		Err(ref e) => e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut,
+		Ok(_read) => false,
+	}
+}
+
+#[test]
+fn turns_away_clients_beyond_the_limit() {
+	let _fds = FDS.read().unwrap();
+	let addr = limited(ConnectionLimits {
+		max_connections:	Some(2),
+		excess_policy:		ExcessPolicy::NotifyAndClose { header: 0x0C09, body: b"full".to_vec() },
+		..ConnectionLimits::default()
+	});
+	let first = TcpStream::connect(addr).unwrap();
+	let _second = TcpStream::connect(addr).unwrap();
+	/* accepted in order, the third finds both slots taken */
+	assert_eq!(rest_of(TcpStream::connect(addr).unwrap()), frame_packet(0x0C09, b"full"));
+
+	/* a free slot is taken again */
+	drop(first);
+	wait_for(|| kept(TcpStream::connect(addr).unwrap()));
+}
+
+#[test]
+fn limits_clients_per_address() {
+	let _fds = FDS.read().unwrap();
+	let addr = limited(ConnectionLimits {
+		max_per_ip:			Some(1),
+		..ConnectionLimits::default()
+	});
+	let first = TcpStream::connect(addr).unwrap();
+
+	/* `Refuse` closes without a word */
+	assert!(rest_of(TcpStream::connect(addr).unwrap()).is_empty());
+	assert!(kept(first));
+}
+
+#[test]
+fn refuses_notifications_the_client_would_not_read() {
+	let _fds = FDS.read().unwrap();
+	let limits = ConnectionLimits {
+		max_connections:	Some(0),
+		excess_policy:		ExcessPolicy::NotifyAndClose { header: 0x0C09, body: vec![0; 2049] },
+		..ConnectionLimits::default()
+	};
+	assert_eq!(limits.validate().unwrap_err().kind(), ErrorKind::InvalidInput);
+	let config = ServerConfig {
+		limits:		limits.clone(),
+		..ServerConfig::default()
+	};
+	let error = FiestaServer::new(any_port(), Box::new(Ignore)).config(config).start().err().unwrap();
+	assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+	/* a handler set up without `start` closes without a word, and goes on */
+	let addr = limited(limits);
+	assert!(rest_of(TcpStream::connect(addr).unwrap()).is_empty());
+	assert!(rest_of(TcpStream::connect(addr).unwrap()).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "at most 2048 are read")]
+fn frame_packet_refuses_bodies_the_server_would_not_read() {
+	frame_packet(0x0C01, &[0; 2049]);
+}
+
+fn set_fd_limit(limit: libc::rlim_t) -> libc::rlim_t {
+	unsafe {
+		let mut rlimit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+		assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit), 0);
+		let previous = rlimit.rlim_cur;
+		rlimit.rlim_cur = limit;
+		assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &rlimit), 0);
+		previous
+	}
+}
+
+/* the lowest free descriptor, the next one the process would get */
+fn next_fd() -> libc::rlim_t {
+	unsafe {
+		let fd = libc::dup(0);
+		assert!(fd >= 0);
+		libc::close(fd);
+		fd as libc::rlim_t
+	}
+}
+
+#[test]
+fn pauses_accepting_after_an_accept_error() {
+	let _fds = FDS.write().unwrap();
+	let config = ServerConfig {
+		limits:		ConnectionLimits {
+			accept_pause_ms:	300,
+			..ConnectionLimits::default()
+		},
+		..ServerConfig::default()
+	};
+	let (go, started) = mpsc::channel();
+	let addr = serve_after(Box::new(Answer), config, move || started.recv().unwrap());
+	/* the client waits in the backlog until the descriptors run out */
+	let mut stream = TcpStream::connect(addr).unwrap();
+	let limit = set_fd_limit(next_fd());
+	let start = Instant::now();
+	go.send(()).unwrap();
+
+	/* the first accept fails; without a pause it would be retried, and
+	 * succeed, right after the limit is lifted */
+	thread::sleep(Duration::from_millis(100));
+	set_fd_limit(limit);
+	stream.write_all(&frame_packet(0x0C01, b"go")).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut answer = [0; 5];
+	stream.read_exact(&mut answer).unwrap();
+	assert_eq!(&answer[..], &frame_packet(0x0C02, b"go")[..]);
+	assert!(start.elapsed() >= Duration::from_millis(250));
+}
@@ -0,0 +1,181 @@
+extern crate fiesta_net;
+extern crate libc;
+
+mod common;
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::connector::Connector;
+use fiesta_net::links::{Backoff, Link, LinkError, LinkEvent, LinkManager, WhenDown};
+use fiesta_net::server::{FiestaServer, RunningServer};
+use common::{any_port, free_addr, wait_for, Answer, Collect};
+
+fn fast() -> Backoff {
+	Backoff {
+		initial:	Duration::from_millis(20),
+		max:		Duration::from_millis(100),
+		factor:		2,
+		jitter:		0.5,
+	}
+}
+
+fn serve(addr: SocketAddr) -> RunningServer {
+	FiestaServer::new(addr, Box::new(Answer)).start().unwrap()
+}
+
+fn next(events: &Receiver<LinkEvent>) -> LinkEvent {
+	events.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn reconnects_after_a_restart() {
+	let addr = free_addr();
+	let received = Collect::new();
+	let connector = Connector::start(Box::new(received.clone()), ServerConfig::default()).unwrap();
+	let handshakes = Arc::new(AtomicUsize::new(0));
+	let counted = handshakes.clone();
+	let links = LinkManager::new(connector)
+		.backoff(fast())
+		.link(Link::new("world", addr).handshake(move |link| {
+			counted.fetch_add(1, Ordering::SeqCst);
+			link.append_send(&frame_packet(0x0C01, b"hello"));
+			Ok(())
+		}))
+		.start()
+		.unwrap();
+	let events = links.subscribe();
+
+	/* nobody listens yet, the manager keeps trying */
+	std::thread::sleep(Duration::from_millis(150));
+	assert!(!links.is_up("world"));
+	links.send("world", &frame_packet(0x0C01, b"early")).unwrap();
+	let server = serve(addr);
+	match next(&events) {
+		LinkEvent::Up { name, .. } => assert_eq!(name, "world"),
+		LinkEvent::Down { .. } => panic!("expected the link to come up"),
+	}
+	wait_for(|| received.has_body(b"hello"));
+	wait_for(|| received.has_body(b"early"));
+
+	server.shutdown();
+	server.join().unwrap();
+	match next(&events) {
+		LinkEvent::Down { name } => assert_eq!(name, "world"),
+		LinkEvent::Up { .. } => panic!("expected the link to go down"),
+	}
+	links.send("world", &frame_packet(0x0C01, b"queued")).unwrap();
+
+	let server = serve(addr);
+	assert!(matches!(next(&events), LinkEvent::Up { .. }));
+	wait_for(|| received.has_body(b"queued"));
+	assert_eq!(handshakes.load(Ordering::SeqCst), 2);
+	links.send("world", &frame_packet(0x0C01, b"direct")).unwrap();
+	wait_for(|| received.has_body(b"direct"));
+
+	links.shutdown();
+	links.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn rejects_sends_while_down() {
+	let addr = free_addr();
+	let connector = Connector::start(Box::new(Answer), ServerConfig::default()).unwrap();
+	let links = LinkManager::new(connector)
+		.backoff(fast())
+		.when_down(WhenDown::Reject)
+		.link(Link::new("world", addr))
+		.start()
+		.unwrap();
+	assert_eq!(links.send("world", b"x"), Err(LinkError::Down("world".to_string())));
+	assert_eq!(links.send("login", b"x"), Err(LinkError::Unknown("login".to_string())));
+	links.shutdown();
+	links.join().unwrap();
+
+	let connector = Connector::start(Box::new(Answer), ServerConfig::default()).unwrap();
+	let links = LinkManager::new(connector)
+		.when_down(WhenDown::Queue(1))
+		.link(Link::new("world", addr))
+		.start()
+		.unwrap();
+	assert_eq!(links.send("world", b"x"), Ok(()));
+	assert_eq!(links.send("world", b"y"), Err(LinkError::QueueFull("world".to_string())));
+	links.shutdown();
+	links.join().unwrap();
+}
+
+#[test]
+fn backs_off_exponentially_with_jitter() {
+	let backoff = Backoff { jitter: 0.0, ..fast() };
+	let delays: Vec<u64> = (1..6).map(|failures| backoff.delay(failures).as_millis() as u64).collect();
+	assert_eq!(delays, vec![20, 40, 80, 100, 100]);
+	for _ in 0..100 {
+		let delay = fast().delay(3);
+		assert!(delay > Duration::from_millis(40) && delay <= Duration::from_millis(80), "{:?}", delay);
+	}
+}
+
+#[test]
+fn backs_off_from_a_peer_that_closes_right_away() {
+	let listener = TcpListener::bind(any_port()).unwrap();
+	let addr = listener.local_addr().unwrap();
+	let accepted = Arc::new(AtomicUsize::new(0));
+	{
+		let accepted = accepted.clone();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				accepted.fetch_add(1, Ordering::SeqCst);
+				drop(stream);
+			}
+		});
+	}
+	let connector = Connector::start(Box::new(Answer), ServerConfig::default()).unwrap();
+	let links = LinkManager::new(connector)
+		.backoff(Backoff { initial: Duration::from_millis(50), max: Duration::from_secs(1), factor: 2, jitter: 0.0 })
+		.link(Link::new("world", addr))
+		.start()
+		.unwrap();
+
+	/* 50, 100, 200 and 400 ms between the connects, not one every check */
+	thread::sleep(Duration::from_millis(1000));
+	let accepted = accepted.load(Ordering::SeqCst);
+	assert!((2..=6).contains(&accepted), "{} connects", accepted);
+	links.shutdown();
+	links.join().unwrap();
+}
+
+#[test]
+fn a_peer_that_does_not_answer_holds_up_no_other_link() {
+	/* with a backlog of 0 and one connection waiting, the SYNs go unanswered */
+	let blackhole = TcpListener::bind(any_port()).unwrap();
+	assert_eq!(unsafe { libc::listen(blackhole.as_raw_fd(), 0) }, 0);
+	let blackhole_addr = blackhole.local_addr().unwrap();
+	let _waiting = TcpStream::connect(blackhole_addr).unwrap();
+
+	let server = serve(any_port());
+	let addr = server.local_addr().unwrap();
+	let connector = Connector::start(Box::new(Answer), ServerConfig::default()).unwrap()
+		.connect_timeout(Duration::from_secs(2));
+	let start = Instant::now();
+	let links = LinkManager::new(connector)
+		.link(Link::new("stuck", blackhole_addr))
+		.link(Link::new("world", addr))
+		.start()
+		.unwrap();
+	wait_for(|| links.is_up("world"));
+	assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+	assert!(!links.is_up("stuck"));
+
+	links.shutdown();
+	links.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
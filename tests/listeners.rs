@@ -0,0 +1,193 @@
+extern crate fiesta_net;
+extern crate mio;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use mio::{EventLoop, EventSet, PollOpt};
+use mio::tcp::TcpListener;
+use fiesta_net::client::{frame_packet, FiestaHandler, SERVER_TOKEN};
+use fiesta_net::config::ServerConfig;
+use fiesta_net::processing::{PacketProcessingInfo, PacketRouter};
+use fiesta_net::server::{FiestaServer, RunningServer};
+use fiesta_net::stream::{ListenAddr, Listener, ListenerTag, DEFAULT_LISTENER};
+use common::{any_port, wait_for, Collect};
+
+const ZONE: ListenerTag = ListenerTag("zone");
+const LOCAL: ListenerTag = ListenerTag("local");
+
+fn socket_path(name: &str) -> PathBuf {
+	env::temp_dir().join(format!("fiesta-listeners-{}-{}.sock", name, std::process::id()))
+}
+
+fn tags(received: &Collect) -> Vec<(u16, &'static str)> {
+	let mut tags: Vec<_> = received.0.lock().unwrap().iter().map(|&(opcode, _, tag)| (opcode, tag)).collect();
+	tags.sort();
+	tags
+}
+
+#[test]
+fn tags_clients_with_their_listener() {
+	let received = Collect::new();
+	let server = FiestaServer::new(any_port(), Box::new(received.clone()))
+		.listen(ZONE, any_port())
+		.start()
+		.unwrap();
+	let game = server.local_addr().unwrap();
+	let zone = server.listener_addr(ZONE).unwrap();
+	assert_ne!(game, zone);
+	assert_eq!(server.listener_addr(DEFAULT_LISTENER), Some(game));
+	assert_eq!(server.listener_addr(LOCAL), None);
+
+	TcpStream::connect(game).unwrap().write_all(&frame_packet(1, &[])[..]).unwrap();
+	TcpStream::connect(zone).unwrap().write_all(&frame_packet(2, &[])[..]).unwrap();
+	wait_for(|| received.0.lock().unwrap().len() == 2);
+	assert_eq!(tags(&received), vec![(1, "default"), (2, "zone")]);
+
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn listens_after_the_metrics_endpoint_and_clients() {
+	let received = Collect::new();
+	let processor = Box::new(received.clone());
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || {
+		let listener = TcpListener::bind(&any_port()).unwrap();
+		let game = listener.local_addr().unwrap();
+		let mut event_loop = EventLoop::new().unwrap();
+		event_loop.register_opt(&listener, SERVER_TOKEN, EventSet::readable(), PollOpt::level()).unwrap();
+		let mut handler = FiestaHandler::with_config(listener, processor, ServerConfig::default());
+		let metrics = handler.serve_metrics(&mut event_loop, TcpListener::bind(&any_port()).unwrap()).unwrap();
+		let zone = TcpListener::bind(&any_port()).unwrap();
+		let addr = zone.local_addr().unwrap();
+		let token = handler.listen(&mut event_loop, Listener::Tcp(zone), ZONE).unwrap();
+		assert!(token != SERVER_TOKEN && token != metrics);
+		sender.send((game, addr)).unwrap();
+		event_loop.run(&mut handler).unwrap();
+	});
+	let (game, zone) = receiver.recv().unwrap();
+
+	/* the tokens of the listeners and clients are all taken from the registry */
+	let mut client = TcpStream::connect(game).unwrap();
+	client.write_all(&frame_packet(1, &[])[..]).unwrap();
+	wait_for(|| received.0.lock().unwrap().len() == 1);
+	TcpStream::connect(zone).unwrap().write_all(&frame_packet(2, &[])[..]).unwrap();
+	client.write_all(&frame_packet(3, &[])[..]).unwrap();
+	wait_for(|| received.0.lock().unwrap().len() == 3);
+	assert_eq!(tags(&received), vec![(1, "default"), (2, "zone"), (3, "default")]);
+}
+
+fn answer(info: &PacketProcessingInfo) {
+	let header = info.packet.read().unwrap().header;
+	info.client.append_send(&frame_packet(header + 1, info.client.listener().name().as_bytes()));
+}
+
+fn router() -> PacketRouter {
+	PacketRouter::new()
+		.route(0x0100, answer)
+		.route_on(0x0200, &[ZONE, LOCAL], answer)
+}
+
+/* sends the packets and reads until `expected` arrived */
+fn exchange(addr: SocketAddr, packets: &[u16], expected: &[u8]) {
+	let mut stream = TcpStream::connect(addr).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	for &opcode in packets {
+		stream.write_all(&frame_packet(opcode, &[])[..]).unwrap();
+	}
+	let mut answers = vec![0; expected.len()];
+	stream.read_exact(&mut answers[..]).unwrap();
+	assert_eq!(answers, expected);
+}
+
+fn routed(router: PacketRouter) -> RunningServer {
+	FiestaServer::new(any_port(), Box::new(router))
+		.listen(ZONE, any_port())
+		.listen(LOCAL, any_port())
+		.start()
+		.unwrap()
+}
+
+#[test]
+fn routes_opcodes_only_on_their_listeners() {
+	let router = router();
+	assert!(router.allows(0x0100, DEFAULT_LISTENER));
+	assert!(router.allows(0x0200, ZONE));
+	assert!(!router.allows(0x0200, DEFAULT_LISTENER));
+	assert!(!router.allows(0x0300, ZONE));
+	assert!(router.clone().fallback(answer).allows(0x0300, ZONE));
+
+	let server = routed(router);
+	let mut expected = frame_packet(0x0101, b"zone");
+	expected.extend_from_slice(&frame_packet(0x0201, b"zone")[..]);
+	exchange(server.listener_addr(ZONE).unwrap(), &[0x0100, 0x0200], &expected[..]);
+
+	/* dropped on the wrong listener, the client stays connected */
+	let mut expected = frame_packet(0x0101, b"default");
+	expected.extend_from_slice(&frame_packet(0x0101, b"default")[..]);
+	exchange(server.local_addr().unwrap(), &[0x0100, 0x0200, 0x0100], &expected[..]);
+
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn sends_unrouted_packets_to_the_fallback() {
+	let server = routed(router().fallback(answer));
+	exchange(server.listener_addr(LOCAL).unwrap(), &[0x0300], &frame_packet(0x0301, b"local")[..]);
+
+	/* a route on another listener is not a missing route */
+	exchange(server.local_addr().unwrap(), &[0x0200, 0x0300], &frame_packet(0x0301, b"default")[..]);
+
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn listens_on_unix_sockets() {
+	let path = socket_path("serve");
+	/* left behind by an earlier run, replaced */
+	drop(UnixListener::bind(&path).unwrap());
+	assert!(path.exists());
+
+	let server = FiestaServer::new(any_port(), Box::new(router()))
+		.listen(LOCAL, ListenAddr::Unix(path.clone()))
+		.start()
+		.unwrap();
+	assert_eq!(server.listener_addr(LOCAL), None);
+	let mut stream = UnixStream::connect(&path).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	stream.write_all(&frame_packet(0x0200, &[])[..]).unwrap();
+	let expected = frame_packet(0x0201, b"local");
+	let mut answer = vec![0; expected.len()];
+	stream.read_exact(&mut answer[..]).unwrap();
+	assert_eq!(answer, expected);
+	assert_eq!(server.registry().clients()[0].peer_addr(), None);
+
+	drop(stream);
+	server.shutdown();
+	server.join().unwrap();
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn leaves_other_files_at_the_socket_path_alone() {
+	let path = socket_path("file");
+	fs::write(&path, b"not a socket").unwrap();
+	let started = FiestaServer::new(any_port(), Box::new(router()))
+		.listen(LOCAL, ListenAddr::Unix(path.clone()))
+		.start();
+	assert_eq!(started.err().map(|e| e.kind()), Some(ErrorKind::AddrInUse));
+	assert_eq!(fs::read(&path).unwrap(), b"not a socket");
+	fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,134 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::metrics::*;
+use fiesta_net::processing::PacketProcessingThreadPool;
+use fiesta_net::server::FiestaServer;
+use common::{any_port, wait_for, Answer};
+
+#[test]
+fn counts_server_traffic() {
+	let config = ServerConfig::default();
+	let metrics = config.metrics.clone();
+	let pool = PacketProcessingThreadPool::with_metrics(2, Box::new(Answer), Some(metrics.clone()));
+	let server = FiestaServer::new(any_port(), Box::new(pool)).config(config).start().unwrap();
+	let addr = server.local_addr().unwrap();
+
+	let mut stream = TcpStream::connect(addr).unwrap();
+	for i in 0..3u8 {
+		stream.write_all(&frame_packet(0x0C01, &[i; 4])[..]).unwrap();
+	}
+	let mut answers = [0; 3 * 7];
+	stream.read_exact(&mut answers[..]).unwrap();
+	assert_eq!(&answers[..7], &frame_packet(0x0C02, &[0; 4])[..]);
+	drop(stream);
+	wait_for(|| metrics.snapshot().connected == 0);
+
+	let snapshot = server.metrics().snapshot();
+	assert_eq!(snapshot.accepted, 1);
+	assert_eq!(snapshot.disconnects.get(&DisconnectReason::PeerClosed), Some(&1));
+	assert_eq!(snapshot.bytes_in, 21);
+	assert_eq!(snapshot.bytes_out, 21);
+	assert_eq!(snapshot.packets_in(), 3);
+	assert_eq!(snapshot.packets_out(), 3);
+	let inbound = &snapshot.opcodes[&0x0C01];
+	assert_eq!((inbound.packets_in, inbound.bytes_in), (3, 12));
+	assert_eq!(inbound.latency.count, 3);
+	assert_eq!(snapshot.opcodes[&0x0C02].packets_out, 3);
+	assert!(snapshot.read_buffer_high >= 7);
+	assert!(snapshot.queue_high >= 1);
+	assert_eq!(snapshot.queue_depth, 0);
+
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn latency_quantiles() {
+	let metrics = Metrics::new();
+	for us in &[10, 40, 200, 900, 400_000] {
+		metrics.processed(0x0101, Duration::from_micros(*us));
+	}
+	let latency = metrics.snapshot().opcodes[&0x0101].latency.clone();
+	assert_eq!(latency.count, 5);
+	assert_eq!(latency.counts[0], 2);
+	assert_eq!(latency.counts[LATENCY_BUCKETS_US.len()], 1);
+	assert_eq!(latency.quantile(0.5), Some(250));
+	assert_eq!(latency.quantile(0.8), Some(1_000));
+	assert_eq!(latency.quantile(1.0), None);
+	assert_eq!(latency.mean_us(), Some(401_150.0 / 5.0));
+}
+
+fn scrape(addr: SocketAddr, request: &str) -> String {
+	let mut stream = TcpStream::connect(addr).unwrap();
+	stream.write_all(request.as_bytes()).unwrap();
+	let mut response = String::new();
+	stream.read_to_string(&mut response).unwrap();
+	response
+}
+
+#[test]
+fn serves_prometheus_metrics() {
+	let config = ServerConfig {
+		metrics_addr:	Some(any_port()),
+		..ServerConfig::default()
+	};
+	let server = FiestaServer::new(any_port(), Box::new(Answer)).config(config).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let metrics_addr = server.metrics_addr().unwrap();
+
+	let mut stream = TcpStream::connect(addr).unwrap();
+	stream.write_all(&frame_packet(0x0C01, &[1, 2])[..]).unwrap();
+	let mut answer = [0; 5];
+	stream.read_exact(&mut answer[..]).unwrap();
+
+	let response = scrape(metrics_addr, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+	assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+	assert!(response.contains("\nfiesta_connected_clients 1\n"));
+	assert!(response.contains("# TYPE fiesta_packets_received_total counter\n"));
+	assert!(response.contains("fiesta_packets_received_total{opcode=\"0x0C01\"} 1\n"));
+	assert!(response.contains("fiesta_packets_sent_total{opcode=\"0x0C02\"} 1\n"));
+
+	let response = scrape(metrics_addr, "GET / HTTP/1.1\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+	let response = scrape(metrics_addr, "POST /metrics HTTP/1.1\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+
+	drop(stream);
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn closes_idle_scrapers() {
+	let config = ServerConfig {
+		metrics_addr:			Some(any_port()),
+		metrics_idle_timeout:	Duration::from_millis(100),
+		..ServerConfig::default()
+	};
+	let server = FiestaServer::new(any_port(), Box::new(Answer)).config(config).start().unwrap();
+	let metrics_addr = server.metrics_addr().unwrap();
+
+	/* more than the endpoint serves at once, one stops halfway through its request */
+	let start = Instant::now();
+	let mut stalled: Vec<TcpStream> = (0..20).map(|_| TcpStream::connect(metrics_addr).unwrap()).collect();
+	stalled[0].write_all(b"GET /metr").unwrap();
+	for stream in &mut stalled {
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let mut rest = Vec::new();
+		assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+	}
+	assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+
+	let response = scrape(metrics_addr, "GET /metrics HTTP/1.1\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+	server.shutdown();
+	server.join().unwrap();
+}
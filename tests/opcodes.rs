@@ -0,0 +1,50 @@
+extern crate fiesta_net;
+
+use fiesta_net::client::FiestaPacket;
+use fiesta_net::metrics::Metrics;
+use fiesta_net::opcodes::{self, Named, OpcodeInfo, WELL_KNOWN};
+use fiesta_net::schema::Origin;
+
+/* the tests share the registry of the process, each one looks at opcodes no
+ * other test registers */
+
+#[test]
+fn well_known_opcodes() {
+	assert!(WELL_KNOWN.windows(2).all(|pair| pair[0].opcode < pair[1].opcode));
+	let seed = opcodes::info(0x0807).unwrap();
+	assert_eq!((seed.name, seed.origin, seed.size), ("NC_MISC_SEED_ACK", Origin::Server, Some(2)));
+	assert_eq!(opcodes::name(0x7F7F), None);
+
+	assert_eq!(Named(0x2017).to_string(), "0x2017 NC_ACT_MOVEWALK_CMD");
+	assert_eq!(Named(0x7F7F).to_string(), "0x7F7F");
+	assert_eq!(format!("{:?}", FiestaPacket::from_vec(0x0807, vec![0, 1])), "FiestaPacket(0x0807 NC_MISC_SEED_ACK, 2 bytes)");
+}
+
+#[test]
+fn registered_opcodes() {
+	opcodes::register(OpcodeInfo { opcode: 0x7F01, name: "NC_CUSTOM_HELLO_REQ", origin: Origin::Client, size: None });
+	/* replaces the well-known entry */
+	opcodes::register(OpcodeInfo { opcode: 0x2401, name: "NC_BAT_TARGET_REQ", origin: Origin::Client, size: Some(2) });
+	assert_eq!(opcodes::name(0x7F01), Some("NC_CUSTOM_HELLO_REQ"));
+	assert_eq!(opcodes::name(0x2401), Some("NC_BAT_TARGET_REQ"));
+
+	let known = opcodes::known();
+	assert!(known.iter().any(|info| info.opcode == 0x7F01));
+	assert_eq!(known.iter().filter(|info| info.opcode == 0x2401).count(), 1);
+
+	let metrics = Metrics::new();
+	metrics.received(0x7F01, 4);
+	metrics.received(0x7F02, 4);
+	let exposed = metrics.snapshot().to_prometheus();
+	assert!(exposed.contains("fiesta_packets_received_total{opcode=\"0x7F01\",name=\"NC_CUSTOM_HELLO_REQ\"} 1\n"));
+	assert!(exposed.contains("fiesta_packets_received_total{opcode=\"0x7F02\"} 1\n"));
+}
+
+#[test]
+fn parses_opcodes_in_hex_and_decimal() {
+	assert_eq!(opcodes::parse_opcode("0x0C01"), Ok(0x0C01));
+	assert_eq!(opcodes::parse_opcode("0X0c01"), Ok(0x0C01));
+	assert_eq!(opcodes::parse_opcode("3073"), Ok(0x0C01));
+	assert_eq!(opcodes::parse_opcode("0x10000"), Err("not an opcode: 0x10000".to_string()));
+	assert!(opcodes::parse_opcode("move").is_err());
+}
@@ -0,0 +1,128 @@
+extern crate fiesta_net;
+
+use fiesta_net::client::FiestaPacket;
+use fiesta_net::dump;
+use fiesta_net::opcodes;
+use fiesta_net::packets::Packet;
+use fiesta_net::packets::defs::*;
+use fiesta_net::reader::DecodeErrorKind;
+use fiesta_net::schema::{Length, Origin, Scalar, Schema, Type};
+
+#[test]
+fn parses_schema() {
+	let schema = Schema::parse("
+		# comment
+		struct Item {
+			id:			u16		# trailing comment
+			slots:		u8[4]
+		}
+
+		packet NC_TEST_REQ 0x7F01 client {
+			name:		string[16]
+			items:		Item[u16]
+			rest:		bytes[]
+		}
+	").unwrap();
+	assert_eq!(schema.structs[0].name, "Item");
+	assert_eq!(schema.structs[0].fields[1].ty, Type::Array(Box::new(Type::Scalar(Scalar::U8)), Length::Fixed(4)));
+	let packet = schema.packet(0x7F01).unwrap();
+	assert_eq!((&packet.name[..], packet.origin, packet.line), ("NC_TEST_REQ", Origin::Client, 8));
+	assert_eq!(packet.fields[0].ty, Type::String(Length::Fixed(16)));
+	assert_eq!(packet.fields[1].ty, Type::Array(Box::new(Type::Struct("Item".to_string())), Length::Prefixed(Scalar::U16)));
+	assert_eq!(packet.fields[2].ty, Type::Bytes(Length::Rest));
+
+	let code = schema.generate("::fiesta_net");
+	assert!(code.contains("pub struct NcTestReq {"));
+	assert!(code.contains("impl ::fiesta_net::packets::Packet for NcTestReq {"));
+}
+
+#[test]
+fn reports_schema_errors() {
+	let error = |source: &str| {
+		let e = Schema::parse(source).unwrap_err();
+		(e.line, e.message)
+	};
+	assert_eq!(error("packet A 0x0101 client {\n\tx: f32\n}").0, 2);
+	assert_eq!(error("packet A 0x10000 client {\n}").0, 1);
+	assert_eq!(error("packet A 0x0101 sideways {\n}").0, 1);
+	assert_eq!(error("packet A 0x0101 client {\n\tx: u8\n").0, 1);
+	assert_eq!(error("packet A 0x0101 client {\n}\npacket B 0x0101 server {\n}").0, 3);
+	assert_eq!(error("packet A 0x0101 client {\n\tx: bytes[]\n\ty: u8\n}").0, 2);
+	assert_eq!(error("packet A 0x0101 client {\n\tx: string\n}").0, 2);
+	assert_eq!(error("packet A 0x0101 client {\n\ttype: u8\n}").0, 2);
+	assert_eq!(error("struct A {\n\tb: B[u8]\n}\nstruct B {\n\ta: A\n}").0, 1);
+
+	/* arrays of structs that can take no bytes would be decoded forever */
+	let (line, message) = error("struct Empty {\n}\npacket A 0x0101 client {\n\tx: Empty[]\n}");
+	assert_eq!((line, &message[..]), (4, "arrays of Empty are not supported, it can be empty"));
+	assert_eq!(error("struct Empty {\n}\npacket A 0x0101 client {\n\tx: Empty[u32]\n}").0, 4);
+	assert_eq!(error("struct B {\n\tx: bytes[]\n}\nstruct A {\n\tb: B[4]\n}").0, 2);
+	assert_eq!(error("struct B {\n\tx: string[0]\n\ty: u8[0]\n}\nstruct A {\n\tb: B[4]\n}").0, 6);
+	assert!(Schema::parse("struct Empty {\n}\nstruct B {\n\te: Empty\n\tx: bytes[u8]\n}\npacket A 0x0101 client {\n\tb: B[]\n}").is_ok());
+}
+
+#[test]
+fn round_trips_generated_packets() {
+	let ack = NcUserLoginAck {
+		worlds: vec![
+			WorldStatus { id: 0, name: "Isya".to_string(), status: 1 },
+			WorldStatus { id: 1, name: "Pagel".to_string(), status: 3 },
+		],
+	};
+	let packet = ack.to_packet();
+	assert_eq!(packet.header, 0x0C0A);
+	assert_eq!(packet.data.len(), 1 + 2 * 18);
+	assert_eq!(packet.data[0], 2);
+	assert_eq!(NcUserLoginAck::from_packet(&packet).unwrap(), ack);
+
+	let chat = NcActChatReq { text: "hi all".to_string() };
+	assert_eq!(chat.to_frame(), fiesta_net::client::frame_packet(0x2001, b"\x06hi all"));
+
+	let walk = NcActMovewalkCmd {
+		from: Position { x: 1, y: 2 },
+		to: Position { x: 3, y: 4 },
+	};
+	assert_eq!(NcActMovewalkCmd::from_packet(&walk.to_packet()).unwrap(), walk);
+
+	/* fixed sizes are padded */
+	let select = NcUserWorldselectAck { key: vec![7; 3], ..Default::default() };
+	let packet = select.to_packet();
+	assert_eq!(packet.data.len(), 1 + 16 + 2 + 64);
+	assert_eq!(NcUserWorldselectAck::from_packet(&packet).unwrap().key[..4], [7, 7, 7, 0]);
+}
+
+#[test]
+fn refuses_to_frame_oversized_packets() {
+	let world = WorldStatus { id: 0, name: "Isya".to_string(), status: 1 };
+	let ack = NcUserLoginAck { worlds: vec![world; 200] };
+	assert_eq!(ack.try_to_frame().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+	let chat = NcActChatReq { text: "hi all".to_string() };
+	assert_eq!(chat.try_to_frame().unwrap(), chat.to_frame());
+}
+
+#[test]
+fn rejects_malformed_bodies() {
+	let short = FiestaPacket::from_vec(0x0C0A, vec![2, 0]);
+	let error = NcUserLoginAck::from_packet(&short).unwrap_err();
+	assert!(matches!(error.kind, DecodeErrorKind::UnexpectedEnd { .. }));
+
+	let long = FiestaPacket::from_vec(0x0807, vec![0, 1, 2]);
+	let error = NcMiscSeedAck::from_packet(&long).unwrap_err();
+	assert_eq!(error.kind, DecodeErrorKind::TrailingBytes(1));
+
+	let other = FiestaPacket::from_vec(0x0808, vec![0, 1]);
+	assert!(NcMiscSeedAck::from_packet(&other).is_err());
+}
+
+#[test]
+fn registers_generated_packets() {
+	register();
+	let info = opcodes::info(0x0C06).unwrap();
+	assert_eq!((info.name, info.origin, info.size), ("NC_USER_LOGIN_REQ", Origin::Client, Some(34)));
+	assert_eq!(NcUserLoginAck::SIZE, None);
+
+	let body = NcMiscSeedAck { seed: 0x0102 }.to_packet();
+	let annotations = dump::schema(0x0807).unwrap().annotate(0x0807, &body.data[..]);
+	assert_eq!(annotations[0].name, "seed");
+	assert_eq!(annotations[0].value, "258 (0x0102)");
+}
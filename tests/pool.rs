@@ -0,0 +1,96 @@
+extern crate fiesta_net;
+
+use std::sync::Mutex;
+use fiesta_net::buffer::Buffer;
+use fiesta_net::pool::{self, ClassStats, MAX_FREE_BYTES, SIZE_CLASSES};
+use fiesta_net::shared::SharedBytes;
+
+/* the pool is process wide, the tests must not see each other's buffers */
+static POOL: Mutex<()> = Mutex::new(());
+
+fn class(size: usize) -> ClassStats {
+	pool::stats().classes.into_iter().find(|c| c.size == size).unwrap()
+}
+
+#[test]
+fn picks_the_smallest_class_that_fits() {
+	let _pool = POOL.lock().unwrap();
+	assert_eq!(pool::take(1).len(), 64);
+	assert_eq!(pool::take(64).len(), 64);
+	assert_eq!(pool::take(65).len(), 256);
+	assert_eq!(pool::take(4096).len(), 4096);
+	assert_eq!(Buffer::with_capacity(100).capacity(), 256);
+
+	/* too big for any class, allocated as asked and never kept */
+	let largest = SIZE_CLASSES[SIZE_CLASSES.len() - 1];
+	let oversized = pool::stats().oversized;
+	let data = pool::take(largest + 1);
+	assert_eq!(data.len(), largest + 1);
+	assert_eq!(pool::stats().oversized, oversized + 1);
+	let free_bytes = pool::stats().free_bytes;
+	drop(data);
+	assert_eq!(pool::stats().free_bytes, free_bytes);
+}
+
+#[test]
+fn reuses_returned_buffers() {
+	let _pool = POOL.lock().unwrap();
+	let before = class(1024);
+	let data = pool::take(1000);
+	drop(data);
+	let returned = class(1024);
+	assert_eq!(returned.returned, before.returned + 1);
+	assert_eq!(returned.free, before.free + 1);
+
+	let data = pool::take(1024);
+	let after = class(1024);
+	assert_eq!(after.reused, before.reused + 1);
+	assert_eq!(after.free, before.free);
+	drop(data);
+
+	/* only buffers of a class size go back */
+	let free_bytes = pool::stats().free_bytes;
+	drop(pool::PooledBuf::from_vec(vec![0; 1000]));
+	assert_eq!(pool::stats().free_bytes, free_bytes);
+}
+
+#[test]
+fn bounds_the_bytes_it_keeps() {
+	let _pool = POOL.lock().unwrap();
+	/* whatever the other tests left behind counts against the limit */
+	let held = pool::stats().free_bytes;
+	pool::set_max_free_bytes(held + 2 * 16384);
+	let taken: Vec<_> = (0..3).map(|_| pool::take(16384)).collect();
+	let before = class(16384);
+	drop(taken);
+
+	let after = class(16384);
+	assert_eq!(after.returned, before.returned + 2);
+	assert_eq!(after.discarded, before.discarded + 1);
+	assert_eq!(pool::stats().free_bytes, held + 2 * 16384);
+
+	/* smaller buffers do not fit either */
+	let discarded = class(64).discarded;
+	drop(pool::take(64));
+	assert_eq!(class(64).discarded, discarded + 1);
+	pool::set_max_free_bytes(MAX_FREE_BYTES);
+}
+
+#[test]
+fn shared_bytes_return_with_the_last_slice() {
+	let _pool = POOL.lock().unwrap();
+	let mut data = pool::take(200);
+	data[..3].copy_from_slice(&[1, 2, 3]);
+	let bytes = SharedBytes::from_pooled(data, 3);
+	assert_eq!(&bytes[..], &[1, 2, 3][..]);
+	let slice = bytes.slice(1, 3);
+	let copy = slice.clone();
+
+	let before = class(256);
+	drop(bytes);
+	drop(slice);
+	assert_eq!(class(256).returned, before.returned);
+	assert_eq!(&copy[..], &[2, 3][..]);
+	drop(copy);
+	assert_eq!(class(256).returned, before.returned + 1);
+}
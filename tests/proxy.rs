@@ -0,0 +1,214 @@
+extern crate fiesta_net;
+extern crate log;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use fiesta_net::capture::Direction;
+use fiesta_net::cipher::XorCipher;
+use fiesta_net::client::{frame_large_packet, frame_packet, FiestaPacket};
+use fiesta_net::dump;
+use fiesta_net::proxy::{self, HookAction, ProxyCipher, ProxyConfig, ProxyHooks};
+use common::{any_port, free_addr};
+use log::{LogLevelFilter, LogMetadata, LogRecord};
+
+/* the packet the server sends its seed with */
+const SEED_OPCODE: u16 = 0x0807;
+
+/* runs a proxy to `upstream` for the rest of the test process, returns the
+ * client's side of a session and the upstream side */
+fn proxied(cipher: Option<ProxyCipher>, hooks: ProxyHooks) -> (TcpStream, TcpStream) {
+	let upstream = TcpListener::bind(any_port()).unwrap();
+	let listen = free_addr();
+	let config = ProxyConfig {
+		upstream:		upstream.local_addr().unwrap(),
+		cipher,
+		dump:			false,
+	};
+	thread::spawn(move || proxy::run(&listen, config, hooks));
+	let client = connect(listen);
+	let (server, _) = upstream.accept().unwrap();
+	for stream in &[&client, &server] {
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	}
+	(client, server)
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+	for _ in 0..100 {
+		if let Ok(stream) = TcpStream::connect(addr) {
+			return stream;
+		}
+		thread::sleep(Duration::from_millis(10));
+	}
+	panic!("the proxy did not start");
+}
+
+/* (opcode, body) as it is on the wire, without decrypting */
+fn read_frame(stream: &mut TcpStream) -> (u16, Vec<u8>) {
+	let mut size = [0; 1];
+	stream.read_exact(&mut size[..]).unwrap();
+	let size = match size[0] {
+		0 => {
+			let mut long = [0; 2];
+			stream.read_exact(&mut long[..]).unwrap();
+			u16::from_be_bytes(long) as usize
+		},
+		size => size as usize,
+	};
+	let mut frame = vec![0; size + 2];
+	stream.read_exact(&mut frame[..]).unwrap();
+	let body = frame.split_off(2);
+	(u16::from_be_bytes([frame[0], frame[1]]), body)
+}
+
+#[test]
+fn forwards_packets_beyond_the_server_limit() {
+	let (mut client, mut server) = proxied(None, ProxyHooks::new());
+	/* a 2049 byte body would be read as an empty one by the server */
+	let large: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+	client.write_all(&frame_large_packet(0x0C01, &large[..2049])[..]).unwrap();
+	client.write_all(&frame_large_packet(0x0C01, &large[..])[..]).unwrap();
+	assert_eq!(read_frame(&mut server), (0x0C01, large[..2049].to_vec()));
+	assert_eq!(read_frame(&mut server), (0x0C01, large.clone()));
+
+	server.write_all(&frame_large_packet(0x0C02, &large[..5000])[..]).unwrap();
+	server.write_all(&frame_packet(0x0C03, &[])[..]).unwrap();
+	assert_eq!(read_frame(&mut client), (0x0C02, large[..5000].to_vec()));
+	assert_eq!(read_frame(&mut client), (0x0C03, Vec::new()));
+}
+
+#[test]
+fn hooks_change_and_drop_packets() {
+	let seen = Arc::new(Mutex::new(Vec::new()));
+	let log = seen.clone();
+	let hooks = ProxyHooks::new()
+		.on_all(move |direction, packet: &mut FiestaPacket| {
+			log.lock().unwrap().push((direction, packet.header));
+			if packet.header == 0x0202 {
+				packet.header = 0x0203;
+			}
+			HookAction::Forward
+		})
+		.on(0x0101, |_, _| HookAction::Drop)
+		/* sees what the catch-all hook made of 0x0202 */
+		.on(0x0203, |_, packet| {
+			*packet = FiestaPacket::from_vec(packet.header, vec![9]);
+			HookAction::Forward
+		});
+	let (mut client, mut server) = proxied(None, hooks);
+
+	client.write_all(&frame_packet(0x0101, &[1])[..]).unwrap();
+	client.write_all(&frame_packet(0x0202, &[2])[..]).unwrap();
+	client.write_all(&frame_packet(0x0303, &[3])[..]).unwrap();
+	assert_eq!(read_frame(&mut server), (0x0203, vec![9]));
+	assert_eq!(read_frame(&mut server), (0x0303, vec![3]));
+
+	server.write_all(&frame_packet(0x0101, &[4])[..]).unwrap();
+	server.write_all(&frame_packet(0x0404, &[5])[..]).unwrap();
+	assert_eq!(read_frame(&mut client), (0x0404, vec![5]));
+	assert_eq!(*seen.lock().unwrap(), vec![
+		(Direction::Inbound, 0x0101),
+		(Direction::Inbound, 0x0202),
+		(Direction::Inbound, 0x0303),
+		(Direction::Outbound, 0x0101),
+		(Direction::Outbound, 0x0404),
+	]);
+}
+
+#[test]
+fn decrypts_from_the_seed_the_server_sends() {
+	let key = Arc::new(vec![0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+	let cipher = ProxyCipher {
+		key:				key.clone(),
+		client_to_server:	true,
+		server_to_client:	false,
+		seed_opcode:		Some(SEED_OPCODE),
+	};
+	let (sender, decrypted) = mpsc::channel();
+	let hooks = ProxyHooks::new().on(0x0C01, move |_, packet| {
+		sender.send(packet.data.to_vec()).unwrap();
+		HookAction::Forward
+	});
+	let (mut client, mut server) = proxied(Some(cipher), hooks);
+
+	/* the server to client direction is not encrypted */
+	server.write_all(&frame_packet(SEED_OPCODE, &5u16.to_be_bytes())[..]).unwrap();
+	assert_eq!(read_frame(&mut client), (SEED_OPCODE, vec![0, 5]));
+
+	let mut encrypt = XorCipher::new(key.clone(), 5);
+	let mut decrypt = XorCipher::new(key, 5);
+	for body in &[&b"hello"[..], &b"again, further into the key"[..]] {
+		let (header, encrypted) = encrypt.apply_packet(0x0C01, body);
+		client.write_all(&frame_packet(header, &encrypted[..])[..]).unwrap();
+		assert_eq!(decrypted.recv_timeout(Duration::from_secs(5)).unwrap(), body.to_vec());
+		/* encrypted again for the server, from the same seed */
+		let (header, encrypted) = read_frame(&mut server);
+		assert_eq!(decrypt.apply_packet(header, &encrypted[..]), (0x0C01, body.to_vec()));
+	}
+}
+
+#[test]
+fn cipher_covers_opcode_and_body() {
+	let key = Arc::new(vec![1, 2, 3]);
+	let mut cipher = XorCipher::new(key.clone(), 5);
+	assert_eq!(cipher.position(), 2);
+	/* the key positions 2, 0, 1, 2, 0 */
+	assert_eq!(cipher.apply_packet(0x0000, &[0, 0, 0]), (0x0301, vec![2, 3, 1]));
+	assert_eq!(cipher.position(), 1);
+	assert_eq!(cipher.apply_packet(0x0000, &[]), (0x0203, vec![]));
+
+	/* its own inverse */
+	let (header, body) = XorCipher::new(key.clone(), 0).apply_packet(0x1234, b"data");
+	assert_eq!(XorCipher::new(key, 0).apply_packet(header, &body[..]), (0x1234, b"data".to_vec()));
+}
+
+/* keeps what is logged to `dump::TRACE_TARGET` */
+struct Traced(Arc<Mutex<Vec<String>>>);
+
+impl log::Log for Traced {
+	fn enabled(&self, metadata: &LogMetadata) -> bool {
+		metadata.target() == dump::TRACE_TARGET
+	}
+
+	fn log(&self, record: &LogRecord) {
+		if self.enabled(record.metadata()) {
+			self.0.lock().unwrap().push(format!("{}", record.args()));
+		}
+	}
+}
+
+#[test]
+fn traces_packets_decrypted() {
+	let lines = Arc::new(Mutex::new(Vec::new()));
+	let traced = Traced(lines.clone());
+	log::set_logger(|max| {
+		max.set(LogLevelFilter::Info);
+		Box::new(traced)
+	}).unwrap();
+	dump::trace_all(true);
+
+	let key = Arc::new(vec![0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+	let cipher = ProxyCipher {
+		key:				key.clone(),
+		client_to_server:	true,
+		server_to_client:	false,
+		seed_opcode:		Some(SEED_OPCODE),
+	};
+	let (mut client, mut server) = proxied(Some(cipher), ProxyHooks::new());
+	server.write_all(&frame_packet(SEED_OPCODE, &5u16.to_be_bytes())[..]).unwrap();
+	read_frame(&mut client);
+	let (header, encrypted) = XorCipher::new(key, 5).apply_packet(0x0C01, b"hello");
+	client.write_all(&frame_packet(header, &encrypted[..])[..]).unwrap();
+	read_frame(&mut server);
+	dump::trace_all(false);
+
+	/* once each, not again encrypted by the legs */
+	let lines = lines.lock().unwrap();
+	assert_eq!(lines.iter().filter(|line| line.contains("0x0C01, 5 bytes")).count(), 1, "{:?}", lines);
+	assert!(!lines.iter().any(|line| line.contains(&format!("0x{:04X}", header))), "{:?}", lines);
+}
@@ -0,0 +1,192 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::metrics::Metrics;
+use fiesta_net::server::FiestaServer;
+use fiesta_net::ratelimit::*;
+use common::{any_port, serve, wait_for, Collect};
+
+fn ms(ms: u64) -> Duration {
+	Duration::from_millis(ms)
+}
+
+/* the packets are just their opcodes */
+fn admit(state: &mut RateLimitState<u16>, config: &RateLimitConfig, packets: &[u16], stats: &mut RateLimitStats) -> Admission<u16> {
+	state.admit(config, packets.to_vec(), |p| *p, stats)
+}
+
+#[test]
+fn buckets_refill_up_to_their_burst() {
+	let start = Instant::now();
+	let mut bucket = TokenBucket::new(BucketConfig::new(10.0, 2.0), start);
+	assert!(bucket.has_token(start));
+	let config = RateLimitConfig::new(RateLimitAction::Drop).limit_connection(BucketConfig::new(10.0, 2.0));
+	let mut limiter = config.limiter();
+	let now = Instant::now();
+	assert_eq!(limiter.check(&config, 1, now), Verdict::Allow);
+	assert_eq!(limiter.check(&config, 1, now), Verdict::Allow);
+	assert_eq!(limiter.check(&config, 1, now), Verdict::Exceeded(ms(100)));
+
+	/* one token every 100 ms */
+	assert_eq!(limiter.check(&config, 1, now + ms(50)), Verdict::Exceeded(ms(50)));
+	assert_eq!(limiter.check(&config, 1, now + ms(100)), Verdict::Allow);
+	assert_eq!(limiter.check(&config, 1, now + ms(100)), Verdict::Exceeded(ms(100)));
+
+	/* but never more than the burst */
+	let later = now + ms(10_000);
+	assert_eq!(limiter.check(&config, 1, later), Verdict::Allow);
+	assert_eq!(limiter.check(&config, 1, later), Verdict::Allow);
+	assert!(matches!(limiter.check(&config, 1, later), Verdict::Exceeded(_)));
+
+	assert_eq!(bucket.wait_time(start), ms(0));
+}
+
+#[test]
+#[should_panic(expected = "must be positive")]
+fn refuses_buckets_that_never_refill() {
+	BucketConfig::new(0.0, 1.0);
+}
+
+#[test]
+fn validates_buckets_filled_in_by_hand() {
+	let stopped = BucketConfig { rate: 0.0, burst: 1.0 };
+	let config = RateLimitConfig::new(RateLimitAction::Drop).limit_opcode(1, stopped);
+	assert_eq!(config.validate().unwrap_err().kind(), ErrorKind::InvalidInput);
+	let nan = BucketConfig { rate: f64::NAN, burst: 1.0 };
+	assert!(RateLimitConfig::new(RateLimitAction::Drop).limit_connection(nan).validate().is_err());
+	assert!(RateLimitConfig::new(RateLimitAction::Drop).limit_connection(BucketConfig::new(1.0, 1.0)).validate().is_ok());
+
+	let config = ServerConfig {
+		rate_limits:	Some(config),
+		..ServerConfig::default()
+	};
+	let err = FiestaServer::new(any_port(), Box::new(Collect::new())).config(config).start().err().unwrap();
+	assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn limits_opcodes_and_groups_separately() {
+	let config = RateLimitConfig::new(RateLimitAction::Drop)
+		.limit_opcode(0x0C01, BucketConfig::new(1.0, 1.0))
+		.limit_group(&[0x2001, 0x2002], BucketConfig::new(1.0, 2.0));
+	assert!(!config.is_empty());
+	assert!(RateLimitConfig::new(RateLimitAction::Drop).is_empty());
+	let mut limiter = config.limiter();
+	let now = Instant::now();
+
+	assert_eq!(limiter.check(&config, 0x0C01, now), Verdict::Allow);
+	assert!(matches!(limiter.check(&config, 0x0C01, now), Verdict::Exceeded(_)));
+	/* the group shares one bucket */
+	assert_eq!(limiter.check(&config, 0x2001, now), Verdict::Allow);
+	assert_eq!(limiter.check(&config, 0x2002, now), Verdict::Allow);
+	assert!(matches!(limiter.check(&config, 0x2001, now), Verdict::Exceeded(_)));
+	/* anything else is not limited at all */
+	for _ in 0..100 {
+		assert_eq!(limiter.check(&config, 0x0C02, now), Verdict::Allow);
+	}
+}
+
+#[test]
+fn drops_what_exceeds_the_limit() {
+	let config = RateLimitConfig::new(RateLimitAction::Drop).limit_opcode(1, BucketConfig::new(1.0, 2.0));
+	let mut state = RateLimitState::new(&config);
+	let mut stats = RateLimitStats::default();
+	let admission = admit(&mut state, &config, &[1, 2, 1, 1, 2], &mut stats);
+	assert_eq!(admission.allowed, vec![1, 2, 1, 2]);
+	assert_eq!((admission.retry_after, admission.disconnect), (None, None));
+	assert_eq!(stats, RateLimitStats { dropped: 1, delayed: 0, disconnects: 0 });
+}
+
+#[test]
+fn disconnects_on_the_first_excess() {
+	let action = RateLimitAction::Disconnect("flooding".to_string());
+	let config = RateLimitConfig::new(action).limit_connection(BucketConfig::new(1.0, 2.0));
+	let mut state = RateLimitState::new(&config);
+	let mut stats = RateLimitStats::default();
+	let admission = admit(&mut state, &config, &[1, 2, 3, 4], &mut stats);
+	assert_eq!(admission.allowed, vec![1, 2]);
+	assert_eq!(admission.disconnect, Some("flooding".to_string()));
+	assert_eq!(stats.disconnects, 1);
+}
+
+#[test]
+fn delays_in_order_and_bounds_the_queue() {
+	let config = RateLimitConfig::new(RateLimitAction::Delay { max_queued: 3 })
+		.limit_connection(BucketConfig::new(100.0, 1.0));
+	let mut state = RateLimitState::new(&config);
+	let mut stats = RateLimitStats::default();
+	let admission = admit(&mut state, &config, &[1, 2, 3, 4, 5], &mut stats);
+	assert_eq!(admission.allowed, vec![1]);
+	assert_eq!(admission.retry_after, Some(ms(10)));
+	assert_eq!(state.delayed_count(), 2);
+	assert_eq!(stats, RateLimitStats { dropped: 2, delayed: 2, disconnects: 0 });
+
+	/* one timer at a time, new packets queue up behind the delayed ones */
+	let admission = admit(&mut state, &config, &[6], &mut stats);
+	assert!(admission.allowed.is_empty());
+	assert_eq!(admission.retry_after, None);
+	assert_eq!(state.delayed_count(), 3);
+
+	std::thread::sleep(ms(50));
+	state.timer_fired();
+	let admission = admit(&mut state, &config, &[], &mut stats);
+	assert_eq!(admission.allowed, vec![2]);
+	assert!(admission.retry_after.is_some());
+	assert_eq!(state.delayed_count(), 2);
+}
+
+fn limited(config: RateLimitConfig) -> (Collect, TcpStream, Arc<Metrics>) {
+	let config = ServerConfig {
+		rate_limits:	Some(config),
+		..ServerConfig::default()
+	};
+	let metrics = config.metrics.clone();
+	let received = Collect::new();
+	let addr = serve(Box::new(received.clone()), config);
+	(received, TcpStream::connect(addr).unwrap(), metrics)
+}
+
+#[test]
+fn releases_delayed_packets_on_a_timer() {
+	let limit = RateLimitConfig::new(RateLimitAction::Delay { max_queued: 16 })
+		.limit_opcode(0x0C01, BucketConfig::new(20.0, 1.0));
+	let (received, mut stream, metrics) = limited(limit);
+	let start = Instant::now();
+	for i in 0..4u8 {
+		stream.write_all(&frame_packet(0x0C01, &[i])[..]).unwrap();
+	}
+
+	/* nothing else arrives from the client, only the timer releases them */
+	wait_for(|| received.0.lock().unwrap().len() == 4);
+	assert!(start.elapsed() >= ms(150), "{:?}", start.elapsed());
+	let bodies: Vec<Vec<u8>> = received.0.lock().unwrap().iter().map(|p| p.1.clone()).collect();
+	assert_eq!(bodies, vec![vec![0], vec![1], vec![2], vec![3]]);
+	let limited = metrics.snapshot().rate_limited;
+	assert!(limited.delayed >= 1, "{:?}", limited);
+	assert_eq!((limited.dropped, limited.disconnects), (0, 0));
+	assert!(metrics.snapshot().to_prometheus().contains("fiesta_rate_limited_total{outcome=\"delayed\"}"));
+}
+
+#[test]
+fn disconnects_a_flooding_client() {
+	let action = RateLimitAction::Disconnect("flooding".to_string());
+	let (received, mut stream, metrics) = limited(RateLimitConfig::new(action).limit_connection(BucketConfig::new(1.0, 2.0)));
+	let mut burst = Vec::new();
+	for i in 0..5u8 {
+		burst.extend_from_slice(&frame_packet(0x0C01, &[i])[..]);
+	}
+	stream.write_all(&burst[..]).unwrap();
+
+	let mut rest = Vec::new();
+	assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+	/* the batch that exceeded the limit is not processed at all */
+	assert!(received.0.lock().unwrap().len() <= 2);
+	assert_eq!(metrics.snapshot().rate_limited.disconnects, 1);
+}
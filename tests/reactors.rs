@@ -0,0 +1,98 @@
+extern crate fiesta_net;
+extern crate mio;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use mio::{NotifyError, Token};
+use fiesta_net::client::{frame_packet, FiestaMessage};
+use fiesta_net::server::{Balance, FiestaServer, ReactorMode, RunningServer};
+use common::{any_port, wait_for, Answer};
+
+fn serve(mode: ReactorMode) -> RunningServer {
+	FiestaServer::new(any_port(), Box::new(Answer)).mode(mode).start().unwrap()
+}
+
+/* connects and waits for the answer, whichever loop serves the client */
+fn connect(server: &RunningServer) -> TcpStream {
+	let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	stream.write_all(&frame_packet(0x0C01, b"hi")[..]).unwrap();
+	let expected = frame_packet(0x0C02, b"hi");
+	let mut answer = vec![0; expected.len()];
+	stream.read_exact(&mut answer[..]).unwrap();
+	assert_eq!(answer, expected);
+	stream
+}
+
+fn loads(server: &RunningServer) -> Vec<usize> {
+	let registry = server.registry();
+	(0..registry.reactors()).map(|r| registry.load(r)).collect()
+}
+
+#[test]
+fn hands_clients_out_in_turn() {
+	let server = serve(ReactorMode::Acceptor { reactors: 3, balance: Balance::RoundRobin });
+	let streams: Vec<TcpStream> = (0..6).map(|_| connect(&server)).collect();
+	assert_eq!(server.registry().len(), 6);
+	assert_eq!(loads(&server), vec![2, 2, 2]);
+
+	drop(streams);
+	wait_for(|| server.registry().is_empty());
+	assert_eq!(loads(&server), vec![0, 0, 0]);
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn fills_up_the_least_loaded_reactor() {
+	let server = serve(ReactorMode::Acceptor { reactors: 2, balance: Balance::LeastLoaded });
+	/* ties go to the first loop, so they alternate */
+	let mut streams: Vec<Option<TcpStream>> = (0..4).map(|_| Some(connect(&server))).collect();
+	assert_eq!(loads(&server), vec![2, 2]);
+
+	/* the first loop's clients leave, the next two go there */
+	streams[0] = None;
+	streams[2] = None;
+	wait_for(|| loads(&server) == vec![0, 2]);
+	streams.push(Some(connect(&server)));
+	streams.push(Some(connect(&server)));
+	assert_eq!(loads(&server), vec![2, 2]);
+
+	drop(streams);
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn serves_the_client_itself_when_a_reactor_is_gone() {
+	let server = serve(ReactorMode::Acceptor { reactors: 2, balance: Balance::RoundRobin });
+	let stopped = &server.channels()[1];
+	stopped.send(FiestaMessage::Shutdown).unwrap();
+	wait_for(|| matches!(stopped.send(FiestaMessage::Flush(Token(usize::MAX))), Err(NotifyError::Closed(_))));
+
+	/* every other client would be the stopped loop's */
+	let streams: Vec<TcpStream> = (0..4).map(|_| connect(&server)).collect();
+	assert_eq!(loads(&server), vec![4, 0]);
+
+	drop(streams);
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn every_reactor_accepts_with_reuseport() {
+	let server = serve(ReactorMode::ReusePort { reactors: 2 });
+	/* the kernel hashes the source ports, 32 clients all on one loop would
+	 * be a 1 in 2^31 chance */
+	let streams: Vec<TcpStream> = (0..32).map(|_| connect(&server)).collect();
+	let loads = loads(&server);
+	assert_eq!(loads.iter().sum::<usize>(), 32);
+	assert!(loads.iter().all(|&load| load > 0), "{:?}", loads);
+
+	drop(streams);
+	server.shutdown();
+	server.join().unwrap();
+}
@@ -0,0 +1,58 @@
+extern crate fiesta_net;
+
+use fiesta_net::client::FiestaPacket;
+use fiesta_net::reader::{DecodeErrorKind, PacketReader};
+
+fn packet() -> FiestaPacket {
+	FiestaPacket::from_vec(0x0C06, vec![1, 0, 2, 0xAB, 0xCD, 0xEF, b'h', b'i', 0, 0])
+}
+
+#[test]
+fn reads_and_seeks() {
+	let packet = packet();
+	let mut reader = packet.reader();
+	assert_eq!((reader.opcode(), reader.len(), reader.remaining()), (0x0C06, 10, 10));
+	assert_eq!(reader.read_u8().unwrap(), 1);
+	assert_eq!(reader.read_u16().unwrap(), 2);
+	reader.skip(3).unwrap();
+	assert_eq!(reader.read_string(4).unwrap(), "hi");
+	assert_eq!(reader.remaining(), 0);
+	reader.expect_end().unwrap();
+
+	/* reads are not destructive */
+	reader.seek(3).unwrap();
+	assert_eq!(reader.read_bytes(3).unwrap(), &[0xAB, 0xCD, 0xEF]);
+	reader.rewind();
+	assert_eq!(reader.position(), 0);
+	reader.seek(10).unwrap();
+	assert_eq!(reader.remaining(), 0);
+}
+
+#[test]
+fn reports_where_decoding_failed() {
+	let packet = packet();
+	let mut reader = packet.reader();
+	reader.skip(8).unwrap();
+	let error = reader.read_u32().unwrap_err();
+	assert_eq!((error.opcode, error.offset), (0x0C06, 8));
+	assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd { wanted: 4, remaining: 2 });
+	assert_eq!(reader.position(), 8);
+
+	let error = reader.skip(3).unwrap_err();
+	assert_eq!((error.offset, error.kind), (8, DecodeErrorKind::OutOfBounds(11)));
+	let error = reader.seek(11).unwrap_err();
+	assert_eq!(error.kind, DecodeErrorKind::OutOfBounds(11));
+	assert_eq!(reader.position(), 8);
+
+	/* a size off the wire must not overflow */
+	let error = reader.skip(usize::MAX).unwrap_err();
+	assert_eq!((error.offset, error.kind), (8, DecodeErrorKind::OutOfBounds(usize::MAX)));
+
+	let error = reader.expect_end().unwrap_err();
+	assert_eq!((error.opcode, error.offset, &error.kind), (0x0C06, 8, &DecodeErrorKind::TrailingBytes(2)));
+	assert_eq!(error.to_string(), "decoding packet 0x0C06 failed at offset 8: 2 trailing bytes");
+
+	let empty = PacketReader::from_slice(0x0804, &[]);
+	assert!(empty.is_empty());
+	empty.expect_end().unwrap();
+}
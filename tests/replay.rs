@@ -0,0 +1,99 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use fiesta_net::capture::*;
+use fiesta_net::cipher::{SeedConfig, SEED_OPCODE};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::harness::Harness;
+use fiesta_net::processing::{PacketProcessingInfo, PacketProcessor};
+use fiesta_net::replay::*;
+use fiesta_net::server::FiestaServer;
+use common::any_port;
+
+/* answers every packet with opcode + `offset` and the same body */
+struct Answer(u16);
+
+impl PacketProcessor for Answer {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let packet = info.packet.read().unwrap();
+		info.client.append_send(&frame_packet(packet.header + self.0, &packet.data[..]));
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(Answer(self.0))
+	}
+}
+
+fn record_session(path: &std::path::Path) -> Session {
+	let recorder = Arc::new(PacketRecorder::new(CaptureConfig::new(path)).unwrap());
+	let mut harness = Harness::new(Box::new(Answer(1)));
+	harness.client().set_recorder(Some(recorder.clone()));
+	for i in 0..5u8 {
+		harness.inject(0x0100, &[i; 3]);
+		harness.run();
+	}
+	recorder.flush().unwrap();
+	Session::load(CaptureReader::open(path).unwrap(), None).unwrap()
+}
+
+#[test]
+fn replay_matches_recording() {
+	let path = env::temp_dir().join(format!("fiesta-replay-match-{}", std::process::id()));
+	let session = record_session(&path);
+	assert_eq!(session.inbound().len(), 5);
+	let report = replay_into(Box::new(Answer(1)), &session, &ReplayOptions::new());
+	assert!(report.is_match(), "{}", report);
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_reports_differences() {
+	let path = env::temp_dir().join(format!("fiesta-replay-diff-{}", std::process::id()));
+	let session = record_session(&path);
+	let report = replay_into(Box::new(Answer(2)), &session, &ReplayOptions::new());
+	assert_eq!(report.differences.len(), 5);
+	assert_eq!(report.differences[0], Difference::Mismatch { index: 0, expected: 0x0101, actual: 0x0102, offset: None });
+	/* ignoring both opcodes leaves nothing to compare */
+	let options = ReplayOptions::new().ignore(0x0101).ignore(0x0102);
+	assert!(replay_into(Box::new(Answer(2)), &session, &options).is_match());
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_encrypts_with_the_seed_it_is_sent() {
+	let path = env::temp_dir().join(format!("fiesta-replay-seed-{}", std::process::id()));
+	let mut session = record_session(&path);
+	/* the recording got another seed than the replay will */
+	let seed = CaptureEntry::now(session.token, None, Direction::Outbound, SEED_OPCODE, &[0, 7]);
+	session.entries.insert(0, seed);
+
+	let key = Arc::new((0..=255u8).collect::<Vec<u8>>());
+	let config = ServerConfig {
+		seed:		Some(SeedConfig::new().key(key.clone())),
+		..ServerConfig::default()
+	};
+	let server = FiestaServer::new(any_port(), Box::new(Answer(1))).config(config).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let options = ReplayOptions::new().settle(Duration::from_millis(200));
+
+	let report = replay_against(&addr, &session, &options.clone().key(key)).unwrap();
+	assert!(report.is_match(), "{}", report);
+	assert_eq!(report.received.len(), 5);
+	/* unencrypted, the server decrypts them to something else */
+	let report = replay_against(&addr, &session, &options).unwrap();
+	assert!(!report.is_match());
+	/* and without leaving out the seeds they differ */
+	let report = replay_against(&addr, &session, &options.seed_opcode(None)).unwrap();
+	assert_eq!(report.differences.len(), 6);
+
+	server.shutdown();
+	server.join().unwrap();
+	fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,200 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::connector::Connector;
+use fiesta_net::dump::{FieldKind, PacketSchema};
+use fiesta_net::packets::{Codec, Packet};
+use fiesta_net::reader::{DecodeError, PacketReader};
+use fiesta_net::rpc::{Request, Responder, Rpc, RpcError, RpcProcessor};
+use fiesta_net::schema::Origin;
+use fiesta_net::server::FiestaServer;
+use fiesta_net::writer::PacketWriter;
+use common::{any_port, wait_for, Collect};
+
+macro_rules! value_packet {
+	($name:ident, $opcode:expr) => {
+		#[derive(Debug, Clone, Default, PartialEq)]
+		struct $name {
+			value:		u32,
+		}
+
+		impl Codec for $name {
+			fn decode(reader: &mut PacketReader) -> Result<Self, DecodeError> {
+				Ok($name { value: reader.read_u32()? })
+			}
+
+			fn encode(&self, writer: PacketWriter) -> PacketWriter {
+				writer.write_u32(self.value)
+			}
+		}
+
+		impl Packet for $name {
+			const OPCODE: u16 = $opcode;
+			const NAME: &'static str = stringify!($name);
+			const ORIGIN: Origin = Origin::Both;
+			const SIZE: Option<usize> = Some(4);
+
+			fn dump_schema() -> PacketSchema {
+				PacketSchema::new(Self::NAME).field("value", FieldKind::U32)
+			}
+		}
+	};
+}
+
+/* answered with the value + 1; 0 is never answered, 1 closes the link, 2
+ * is answered late and 4 also gets a pong that answers no call */
+value_packet!(Ping, 0x7E01);
+value_packet!(Pong, 0x7E02);
+
+impl Request for Ping {
+	type Response = Pong;
+}
+
+fn answering() -> RpcProcessor {
+	RpcProcessor::new(Rpc::new()).handle(|ping: Ping, reply: Responder<Pong>| {
+		match ping.value {
+			0 => (),
+			1 => reply.link().close(),
+			2 => {
+				thread::spawn(move || {
+					thread::sleep(Duration::from_millis(300));
+					reply.send(&Pong { value: 3 });
+				});
+			},
+			4 => {
+				reply.link().append_send(&PacketWriter::new(Pong::OPCODE).write_u32(0).write_u32(99).to_frame()[..]);
+				reply.send(&Pong { value: 5 });
+			},
+			value => reply.send(&Pong { value: value + 1 }),
+		}
+	})
+}
+
+#[test]
+fn correlates_responses() {
+	let server = FiestaServer::new(any_port(), Box::new(answering())).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let rpc = Rpc::new();
+	let connector = Connector::start(Box::new(RpcProcessor::new(rpc.clone())), ServerConfig::default()).unwrap();
+	let link = connector.connect(&addr).unwrap();
+
+	/* in flight together, each gets its own response */
+	let calls: Vec<_> = (10..20).map(|value| rpc.call(&link, &Ping { value }, Duration::from_secs(5))).collect();
+	for (value, call) in (10..20).zip(calls) {
+		assert_eq!(call.wait(), Ok(Pong { value: value + 1 }));
+	}
+
+	let (sender, receiver) = mpsc::channel();
+	rpc.call_with(&link, &Ping { value: 41 }, Duration::from_secs(5), move |result| sender.send(result).unwrap());
+	assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), Ok(Pong { value: 42 }));
+	assert_eq!(rpc.pending(), 0);
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn times_out_and_drops_late_responses() {
+	let server = FiestaServer::new(any_port(), Box::new(answering())).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let rpc = Rpc::new();
+	let connector = Connector::start(Box::new(RpcProcessor::new(rpc.clone())), ServerConfig::default()).unwrap();
+	let link = connector.connect(&addr).unwrap();
+
+	assert_eq!(rpc.call(&link, &Ping { value: 0 }, Duration::from_millis(100)).wait(), Err(RpcError::Timeout));
+	assert_eq!(rpc.call(&link, &Ping { value: 2 }, Duration::from_millis(100)).wait(), Err(RpcError::Timeout));
+	assert_eq!(rpc.pending(), 0);
+
+	/* the late response is recognized and dropped, the link keeps working */
+	wait_for(|| rpc.late_responses() == 1);
+	assert_eq!(rpc.call(&link, &Ping { value: 7 }, Duration::from_secs(5)).wait(), Ok(Pong { value: 8 }));
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn fails_calls_when_the_link_drops() {
+	let server = FiestaServer::new(any_port(), Box::new(answering())).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let rpc = Rpc::new();
+	let connector = Connector::start(Box::new(RpcProcessor::new(rpc.clone())), ServerConfig::default()).unwrap();
+	let link = connector.connect(&addr).unwrap();
+
+	let waiting = rpc.call(&link, &Ping { value: 0 }, Duration::from_secs(30));
+	assert_eq!(rpc.call(&link, &Ping { value: 1 }, Duration::from_secs(30)).wait(), Err(RpcError::Disconnected));
+	assert_eq!(waiting.wait(), Err(RpcError::Disconnected));
+	assert_eq!(rpc.pending(), 0);
+
+	/* no call is even sent over a dead link */
+	assert_eq!(rpc.call(&link, &Ping { value: 5 }, Duration::from_secs(30)).wait(), Err(RpcError::Disconnected));
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn passes_on_what_answers_no_call() {
+	let server = FiestaServer::new(any_port(), Box::new(answering())).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let rpc = Rpc::new();
+	let received = Collect::new();
+	let processor = RpcProcessor::new(rpc.clone()).fallback(Box::new(received.clone()));
+	let connector = Connector::start(Box::new(processor), ServerConfig::default()).unwrap();
+	let link = connector.connect(&addr).unwrap();
+
+	/* a pong with an unknown ID goes to the fallback, before and after calls */
+	for _ in 0..2 {
+		assert_eq!(rpc.call(&link, &Ping { value: 4 }, Duration::from_secs(5)).wait(), Ok(Pong { value: 5 }));
+	}
+	wait_for(|| received.0.lock().unwrap().len() == 2);
+	for packet in received.0.lock().unwrap().iter() {
+		assert_eq!((packet.0, &packet.1[..]), (Pong::OPCODE, &[0, 0, 0, 0, 0, 0, 0, 99][..]));
+	}
+
+	/* but not the late response to a call that timed out */
+	assert_eq!(rpc.call(&link, &Ping { value: 2 }, Duration::from_millis(100)).wait(), Err(RpcError::Timeout));
+	wait_for(|| rpc.late_responses() == 1);
+	assert_eq!(received.0.lock().unwrap().len(), 2);
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn times_out_the_calls_of_every_rpc() {
+	let server = FiestaServer::new(any_port(), Box::new(answering())).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let rpcs: Vec<Rpc> = (0..8).map(|_| Rpc::new()).collect();
+	let connector = Connector::start(Box::new(RpcProcessor::new(rpcs[0].clone())), ServerConfig::default()).unwrap();
+	let link = connector.connect(&addr).unwrap();
+
+	let calls: Vec<_> = rpcs.iter().map(|rpc| rpc.call(&link, &Ping { value: 0 }, Duration::from_millis(100))).collect();
+	for call in calls {
+		assert_eq!(call.wait(), Err(RpcError::Timeout));
+	}
+
+	/* the shared thread may have stopped with the last `Rpc`, a new one starts it again */
+	drop(rpcs);
+	thread::sleep(Duration::from_millis(100));
+	let rpc = Rpc::new();
+	assert_eq!(rpc.call(&link, &Ping { value: 0 }, Duration::from_millis(100)).wait(), Err(RpcError::Timeout));
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}
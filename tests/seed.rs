@@ -0,0 +1,98 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, RwLock};
+use fiesta_net::cipher::{SeedConfig, XorCipher, SEED_OPCODE};
+use fiesta_net::client::frame_packet;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::processing::{PacketProcessingInfo, PacketProcessor};
+use fiesta_net::server::FiestaServer;
+use fiesta_net::stream::{ListenerTag, DEFAULT_LISTENER};
+use common::any_port;
+
+const INTERNAL: ListenerTag = ListenerTag("internal");
+
+/* answers with the opcode + 1, the seed it knows and the body */
+struct Answer;
+
+impl PacketProcessor for Answer {
+	fn process_packet(&mut self, info: Arc<RwLock<Box<PacketProcessingInfo>>>) {
+		let info = info.read().unwrap();
+		let packet = info.packet.read().unwrap();
+		let mut body = info.client.seed().unwrap_or(0xFFFF).to_be_bytes().to_vec();
+		body.extend_from_slice(&packet.data[..]);
+		info.client.append_send(&frame_packet(packet.header + 1, &body[..]));
+	}
+
+	fn clone(&self) -> Box<dyn PacketProcessor> {
+		Box::new(Answer)
+	}
+}
+
+/* a frame with a one byte size prefix */
+fn read_frame(stream: &mut TcpStream, body: usize) -> Vec<u8> {
+	let mut frame = vec![0; 3 + body];
+	stream.read_exact(&mut frame[..]).unwrap();
+	frame
+}
+
+#[test]
+fn sends_seed_and_decrypts() {
+	let key = Arc::new((0..=255u8).rev().collect::<Vec<u8>>());
+	let config = ServerConfig {
+		seed: Some(SeedConfig::new().key(key.clone()).only_on(&[DEFAULT_LISTENER])),
+		..ServerConfig::default()
+	};
+	let server = FiestaServer::new(any_port(), Box::new(Answer)).listen(INTERNAL, any_port()).config(config).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let internal = server.listener_addr(INTERNAL).unwrap();
+
+	let mut stream = TcpStream::connect(addr).unwrap();
+	let frame = read_frame(&mut stream, 2);
+	assert_eq!(&frame[..3], &[2, (SEED_OPCODE >> 8) as u8, SEED_OPCODE as u8]);
+	let seed = u16::from_be_bytes([frame[3], frame[4]]);
+	assert!((seed as usize) < key.len());
+
+	/* two packets, the cipher continues where the first one ended */
+	let mut cipher = XorCipher::new(key, seed);
+	for body in &[&b"abc"[..], &b"defg"[..]] {
+		let (header, encrypted) = cipher.apply_packet(0x0C01, body);
+		stream.write_all(&frame_packet(header, &encrypted[..])[..]).unwrap();
+		let answer = read_frame(&mut stream, 2 + body.len());
+		let mut expected = seed.to_be_bytes().to_vec();
+		expected.extend_from_slice(body);
+		assert_eq!(answer, frame_packet(0x0C02, &expected[..]));
+	}
+
+	/* not on the internal listener */
+	let mut stream = TcpStream::connect(internal).unwrap();
+	stream.write_all(&frame_packet(0x0C01, b"x")[..]).unwrap();
+	assert_eq!(read_frame(&mut stream, 3), frame_packet(0x0C02, &[0xFF, 0xFF, b'x']));
+
+	server.shutdown();
+	server.join().unwrap();
+}
+
+#[test]
+fn seeds_stay_in_range() {
+	let config = SeedConfig::new();
+	assert!((0..1000).all(|_| (config.generate() as usize) < fiesta_net::cipher::SEED_RANGE));
+	let config = SeedConfig::new().key(Arc::new(vec![1; 7]));
+	assert!((0..1000).all(|_| config.generate() < 7));
+	assert!(config.applies_to(INTERNAL));
+	assert!(!config.only_on(&[]).applies_to(INTERNAL));
+}
+
+#[test]
+fn refuses_an_empty_key() {
+	assert!(SeedConfig::new().validate().is_ok());
+	let config = ServerConfig {
+		seed:		Some(SeedConfig::new().key(Arc::new(Vec::new()))),
+		..ServerConfig::default()
+	};
+	let error = FiestaServer::new(any_port(), Box::new(Answer)).config(config).start().err().unwrap();
+	assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
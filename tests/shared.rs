@@ -0,0 +1,54 @@
+extern crate fiesta_net;
+
+use fiesta_net::buffer::BinaryReadable;
+use fiesta_net::pool;
+use fiesta_net::shared::SharedBytes;
+
+#[test]
+fn slices_share_the_bytes() {
+	let bytes = SharedBytes::from_vec((0..10).collect());
+	let slice = bytes.slice(2, 8);
+	assert_eq!(&slice[..], &[2, 3, 4, 5, 6, 7][..]);
+	assert_eq!(slice.as_ptr(), bytes[2..].as_ptr());
+
+	/* bounds are relative to the slice */
+	let inner = slice.slice(1, 3);
+	assert_eq!(&inner[..], &[3, 4][..]);
+	assert_eq!(inner.slice(2, 2).len(), 0);
+	assert!(inner.slice(2, 2).is_empty());
+	assert_eq!(slice.slice(0, 6).as_slice(), slice.as_slice());
+
+	/* the parent can go, the slice keeps the allocation */
+	drop(bytes);
+	drop(slice);
+	assert_eq!(inner.as_slice(), &[3, 4][..]);
+}
+
+#[test]
+#[should_panic(expected = "slice out of bounds")]
+fn slices_past_the_end_panic() {
+	let bytes = SharedBytes::from_vec(vec![0; 10]);
+	bytes.slice(2, 8).slice(0, 7);
+}
+
+#[test]
+#[should_panic(expected = "slice out of bounds")]
+fn reversed_slices_panic() {
+	SharedBytes::from_vec(vec![0; 10]).slice(5, 4);
+}
+
+#[test]
+fn cursors_read_the_slice_only() {
+	let bytes = SharedBytes::copy_from_slice(&[0xFF, 0x12, 0x34, 0xFF]);
+	let slice = bytes.slice(1, 3);
+	let mut cursor = slice.cursor();
+	assert_eq!(cursor.read_u16().unwrap(), 0x1234);
+	assert!(cursor.read_u8().is_err());
+	assert_eq!(SharedBytes::new().cursor().remaining(), 0);
+}
+
+#[test]
+#[should_panic(expected = "length past the end of the buffer")]
+fn lengths_past_the_pooled_buffer_panic() {
+	SharedBytes::from_pooled(pool::take(64), 65);
+}
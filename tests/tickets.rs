@@ -0,0 +1,112 @@
+extern crate fiesta_net;
+
+mod common;
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use fiesta_net::config::ServerConfig;
+use fiesta_net::connector::Connector;
+use fiesta_net::rpc::{Rpc, RpcProcessor};
+use fiesta_net::server::FiestaServer;
+use fiesta_net::tickets::{self, LinkStore, MemoryStore, TicketError, TicketToken, Tickets, MAX_SESSION_SIZE};
+use common::any_port;
+
+fn ip(ip: &str) -> IpAddr {
+	ip.parse().unwrap()
+}
+
+#[test]
+fn redeems_once() {
+	let store = Arc::new(MemoryStore::new());
+	let tickets = Tickets::new(store.clone());
+	let token = tickets.issue_for(ip("10.0.0.1"), b"account 7".to_vec()).unwrap();
+	assert_eq!(store.len(), 1);
+
+	assert_eq!(tickets.redeem_from(ip("10.0.0.1"), &token), Ok(b"account 7".to_vec()));
+	assert_eq!(tickets.redeem_from(ip("10.0.0.1"), &token), Err(TicketError::Unknown));
+	assert_eq!(tickets.redeem_from(ip("10.0.0.1"), &TicketToken::generate().unwrap()), Err(TicketError::Unknown));
+	assert!(store.is_empty());
+
+	/* a wrong address uses the ticket up, too */
+	let token = tickets.issue_for(ip("10.0.0.1"), Vec::new()).unwrap();
+	assert_eq!(tickets.redeem_from(ip("10.0.0.2"), &token),
+		Err(TicketError::WrongAddress { expected: ip("10.0.0.1"), actual: ip("10.0.0.2") }));
+	assert_eq!(tickets.redeem_from(ip("10.0.0.1"), &token), Err(TicketError::Unknown));
+	assert_ne!(TicketToken::generate().unwrap(), TicketToken::generate().unwrap());
+}
+
+#[test]
+fn expires() {
+	let store = Arc::new(MemoryStore::new());
+	let tickets = Tickets::new(store.clone()).ttl(Duration::from_millis(50));
+	let token = tickets.issue_for(ip("10.0.0.1"), Vec::new()).unwrap();
+	let stale = tickets.issue_for(ip("10.0.0.1"), Vec::new()).unwrap();
+	thread::sleep(Duration::from_millis(100));
+	assert_eq!(tickets.redeem_from(ip("10.0.0.1"), &token), Err(TicketError::Expired));
+
+	/* dropped from the store once the next ticket comes in */
+	tickets.issue_for(ip("10.0.0.1"), Vec::new()).unwrap();
+	assert_eq!(store.len(), 1);
+	assert_eq!(tickets.redeem_from(ip("10.0.0.1"), &stale), Err(TicketError::Unknown));
+}
+
+#[test]
+fn shares_tickets_over_a_link() {
+	let store = Arc::new(MemoryStore::new());
+	let world = tickets::serve(RpcProcessor::new(Rpc::new()), store.clone());
+	let server = FiestaServer::new(any_port(), Box::new(world)).start().unwrap();
+	let addr = server.local_addr().unwrap();
+	let rpc = Rpc::new();
+	let connector = Connector::start(Box::new(RpcProcessor::new(rpc.clone())), ServerConfig::default()).unwrap();
+	let link = connector.connect(&addr).unwrap();
+
+	let shared = {
+		let link = link.clone();
+		Arc::new(LinkStore::new(rpc.clone(), move || Some(link.clone())))
+	};
+	let leaving = Tickets::new(shared.clone());
+	let arriving = Tickets::new(shared).ttl(Duration::from_secs(1));
+
+	/* the link's address stands in for a player's */
+	let token = leaving.issue(&link, b"character 3".to_vec()).unwrap();
+	assert_eq!(store.len(), 1);
+	assert_eq!(arriving.redeem(&link, &token), Ok(b"character 3".to_vec()));
+	assert_eq!(arriving.redeem(&link, &token), Err(TicketError::Unknown));
+
+	let token = arriving.issue_for(ip("10.0.0.1"), Vec::new()).unwrap();
+	assert_eq!(leaving.redeem(&link, &token),
+		Err(TicketError::WrongAddress { expected: ip("10.0.0.1"), actual: ip("127.0.0.1") }));
+
+	/* the largest session still fits into the packets, from an IPv6 address too */
+	let session = vec![7; MAX_SESSION_SIZE];
+	let token = leaving.issue_for(ip("2001:db8::1"), session.clone()).unwrap();
+	assert_eq!(arriving.redeem_from(ip("2001:db8::1"), &token), Ok(session));
+	let (issued, token) = mpsc::channel();
+	leaving.issue_with(&link, vec![7; MAX_SESSION_SIZE + 1], move |token| issued.send(token).unwrap());
+	assert_eq!(token.recv_timeout(Duration::from_secs(5)).unwrap(), Err(TicketError::TooLarge(MAX_SESSION_SIZE + 1)));
+	assert_eq!(leaving.issue_for(ip("10.0.0.1"), vec![0; 4096]), Err(TicketError::TooLarge(4096)));
+	assert!(store.is_empty());
+
+	let down = Tickets::new(Arc::new(LinkStore::new(rpc.clone(), || None)));
+	assert!(matches!(down.issue_for(ip("10.0.0.1"), Vec::new()), Err(TicketError::Store(_))));
+
+	/* the same without blocking, the callbacks get the results */
+	let (issued, token) = mpsc::channel();
+	leaving.issue_with(&link, b"character 4".to_vec(), move |token| issued.send(token).unwrap());
+	let token = token.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+	let (redeemed, session) = mpsc::channel();
+	arriving.redeem_with(&link, &token, move |session| redeemed.send(session).unwrap());
+	assert_eq!(session.recv_timeout(Duration::from_secs(5)).unwrap(), Ok(b"character 4".to_vec()));
+	let (redeemed, session) = mpsc::channel();
+	down.redeem_with(&link, &token, move |session| redeemed.send(session).unwrap());
+	assert!(matches!(session.recv_timeout(Duration::from_secs(5)).unwrap(), Err(TicketError::Store(_))));
+	assert!(store.is_empty());
+
+	connector.shutdown();
+	connector.join().unwrap();
+	server.shutdown();
+	server.join().unwrap();
+}